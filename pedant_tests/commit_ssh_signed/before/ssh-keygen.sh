@@ -0,0 +1,4 @@
+#!/bin/sh
+# Private keys must only be readable by their owner, which copies of this fixture may not preserve
+chmod 600 signing_key
+exec ssh-keygen "$@"
@@ -0,0 +1,24 @@
+int one()
+{
+	return 1;
+}
+
+int two()
+{
+	return 2;
+}
+
+int three()
+{
+	return 3;
+}
+
+void greet()
+{
+	if (ready) {
+		say("hello");
+	}
+	if (done) {
+		say("bye");
+	}
+}
@@ -0,0 +1,16 @@
+int one()
+{
+	return 1;
+}
+
+int three()
+{
+	return 3;
+}
+
+void greet()
+{
+	if (ready) {
+		say("hello");
+	}
+}
@@ -0,0 +1,2 @@
+#!/bin/sh
+sed "/^# local marker$/d"
@@ -0,0 +1,3 @@
+#!/bin/sh
+echo "# local marker"
+cat
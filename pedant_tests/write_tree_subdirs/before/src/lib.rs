@@ -0,0 +1 @@
+pub mod x;
\ No newline at end of file
@@ -1,14 +1,18 @@
-use std::{fs, env, ffi::CString, mem, path::PathBuf};
-use anyhow::{Result, anyhow};
-use clap::{arg, Args};
+use std::{fs, env, path::{Component, Path, PathBuf}};
+use anyhow::{Result, anyhow, bail};
+use clap::Args;
 
-use crate::{GlobalOpts, index::{Index, IndexItem}, repo_find, git_dir_name, objects::{Blob, GitObject}};
+use crate::{GlobalOpts, add_patch::add_patch, config::Config, filter::Filters, index::{index_path, lock_index, read_index, IndexItem}, repo_find, git_dir_name, objects::{Blob, GitObject}};
 
 #[derive(Args)]
 pub struct AddArgs {
     #[arg(short, long)]
     verbose: bool,
-    pathspec: String,
+    /// Choose hunks of the changes to tracked files to stage, limited to those under the path if one is given
+    #[arg(short, long)]
+    patch: bool,
+    #[arg(required_unless_present = "patch")]
+    pathspec: Option<String>,
 }
 
 pub fn cmd_add(args: AddArgs, global_opts: GlobalOpts) -> Result<()> {
@@ -19,110 +23,81 @@ pub fn cmd_add(args: AddArgs, global_opts: GlobalOpts) -> Result<()> {
 
     // For now, we assume the pathspec is a single file
     // The provided path may be relative or absolute
-    let provided_path = PathBuf::from(args.pathspec);
-    let index_item_path = rebase_path(&provided_path, &root)?;
+    let pathspec = args.pathspec.clone().unwrap_or_default();
+    let provided_path = PathBuf::from(&pathspec);
+    let index_item_path = rebase_path(&provided_path, &cwd, &root).ok_or_else(|| anyhow!(
+        "fatal: {0}: '{0}' is outside repository at '{1}'", pathspec, root.to_string_lossy()
+    ))?;
+    if args.patch {
+        return add_patch(&root, args.pathspec.is_some().then_some(index_item_path.as_path()), global_opts);
+    }
+    // Like Git, the index is locked before anything is written, so nothing is added while another process holds it
+    let lock = lock_index(&root, global_opts)?;
+    let mut index = read_index(&root, global_opts)?;
 
-    // Hash the object and write it to the store
-    let bytes = fs::read(provided_path)?;
+    // A directory in the path that is a symbolic link could lead anywhere, even out of the working tree
+    let through_link = index_item_path.ancestors().skip(1).filter(|x| !x.as_os_str().is_empty())
+        .any(|x| fs::symlink_metadata(root.join(x)).is_ok_and(|x| x.file_type().is_symlink()));
+    if through_link {
+        bail!("fatal: pathspec '{}' is beyond a symbolic link", pathspec);
+    }
+    // Where case is ignored, a path the index has in another case keeps the spelling it has there. Like Git, the path
+    // as given must exist, but the file is then read by the index's spelling when there is one, which on a
+    // case-sensitive file system may be another file.
+    let given_file = root.join(&index_item_path);
+    if fs::symlink_metadata(&given_file).is_err() {
+        bail!("fatal: pathspec '{}' did not match any files", pathspec);
+    }
+    let mut index_item_path = index_item_path;
+    if Config::load(&root, global_opts)?.ignore_case()? {
+        index_item_path = index.existing_case(&index_item_path);
+    }
+    let file = match fs::symlink_metadata(root.join(&index_item_path)) {
+        Ok(_) => root.join(&index_item_path),
+        Err(_) => given_file
+    };
+    let metadata = fs::symlink_metadata(&file)?;
+
+    // Hash the object and write it to the store, after any clean filter has converted it. A symbolic link is stored
+    // as the path it points at, without reading the file there, which may be outside the working tree.
+    let is_link = metadata.file_type().is_symlink();
+    let bytes = if is_link {
+        fs::read_link(&file)?.into_os_string().into_encoded_bytes()
+    } else {
+        let mut filters = Filters::new(&root, global_opts)?;
+        filters.clean(&index_item_path, fs::read(&file)?)?
+    };
 
     let blob = Blob { bytes };
     blob.write(&root, global_opts)?;
-
-    let item: IndexItem;
-
-    // Get status information on the file by calling the C standard library
-    let c_path = CString::new(index_item_path.to_string_lossy().as_bytes())?;
-    unsafe {
-        let mut stat: libc::stat = mem::zeroed();
-        libc::stat(c_path.as_ptr(), &mut stat);
-
-        item = IndexItem {
-            ctime: u32::try_from(stat.st_ctime).unwrap(),
-            ctime_nsec: u32::try_from(stat.st_ctime_nsec).unwrap(),
-            mtime: u32::try_from(stat.st_mtime).unwrap(),
-            mtime_nsec: u32::try_from(stat.st_mtime_nsec).unwrap(),
-            dev: u32::try_from(stat.st_dev).unwrap(),
-            ino: u32::try_from(stat.st_ino).unwrap(),
-            mode: u32::try_from(stat.st_mode).unwrap(),
-            uid: u32::try_from(stat.st_uid).unwrap(),
-            gid: u32::try_from(stat.st_gid).unwrap(),
-            size: u32::try_from(stat.st_size).unwrap(),
-            hash: blob.hash(),
-            path: index_item_path
-        }
-    }
-
-    let index_path = root.join(format!("{}/index", git_dir_name(global_opts)));
-    let mut index: Index;
-    if index_path.exists() {
-        let index_bytes = fs::read(&index_path)?;
-        index = Index::deserialize(index_bytes)?;
-
-        // Remove any existing entry for this path
-        index.items.retain(|x| x.path != item.path);
-
-        // Find position to insert this item in that will preserve ordering by path name
-        let new_path_str = item.path.to_string_lossy();
-        let new_path_bytes = new_path_str.as_bytes();
-
-        let mut inserted = false;
-        for i in 0..index.items.len() {
-            let current_path_str = index.items[i].path.to_string_lossy();
-            let current_path_bytes = current_path_str.as_bytes();
-            if mem_cmp(new_path_bytes, current_path_bytes) > 0 {
-                index.items.insert(i, item.clone());
-                inserted = true;
-                break;
-            }
-        }
-
-        if !inserted {
-            index.items.push(item.clone());
-        }
-    } else {
-        index = Index {
-            version: 2,
-            items: vec![item]
-        };
-    }
-
-    let index_bytes = index.serialize()?;
-    fs::write(index_path, index_bytes)?;
+    index.add_item(IndexItem::from_file(&file, index_item_path, blob.hash())?);
+    lock.commit(&index)?;
 
     Ok(())
 }
 
 /// Paths may be provided as absolute or relative to the current working directory.
 /// When written to the index, they are stored relative to the repository root.
-/// This fuction returns the path relative to the repository root, if the provided path is within the repository.
-/// Otherwise returns an error.
-fn rebase_path(path: &PathBuf, root: &PathBuf) -> Result<PathBuf> {
-    let path = path.canonicalize().map_err(|_| anyhow!("Invalid path {:?}", path))?;
-    let rel_path = path.strip_prefix(root)
-        .map_err(|_| anyhow!("{:?} is outside repository at {:?}", path, root))?;
-
-    Ok(rel_path.to_path_buf())
+/// This function returns the path relative to the repository root, with any `.` and `..` components resolved, so that
+/// each spelling of a path gives the same index entry. Like Git, the path is resolved without following symbolic
+/// links, and a relative path that leaves the working tree is refused even if it comes back into it. Returns None
+/// for a path outside the working tree.
+fn rebase_path(path: &Path, cwd: &Path, root: &Path) -> Option<PathBuf> {
+    // A relative path starts from the current directory, and an absolute one is taken apart from the top
+    let mut result = if path.is_absolute() { PathBuf::new() } else { cwd.strip_prefix(root).ok()?.to_path_buf() };
+    for component in path.components() {
+        match component {
+            Component::CurDir => (),
+            Component::ParentDir => {
+                if !result.pop() {
+                    return None;
+                }
+            },
+            component => result.push(component)
+        }
+    }
+    if path.is_absolute() {
+        return result.strip_prefix(root).ok().map(index_path);
+    }
+    Some(index_path(&result))
 }
-
-// Compares the byte arrays as a string of unsigned bytes. Returns -1 if left is greater, 0 if equal, 1 if right is greater.
-fn mem_cmp(left: &[u8], right: &[u8]) -> isize {
-    let min_len: usize = std::cmp::min(left.len(), right.len());
-    for i in 0..min_len {
-        if left[i] < right[i] {
-            return 1;
-        }
-        if left[i] > right[i] {
-            return -1;
-        }
-    }
-
-    // All aligned bytes were equal: the larger string is the longer one
-    if left.len() > right.len() {
-        return -1;
-    }
-    if left.len() < right.len() {
-        return 1;
-    }
-
-    return 0;
-}
\ No newline at end of file
@@ -0,0 +1,233 @@
+// Choosing which hunks of the changes to tracked files to stage, as `git add --patch` does
+
+use std::{fs, io::{self, BufRead, Write}, path::Path};
+use anyhow::Result;
+
+use crate::GlobalOpts;
+use crate::apply::{apply_hunks, parse_patch, PatchHunk};
+use crate::attributes::Attributes;
+use crate::config::Config;
+use crate::diff::{configured_algorithm, file_patch, read_blob, DiffSide};
+use crate::filter::Filters;
+use crate::index::{read_index, write_index};
+use crate::line_diff::split_lines;
+use crate::objects::{Blob, GitObject};
+
+// The help for the answers that can always be given
+const HELP: &str = "\
+y - stage this hunk
+n - do not stage this hunk
+q - quit; do not stage this hunk or any of the remaining ones
+a - stage this hunk and all later hunks in the file
+d - do not stage this hunk or any of the later hunks in the file
+";
+
+// The help for the answers that are only offered for some hunks, each shown only when the prompt offers it
+const HELP_REMAINDER: &str = "\
+j - leave this hunk undecided, see next undecided hunk
+J - leave this hunk undecided, see next hunk
+k - leave this hunk undecided, see previous undecided hunk
+K - leave this hunk undecided, see previous hunk
+g - select a hunk to go to
+/ - search for a hunk matching the given regex
+s - split the current hunk into smaller hunks
+e - manually edit the current hunk
+? - print help
+";
+
+// What has been decided for a hunk
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Choice {
+    Undecided,
+    Stage,
+    Skip,
+}
+
+// A hunk of the changes to a file as it's shown, and as it's applied once chosen
+struct Hunk {
+    text: Vec<u8>,
+    patch: PatchHunk,
+    choice: Choice,
+}
+
+/// Shows each hunk of the differences between the index and the working tree for the tracked files under `prefix`, or
+/// every tracked file if it's None, asking on standard input whether to stage it. The blob staged for a file is the
+/// one in the index with only the chosen hunks applied. Only changes to the contents of files are offered, not
+/// changes of mode or deletions.
+pub fn add_patch(root: &Path, prefix: Option<&Path>, global_opts: GlobalOpts) -> Result<()> {
+    let git_mode = global_opts.git_mode;
+    let mut index = read_index(root, global_opts)?;
+    let mut filters = Filters::new(root, global_opts)?;
+    let config = Config::load(root, global_opts)?;
+    let algorithm = configured_algorithm(&config)?;
+    let mut attributes = Attributes::new(root, &config, global_opts)?;
+
+    // The header and hunks of each file with changes to stage, by position in the index
+    let mut files = Vec::new();
+    let mut binary = false;
+    for (position, item) in index.items.iter().enumerate() {
+        let under_prefix = prefix.is_none_or(|x| x.as_os_str().is_empty() || item.path.starts_with(x));
+        if !under_prefix || item.stage != 0 || item.skip_worktree {
+            continue;
+        }
+        let file = root.join(&item.path);
+        if !fs::symlink_metadata(&file).is_ok_and(|x| x.is_file()) {
+            continue;
+        }
+        let contents = filters.clean(&item.path, fs::read(&file)?)?;
+        let hash = Blob { bytes: contents.clone() }.hash();
+        if hash == item.hash {
+            continue;
+        }
+
+        let old = read_blob(root, &item.hash, item.mode, git_mode)?;
+        let new = DiffSide { hash, mode: old.mode, contents };
+        let name = item.path.to_string_lossy();
+        let patch = file_patch(root, &name, Some(&old), Some(&new), &config, &mut attributes, algorithm)?;
+        let Some(parsed) = parse_patch(&patch)?.pop().filter(|x| !x.binary) else {
+            binary = true;
+            continue;
+        };
+
+        // Everything before the first hunk is the header, and each hunk runs up to the next
+        let lines = split_lines(&patch);
+        let starts: Vec<usize> = (0..lines.len()).filter(|x| lines[*x].starts_with(b"@@ ")).collect();
+        let header = lines[..starts[0]].concat();
+        let hunks = parsed.hunks.into_iter().enumerate().map(|(i, patch)| {
+            let end = starts.get(i + 1).copied().unwrap_or(lines.len());
+            Hunk { text: lines[starts[i]..end].concat(), patch, choice: Choice::Undecided }
+        });
+        files.push((position, old.contents, header, hunks.collect::<Vec<_>>()));
+    }
+    if files.is_empty() {
+        eprintln!("{}", if binary { "Only binary files changed." } else { "No changes." });
+        return Ok(());
+    }
+
+    let mut input = io::stdin().lock();
+    let mut stdout = io::stdout().lock();
+    let mut changed = false;
+    for (position, old_contents, header, mut hunks) in files {
+        stdout.write_all(&header)?;
+        let quit = choose_hunks(&mut hunks, &mut input, &mut stdout)?;
+
+        let chosen: Vec<PatchHunk> = hunks.into_iter().filter(|x| x.choice == Choice::Stage).map(|x| x.patch).collect();
+        if !chosen.is_empty() {
+            let item = &mut index.items[position];
+            let blob = Blob { bytes: apply_hunks(&item.path.to_string_lossy(), &old_contents, &chosen)? };
+            blob.write(root, global_opts)?;
+            item.hash = blob.hash();
+            changed = true;
+        }
+        writeln!(stdout)?;
+        if quit {
+            break;
+        }
+    }
+    if changed {
+        write_index(&index, root, global_opts)?;
+    }
+    Ok(())
+}
+
+// Asks about the hunks of one file until each has been decided, the input runs out or the user quits, in which case
+// this returns true. Like Git, it moves on to the next undecided hunk after each answer, and comes back round to
+// the first hunk if there's one left undecided before it.
+fn choose_hunks(hunks: &mut [Hunk], input: &mut impl BufRead, out: &mut impl Write) -> Result<bool> {
+    let count = hunks.len();
+    let mut current = 0;
+    loop {
+        if current >= count {
+            current = 0;
+        }
+        let previous_undecided = (0..current).rev().find(|x| hunks[*x].choice == Choice::Undecided);
+        let next_undecided = (current + 1..count).find(|x| hunks[*x].choice == Choice::Undecided);
+        if previous_undecided.is_none() && next_undecided.is_none() && hunks[current].choice != Choice::Undecided {
+            return Ok(false);
+        }
+
+        // The answers that only make sense for some hunks are left out of the prompt for the others
+        let mut options = String::new();
+        if previous_undecided.is_some() {
+            options += ",k";
+        }
+        if current > 0 {
+            options += ",K";
+        }
+        if next_undecided.is_some() {
+            options += ",j";
+        }
+        if current + 1 < count {
+            options += ",J";
+        }
+        if count > 1 {
+            options += ",g,/";
+        }
+        if splittable_into(&hunks[current].patch) > 1 {
+            options += ",s";
+        }
+        options += ",e";
+
+        out.write_all(&hunks[current].text)?;
+        write!(out, "({}/{}) Stage this hunk [y,n,q,a,d{},?]? ", current + 1, count, options)?;
+        out.flush()?;
+
+        let mut answer = String::new();
+        if input.read_line(&mut answer)? == 0 {
+            return Ok(false);
+        }
+        let Some(first) = answer.trim().chars().next() else {
+            continue;
+        };
+        match first.to_ascii_lowercase() {
+            'y' | 'n' => {
+                hunks[current].choice = if first.eq_ignore_ascii_case(&'y') { Choice::Stage } else { Choice::Skip };
+                current = next_undecided.unwrap_or(count);
+                continue;
+            },
+            'a' | 'd' => {
+                let choice = if first.eq_ignore_ascii_case(&'a') { Choice::Stage } else { Choice::Skip };
+                for hunk in hunks[current..].iter_mut().filter(|x| x.choice == Choice::Undecided) {
+                    hunk.choice = choice;
+                }
+                current = count;
+                continue;
+            },
+            'q' => return Ok(true),
+            _ => ()
+        }
+        match first {
+            'K' if current > 0 => current -= 1,
+            'J' if current + 1 < count => current += 1,
+            'k' if previous_undecided.is_some() => current = previous_undecided.unwrap(),
+            'j' if next_undecided.is_some() => current = next_undecided.unwrap(),
+            'K' | 'k' => eprintln!("No previous hunk"),
+            'J' | 'j' => eprintln!("No next hunk"),
+            'g' if count == 1 => eprintln!("No other hunks to goto"),
+            '/' if count == 1 => eprintln!("No other hunks to search"),
+            's' if !options.contains('s') => eprintln!("Sorry, cannot split this hunk"),
+            'g' | '/' | 's' | 'e' => eprintln!("Sorry, '{}' is not supported yet", first),
+            _ => {
+                write!(out, "{}", HELP)?;
+                for line in HELP_REMAINDER.lines() {
+                    if line.starts_with('?') || line.chars().next().is_some_and(|x| options.contains(x)) {
+                        writeln!(out, "{}", line)?;
+                    }
+                }
+            }
+        }
+    }
+}
+
+// The number of hunks a hunk could be split into, one for each run of changed lines separated by context
+fn splittable_into(hunk: &PatchHunk) -> usize {
+    let mut runs = 0;
+    let mut in_run = false;
+    for (kind, _) in &hunk.lines {
+        if *kind != b' ' && !in_run {
+            runs += 1;
+        }
+        in_run = *kind != b' ';
+    }
+    runs
+}
@@ -0,0 +1,315 @@
+// Applying a series of patches from a mailbox, such as those written by `format-patch`, as new commits
+
+use std::{collections::BTreeMap, env, fs, io::Read, path::{Path, PathBuf}, process};
+use anyhow::{anyhow, bail, Result};
+use clap::Args;
+
+use crate::{GlobalOpts, repo_find, git_dir_name};
+use crate::apply::{apply_hunks, parse_patch};
+use crate::checkout::{commit_files, hash_worktree_file, switch_files};
+use crate::commit::signature;
+use crate::config::Config;
+use crate::date::{now_with_timezone, parse_rfc2822_date};
+use crate::filter::Filters;
+use crate::index::read_index;
+use crate::line_diff::split_lines;
+use crate::objects::{get_blob, Blob, Commit, GitObject, Signature, TreeEntry};
+use crate::refs::{append_reflog, head_commit, read_head, write_head, write_ref, Head};
+use crate::stripspace::stripspace;
+use crate::write_tree::write_tree;
+
+#[derive(Args)]
+pub struct AmArgs {
+    /// The mailbox files to read patches from. Standard input is read if none are given
+    mbox: Vec<String>,
+}
+
+/// A patch read from an email, with the details of the commit it was made from
+struct Mail {
+    author: Signature,
+    subject: String,
+    message: String,
+    patch: Vec<u8>,
+}
+
+pub fn cmd_am(args: AmArgs, global_opts: GlobalOpts) -> Result<()> {
+    let path = env::current_dir().unwrap_or_else(|_| { panic!() });
+    let root = repo_find(&path, global_opts).unwrap_or_else(|| {
+        panic!("fatal: not a grit repository");
+    });
+    let git_mode = global_opts.git_mode;
+
+    let mut mails = Vec::new();
+    if args.mbox.is_empty() {
+        let mut input = Vec::new();
+        std::io::stdin().read_to_end(&mut input)?;
+        mails.extend(split_mbox(&input));
+    }
+    for file in &args.mbox {
+        let contents = fs::read(path.join(file)).map_err(|_| anyhow!("fatal: could not open '{}' for reading", file))?;
+        mails.extend(split_mbox(&contents));
+    }
+
+    let head = head_commit(&root, global_opts)?;
+    if let Some(hash) = &head {
+        fs::write(root.join(git_dir_name(global_opts)).join("ORIG_HEAD"), format!("{}\n", hex::encode(hash)))?;
+    }
+
+    // The patches are applied to the index, so it mustn't have changes of its own
+    let mut files = match &head {
+        Some(hash) => commit_files(&root, hash, git_mode)?,
+        None => BTreeMap::new()
+    };
+    let index = read_index(&root, global_opts)?;
+    let mut dirty: Vec<String> = index.items.iter()
+        .filter(|x| x.stage != 0 || files.get(&x.path).map(|e| e.hash) != Some(x.hash))
+        .map(|x| x.path.to_string_lossy().to_string())
+        .collect();
+    dirty.extend(files.keys().filter(|x| !index.items.iter().any(|i| &i.path == *x)).map(|x| x.to_string_lossy().to_string()));
+    dirty.dedup();
+    if !dirty.is_empty() {
+        eprintln!("fatal: Dirty index: cannot apply patches (dirty: {})", dirty.join(" "));
+        process::exit(128);
+    }
+    let config = Config::load(&root, global_opts)?;
+    for (i, mail) in mails.iter().enumerate() {
+        let mail = parse_mail(mail)?;
+        println!("Applying: {}", mail.subject);
+
+        let new_files = match apply_mail(&root, &files, &mail.patch, global_opts) {
+            Ok(new_files) => new_files,
+            Err(e) => {
+                eprintln!("{}", e);
+                println!("Patch failed at {:04} {}", i + 1, mail.subject);
+                process::exit(128);
+            }
+        };
+        switch_files(&root, &files, &new_files, global_opts)?;
+        files = new_files;
+
+        let tree = write_tree(read_index(&root, global_opts)?, &root, global_opts)?;
+        let committer = signature(&config, "COMMITTER", global_opts)?;
+        let parent = head_commit(&root, global_opts)?;
+        let commit = Commit {
+            tree: tree.hash(),
+            author: mail.author.to_string(),
+            committer: committer.to_string(),
+            parents: parent.into_iter().collect(),
+            message: mail.message,
+            gpgsig: None,
+        };
+        commit.write(&root, global_opts)?;
+        let hash = commit.hash();
+        let head = read_head(&root, global_opts)?;
+        match &head {
+            Head::Branch(name) => write_ref(&root, name, &hash, global_opts)?,
+            Head::Detached(_) => write_head(&root, &Head::Detached(hash), global_opts)?
+        }
+        let reflog_message = format!("am: {}", commit.subject());
+        append_reflog(&root, "HEAD", parent, &hash, &committer, &reflog_message, global_opts)?;
+        if let Head::Branch(name) = &head {
+            append_reflog(&root, name, parent, &hash, &committer, &reflog_message, global_opts)?;
+        }
+    }
+    Ok(())
+}
+
+// Splits a mailbox into its messages, each of which starts with a line like `From <hash> <date>`
+fn split_mbox(contents: &[u8]) -> Vec<Vec<u8>> {
+    let mut mails: Vec<Vec<u8>> = Vec::new();
+    let mut previous_blank = true;
+    for line in split_lines(contents) {
+        if (previous_blank && line.starts_with(b"From ")) || mails.is_empty() {
+            mails.push(Vec::new());
+        }
+        if let Some(mail) = mails.last_mut() {
+            mail.extend_from_slice(line);
+        }
+        previous_blank = line == b"\n";
+    }
+    mails
+}
+
+// Reads the author, message and patch from an email. As in Git, the message is the subject followed by the body,
+// which runs up to the `---` line that starts the patch.
+fn parse_mail(mail: &[u8]) -> Result<Mail> {
+    let text = String::from_utf8_lossy(mail);
+    let mut lines = text.split_inclusive('\n').skip_while(|x| x.starts_with("From "));
+
+    // Long headers are folded onto continuation lines, which start with whitespace
+    let mut headers: Vec<(String, String)> = Vec::new();
+    for line in lines.by_ref() {
+        let line = line.trim_end_matches('\n');
+        if line.is_empty() {
+            break;
+        }
+        if line.starts_with([' ', '\t']) {
+            if let Some((_, value)) = headers.last_mut() {
+                value.push_str(line);
+            }
+        } else if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.to_ascii_lowercase(), value.trim().to_string()));
+        }
+    }
+    let header = |name: &str| headers.iter().find(|x| x.0 == name).map(|x| decode_rfc2047(&x.1));
+
+    let from = header("from").ok_or(anyhow!("fatal: patch does not have a valid e-mail address"))?;
+    let (name, email) = from.trim_end_matches('>').rsplit_once('<')
+        .ok_or(anyhow!("fatal: patch does not have a valid e-mail address"))?;
+    let name = name.trim();
+    let name = match name.strip_prefix('"').and_then(|x| x.strip_suffix('"')) {
+        Some(quoted) => quoted.replace("\\\"", "\"").replace("\\\\", "\\"),
+        None => name.to_string()
+    };
+    let (timestamp, timezone) = match header("date") {
+        Some(date) => parse_rfc2822_date(&date)?,
+        None => now_with_timezone()
+    };
+    let author = Signature { name, email: email.to_string(), timestamp, timezone };
+    let subject = clean_subject(&header("subject").unwrap_or_default());
+
+    let mut body = String::new();
+    let mut patch = String::new();
+    for line in lines {
+        if patch.is_empty() && !is_patch_start(line) {
+            body.push_str(line);
+        } else {
+            patch.push_str(line);
+        }
+    }
+    let message = stripspace(&format!("{}\n\n{}", subject, body), false);
+    if patch.is_empty() {
+        bail!("Patch is empty.");
+    }
+    Ok(Mail { author, subject, message, patch: patch.into_bytes() })
+}
+
+// The message ends where the diffstat or diff begins
+fn is_patch_start(line: &str) -> bool {
+    let line = line.trim_end_matches('\n');
+    line.strip_prefix("---").is_some_and(|x| x.is_empty() || x.starts_with([' ', '\t']))
+        || line.starts_with("diff -")
+        || line.starts_with("Index: ")
+}
+
+// Removes the `[PATCH n/m]` tags and `Re:` prefixes that mailing lists add to the start of subjects
+fn clean_subject(subject: &str) -> String {
+    let mut subject = subject.trim();
+    loop {
+        if let Some(tag) = subject.strip_prefix('[') {
+            match tag.split_once(']') {
+                Some((_, rest)) => subject = rest.trim_start(),
+                None => break
+            }
+        } else if subject.get(..3).is_some_and(|x| x.eq_ignore_ascii_case("re:")) {
+            subject = subject[3..].trim_start();
+        } else {
+            break;
+        }
+    }
+    subject.to_string()
+}
+
+// Decodes the quoted-printable words described in RFC 2047, e.g. `=?UTF-8?q?Caf=C3=A9?=`, as written by
+// `format-patch` for headers that aren't ASCII. Whitespace between two encoded words is dropped.
+fn decode_rfc2047(text: &str) -> String {
+    let mut decoded: Vec<u8> = Vec::new();
+    let mut rest = text;
+    let mut after_word = false;
+    while let Some(start) = rest.find("=?") {
+        let word = rest[start + 2..].splitn(3, '?').collect::<Vec<&str>>();
+        let end = match word[..] {
+            [_, encoding, data] if encoding.eq_ignore_ascii_case("q") => data.find("?="),
+            _ => None
+        };
+        let Some(end) = end else {
+            decoded.extend(&rest.as_bytes()[..start + 2]);
+            rest = &rest[start + 2..];
+            after_word = false;
+            continue;
+        };
+
+        let between = &rest[..start];
+        if !(after_word && between.trim().is_empty()) {
+            decoded.extend(between.as_bytes());
+        }
+        let data = &word[2][..end];
+        let mut bytes = data.bytes();
+        while let Some(byte) = bytes.next() {
+            match byte {
+                b'_' => decoded.push(b' '),
+                b'=' => {
+                    let hex_digits = [bytes.next().unwrap_or(b'0'), bytes.next().unwrap_or(b'0')];
+                    let value = std::str::from_utf8(&hex_digits).ok().and_then(|x| u8::from_str_radix(x, 16).ok());
+                    decoded.push(value.unwrap_or(b'?'));
+                },
+                _ => decoded.push(byte)
+            }
+        }
+        let consumed = start + 2 + word[0].len() + 1 + word[1].len() + 1 + end + 2;
+        rest = &rest[consumed..];
+        after_word = true;
+    }
+    decoded.extend(rest.as_bytes());
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+// Applies the patch from an email to the given files, returning the files as they are after it.
+// Nothing is written to the working tree, so a patch that fails part way through changes nothing.
+fn apply_mail(
+    root: &Path,
+    files: &BTreeMap<PathBuf, TreeEntry>,
+    patch: &[u8],
+    global_opts: GlobalOpts
+) -> Result<BTreeMap<PathBuf, TreeEntry>> {
+    let mut filters = Filters::new(root, global_opts)?;
+    let mut new_files = files.clone();
+    for file in parse_patch(patch)? {
+        let path = file.path();
+        if file.binary {
+            bail!("error: cannot apply binary patch to '{}' without full index line\nerror: {}: patch does not apply", path, path);
+        }
+
+        let old = match &file.old_path {
+            Some(old_path) => {
+                let entry = new_files.get(Path::new(old_path)).ok_or(anyhow!("error: {}: does not exist in index", old_path))?;
+                if hash_worktree_file(root, Path::new(old_path), &mut filters)? != Some(entry.hash) {
+                    bail!("error: {}: does not match index", old_path);
+                }
+                Some((entry.mode, get_blob(root, &entry.hash, global_opts.git_mode)?))
+            },
+            None => {
+                if new_files.contains_key(Path::new(path)) {
+                    bail!("error: {}: already exists in index", path);
+                }
+                if root.join(path).exists() {
+                    bail!("error: {}: already exists in working directory", path);
+                }
+                None
+            }
+        };
+        let contents = apply_hunks(path, old.as_ref().map(|x| &x.1[..]).unwrap_or_default(), &file.hunks)?;
+
+        if let Some(old_path) = &file.old_path {
+            new_files.remove(Path::new(old_path));
+        }
+        match &file.new_path {
+            Some(new_path) => {
+                let blob = Blob { bytes: contents };
+                blob.write(root, global_opts)?;
+                let new_path = PathBuf::from(new_path);
+                let entry = TreeEntry {
+                    mode: file.new_mode.or(old.map(|x| x.0)).unwrap_or(0o100644),
+                    name: new_path.file_name().map(|x| x.to_string_lossy().to_string()).unwrap_or_default(),
+                    hash: blob.hash(),
+                };
+                new_files.insert(new_path, entry);
+            },
+            None if !contents.is_empty() => {
+                bail!("error: removal patch leaves file contents\nerror: {}: patch does not apply", path);
+            },
+            None => ()
+        }
+    }
+    Ok(new_files)
+}
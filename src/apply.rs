@@ -0,0 +1,199 @@
+// Applying patches in the unified diff format, as written by `diff` and `format-patch`
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::line_diff::split_lines;
+
+/// A run of changed lines in a patch, with the lines of context around them
+pub struct PatchHunk {
+    /// The line the hunk starts at in the file before the change, counting from 1
+    pub old_start: usize,
+    /// Each line of the hunk with its prefix: ` ` for context, `-` for a removed line and `+` for an added one.
+    /// Lines include their new line, unless the patch marks them as not having one.
+    pub lines: Vec<(u8, Vec<u8>)>,
+}
+
+/// The changes a patch makes to one file
+pub struct FilePatch {
+    /// The path before the change, or None if the patch creates the file
+    pub old_path: Option<String>,
+    /// The path after the change, or None if the patch deletes the file
+    pub new_path: Option<String>,
+    /// The mode after the change, if the patch gives one
+    pub new_mode: Option<u32>,
+    pub hunks: Vec<PatchHunk>,
+    /// True if the patch only says that a binary file differs, without saying how
+    pub binary: bool,
+}
+
+impl FilePatch {
+    /// The path the file has after the change, or before it for a deletion
+    pub fn path(&self) -> &str {
+        self.new_path.as_deref().or(self.old_path.as_deref()).unwrap_or_default()
+    }
+}
+
+/// Parses each `diff --git` section of a patch. Text before the first section, such as a commit message
+/// or diffstat, is ignored.
+pub fn parse_patch(text: &[u8]) -> Result<Vec<FilePatch>> {
+    let lines = split_lines(text);
+    let mut patches = Vec::new();
+    let mut i = 0;
+    while i < lines.len() {
+        let Some(header) = lines[i].strip_prefix(b"diff --git ") else {
+            i += 1;
+            continue;
+        };
+        let path = header_path(&String::from_utf8_lossy(header))
+            .ok_or(anyhow!("error: git diff header lacks filename information (line {})", i + 1))?;
+        let mut patch = FilePatch {
+            old_path: Some(path.clone()),
+            new_path: Some(path),
+            new_mode: None,
+            hunks: Vec::new(),
+            binary: false,
+        };
+        i += 1;
+
+        // Extended header lines describe changes to the file other than to its contents
+        while i < lines.len() && !lines[i].starts_with(b"diff --git ") && !lines[i].starts_with(b"@@ ") {
+            let line = String::from_utf8_lossy(lines[i]);
+            let line = line.trim_end_matches('\n');
+            if let Some(mode) = line.strip_prefix("new file mode ") {
+                patch.old_path = None;
+                patch.new_mode = Some(parse_mode(mode)?);
+            } else if line.starts_with("deleted file mode ") {
+                patch.new_path = None;
+            } else if let Some(mode) = line.strip_prefix("new mode ") {
+                patch.new_mode = Some(parse_mode(mode)?);
+            } else if let Some(from) = line.strip_prefix("rename from ") {
+                patch.old_path = Some(from.to_string());
+            } else if let Some(to) = line.strip_prefix("rename to ") {
+                patch.new_path = Some(to.to_string());
+            } else if line.starts_with("Binary files ") || line == "GIT binary patch" {
+                patch.binary = true;
+            }
+            i += 1;
+        }
+
+        while i < lines.len() && lines[i].starts_with(b"@@ ") {
+            let (old_start, mut old_count, mut new_count) = parse_hunk_header(&String::from_utf8_lossy(lines[i]))
+                .ok_or(anyhow!("error: corrupt patch at line {}", i + 1))?;
+            i += 1;
+
+            let mut hunk = PatchHunk { old_start, lines: Vec::new() };
+            while old_count > 0 || new_count > 0 {
+                let line = lines.get(i).copied().unwrap_or_default();
+                // Some mail programs strip the space from empty lines of context
+                let (kind, text) = match line.first() {
+                    Some(b'\n') => (b' ', line),
+                    Some(kind @ (b' ' | b'-' | b'+')) => (*kind, &line[1..]),
+                    _ => bail!("error: corrupt patch at line {}", i + 1)
+                };
+                if kind != b'+' {
+                    old_count = old_count.checked_sub(1).ok_or(anyhow!("error: corrupt patch at line {}", i + 1))?;
+                }
+                if kind != b'-' {
+                    new_count = new_count.checked_sub(1).ok_or(anyhow!("error: corrupt patch at line {}", i + 1))?;
+                }
+                hunk.lines.push((kind, text.to_vec()));
+                i += 1;
+
+                // A line without a new line is followed by a marker saying so
+                if lines.get(i).is_some_and(|x| x.starts_with(b"\\")) {
+                    if let Some((_, text)) = hunk.lines.last_mut() {
+                        text.pop();
+                    }
+                    i += 1;
+                }
+            }
+            patch.hunks.push(hunk);
+        }
+        patches.push(patch);
+    }
+    Ok(patches)
+}
+
+// Takes the path from a header such as `diff --git a/file.txt b/file.txt`. Paths containing spaces are ambiguous,
+// so as in Git, the header is split in the middle if the two names are the same.
+fn header_path(header: &str) -> Option<String> {
+    let names = header.trim_end_matches('\n').strip_prefix("a/")?;
+    if names.len() > 3 && (names.len() - 3) % 2 == 0 {
+        let half = (names.len() - 3) / 2;
+        if names.is_char_boundary(half) && names[half..].starts_with(" b/") && names[..half] == names[half + 3..] {
+            return Some(names[..half].to_string());
+        }
+    }
+    names.split_once(" b/").map(|x| x.1.to_string())
+}
+
+fn parse_mode(mode: &str) -> Result<u32> {
+    u32::from_str_radix(mode.trim(), 8).map_err(|_| anyhow!("error: invalid mode '{}'", mode))
+}
+
+// Reads the start and length of each side of a hunk from a header like `@@ -1,3 +1,4 @@`.
+// A length that is left out is 1.
+fn parse_hunk_header(header: &str) -> Option<(usize, usize, usize)> {
+    let mut fields = header.split(' ').skip(1);
+    let old = fields.next()?.strip_prefix('-')?;
+    let new = fields.next()?.strip_prefix('+')?;
+    let parse_range = |range: &str| -> Option<(usize, usize)> {
+        match range.split_once(',') {
+            Some((start, count)) => Some((start.parse().ok()?, count.parse().ok()?)),
+            None => Some((range.parse().ok()?, 1))
+        }
+    };
+    let (old_start, old_count) = parse_range(old)?;
+    let (_, new_count) = parse_range(new)?;
+    Some((old_start, old_count, new_count))
+}
+
+/// Applies the hunks of a patch to the contents of a file, returning the new contents. As in `git apply`,
+/// the lines each hunk removes and its context must match the file exactly, but they may be found some lines
+/// away from where the hunk says if the file has changed since the patch was made.
+pub fn apply_hunks(path: &str, contents: &[u8], hunks: &[PatchHunk]) -> Result<Vec<u8>> {
+    let mut lines: Vec<Vec<u8>> = split_lines(contents).into_iter().map(|x| x.to_vec()).collect();
+    let mut offset: isize = 0;
+    let mut earliest = 0;
+    for hunk in hunks {
+        let before: Vec<&[u8]> = hunk.lines.iter().filter(|x| x.0 != b'+').map(|x| &x.1[..]).collect();
+        let after: Vec<Vec<u8>> = hunk.lines.iter().filter(|x| x.0 != b'-').map(|x| x.1.clone()).collect();
+
+        // A hunk at the start of the file, or without context after its changes, can't be moved from the start
+        // or end of the file
+        let leading = hunk.lines.iter().take_while(|x| x.0 == b' ').count();
+        let trailing = hunk.lines.iter().rev().take_while(|x| x.0 == b' ').count();
+        let match_beginning = hunk.old_start <= 1;
+        let match_end = trailing == 0 && leading < hunk.lines.len();
+
+        let expected = if before.is_empty() { hunk.old_start } else { hunk.old_start.saturating_sub(1) } as isize + offset;
+        let fits = |pos: usize| -> bool {
+            pos >= earliest
+                && pos + before.len() <= lines.len()
+                && (!match_beginning || pos == 0)
+                && (!match_end || pos + before.len() == lines.len())
+                && lines[pos..pos + before.len()].iter().zip(&before).all(|(a, b)| a == b)
+        };
+
+        // Search outwards from where the hunk should be, trying the closer lines first
+        let mut found = None;
+        for distance in 0..=lines.len() as isize + 1 {
+            for pos in [expected - distance, expected + distance] {
+                if found.is_none() && pos >= 0 && fits(pos as usize) {
+                    found = Some(pos as usize);
+                }
+            }
+            if found.is_some() {
+                break;
+            }
+        }
+        let Some(pos) = found else {
+            bail!("error: patch failed: {}:{}\nerror: {}: patch does not apply", path, hunk.old_start, path);
+        };
+
+        offset += pos as isize - expected + after.len() as isize - before.len() as isize;
+        earliest = pos + after.len();
+        lines.splice(pos..pos + before.len(), after);
+    }
+    Ok(lines.concat())
+}
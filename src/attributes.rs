@@ -0,0 +1,266 @@
+// Looking up the attributes assigned to paths by .gitattributes files, info/attributes and the global attributes file
+
+use std::{collections::HashMap, env, fs, path::{Path, PathBuf}};
+use anyhow::Result;
+
+use crate::{GlobalOpts, git_dir_name};
+use crate::config::Config;
+use crate::ignore::wildmatch;
+
+/// The state of an attribute for a particular path
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AttrValue {
+    /// No pattern matching the path mentions the attribute
+    Unspecified,
+    /// e.g. `text`
+    Set,
+    /// e.g. `-text`
+    Unset,
+    /// e.g. `diff=pdf`
+    Value(String),
+}
+
+// Git's only built-in macro, which turns off diffing, merging and end-of-line conversion
+const BUILTIN_MACROS: &str = "[attr]binary -diff -merge -text";
+
+// A line of a .gitattributes file: a pattern followed by the attributes it assigns, or a macro definition
+struct AttrLine {
+    glob: String,
+    anchored: bool,
+    /// The directory containing the source file, relative to the repository root
+    base: PathBuf,
+    attrs: Vec<(String, AttrValue)>,
+    /// For a line like `[attr]<name> <attrs>`, the name of the macro that stands for the attributes. Such a line
+    /// matches no paths.
+    macro_name: Option<String>,
+}
+
+impl AttrLine {
+    fn parse(line: &str, base: &Path) -> Option<AttrLine> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+
+        let mut fields = line.split_whitespace();
+        let pattern = fields.next()?;
+
+        let mut attrs = Vec::new();
+        for field in fields {
+            let (name, value) = if let Some(name) = field.strip_prefix('-') {
+                (name, AttrValue::Unset)
+            } else if let Some(name) = field.strip_prefix('!') {
+                (name, AttrValue::Unspecified)
+            } else if let Some((name, value)) = field.split_once('=') {
+                (name, AttrValue::Value(value.to_string()))
+            } else {
+                (field, AttrValue::Set)
+            };
+            attrs.push((name.to_string(), value));
+        }
+
+        // As in .gitignore files, a pattern containing a slash is matched relative to its source directory
+        let anchored = pattern.contains('/');
+        let glob = pattern.strip_prefix('/').unwrap_or(pattern);
+
+        Some(AttrLine {
+            glob: glob.to_string(),
+            anchored,
+            base: base.to_path_buf(),
+            attrs,
+            macro_name: pattern.strip_prefix("[attr]").filter(|x| !x.is_empty()).map(String::from),
+        })
+    }
+
+    fn matches(&self, path: &Path) -> bool {
+        if self.macro_name.is_some() {
+            return false;
+        }
+        let Ok(relative) = path.strip_prefix(&self.base) else { return false };
+
+        if self.anchored {
+            wildmatch(self.glob.as_bytes(), relative.to_string_lossy().as_bytes())
+        } else {
+            match relative.file_name() {
+                Some(name) => wildmatch(self.glob.as_bytes(), name.to_string_lossy().as_bytes()),
+                None => false
+            }
+        }
+    }
+}
+
+/// The attribute rules of a repository. As in Git, the rules in `info/attributes` take precedence over those in any
+/// `.gitattributes` file, and the rules in the file named by `core.attributesFile` are overridden by them all. The
+/// `.gitattributes` file of each directory is read the first time a path within that directory is looked up.
+/// Macros, which stand for several attributes at once, can be defined in any of these but the `.gitattributes` files
+/// below the top level.
+pub struct Attributes {
+    root: PathBuf,
+    info: Vec<AttrLine>,
+    global: Vec<AttrLine>,
+    per_directory: HashMap<PathBuf, Vec<AttrLine>>,
+    /// The attributes each macro stands for
+    macros: HashMap<String, Vec<(String, AttrValue)>>,
+    /// The names of the attributes in the rules read so far, in the order they were first seen
+    names: Vec<String>,
+}
+
+impl Attributes {
+    pub fn new(root: &Path, config: &Config, global_opts: GlobalOpts) -> Result<Attributes> {
+        let info_file = root.join(git_dir_name(global_opts)).join("info").join("attributes");
+        let info = read_lines(&info_file, Path::new(""), true)?;
+
+        // Without `core.attributesFile`, Git looks for the global file in its directory of the user's config
+        let home = env::var_os("HOME").map(PathBuf::from);
+        let global_file = match config.get("core.attributesFile") {
+            Some(file) => match (file.strip_prefix("~/"), &home) {
+                (Some(rest), Some(home)) => Some(home.join(rest)),
+                _ => Some(PathBuf::from(file))
+            },
+            None => match env::var_os("XDG_CONFIG_HOME").filter(|x| !x.is_empty()) {
+                Some(config_home) => Some(PathBuf::from(config_home).join("git").join("attributes")),
+                None => home.map(|x| x.join(".config").join("git").join("attributes"))
+            }
+        };
+        let global = match global_file {
+            Some(file) => read_lines(&file, Path::new(""), true)?,
+            None => Vec::new()
+        };
+
+        // Like Git, the built-in `binary` macro is seen first, then the rules in the order they're read: the global
+        // file, then the top-level `.gitattributes`, then `info/attributes`
+        let builtin: Vec<AttrLine> = AttrLine::parse(BUILTIN_MACROS, Path::new("")).into_iter().collect();
+        let mut names = Vec::new();
+        add_names(&mut names, &builtin);
+        add_names(&mut names, &global);
+        let mut attributes = Attributes {
+            root: root.to_path_buf(),
+            info: Vec::new(),
+            global,
+            per_directory: HashMap::new(),
+            macros: HashMap::new(),
+            names,
+        };
+        attributes.directory_lines(Path::new(""))?;
+        add_names(&mut attributes.names, &info);
+        attributes.info = info;
+
+        // Where a macro is defined more than once, the definition that takes precedence, as for a rule, is used
+        let sources = [&attributes.info, &attributes.per_directory[Path::new("")], &attributes.global, &builtin];
+        for line in sources.into_iter().flat_map(|x| x.iter().rev()) {
+            if let Some(name) = &line.macro_name {
+                attributes.macros.entry(name.clone()).or_insert(line.attrs.clone());
+            }
+        }
+        Ok(attributes)
+    }
+
+    /// Returns the value of the named attribute for the given path, relative to the repository root.
+    /// Files in deeper directories take precedence, as do later lines within a file.
+    pub fn get(&mut self, path: &Path, name: &str) -> Result<AttrValue> {
+        Ok(self.values(path)?.remove(name).unwrap_or(AttrValue::Unspecified))
+    }
+
+    /// Returns every attribute that is set, unset or given a value for the given path, in the order Git lists them,
+    /// which is the order the attributes were first seen in the rules
+    pub fn get_all(&mut self, path: &Path) -> Result<Vec<(String, AttrValue)>> {
+        let mut values = self.values(path)?;
+        let mut result = Vec::new();
+        for name in &self.names {
+            match values.remove(name) {
+                None | Some(AttrValue::Unspecified) => (),
+                Some(value) => result.push((name.clone(), value))
+            }
+        }
+        Ok(result)
+    }
+
+    // Finds the value of every attribute the rules give a path. As in Git, the lines matching the path are taken
+    // from the one with the highest precedence down, the attributes of each from last to first, and an attribute
+    // keeps the first value it's given.
+    fn values(&mut self, path: &Path) -> Result<HashMap<String, AttrValue>> {
+        let dirs = directories(path);
+        for dir in &dirs {
+            self.directory_lines(dir)?;
+        }
+        let per_directory = dirs.iter().rev().map(|x| &self.per_directory[x]);
+        let sources = [&self.info].into_iter().chain(per_directory).chain([&self.global]);
+
+        let mut values = HashMap::new();
+        for line in sources.flat_map(|x| x.iter().rev()).filter(|x| x.matches(path)) {
+            fill_values(&line.attrs, &self.macros, &mut values);
+        }
+        Ok(values)
+    }
+
+    fn directory_lines(&mut self, dir: &Path) -> Result<&Vec<AttrLine>> {
+        if !self.per_directory.contains_key(dir) {
+            let top_level = dir.as_os_str().is_empty();
+            let lines = read_lines(&self.root.join(dir).join(".gitattributes"), dir, top_level)?;
+            add_names(&mut self.names, &lines);
+            self.per_directory.insert(dir.to_path_buf(), lines);
+        }
+        Ok(&self.per_directory[dir])
+    }
+}
+
+// The directories whose `.gitattributes` files could apply to a path, starting with the top level
+fn directories(path: &Path) -> Vec<PathBuf> {
+    let mut dirs = vec![PathBuf::new()];
+    if let Some(parent) = path.parent() {
+        let mut dir = PathBuf::new();
+        for component in parent.components() {
+            dir.push(component);
+            dirs.push(dir.clone());
+        }
+    }
+    dirs
+}
+
+// Adds the names of the macros and attributes the lines mention that aren't already known, in the order they appear
+fn add_names(names: &mut Vec<String>, lines: &[AttrLine]) {
+    for line in lines {
+        for name in line.macro_name.iter().chain(line.attrs.iter().map(|(name, _)| name)) {
+            if !names.contains(name) {
+                names.push(name.clone());
+            }
+        }
+    }
+}
+
+// Reads the lines of an attributes file, if there is one, whose patterns are matched relative to `base`. Where
+// macros can't be defined, Git warns of a line defining one and leaves it out.
+fn read_lines(file: &Path, base: &Path, macros_allowed: bool) -> Result<Vec<AttrLine>> {
+    let mut lines = Vec::new();
+    if file.is_file() {
+        for (number, text) in fs::read_to_string(file)?.lines().enumerate() {
+            let Some(line) = AttrLine::parse(text, base) else { continue };
+            if line.macro_name.is_some() && !macros_allowed {
+                let source = base.join(".gitattributes");
+                eprintln!("{} not allowed: {}:{}", text.trim(), source.to_string_lossy(), number + 1);
+                continue;
+            }
+            lines.push(line);
+        }
+    }
+    Ok(lines)
+}
+
+// Gives each attribute of a line that doesn't have a value yet the one the line gives it, from the last attribute to
+// the first. A macro that's set sets the attributes it stands for in turn, which like Git is done as soon as it's
+// reached, so it takes precedence over the attributes before it on the line but not those after.
+fn fill_values(
+    attrs: &[(String, AttrValue)],
+    macros: &HashMap<String, Vec<(String, AttrValue)>>,
+    values: &mut HashMap<String, AttrValue>
+) {
+    for (name, value) in attrs.iter().rev() {
+        if values.contains_key(name) {
+            continue;
+        }
+        values.insert(name.clone(), value.clone());
+        if let (AttrValue::Set, Some(members)) = (value, macros.get(name)) {
+            fill_values(members, macros, values);
+        }
+    }
+}
@@ -1,9 +1,18 @@
 /*
 This is a command line utility for comparing the output of the Grit binary to that of Git.
 */
+mod report;
+
 use clap::Parser;
-use std::{fs, path::PathBuf, process::Command, env};
+use std::{ffi::OsString, fs, io::{BufRead, BufReader, Read, Write}, path::{Path, PathBuf}, process::{self, Command, Stdio}, thread};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{atomic::{AtomicUsize, Ordering}, Mutex};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use flate2::read::ZlibDecoder;
 use anyhow::{Result, bail, anyhow};
+use grit::index::Index;
+
+use report::{summary, write_report, Mismatch, Outcome, ReportFormat, TestResult};
 
 
 #[derive(Parser, Debug)]
@@ -11,21 +20,67 @@ use anyhow::{Result, bail, anyhow};
 struct Args {
     #[arg(long)]
     no_clean: bool,
+    /// Save the right command's output for each test into its golden directory
+    #[arg(long, conflicts_with = "replay")]
+    record: bool,
+    /// Compare against the output saved with --record instead of running the right command
+    #[arg(long)]
+    replay: bool,
+    /// Also compare the entries of the index and the set of objects in each repository
+    #[arg(long)]
+    internals: bool,
+    /// Also write a report of each test's results to this file
+    #[arg(long)]
+    output: Option<PathBuf>,
+    /// The format of the report written with --output
+    #[arg(long, value_enum, default_value = "json", requires = "output")]
+    format: ReportFormat,
     test_dir: String,
     left_exe: String,
-    right_exe: String
+    #[arg(required_unless_present = "replay")]
+    right_exe: Option<String>
+}
+
+// What running a command line gave
+struct CommandOutput {
+    stdout: String,
+    stderr: String,
+    /// The exit code, or `None` if the command was killed by a signal
+    status: Option<i32>,
+}
+
+// Commits record when they were made, so both programs are given the same time to make identical commits
+const FIXED_DATES: [(&str, &str); 2] = [
+    ("GIT_AUTHOR_DATE", "1700000000 +0000"),
+    ("GIT_COMMITTER_DATE", "1700000000 +0000"),
+];
+
+// The settings shared by every test
+struct Runner {
+    left_exe: PathBuf,
+    /// The program to compare against, or `None` when replaying recorded output
+    right_exe: Option<PathBuf>,
+    /// Patterns for the names of files that aren't compared, such as the index, which differs in stat information
+    excludes: Vec<String>,
+    no_clean: bool,
+    record: bool,
+    internals: bool,
 }
 
 fn main() {
     let args = Args::parse();
-    let result = run(args);
-
-    if let Err(e) = result {
-        println!("Error: {}", e.to_string());
+    match run(args) {
+        Ok(true) => (),
+        Ok(false) => process::exit(1),
+        Err(e) => {
+            println!("Error: {}", e);
+            process::exit(1);
+        }
     }
 }
 
-fn run(args: Args) -> Result<()> {
+// Runs every test in the test directory, returning whether they all passed
+fn run(args: Args) -> Result<bool> {
     println!("Running Pedant tests");
     let test_root = PathBuf::from(args.test_dir).canonicalize()?;
     if !test_root.exists() {
@@ -35,143 +90,672 @@ fn run(args: Args) -> Result<()> {
     let left_exe = PathBuf::from(&args.left_exe).canonicalize()
         .map_err(|_| anyhow!("Could not find executable {}", &args.left_exe))?;
 
-    let right_exe = PathBuf::from(&args.right_exe).canonicalize()
-        .map_err(|_| anyhow!("Could not find executable {}", &args.right_exe))?;
+    let right_exe = match &args.right_exe {
+        Some(exe) if !args.replay => Some(PathBuf::from(exe).canonicalize()
+            .map_err(|_| anyhow!("Could not find executable {}", exe))?),
+        _ => None
+    };
 
-    for entry in fs::read_dir(test_root)? {
-        let entry = entry?;
-        let path = entry.path().canonicalize()?;
+    let excludes: Vec<String> = match fs::read_to_string(test_root.join("exclude")) {
+        Ok(text) => text.lines().filter(|x| !x.is_empty()).map(String::from).collect(),
+        Err(_) => Vec::new()
+    };
+    let runner = Runner { left_exe, right_exe, excludes, no_clean: args.no_clean, record: args.record,
+        internals: args.internals };
+
+    let mut tests = Vec::new();
+    for entry in fs::read_dir(&test_root)? {
+        let path = entry?.path().canonicalize()?;
         if path.is_dir() {
-            let default_name = String::from("???");
-            let test_name = path.file_name().map(|x| x.to_string_lossy()).unwrap_or(default_name.into());
+            tests.push(path);
+        }
+    }
+    tests.sort();
 
-            // Copy the "before" directory into working directories for the left and right commands
-            let before_dir = path.join("before");
-            if !before_dir.exists() {
-                println!("WARN: Test {} does not have a 'before' directory", test_name);
-            }
+    // Each test works only in its own directory, so tests are shared out between threads. Results are printed once
+    // every test has finished so that they aren't interleaved.
+    let started = now();
+    let next_test = AtomicUsize::new(0);
+    let results = Mutex::new(Vec::new());
+    let thread_count = thread::available_parallelism().map(|x| x.get()).unwrap_or(1).min(tests.len());
+    thread::scope(|scope| {
+        for _ in 0..thread_count {
+            scope.spawn(|| loop {
+                let i = next_test.fetch_add(1, Ordering::SeqCst);
+                let Some(test) = tests.get(i) else {
+                    break;
+                };
+                let result = runner.run_test(test);
+                results.lock().unwrap().push((i, result));
+            });
+        }
+    });
+    let mut results = results.into_inner().unwrap();
+    results.sort_by_key(|x| x.0);
+    let results: Vec<TestResult> = results.into_iter().map(|x| x.1).collect();
 
-            let after_left = path.join("after_left");
-            let after_right = path.join("after_right");
+    for result in &results {
+        result.print();
+    }
+    println!("{}", summary(&results));
+    if let Some(output) = &args.output {
+        write_report(output, args.format, &results, started)?;
+    }
+    Ok(results.iter().all(|x| matches!(x.outcome, Outcome::Passed | Outcome::Skipped)))
+}
 
-            if after_left.exists() {
-                fs::remove_dir_all(&after_left)?;
-            }
-            if after_right.exists() {
-                fs::remove_dir_all(&after_right)?;
-            }
+fn test_name(path: &Path) -> String {
+    path.file_name().map(|x| x.to_string_lossy().to_string()).unwrap_or(String::from("???"))
+}
 
-            copy_dir(&before_dir, &after_left).unwrap();
-            copy_dir(&before_dir, &after_right).unwrap();
-            
-            let cmd_path = path.join("cmds");
-            let cmd_bytes = fs::read(cmd_path)?;
-            let cmd_str = String::from_utf8_lossy(&cmd_bytes); 
-            let cmd_lines: Vec<&str> = cmd_str.split("\n").collect();
-
-            let mut left_stdout = String::new();
-            let mut left_stderr = String::new();
-            let mut right_stdout = String::new();
-            let mut right_stderr = String::new();
-            
-            // Run left command
-            if env::set_current_dir(&after_left).is_err() {
-                bail!("Failed to set current dir to {}", after_left.to_string_lossy());
-            }
-            for cmd_line in &cmd_lines {
-                // Always run the Grit command in Git compatibility mode for tests
-                let mut cmd_tokens: Vec<&str> = cmd_line.split(" ").collect();
-                cmd_tokens.push("-g");
-                let output = Command::new(&left_exe)
-                    .args(&cmd_tokens)
-                    .output()
-                    .unwrap();
-
-                left_stdout += &String::from_utf8_lossy(&output.stdout);
-                left_stderr += &String::from_utf8_lossy(&output.stderr);
-            }
+// The current time in seconds since the epoch
+fn now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|x| x.as_secs() as i64).unwrap_or(0)
+}
 
-            // Run right command
-            if env::set_current_dir(&after_right).is_err() {
-                bail!("Failed to set current dir to {}", after_right.to_string_lossy());
+impl Runner {
+    // Runs the test in the directory `path`
+    fn run_test(&self, path: &Path) -> TestResult {
+        let mut result = TestResult {
+            name: test_name(path),
+            outcome: Outcome::Passed,
+            started: now(),
+            duration: Default::default(),
+            warnings: Vec::new(),
+            mismatches: Vec::new(),
+            error: None,
+        };
+        let timer = Instant::now();
+        match self.compare(path, &mut result) {
+            Ok(()) if !result.mismatches.is_empty() => result.outcome = Outcome::Failed,
+            Ok(()) => (),
+            Err(e) => {
+                result.outcome = Outcome::Error;
+                result.error = Some(e.to_string());
             }
-            for cmd_line in &cmd_lines {
-                let cmd_tokens: Vec<&str> = cmd_line.split(" ").collect();
-                let output = Command::new(&right_exe)
-                    .args(&cmd_tokens)
-                    .output()
-                    .unwrap();
-
-                right_stdout += &String::from_utf8_lossy(&output.stdout);
-                right_stderr += &String::from_utf8_lossy(&output.stderr);
+        }
+        result.duration = timer.elapsed();
+        result
+    }
+
+    // Runs the test's commands with both programs and adds the ways their results differ to `result`
+    fn compare(&self, path: &Path, result: &mut TestResult) -> Result<()> {
+        let test_name = test_name(path);
+
+        // Options Grit has that Git doesn't are tested against output written by hand into the golden directory, which
+        // tests marked with a `grit_only` file are always compared with and which recording leaves alone
+        let golden_dir = path.join("golden");
+        let grit_only = path.join("grit_only").exists();
+        if (self.right_exe.is_none() || grit_only) && !golden_dir.is_dir() {
+            result.warnings.push(format!("Test {} has no recorded output to replay", test_name));
+            result.outcome = Outcome::Skipped;
+            return Ok(());
+        }
+
+        let cmd_path = path.join("cmds");
+        let cmd_bytes = fs::read(cmd_path)?;
+        let cmd_str = String::from_utf8_lossy(&cmd_bytes);
+        // Blank lines and comments starting with `#` are left out
+        let cmd_lines: Vec<&str> = cmd_str.lines()
+            .filter(|x| !x.trim().is_empty() && !x.trim_start().starts_with('#'))
+            .collect();
+        let mut commands = cmd_lines.iter().map(|x| split_command(x)).collect::<Result<Vec<Vec<String>>>>()?;
+
+        // Copy the "before" directory into working directories for the left and right commands. Without one, the
+        // commands would run in whatever repository the tests are kept in, so only a test that starts by making its
+        // own repository may leave it out.
+        let before_dir = path.join("before");
+        if !before_dir.exists() && commands.first().and_then(|x| x.first()).map(String::as_str) != Some("init") {
+            bail!("Test {} does not have a 'before' directory", test_name);
+        }
+
+        let after_left = path.join("after_left");
+        let after_right = path.join("after_right");
+
+        if after_left.exists() {
+            fs::remove_dir_all(&after_left)?;
+        }
+        if after_right.exists() {
+            fs::remove_dir_all(&after_right)?;
+        }
+
+        copy_dir(&before_dir, &after_left)?;
+        copy_dir(&before_dir, &after_right)?;
+
+        // The files in a test's `served` directory are served over HTTP while it runs, as by a server that knows
+        // nothing of Git, and `<url>` in its commands stands for the server's address
+        let served_dir = path.join("served");
+        let url = if served_dir.is_dir() { Some(serve(served_dir)?) } else { None };
+        if let Some(url) = &url {
+            for arg in commands.iter_mut().flatten() {
+                *arg = arg.replace("<url>", url);
             }
+        }
+
+        // Always run the Grit command in Git compatibility mode for tests
+        let left_outputs = run_commands(&self.left_exe, &after_left, &commands, &["-g"], url.as_deref())?;
+        let right_outputs = match &self.right_exe {
+            Some(right_exe) if !grit_only => {
+                let outputs = run_commands(right_exe, &after_right, &commands, &[], url.as_deref())?;
+                if self.record {
+                    write_golden(&golden_dir, &outputs)?;
+                }
+                outputs
+            },
+            _ => read_golden(&golden_dir, commands.len())?
+        };
 
-            // Replace references to test directory names in output
-            let left_stdout = clean_output(left_stdout, "after_left");
-            let right_stdout = clean_output(right_stdout, "after_right");
-            let left_stderr = clean_output(left_stderr, "after_left");
-            let right_stderr = clean_output(right_stderr, "after_right");
-
-            if left_stdout != right_stdout {
-                println!("Test {} fail", test_name);
-                println!("stdout mismatch: expected");
-                println!("{}", right_stdout);
-                println!("but read:");
-                println!("{}", left_stdout);
+        let left_stdout = clean_output(left_outputs.iter().map(|x| x.stdout.as_str()).collect());
+        let right_stdout = clean_output(right_outputs.iter().map(|x| x.stdout.as_str()).collect());
+        let left_stderr = clean_output(left_outputs.iter().map(|x| x.stderr.as_str()).collect());
+        let right_stderr = clean_output(right_outputs.iter().map(|x| x.stderr.as_str()).collect());
+
+        for ((cmd_line, left), right) in cmd_lines.iter().zip(&left_outputs).zip(&right_outputs) {
+            if left.status != right.status {
+                result.mismatches.push(Mismatch {
+                    kind: "exit code",
+                    message: format!("exit code mismatch for '{}': expected {} but read {}",
+                        cmd_line, format_status(right.status), format_status(left.status)),
+                });
             }
+        }
+
+        if left_stdout != right_stdout {
+            result.mismatches.push(Mismatch {
+                kind: "stdout",
+                message: format!("stdout mismatch: expected\n{}\nbut read:\n{}", right_stdout, left_stdout),
+            });
+        }
 
-            if left_stderr != right_stderr {
-                println!("Test {} fail", test_name);
-                println!("stderr mismatch: expected");
-                println!("{}", right_stderr);
-                println!("but read:");
-                println!("{}", left_stderr);
+        if left_stderr != right_stderr {
+            result.mismatches.push(Mismatch {
+                kind: "stderr",
+                message: format!("stderr mismatch: expected\n{}\nbut read:\n{}", right_stderr, left_stderr),
+            });
+        }
+
+        if self.internals {
+            let left_internals = read_internals(&after_left)?;
+            let right_internals = match &self.right_exe {
+                Some(_) if !grit_only => {
+                    let internals = read_internals(&after_right)?;
+                    if self.record {
+                        fs::write(golden_dir.join("index"), internals.index.join("\n"))?;
+                        fs::write(golden_dir.join("objects"), internals.objects.join("\n"))?;
+                    }
+                    internals
+                },
+                _ => read_golden_internals(&golden_dir)?
+            };
+            let differences = diff_internals(&left_internals, &right_internals);
+            if !differences.is_empty() {
+                result.mismatches.push(Mismatch { kind: "internals", message: differences.join("\n") });
             }
+        }
+
+        // Only the output is recorded, so there is no right directory to compare against when replaying
+        if self.right_exe.is_some() && !grit_only {
+            // Different zlib implementations may compress the same object differently, so objects are compared uncompressed
+            decompress_objects(&after_left)?;
+            decompress_objects(&after_right)?;
+            // Packs are named after their contents, which depend on compression and deltas, so only what they hold is
+            // compared
+            list_packs(&after_left)?;
+            list_packs(&after_right)?;
 
-            // Run Unix diff command to print differences between left and right directories
-            let diff_args = vec![
-                after_left.to_string_lossy().to_string(),
-                after_right.to_string_lossy().to_string(),
-                String::from("--recursive"),
-                String::from("--color"),
-                String::from("--exclude-from"),
-                String::from("../../exclude")
-            ];
-            let diff_output = Command::new("diff").args(diff_args).output().unwrap();
-
-            if diff_output.stderr.len() > 0 || diff_output.stdout.len() > 0 {
-                println!("Test {} failed:", &test_name);
-                println!("{}", String::from_utf8_lossy(&diff_output.stderr));
-                println!("{}", String::from_utf8_lossy(&diff_output.stdout));
+            let mut differences = Vec::new();
+            diff_dirs(&after_left, &after_right, &self.excludes, &mut differences)?;
+            if !differences.is_empty() {
+                result.mismatches.push(Mismatch { kind: "files", message: differences.join("\n") });
             }
+        }
+
+        // CLEANUP
+        if !self.no_clean {
+            fs::remove_dir_all(&after_left)?;
+            fs::remove_dir_all(&after_right)?;
+        }
+        Ok(())
+    }
+}
+
+// Runs `exe` in `dir` with the arguments of each command, giving `extra_args` first so that they can't be taken for
+// paths after a `--`. References to the directory in the output are replaced with `<dir_name>`, and to the test's
+// server with `<url>`, so that the output doesn't depend on where the tests are or which port the server has.
+fn run_commands(
+    exe: &Path,
+    dir: &Path,
+    commands: &[Vec<String>],
+    extra_args: &[&str],
+    url: Option<&str>
+) -> Result<Vec<CommandOutput>> {
+    let full_name = dir.to_string_lossy();
+    let name = dir.file_name().map(|x| x.to_string_lossy()).unwrap_or_default();
+    let clean = |x: &[u8]| {
+        let output = String::from_utf8_lossy(x).replace(&*full_name, "<dir_name>").replace(&*name, "<dir_name>");
+        match url {
+            Some(url) => output.replace(url, "<url>"),
+            None => output
+        }
+    };
+
+    let mut outputs = Vec::new();
+    for command in commands {
+        // A command ending in `< <file>` is given the contents of that file in the test's directory as its input
+        let (args, input) = match command.as_slice() {
+            [args @ .., redirect, file] if redirect == "<" => (args, Some(fs::read(dir.join(file))?)),
+            args => (args, None)
+        };
+        let mut child = Command::new(exe)
+            .args(extra_args)
+            .args(args)
+            .current_dir(dir)
+            .envs(FIXED_DATES)
+            .stdin(if input.is_some() { Stdio::piped() } else { Stdio::null() })
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| anyhow!("Could not run {}: {}", exe.to_string_lossy(), e))?;
+        // The input is written from another thread while the output is read, as a command may not read more of its
+        // input until its output is read. The command may exit without reading all of its input, which isn't an
+        // error of the test.
+        let writer = match (input, child.stdin.take()) {
+            (Some(input), Some(mut stdin)) => Some(thread::spawn(move || stdin.write_all(&input).ok())),
+            _ => None
+        };
+        let output = child.wait_with_output()?;
+        if let Some(writer) = writer {
+            writer.join().ok();
+        }
+        outputs.push(CommandOutput {
+            stdout: clean(&output.stdout),
+            stderr: clean(&output.stderr),
+            status: output.status.code(),
+        });
+    }
+    Ok(outputs)
+}
+
+// Serves the files under `dir` over HTTP from another thread for as long as pedant runs, returning the server's URL.
+// Only what a client of Git's dumb protocol needs is supported: every request is taken to be a GET, and any query is
+// ignored.
+fn serve(dir: PathBuf) -> Result<String> {
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let url = format!("http://{}", listener.local_addr()?);
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            respond(&dir, stream).ok();
+        }
+    });
+    Ok(url)
+}
+
+// Answers a request with the file it names under `dir`, or with Not Found if there's no such file
+fn respond(dir: &Path, mut stream: TcpStream) -> Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+    // The headers aren't needed, but are read up to the blank line that ends them
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 0 && !line.trim_end().is_empty() {
+        line.clear();
+    }
+
+    let target = request.split(' ').nth(1).unwrap_or_default();
+    let file = target.split('?').next().unwrap_or_default().trim_start_matches('/');
+    let contents = match file.split('/').any(|x| x == "..") {
+        true => None,
+        false => fs::read(dir.join(file)).ok()
+    };
+    match contents {
+        Some(contents) => {
+            write!(stream, "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", contents.len())?;
+            stream.write_all(&contents)?;
+        },
+        None => write!(stream, "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")?
+    }
+    Ok(())
+}
+
+// Splits a command line into its arguments the way a shell would. Arguments are separated by spaces or tabs, except
+// within single quotes, which keep everything up to the next single quote as it is, or double quotes, within which
+// a backslash escapes `"` and `\`. Outside quotes, a backslash escapes any character.
+fn split_command(line: &str) -> Result<Vec<String>> {
+    let mut args = Vec::new();
+    let mut current: Option<String> = None;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            ' ' | '\t' => {
+                args.extend(current.take());
+                continue;
+            },
+            '\'' => {
+                let arg = current.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => arg.push(c),
+                        None => bail!("Unterminated single quote in command '{}'", line)
+                    }
+                }
+            },
+            '"' => {
+                let arg = current.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\')) => arg.push(c),
+                            Some(c) => {
+                                arg.push('\\');
+                                arg.push(c);
+                            },
+                            None => bail!("Unterminated double quote in command '{}'", line)
+                        },
+                        Some(c) => arg.push(c),
+                        None => bail!("Unterminated double quote in command '{}'", line)
+                    }
+                }
+            },
+            '\\' => match chars.next() {
+                Some(c) => current.get_or_insert_with(String::new).push(c),
+                None => bail!("Trailing backslash in command '{}'", line)
+            },
+            c => current.get_or_insert_with(String::new).push(c)
+        }
+    }
+    args.extend(current);
+    Ok(args)
+}
+
+fn format_status(status: Option<i32>) -> String {
+    status.map(|x| x.to_string()).unwrap_or(String::from("signal"))
+}
+
+// Saves the output of each command as `<n>.stdout`, `<n>.stderr` and `<n>.status`, numbering the commands in the
+// cmds file from 1
+fn write_golden(golden_dir: &Path, outputs: &[CommandOutput]) -> Result<()> {
+    if golden_dir.exists() {
+        fs::remove_dir_all(golden_dir)?;
+    }
+    fs::create_dir(golden_dir)?;
+    for (i, output) in outputs.iter().enumerate() {
+        fs::write(golden_dir.join(format!("{}.stdout", i + 1)), &output.stdout)?;
+        fs::write(golden_dir.join(format!("{}.stderr", i + 1)), &output.stderr)?;
+        fs::write(golden_dir.join(format!("{}.status", i + 1)), format_status(output.status) + "\n")?;
+    }
+    Ok(())
+}
+
+// Reads the output saved by `write_golden` for each of the `count` commands
+fn read_golden(golden_dir: &Path, count: usize) -> Result<Vec<CommandOutput>> {
+    let mut outputs = Vec::new();
+    for i in 1..=count {
+        let read = |extension: &str| -> Result<String> {
+            let file = golden_dir.join(format!("{}.{}", i, extension));
+            let bytes = fs::read(&file)
+                .map_err(|_| anyhow!("Missing golden file {}", file.to_string_lossy()))?;
+            Ok(String::from_utf8_lossy(&bytes).to_string())
+        };
+        let status = read("status")?;
+        outputs.push(CommandOutput {
+            stdout: read("stdout")?,
+            stderr: read("stderr")?,
+            status: status.trim().parse().ok(),
+        });
+    }
+    Ok(outputs)
+}
+
+// The parts of a repository that aren't compared as files, summarised so that they can be compared and recorded
+struct Internals {
+    /// A line for each index entry: its mode, hash and stage and whether it's marked skip-worktree, then its path
+    /// after a tab
+    index: Vec<String>,
+    /// The hashes of the loose objects, in order
+    objects: Vec<String>,
+}
+
+// Reads the index and the names of the loose objects in the repository in the working directory `dir`
+fn read_internals(dir: &Path) -> Result<Internals> {
+    let repo_dir = [".git", ".grit"].iter().map(|x| dir.join(x)).find(|x| x.is_dir());
+    let Some(repo_dir) = repo_dir else {
+        return Ok(Internals { index: Vec::new(), objects: Vec::new() });
+    };
+
+    let mut index = Vec::new();
+    if let Ok(bytes) = fs::read(repo_dir.join("index")) {
+        for item in Index::deserialize(bytes)?.items {
+            let skip = if item.skip_worktree { " skip-worktree" } else { "" };
+            index.push(format!(
+                "{:06o} {} {}{}\t{}", item.mode, hex::encode(item.hash), item.stage, skip, item.path.to_string_lossy()
+            ));
+        }
+    }
+
+    let mut objects = Vec::new();
+    for entry in fs::read_dir(repo_dir.join("objects"))? {
+        let entry = entry?;
+        let prefix = entry.file_name().to_string_lossy().to_string();
+        if prefix.len() != 2 || !entry.file_type()?.is_dir() {
+            continue;
+        }
+        for file in fs::read_dir(entry.path())? {
+            objects.push(format!("{}{}", prefix, file?.file_name().to_string_lossy()));
+        }
+    }
+    objects.sort();
+    Ok(Internals { index, objects })
+}
+
+// Reads the internals saved by --record --internals
+fn read_golden_internals(golden_dir: &Path) -> Result<Internals> {
+    let read = |name: &str| -> Result<Vec<String>> {
+        let file = golden_dir.join(name);
+        let text = fs::read_to_string(&file)
+            .map_err(|_| anyhow!("Missing golden file {}", file.to_string_lossy()))?;
+        Ok(text.lines().map(String::from).collect())
+    };
+    Ok(Internals { index: read("index")?, objects: read("objects")? })
+}
+
+// Describes how the left repository's internals differ from the right's
+fn diff_internals(left: &Internals, right: &Internals) -> Vec<String> {
+    let mut differences = Vec::new();
+    for object in right.objects.iter().filter(|x| !left.objects.contains(x)) {
+        differences.push(format!("missing object {}", object));
+    }
+    for object in left.objects.iter().filter(|x| !right.objects.contains(x)) {
+        differences.push(format!("unexpected object {}", object));
+    }
+
+    // Entries are matched up by their stage and path, which follow the mode and hash
+    let key = |x: &String| x.split_once(' ').and_then(|(_, rest)| rest.split_once(' ')).map(|(_, key)| key.to_string());
+    for entry in &right.index {
+        match left.index.iter().find(|x| key(x) == key(entry)) {
+            None => differences.push(format!("missing index entry {}", entry)),
+            Some(x) if x != entry => differences.push(format!("index entry differs: expected {} but read {}", entry, x)),
+            _ => ()
+        }
+    }
+    for entry in left.index.iter().filter(|x| !right.index.iter().any(|y| key(x) == key(y))) {
+        differences.push(format!("unexpected index entry {}", entry));
+    }
+    differences
+}
+
+// Copies the directory `from` and everything in it to `to`. A missing `from` is copied as an empty directory.
+// A directory named `dot-git` is copied as `.git`, so that tests can start from a repository in a particular state
+// even though this repository can't hold another's git directory.
+fn copy_dir(from: &Path, to: &Path) -> Result<()> {
+    fs::create_dir_all(to)?;
+    if !from.exists() {
+        return Ok(());
+    }
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let name = if entry.file_name() == "dot-git" { OsString::from(".git") } else { entry.file_name() };
+        let target = to.join(name);
+        // Symbolic links are copied as links, so that tests can have them
+        if entry.file_type()?.is_symlink() {
+            std::os::unix::fs::symlink(fs::read_link(entry.path())?, &target)?;
+        } else if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else {
+            fs::copy(entry.path(), &target)?;
+        }
+    }
+    Ok(())
+}
 
-            // CLEANUP
-            if !args.no_clean {
-                fs::remove_dir_all(&after_left)?;
-                fs::remove_dir_all(&after_right)?;
+// Adds a line to `differences` for each file that is only in one of the directories or differs between them,
+// leaving out files whose names match one of the `excludes` patterns
+fn diff_dirs(left: &Path, right: &Path, excludes: &[String], differences: &mut Vec<String>) -> Result<()> {
+    let mut names = Vec::new();
+    for dir in [left, right] {
+        for entry in fs::read_dir(dir)? {
+            let name = entry?.file_name();
+            if !names.contains(&name) && !excludes.iter().any(|x| glob_match(x.as_bytes(), name.as_encoded_bytes())) {
+                names.push(name);
             }
         }
     }
+    names.sort();
 
+    for name in names {
+        let (left_file, right_file) = (left.join(&name), right.join(&name));
+        match (left_file.exists(), right_file.exists()) {
+            (false, _) => differences.push(format!("Only in {}: {}", right.to_string_lossy(), name.to_string_lossy())),
+            (_, false) => differences.push(format!("Only in {}: {}", left.to_string_lossy(), name.to_string_lossy())),
+            _ if left_file.is_dir() && right_file.is_dir() => diff_dirs(&left_file, &right_file, excludes, differences)?,
+            _ if left_file.is_dir() || right_file.is_dir() => differences.push(format!(
+                "Only one of {} and {} is a directory", left_file.to_string_lossy(), right_file.to_string_lossy())),
+            _ => diff_files(&left_file, &right_file, differences)?
+        }
+    }
+    Ok(())
+}
+
+// Adds a line to `differences` if the files differ, followed by the lines that differ when both are text
+fn diff_files(left: &Path, right: &Path, differences: &mut Vec<String>) -> Result<()> {
+    let (left_bytes, right_bytes) = (fs::read(left)?, fs::read(right)?);
+    if left_bytes == right_bytes {
+        return Ok(());
+    }
+    differences.push(format!("Files {} and {} differ", left.to_string_lossy(), right.to_string_lossy()));
+
+    let (Ok(left_text), Ok(right_text)) = (std::str::from_utf8(&left_bytes), std::str::from_utf8(&right_bytes)) else {
+        return Ok(());
+    };
+    let (left_lines, right_lines): (Vec<&str>, Vec<&str>) = (left_text.lines().collect(), right_text.lines().collect());
+    for i in 0..left_lines.len().max(right_lines.len()) {
+        let (left_line, right_line) = (left_lines.get(i), right_lines.get(i));
+        if left_line == right_line {
+            continue;
+        }
+        differences.push(format!("line {}:", i + 1));
+        if let Some(line) = left_line {
+            differences.push(format!("< {}", line));
+        }
+        if let Some(line) = right_line {
+            differences.push(format!("> {}", line));
+        }
+    }
     Ok(())
 }
 
-fn copy_dir(from: &PathBuf, to: &PathBuf) -> Result<()> {
-    let args = vec![
-        String::from("-r"),
-        from.to_string_lossy().to_string(),
-        to.to_string_lossy().to_string()
-    ];
-    let output = Command::new("cp").args(args).output()?;
-    if output.stderr.len() > 0 { 
-        eprintln!("{}", String::from_utf8_lossy(&output.stderr));
+// Whether `name` matches `pattern`, in which `*` matches any run of characters and `?` any one character
+fn glob_match(pattern: &[u8], name: &[u8]) -> bool {
+    match (pattern.first(), name.first()) {
+        (None, None) => true,
+        (Some(b'*'), _) => glob_match(&pattern[1..], name) || (!name.is_empty() && glob_match(pattern, &name[1..])),
+        (Some(b'?'), Some(_)) => glob_match(&pattern[1..], &name[1..]),
+        (Some(x), Some(y)) if x == y => glob_match(&pattern[1..], &name[1..]),
+        _ => false
     }
-    if output.stdout.len() > 0 {
-        println!("{}", String::from_utf8_lossy(&output.stdout));
+}
+
+fn clean_output(output: String) -> String {
+    output.trim().to_string()
+}
+
+// Replaces each loose object in the repository at `dir` with its decompressed contents, and likewise in any bare
+// repositories the test made directly inside it. Empty directories of loose objects, which Git can leave when it
+// looks for an object loose before finding it elsewhere, hold nothing to compare and are removed.
+fn decompress_objects(dir: &Path) -> Result<()> {
+    for objects_dir in objects_dirs(dir)? {
+        for entry in fs::read_dir(objects_dir)? {
+            let entry = entry?;
+            if entry.file_name().len() != 2 || !entry.file_type()?.is_dir() {
+                continue;
+            }
+            for file in fs::read_dir(entry.path())? {
+                let file = file?.path();
+                let bytes = fs::read(&file)?;
+                let mut decoder = ZlibDecoder::new(&bytes[..]);
+                let mut contents = Vec::new();
+                if decoder.read_to_end(&mut contents).is_ok() {
+                    fs::write(&file, contents)?;
+                }
+            }
+            if fs::read_dir(entry.path())?.next().is_none() {
+                fs::remove_dir(entry.path())?;
+            }
+        }
     }
     Ok(())
 }
 
-fn clean_output(output: String, dir_name: &str) -> String {
-    output.replace(dir_name, "<dir_name>").trim().to_string()
-}
\ No newline at end of file
+// The object stores of the repository in `dir` and of any bare repositories beside it
+fn objects_dirs(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut objects_dirs = vec![dir.join(".git/objects")];
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.join("HEAD").is_file() && path.join("objects").is_dir() {
+            objects_dirs.push(path.join("objects"));
+        }
+    }
+    objects_dirs.retain(|x| x.is_dir());
+    Ok(objects_dirs)
+}
+
+// Replaces the packs in each object store with a `packs` file listing the objects in each pack, one pack per line, and
+// the pack names in `info/packs` with a placeholder
+fn list_packs(dir: &Path) -> Result<()> {
+    for objects_dir in objects_dirs(dir)? {
+        let pack_dir = objects_dir.join("pack");
+        if pack_dir.is_dir() {
+            let mut lines = Vec::new();
+            for entry in fs::read_dir(&pack_dir)? {
+                let file = entry?.path();
+                if file.extension().is_some_and(|x| x == "idx") {
+                    // Version 2 indexes start with an 8 byte header and a 256 entry fan-out table, whose last entry
+                    // is the number of objects, followed by the sorted object names
+                    let bytes = fs::read(&file)?;
+                    let count = u32::from_be_bytes(bytes[1028..1032].try_into()?) as usize;
+                    let names: Vec<String> = bytes[1032..1032 + 20 * count].chunks(20).map(hex::encode).collect();
+                    lines.push(names.join(" "));
+                }
+            }
+            for entry in fs::read_dir(&pack_dir)? {
+                let file = entry?.path();
+                if file.file_name().is_some_and(|x| x.to_string_lossy().starts_with("pack-")) {
+                    fs::remove_file(file)?;
+                }
+            }
+            if !lines.is_empty() {
+                lines.sort();
+                fs::write(pack_dir.join("packs"), lines.join("\n") + "\n")?;
+            }
+        }
+
+        let info_packs = objects_dir.join("info").join("packs");
+        if info_packs.is_file() {
+            let contents = fs::read_to_string(&info_packs)?;
+            let lines: Vec<&str> = contents.lines().map(|x| if x.starts_with("P ") { "P <pack>" } else { x }).collect();
+            fs::write(&info_packs, lines.join("\n") + "\n")?;
+        }
+    }
+    Ok(())
+}
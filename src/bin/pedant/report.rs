@@ -0,0 +1,198 @@
+// The results of running the tests, and the ways they can be reported
+
+use std::{fs, path::Path, time::Duration};
+use anyhow::Result;
+use clap::ValueEnum;
+use grit::date::{format_date, DateFormat};
+
+/// The formats a machine-readable report can be written in
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum ReportFormat {
+    Json,
+    /// The JUnit XML format most CI systems can display
+    Junit,
+}
+
+/// How a test went
+#[derive(Clone, Copy, PartialEq)]
+pub enum Outcome {
+    Passed,
+    Failed,
+    Skipped,
+    /// The test couldn't be run, e.g. because its cmds file is missing
+    Error,
+}
+
+impl Outcome {
+    fn name(&self) -> &'static str {
+        match self {
+            Outcome::Passed => "passed",
+            Outcome::Failed => "failed",
+            Outcome::Skipped => "skipped",
+            Outcome::Error => "error",
+        }
+    }
+}
+
+/// A way the left program's results differed from the right's
+pub struct Mismatch {
+    /// What differed: `exit code`, `stdout`, `stderr`, `internals` or `files`
+    pub kind: &'static str,
+    pub message: String,
+}
+
+pub struct TestResult {
+    pub name: String,
+    pub outcome: Outcome,
+    /// When the test started, in seconds since the epoch
+    pub started: i64,
+    pub duration: Duration,
+    pub warnings: Vec<String>,
+    pub mismatches: Vec<Mismatch>,
+    /// Why the test couldn't be run, if it couldn't
+    pub error: Option<String>,
+}
+
+impl TestResult {
+    /// Prints the test's warnings and mismatches to the console
+    pub fn print(&self) {
+        for warning in &self.warnings {
+            println!("WARN: {}", warning);
+        }
+        for mismatch in &self.mismatches {
+            println!("Test {} fail", self.name);
+            println!("{}", mismatch.message);
+        }
+        if let Some(error) = &self.error {
+            println!("Test {} could not be run: {}", self.name, error);
+        }
+    }
+}
+
+/// Counts the tests with each outcome, e.g. `3 passed, 1 failed`. Skipped tests and tests that couldn't be run are
+/// only mentioned if there are any.
+pub fn summary(results: &[TestResult]) -> String {
+    let count = |outcome: Outcome| results.iter().filter(|x| x.outcome == outcome).count();
+    let mut summary = format!("{} passed, {} failed", count(Outcome::Passed), count(Outcome::Failed));
+    for outcome in [Outcome::Error, Outcome::Skipped] {
+        if count(outcome) > 0 {
+            summary += &format!(", {} {}", count(outcome), outcome.name());
+        }
+    }
+    summary
+}
+
+/// Writes a report of every test's results to `path`. `started` is when the run started, in seconds since the epoch.
+pub fn write_report(path: &Path, format: ReportFormat, results: &[TestResult], started: i64) -> Result<()> {
+    let report = match format {
+        ReportFormat::Json => json_report(results, started),
+        ReportFormat::Junit => junit_report(results, started),
+    };
+    fs::write(path, report)?;
+    Ok(())
+}
+
+fn json_report(results: &[TestResult], started: i64) -> String {
+    let count = |outcome: Outcome| results.iter().filter(|x| x.outcome == outcome).count();
+    let mut tests = Vec::new();
+    for result in results {
+        let warnings: Vec<String> = result.warnings.iter().map(|x| json_string(x)).collect();
+        let mismatches: Vec<String> = result.mismatches.iter()
+            .map(|x| format!("{{\"kind\": {}, \"message\": {}}}", json_string(x.kind), json_string(&x.message)))
+            .collect();
+        let error = result.error.as_ref().map(|x| json_string(x)).unwrap_or(String::from("null"));
+        tests.push(format!(
+            "    {{\"name\": {}, \"status\": \"{}\", \"started\": \"{}\", \"duration\": {:.3}, \"warnings\": [{}], \"mismatches\": [{}], \"error\": {}}}",
+            json_string(&result.name),
+            result.outcome.name(),
+            timestamp(result.started),
+            result.duration.as_secs_f64(),
+            warnings.join(", "),
+            mismatches.join(", "),
+            error
+        ));
+    }
+    format!(
+        "{{\n  \"started\": \"{}\",\n  \"passed\": {},\n  \"failed\": {},\n  \"skipped\": {},\n  \"errors\": {},\n  \"tests\": [\n{}\n  ]\n}}\n",
+        timestamp(started),
+        count(Outcome::Passed),
+        count(Outcome::Failed),
+        count(Outcome::Skipped),
+        count(Outcome::Error),
+        tests.join(",\n")
+    )
+}
+
+fn junit_report(results: &[TestResult], started: i64) -> String {
+    let count = |outcome: Outcome| results.iter().filter(|x| x.outcome == outcome).count();
+    let total_time: f64 = results.iter().map(|x| x.duration.as_secs_f64()).sum();
+    let mut report = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    report += &format!(
+        "<testsuite name=\"pedant\" tests=\"{}\" failures=\"{}\" errors=\"{}\" skipped=\"{}\" timestamp=\"{}\" time=\"{:.3}\">\n",
+        results.len(),
+        count(Outcome::Failed),
+        count(Outcome::Error),
+        count(Outcome::Skipped),
+        timestamp(started),
+        total_time
+    );
+    for result in results {
+        report += &format!("  <testcase name=\"{}\" classname=\"pedant\" time=\"{:.3}\">\n",
+            xml_escape(&result.name), result.duration.as_secs_f64());
+        if !result.mismatches.is_empty() {
+            let kinds: Vec<&str> = result.mismatches.iter().map(|x| x.kind).collect();
+            let messages: Vec<&str> = result.mismatches.iter().map(|x| x.message.as_str()).collect();
+            report += &format!("    <failure message=\"{} mismatch\">{}</failure>\n",
+                xml_escape(&kinds.join(", ")), xml_escape(&messages.join("\n")));
+        }
+        if let Some(error) = &result.error {
+            report += &format!("    <error message=\"{}\"/>\n", xml_escape(error));
+        }
+        if result.outcome == Outcome::Skipped {
+            report += "    <skipped/>\n";
+        }
+        if !result.warnings.is_empty() {
+            report += &format!("    <system-out>{}</system-out>\n", xml_escape(&result.warnings.join("\n")));
+        }
+        report += "  </testcase>\n";
+    }
+    report + "</testsuite>\n"
+}
+
+// Formats a time in seconds since the epoch as an ISO 8601 timestamp in UTC
+fn timestamp(seconds: i64) -> String {
+    format_date(seconds, "+0000", DateFormat::IsoStrict)
+}
+
+fn json_string(text: &str) -> String {
+    let mut escaped = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => escaped += "\\\"",
+            '\\' => escaped += "\\\\",
+            '\n' => escaped += "\\n",
+            '\r' => escaped += "\\r",
+            '\t' => escaped += "\\t",
+            c if (c as u32) < 0x20 => escaped += &format!("\\u{:04x}", c as u32),
+            c => escaped.push(c),
+        }
+    }
+    escaped + "\""
+}
+
+fn xml_escape(text: &str) -> String {
+    let mut escaped = String::new();
+    for c in text.chars() {
+        match c {
+            '&' => escaped += "&amp;",
+            '<' => escaped += "&lt;",
+            '>' => escaped += "&gt;",
+            '"' => escaped += "&quot;",
+            '\'' => escaped += "&apos;",
+            // Other control characters can't appear in XML at all
+            c if (c as u32) < 0x20 && !matches!(c, '\n' | '\r' | '\t') => escaped.push('?'),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
@@ -0,0 +1,451 @@
+// Finding the commit that introduced a bug by binary search through the history.
+// The state of a bisection is kept in BISECT_* files in the repository directory, and in references under refs/bisect.
+
+use std::{collections::{HashMap, HashSet}, env, fs, path::Path, process};
+use anyhow::{anyhow, bail, Result};
+use clap::{Args, Subcommand};
+
+use crate::{GlobalOpts, repo_find, git_dir_name};
+use crate::checkout::switch_commit;
+use crate::dag::{merge_bases, CommitDag};
+use crate::date::DateFormat;
+use crate::diffstat::diff_stat;
+use crate::log::{print_commit, PrettyFormat};
+use crate::mailmap::Mailmap;
+use crate::objects::{get_object, peel, short_hash, Commit, Object};
+use crate::refs::{delete_refs, head_commit, list_refs, read_head, read_ref, resolve_revision, write_head, write_ref, Head};
+
+const STATE_FILES: [&str; 9] = [
+    "BISECT_ANCESTORS_OK",
+    "BISECT_EXPECTED_REV",
+    "BISECT_FIRST_PARENT",
+    "BISECT_HEAD",
+    "BISECT_LOG",
+    "BISECT_NAMES",
+    "BISECT_RUN",
+    "BISECT_START",
+    "BISECT_TERMS",
+];
+// The width the diffstat of the first bad commit is fitted into
+const STAT_WIDTH: usize = 80;
+
+#[derive(Args)]
+pub struct BisectArgs {
+    #[command(subcommand)]
+    pub command: BisectCommand,
+}
+
+#[derive(Subcommand)]
+pub enum BisectCommand {
+    /// Start bisecting, optionally giving the bad commit followed by any known good commits
+    Start {
+        bad: Option<String>,
+        good: Vec<String>,
+    },
+    /// Mark commits as not having the bug, by default the commit checked out
+    Good {
+        revs: Vec<String>,
+    },
+    /// Mark a commit as having the bug, by default the commit checked out
+    Bad {
+        revs: Vec<String>,
+    },
+    /// Stop bisecting and return to the branch bisecting started from, or to the given commit
+    Reset {
+        commit: Option<String>,
+    },
+}
+
+pub fn cmd_bisect(args: BisectArgs, global_opts: GlobalOpts) -> Result<()> {
+    let path = env::current_dir().unwrap_or_else(|_| { panic!() });
+    let root = repo_find(&path, global_opts).unwrap_or_else(|| {
+        panic!("fatal: not a grit repository");
+    });
+    let program = if global_opts.git_mode { "git" } else { "grit" };
+    let git_dir = root.join(git_dir_name(global_opts));
+
+    let marking = matches!(args.command, BisectCommand::Good { .. } | BisectCommand::Bad { .. });
+    if marking && !git_dir.join("BISECT_START").exists() {
+        println!("You need to start by \"{} bisect start\"\n", program);
+        process::exit(1);
+    }
+
+    match args.command {
+        BisectCommand::Start { bad, good } => {
+            let revs: Vec<String> = bad.into_iter().chain(good).collect();
+            let hashes = revs.iter().map(|x| resolve_commit(&root, x, global_opts)).collect::<Result<Vec<_>>>()?;
+
+            // Starting again keeps the branch to return to from the first start
+            let start = match fs::read_to_string(git_dir.join("BISECT_START")) {
+                Ok(start) => start.trim_end().to_string(),
+                Err(_) => match read_head(&root, global_opts)? {
+                    Head::Branch(name) => name.strip_prefix("refs/heads/").unwrap_or(&name).to_string(),
+                    Head::Detached(hash) => hex::encode(hash)
+                }
+            };
+            clean_state(&root, global_opts)?;
+            fs::write(git_dir.join("BISECT_START"), format!("{}\n", start))?;
+            fs::write(git_dir.join("BISECT_NAMES"), "\n")?;
+            fs::write(git_dir.join("BISECT_LOG"), "")?;
+
+            // The first commit given is bad and the rest are good
+            let mut command = format!("{} bisect start", program);
+            for (i, (rev, hash)) in revs.iter().zip(&hashes).enumerate() {
+                mark(&root, if i == 0 { "bad" } else { "good" }, hash, false, program, global_opts)?;
+                command += &format!(" '{}'", rev.replace('\'', "'\\''"));
+            }
+            append_log(&root, &format!("{}\n", command), global_opts)?;
+            next(&root, program, global_opts)
+        },
+        BisectCommand::Good { revs } => {
+            let revs = if revs.is_empty() { vec![String::from("HEAD")] } else { revs };
+            let hashes = revs.iter().map(|x| resolve_commit(&root, x, global_opts)).collect::<Result<Vec<_>>>()?;
+            for hash in hashes {
+                mark(&root, "good", &hash, true, program, global_opts)?;
+            }
+            next(&root, program, global_opts)
+        },
+        BisectCommand::Bad { revs } => {
+            if revs.len() > 1 {
+                bail!("error: '{} bisect bad' can take only one argument.", program);
+            }
+            let rev = revs.first().map(String::as_str).unwrap_or("HEAD");
+            let hash = resolve_commit(&root, rev, global_opts)?;
+            mark(&root, "bad", &hash, true, program, global_opts)?;
+            next(&root, program, global_opts)
+        },
+        BisectCommand::Reset { commit } => {
+            let Ok(start) = fs::read_to_string(git_dir.join("BISECT_START")) else {
+                println!("We are not bisecting.");
+                return Ok(());
+            };
+            let target = commit.unwrap_or(start.trim_end().to_string());
+            return_to(&root, &target, global_opts)?;
+            clean_state(&root, global_opts)
+        }
+    }
+}
+
+// Resolves a revision given on the command line to the commit it names
+fn resolve_commit(root: &Path, rev: &str, global_opts: GlobalOpts) -> Result<[u8; 20]> {
+    let hash = resolve_revision(root, rev, global_opts).map_err(|_| anyhow!("error: Bad rev input: {}", rev))?;
+    let hash = peel(root, &hash, global_opts.git_mode)?;
+    read_commit(root, &hash, global_opts)?;
+    Ok(hash)
+}
+
+fn read_commit(root: &Path, hash: &[u8; 20], global_opts: GlobalOpts) -> Result<Commit> {
+    match get_object(root, hash, global_opts.git_mode)? {
+        Object::Commit(c) => Ok(c),
+        _ => bail!("error: Bad rev input: {}", hex::encode(hash))
+    }
+}
+
+// Records a commit as good or bad. `log_command` is false when the marks are given to `start`,
+// since the start command that made them is logged instead.
+fn mark(root: &Path, term: &str, hash: &[u8; 20], log_command: bool, program: &str, global_opts: GlobalOpts) -> Result<()> {
+    let hash_str = hex::encode(hash);
+    let ref_name = match term {
+        "bad" => String::from("refs/bisect/bad"),
+        _ => format!("refs/bisect/good-{}", hash_str)
+    };
+    write_ref(root, &ref_name, hash, global_opts)?;
+
+    let terms_path = root.join(git_dir_name(global_opts)).join("BISECT_TERMS");
+    if !terms_path.exists() {
+        fs::write(terms_path, "bad\ngood\n")?;
+    }
+
+    let subject = read_commit(root, hash, global_opts)?.subject();
+    let mut entry = format!("# {}: [{}] {}\n", term, hash_str, subject);
+    if log_command {
+        entry += &format!("{} bisect {} {}\n", program, term, hash_str);
+    }
+    append_log(root, &entry, global_opts)
+}
+
+// Checks out the next commit to test, or reports the first bad commit if there is nothing left to test
+fn next(root: &Path, program: &str, global_opts: GlobalOpts) -> Result<()> {
+    let git_dir = root.join(git_dir_name(global_opts));
+    let bad = read_ref(root, "refs/bisect/bad", global_opts)?;
+    let good: Vec<[u8; 20]> = list_refs(root, global_opts)?
+        .into_iter()
+        .filter(|(name, _)| name.starts_with("refs/bisect/good-"))
+        .map(|(_, hash)| hash)
+        .collect();
+
+    let status = match (bad, good.len()) {
+        (None, 0) => String::from("waiting for both good and bad commits"),
+        (None, 1) => String::from("waiting for bad commit, 1 good commit known"),
+        (None, count) => format!("waiting for bad commit, {} good commits known", count),
+        (Some(_), 0) => String::from("waiting for good commit(s), bad commit known"),
+        _ => String::new()
+    };
+    let Some(bad) = bad.filter(|_| status.is_empty()) else {
+        println!("status: {}", status);
+        return append_log(root, &format!("# status: {}\n", status), global_opts);
+    };
+
+    if good.contains(&bad) {
+        println!("{} was both good and bad", hex::encode(bad));
+        process::exit(1);
+    }
+
+    // Every good commit should be an ancestor of the bad one. Otherwise a merge base of the two must be tested first.
+    let dag = CommitDag::open(root, global_opts.git_mode)?;
+    let ancestors_ok = git_dir.join("BISECT_ANCESTORS_OK");
+    if !ancestors_ok.exists() {
+        for good_hash in &good {
+            for base in merge_bases(&dag, &bad, good_hash)? {
+                if base == bad {
+                    let expected = fs::read_to_string(git_dir.join("BISECT_EXPECTED_REV")).unwrap_or_default();
+                    if expected.trim_end() == hex::encode(bad) {
+                        let good_list: Vec<String> = good.iter().map(hex::encode).collect();
+                        eprintln!("The merge base {} is bad.", hex::encode(bad));
+                        eprintln!("This means the bug has been fixed between {} and [{}].", hex::encode(bad), good_list.join(" "));
+                        process::exit(3);
+                    }
+                    eprintln!("Some good revs are not ancestors of the bad rev.");
+                    eprintln!("{} bisect cannot work properly in this case.", program);
+                    eprintln!("Maybe you mistook good and bad revs?");
+                    process::exit(1);
+                }
+                if good.contains(&base) {
+                    continue;
+                }
+                println!("Bisecting: a merge base must be tested");
+                return check_out(root, &base, global_opts);
+            }
+        }
+        fs::write(&ancestors_ok, "")?;
+    }
+
+    let (best, reaches, all) = find_bisection(&dag, &bad, &good)?;
+    if best == bad {
+        let commit = read_commit(root, &bad, global_opts)?;
+        println!("{} is the first bad commit", hex::encode(bad));
+        let short = short_hash(root, &bad, global_opts)?;
+        let mailmap = Mailmap::default();
+        print_commit(&commit, &bad, &short, false, &PrettyFormat::Medium, DateFormat::Default, &mailmap, false)?;
+
+        // As with `diff-tree`, a root commit is shown without its changes
+        if let Some(parent) = commit.parents.first() {
+            let stat = diff_stat(root, Some(parent), &bad, global_opts.git_mode)?;
+            println!();
+            for line in stat.graph(STAT_WIDTH) {
+                println!("{}", line);
+            }
+            println!("{}", stat.summary());
+            for line in stat.mode_changes {
+                println!("{}", line);
+            }
+        }
+        return append_log(root, &format!("# first bad commit: [{}] {}\n", hex::encode(bad), commit.subject()), global_opts);
+    }
+
+    let left = all - reaches - 1;
+    let steps = estimate_steps(all);
+    println!(
+        "Bisecting: {} revision{} left to test after this (roughly {} step{})",
+        left, if left == 1 { "" } else { "s" }, steps, if steps == 1 { "" } else { "s" }
+    );
+    check_out(root, &best, global_opts)
+}
+
+// Checks out a commit to be tested, detaching HEAD
+fn check_out(root: &Path, hash: &[u8; 20], global_opts: GlobalOpts) -> Result<()> {
+    fs::write(root.join(git_dir_name(global_opts)).join("BISECT_EXPECTED_REV"), format!("{}\n", hex::encode(hash)))?;
+    switch_commit(root, head_commit(root, global_opts)?, hash, global_opts)?;
+    write_head(root, &Head::Detached(*hash), global_opts)?;
+    println!("[{}] {}", hex::encode(hash), read_commit(root, hash, global_opts)?.subject());
+    Ok(())
+}
+
+// Returns to the branch or commit given to `reset`
+fn return_to(root: &Path, target: &str, global_opts: GlobalOpts) -> Result<()> {
+    let head = read_head(root, global_opts)?;
+    let current = head_commit(root, global_opts)?;
+    let branch_ref = format!("refs/heads/{}", target);
+    let (hash, new_head) = match read_ref(root, &branch_ref, global_opts)? {
+        Some(hash) => (hash, Head::Branch(branch_ref.clone())),
+        None => {
+            let hash = resolve_commit(root, target, global_opts)?;
+            (hash, Head::Detached(hash))
+        }
+    };
+
+    if head == Head::Branch(branch_ref) {
+        eprintln!("Already on '{}'", target);
+        return Ok(());
+    }
+    switch_commit(root, current, &hash, global_opts)?;
+    write_head(root, &new_head, global_opts)?;
+
+    if let Head::Detached(old) = head {
+        if old != hash {
+            eprintln!("Previous HEAD position was {} {}", &hex::encode(old)[..7], read_commit(root, &old, global_opts)?.subject());
+        }
+    }
+    match new_head {
+        Head::Branch(_) => eprintln!("Switched to branch '{}'", target),
+        Head::Detached(_) => eprintln!("HEAD is now at {} {}", &hex::encode(hash)[..7], read_commit(root, &hash, global_opts)?.subject())
+    }
+    Ok(())
+}
+
+// Removes the state files and the references marking good and bad commits
+fn clean_state(root: &Path, global_opts: GlobalOpts) -> Result<()> {
+    let refs: Vec<String> = list_refs(root, global_opts)?
+        .into_iter()
+        .map(|(name, _)| name)
+        .filter(|x| x.starts_with("refs/bisect/"))
+        .collect();
+    delete_refs(root, &refs, global_opts)?;
+
+    for name in STATE_FILES {
+        let path = root.join(git_dir_name(global_opts)).join(name);
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+    }
+    Ok(())
+}
+
+fn append_log(root: &Path, text: &str, global_opts: GlobalOpts) -> Result<()> {
+    let path = root.join(git_dir_name(global_opts)).join("BISECT_LOG");
+    let mut log = fs::read_to_string(&path).unwrap_or_default();
+    log += text;
+    fs::write(path, log)?;
+    Ok(())
+}
+
+// Chooses the commit to test next, the same way as Git. The candidates are the commits the bad commit can reach
+// but no good commit can. Each candidate is weighted by the number of candidates it can reach, itself included,
+// and the one closest to reaching half of them is chosen.
+// Returns the chosen commit, its weight and the number of candidates.
+fn find_bisection(dag: &CommitDag, bad: &[u8; 20], good: &[[u8; 20]]) -> Result<([u8; 20], usize, usize)> {
+    let mut excluded = HashSet::new();
+    for hash in good {
+        excluded.extend(dag.ancestors(hash)?);
+    }
+
+    // Candidates are ordered oldest first, the reverse of the order a walk by commit date finds them in
+    let mut list = walk_by_date(dag, bad, good, &excluded)?;
+    list.reverse();
+    let total = list.len();
+    if total == 0 {
+        bail!("No testable commit found.\nMaybe you started with bad path arguments?");
+    }
+    let candidates: HashSet<[u8; 20]> = list.iter().copied().collect();
+
+    let mut parents = HashMap::new();
+    for hash in &list {
+        let candidate_parents: Vec<[u8; 20]> = dag.parents(hash)?.into_iter().filter(|x| candidates.contains(x)).collect();
+        parents.insert(*hash, candidate_parents);
+    }
+
+    // Commits with no parents among the candidates reach only themselves. Merges are counted by walking,
+    // since their parents may reach the same commits, then every other commit reaches one more than its parent.
+    let mut weights: HashMap<[u8; 20], usize> = HashMap::new();
+    for hash in &list {
+        if parents[hash].is_empty() {
+            weights.insert(*hash, 1);
+        }
+    }
+    for hash in &list {
+        if parents[hash].len() > 1 {
+            let weight = count_reachable(hash, &parents);
+            weights.insert(*hash, weight);
+            if is_halfway(weight, total) {
+                return Ok((*hash, weight, total));
+            }
+        }
+    }
+    while weights.len() < total {
+        for hash in &list {
+            if weights.contains_key(hash) {
+                continue;
+            }
+            let Some(parent_weight) = parents[hash].iter().find_map(|x| weights.get(x)) else { continue };
+            let weight = parent_weight + 1;
+            weights.insert(*hash, weight);
+            if is_halfway(weight, total) {
+                return Ok((*hash, weight, total));
+            }
+        }
+    }
+
+    // Otherwise the first commit closest to halfway is chosen
+    let mut best = (list[0], weights[&list[0]]);
+    let mut best_distance = 0;
+    for hash in &list {
+        let weight = weights[hash];
+        let distance = weight.min(total - weight);
+        if distance > best_distance {
+            best = (*hash, weight);
+            best_distance = distance;
+        }
+    }
+    Ok((best.0, best.1, total))
+}
+
+// Lists the candidates in the order Git's revision walk finds them: newest first by commit date,
+// with commits of the same date in the order they were queued. The bad commit is queued before the good ones.
+fn walk_by_date(dag: &CommitDag, bad: &[u8; 20], good: &[[u8; 20]], excluded: &HashSet<[u8; 20]>) -> Result<Vec<[u8; 20]>> {
+    let mut queue: Vec<([u8; 20], i64)> = Vec::new();
+    let mut seen = HashSet::new();
+    for hash in std::iter::once(bad).chain(good) {
+        if seen.insert(*hash) {
+            insert_by_date(&mut queue, *hash, dag.commit(hash)?.timestamp);
+        }
+    }
+
+    let mut found = Vec::new();
+    while queue.iter().any(|(hash, _)| !excluded.contains(hash)) {
+        let (current, _) = queue.remove(0);
+        if !excluded.contains(&current) {
+            found.push(current);
+        }
+        for parent in dag.parents(&current)? {
+            if seen.insert(parent) {
+                insert_by_date(&mut queue, parent, dag.commit(&parent)?.timestamp);
+            }
+        }
+    }
+    Ok(found)
+}
+
+fn insert_by_date(queue: &mut Vec<([u8; 20], i64)>, hash: [u8; 20], timestamp: i64) {
+    let pos = queue.iter().position(|(_, x)| *x < timestamp).unwrap_or(queue.len());
+    queue.insert(pos, (hash, timestamp));
+}
+
+// Counts the candidates a commit can reach, itself included
+fn count_reachable(hash: &[u8; 20], parents: &HashMap<[u8; 20], Vec<[u8; 20]>>) -> usize {
+    let mut visited = HashSet::new();
+    let mut pending = vec![*hash];
+    while let Some(current) = pending.pop() {
+        if visited.insert(current) {
+            pending.extend(&parents[&current]);
+        }
+    }
+    visited.len()
+}
+
+// Whether a commit reaching `weight` of the `total` candidates splits them in half, give or take one.
+// For large numbers of candidates anything within about 0.1% of halfway is close enough.
+fn is_halfway(weight: usize, total: usize) -> bool {
+    let diff = (2 * weight as i64 - total as i64).abs();
+    diff <= 1 || diff < total as i64 / 1024
+}
+
+// Estimates how many more steps bisecting will take after testing the next commit
+fn estimate_steps(all: usize) -> usize {
+    if all < 3 {
+        return 0;
+    }
+    let n = all.ilog2() as usize;
+    let e = 1 << n;
+    let x = all - e;
+    if e < 3 * x { n } else { n - 1 }
+}
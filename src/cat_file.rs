@@ -1,16 +1,74 @@
-use std::env;
-use anyhow::{bail, Result};
+use std::{env, fs, io::{self, BufRead, Write}, path::Path};
+use anyhow::{anyhow, bail, Result};
 use clap::Args;
 
-use crate::{GlobalOpts, repo_find, ObjectTypeExternal};
-use crate::objects::{Object, GitObject, search_object};
+use crate::{GlobalOpts, repo_find};
+use crate::attributes::Attributes;
+use crate::checkout::commit_files;
+use crate::config::Config;
+use crate::diff::{run_textconv, textconv_command};
+use crate::filter::Filters;
+use crate::objects::{get_object, object_path, peel, read_object_header, search_object, FileStore, GitObject, Object, ObjectStore, ObjectType};
+use crate::refs::resolve_revision;
 
 
 #[derive(Args)]
 pub struct CatFileArgs {
-    #[arg(value_enum)]
-    r#type: ObjectTypeExternal,
-    object: String,
+    /// The type the object is expected to have. Left out when the object is given with `-t`, `-s` or `-p`.
+    r#type: Option<String>,
+    object: Option<String>,
+    /// Show the type of the object
+    #[arg(short = 't', group = "query")]
+    show_type: bool,
+    /// Show the size of the object's contents
+    #[arg(short = 's', group = "query")]
+    show_size: bool,
+    /// Show the contents of the object, formatted according to its type
+    #[arg(short = 'p', group = "query")]
+    pretty: bool,
+    /// Show the contents of a blob as it would be written to the working tree at its path, with the smudge filter
+    /// and line ending conversion applied. The object is given as `<rev>:<path>` unless --path is given.
+    #[arg(long, group = "query")]
+    filters: bool,
+    /// Show the contents of a blob converted to text by the textconv program of its diff driver, if it has one.
+    /// The object is given as `<rev>:<path>` unless --path is given.
+    #[arg(long, group = "query")]
+    textconv: bool,
+    /// The path whose attributes decide how the object is converted for --filters or --textconv
+    #[arg(long)]
+    path: Option<String>,
+    /// Allow objects whose type isn't one Git knows of
+    #[arg(long)]
+    allow_unknown_type: bool,
+    /// Read object names from standard input, one per line, and show the name, type and size of each. The output
+    /// can instead be given as a format, in which `%(objectname)`, `%(objecttype)`, `%(objectsize)`,
+    /// `%(objectsize:disk)`, `%(deltabase)` and `%(rest)` are replaced.
+    #[arg(
+        long, group = "query", value_name = "FORMAT", num_args = 0..=1, require_equals = true,
+        default_missing_value = DEFAULT_BATCH_FORMAT
+    )]
+    batch_check: Option<String>,
+    /// With --batch-check, show every object in the store, loose or packed, instead of reading names
+    #[arg(long)]
+    batch_all_objects: bool,
+}
+
+
+// What --batch-check shows for each object when no format is given
+const DEFAULT_BATCH_FORMAT: &str = "%(objectname) %(objecttype) %(objectsize)";
+
+// A piece of a --batch-check format
+enum FormatPart {
+    Literal(String),
+    ObjectName,
+    ObjectType,
+    ObjectSize,
+    /// The number of bytes the object takes in the store, compressed and possibly as a delta
+    DiskSize,
+    /// The object this one is stored as a delta against, or the null hash if it isn't a delta
+    DeltaBase,
+    /// The text after the object name on the input line
+    Rest,
 }
 
 pub fn cmd_cat_file(args: CatFileArgs, global_opts: GlobalOpts) -> Result<()>{
@@ -19,29 +77,248 @@ pub fn cmd_cat_file(args: CatFileArgs, global_opts: GlobalOpts) -> Result<()>{
         panic!("fatal: not a grit repository");
     });
 
-    let hash_bytes = hex::decode(&args.object)?;
+    if let Some(format) = &args.batch_check {
+        if args.r#type.is_some() {
+            bail!("fatal: batch modes take no arguments");
+        }
+        return batch_check(&root, format, args.batch_all_objects, global_opts);
+    }
+    if args.batch_all_objects {
+        bail!("fatal: '--batch-all-objects' requires a batch mode");
+    }
+
+    if args.path.is_some() && !args.filters && !args.textconv {
+        bail!("fatal: '--path=<path|tree-ish>' needs '--filters' or '--textconv'");
+    }
+    if args.filters || args.textconv {
+        let (Some(rev), None) = (&args.r#type, &args.object) else {
+            bail!("fatal: too many arguments");
+        };
+        return show_converted(&root, rev, args.path.as_deref(), args.textconv, global_opts);
+    }
+
+    if args.show_type || args.show_size || args.pretty {
+        // The object is given in place of the type
+        let (Some(rev), None) = (&args.r#type, &args.object) else {
+            bail!("fatal: too many arguments");
+        };
+        let hash = resolve_revision(&root, rev, global_opts)
+            .map_err(|_| anyhow!("fatal: Not a valid object name {}", rev))?;
+        // The type and size are known from the header, so the object needn't be read in full to show them
+        let (object_type, size) = read_object_header(&root, &hash, global_opts.git_mode)?
+            .ok_or(anyhow!("fatal: git cat-file: could not get object info"))?;
+        // Only the types Git uses are shown without --allow-unknown-type
+        if matches!(object_type, ObjectType::Unknown(_)) && !args.allow_unknown_type {
+            bail!("fatal: invalid object type");
+        }
+        if args.show_type {
+            println!("{}", object_type);
+        } else if args.show_size {
+            println!("{}", size);
+        } else {
+            match get_object(&root, &hash, global_opts.git_mode)? {
+                Object::Tree(tree) => for entry in &tree.children {
+                    println!("{:06o} {} {}\t{}", entry.mode, entry.object_type(), hex::encode(entry.hash), entry.name);
+                },
+                object => std::io::stdout().write_all(&object.content_bytes())?
+            }
+        }
+        return Ok(());
+    }
+
+    let (Some(type_name), Some(object)) = (&args.r#type, &args.object) else {
+        bail!("fatal: only two arguments allowed in <type> <object> mode, not 1");
+    };
+    let expected_type = ObjectType::from_bytes(type_name.as_bytes());
+    if let ObjectType::Unknown(name) = &expected_type {
+        bail!("fatal: invalid object type \"{}\"", name);
+    }
+
+    let hash_bytes = hex::decode(object)?;
     let hash: [u8; 20] = hash_bytes.try_into().expect("invalid object hash");
 
     let object = match search_object(&root, &hash, global_opts.git_mode) {
-        Ok(None) => bail!("object {} not found in store", args.object),
+        Ok(None) => bail!("object {} not found in store", object),
         Err(e) => return Err(e),
         Ok(Some(x)) => x
     };
 
     // Check that object has expected type
-    match (&object, &args.r#type) {
-        (Object::Blob(_), ObjectTypeExternal::Blob) | 
-        (Object::Commit(_), ObjectTypeExternal::Commit) | 
-        (Object::Tree(_), ObjectTypeExternal::Tree) | 
-        (Object::Tag(_), ObjectTypeExternal::Tag) => (),
-        _ => {
-            let hash_str = hex::encode(&hash);
-            bail!("fatal: git cat-file {}: bad file", hash_str);
+    match object.object_type() {
+        ObjectType::Unknown(_) => bail!("fatal: invalid object type"),
+        x if x != expected_type => bail!("fatal: git cat-file {}: bad file", hex::encode(hash)),
+        _ => ()
+    }
+
+    io::stdout().write_all(&object.content_bytes())?;
+    Ok(())
+}
+
+// Shows an object converted as the file at `path` would be, for --filters or --textconv. Without a path, the object
+// must be given as `<rev>:<path>`, naming a file in the tree of a commit. Objects other than blobs are shown as they
+// are.
+fn show_converted(root: &Path, rev: &str, path: Option<&str>, textconv: bool, global_opts: GlobalOpts) -> Result<()> {
+    let git_mode = global_opts.git_mode;
+    let (hash, path) = match path {
+        Some(path) => {
+            let hash = resolve_revision(root, rev, global_opts)
+                .map_err(|_| anyhow!("fatal: Not a valid object name {}", rev))?;
+            (hash, path)
+        },
+        None => {
+            let Some((commit, path)) = rev.split_once(':') else {
+                bail!("fatal: <object>:<path> required, only <object> '{}' given", rev);
+            };
+            let commit_hash = resolve_revision(root, commit, global_opts)
+                .map_err(|_| anyhow!("fatal: Not a valid object name {}", rev))?;
+            let entry = commit_files(root, &peel(root, &commit_hash, git_mode)?, git_mode)?.remove(Path::new(path))
+                .ok_or_else(|| anyhow!("fatal: path '{}' does not exist in '{}'", path, commit))?;
+            (entry.hash, path)
+        }
+    };
+
+    let object = search_object(root, &hash, git_mode)?
+        .ok_or_else(|| anyhow!("fatal: Not a valid object name {}", rev))?;
+    let Object::Blob(blob) = object else {
+        io::stdout().write_all(&object.content_bytes())?;
+        return Ok(());
+    };
+    let bytes = if textconv {
+        let config = Config::load(root, global_opts)?;
+        match textconv_command(Path::new(path), &config, &mut Attributes::new(root, &config, global_opts)?)? {
+            Some(command) => run_textconv(root, &command, path, &blob.bytes)?,
+            None => blob.bytes
         }
+    } else {
+        Filters::new(root, global_opts)?.smudge(Path::new(path), blob.bytes)?
+    };
+    io::stdout().write_all(&bytes)?;
+    Ok(())
+}
+
+// Shows the name, type and size of each object named on standard input in the given format, or of every object in
+// the store. Names that don't resolve to an object in the store are shown as missing.
+fn batch_check(root: &Path, format: &str, all: bool, global_opts: GlobalOpts) -> Result<()> {
+    let parts = parse_batch_format(format)?;
+    let mut stdout = io::stdout().lock();
+    // One store serves every line, so each pack is only opened once however many objects are looked up
+    let store = FileStore::new(root, global_opts.git_mode)?;
+
+    if all {
+        for hash in store.iter()? {
+            let header = store.read_header(&hash)?
+                .ok_or_else(|| anyhow!("fatal: object {} not found in store", hex::encode(hash)))?;
+            write_object_info(&mut stdout, &parts, root, &store, &hash, &header, "", global_opts.git_mode)?;
+        }
+        return Ok(());
+    }
+
+    // Only when the rest of the line is shown is the name taken to end at the first space
+    let split_rest = parts.iter().any(|x| matches!(x, FormatPart::Rest));
+    for line in io::stdin().lock().lines() {
+        let line = line?;
+        let (name, rest) = match line.split_once(char::is_whitespace) {
+            Some((name, rest)) if split_rest => (name, rest.trim_start()),
+            _ => (line.as_str(), "")
+        };
+        let header = match resolve_revision(root, name, global_opts) {
+            Ok(hash) => store.read_header(&hash)?.map(|x| (hash, x)),
+            Err(_) => None
+        };
+        let Some((hash, header)) = header else {
+            writeln!(stdout, "{} missing", name)?;
+            continue;
+        };
+        write_object_info(&mut stdout, &parts, root, &store, &hash, &header, rest, global_opts.git_mode)?;
     }
+    Ok(())
+}
 
-    // TODO: Actually write object contents
-    let content_bytes = object.content_bytes().to_vec();
-    println!("{}", String::from_utf8_lossy(&content_bytes));
+// Writes the line --batch-check shows for an object, whose header declared the given type and size. `rest` is the
+// text that followed its name on the input line.
+#[allow(clippy::too_many_arguments)]
+fn write_object_info(
+    out: &mut impl Write,
+    parts: &[FormatPart],
+    root: &Path,
+    store: &FileStore,
+    hash: &[u8; 20],
+    (object_type, size): &(ObjectType, usize),
+    rest: &str,
+    git_mode: bool
+) -> Result<()> {
+    // Git doesn't read the type or size of an object whose type it doesn't know, even with --allow-unknown-type
+    let reads_header = parts.iter().any(|x| matches!(x, FormatPart::ObjectType | FormatPart::ObjectSize));
+    if matches!(object_type, ObjectType::Unknown(_)) && reads_header {
+        bail!("fatal: invalid object type");
+    }
+    // As in Git, an object in a pack is described as it's kept there, even if there's also a loose copy
+    let packed = if parts.iter().any(|x| matches!(x, FormatPart::DiskSize | FormatPart::DeltaBase)) {
+        store.packed_entry(hash)?
+    } else {
+        None
+    };
+    for part in parts {
+        match part {
+            FormatPart::Literal(text) => write!(out, "{}", text)?,
+            FormatPart::ObjectName => write!(out, "{}", hex::encode(hash))?,
+            FormatPart::ObjectType => write!(out, "{}", object_type)?,
+            FormatPart::ObjectSize => write!(out, "{}", size)?,
+            FormatPart::DiskSize => match &packed {
+                Some(entry) => write!(out, "{}", entry.disk_size)?,
+                None => write!(out, "{}", fs::metadata(object_path(root, hash, git_mode))?.len())?
+            },
+            FormatPart::DeltaBase => {
+                let base = packed.as_ref().and_then(|x| x.delta_base).unwrap_or([0; 20]);
+                write!(out, "{}", hex::encode(base))?
+            },
+            FormatPart::Rest => write!(out, "{}", rest)?
+        }
+    }
+    writeln!(out)?;
     Ok(())
 }
+
+// Splits a --batch-check format into literal text and the elements to be replaced. As in Git, `%%` stands for `%`,
+// and a `%` followed by anything but a parenthesis is left as it is.
+fn parse_batch_format(format: &str) -> Result<Vec<FormatPart>> {
+    let mut parts = Vec::new();
+    let mut literal = String::new();
+    let mut rest = format;
+    while let Some(start) = rest.find('%') {
+        literal.push_str(&rest[..start]);
+        rest = &rest[start + 1..];
+        if let Some(after) = rest.strip_prefix('%') {
+            literal.push('%');
+            rest = after;
+            continue;
+        }
+        if !rest.starts_with('(') {
+            literal.push('%');
+            continue;
+        }
+        let Some(end) = rest.find(')') else {
+            bail!("fatal: format element '{}' does not end in ')'", rest);
+        };
+        let part = match &rest[1..end] {
+            "objectname" => FormatPart::ObjectName,
+            "objecttype" => FormatPart::ObjectType,
+            "objectsize" => FormatPart::ObjectSize,
+            "objectsize:disk" => FormatPart::DiskSize,
+            "deltabase" => FormatPart::DeltaBase,
+            "rest" => FormatPart::Rest,
+            element => bail!("fatal: unknown format element: {}", element)
+        };
+        if !literal.is_empty() {
+            parts.push(FormatPart::Literal(literal.clone()));
+            literal.clear();
+        }
+        parts.push(part);
+        rest = &rest[end + 1..];
+    }
+    literal.push_str(rest);
+    if !literal.is_empty() {
+        parts.push(FormatPart::Literal(literal));
+    }
+    Ok(parts)
+}
@@ -0,0 +1,82 @@
+// Show the attributes .gitattributes files give paths, to debug filters, diff drivers and line ending settings
+
+use std::{env, path::Path, process};
+use anyhow::{anyhow, Result};
+use clap::Args;
+
+use crate::{GlobalOpts, repo_find};
+use crate::attributes::{AttrValue, Attributes};
+use crate::check_ignore::normalize;
+use crate::config::Config;
+use crate::quote::PathQuoting;
+
+#[derive(Args)]
+pub struct CheckAttrArgs {
+    /// Show every attribute set, unset or given a value for each path, rather than the attributes named
+    #[arg(short, long)]
+    pub all: bool,
+    /// The attributes to show followed by the paths, or only the attributes if the paths are given after `--`. With
+    /// --all, only the paths.
+    pub names: Vec<String>,
+    #[arg(last = true)]
+    pub paths: Vec<String>,
+}
+
+pub fn cmd_check_attr(args: CheckAttrArgs, global_opts: GlobalOpts) -> Result<()> {
+    let cwd = env::current_dir().unwrap_or_else(|_| { panic!() });
+    let root = repo_find(&cwd, global_opts).unwrap_or_else(|| {
+        panic!("fatal: not a grit repository");
+    });
+
+    let (attrs, paths) = if args.all {
+        if !args.names.is_empty() && !args.paths.is_empty() {
+            usage_error("Attributes and --all both specified", global_opts);
+        }
+        (Vec::new(), [args.names, args.paths].concat())
+    } else if !args.paths.is_empty() {
+        (args.names, args.paths)
+    } else {
+        // Like Git, without `--` the first argument is the only attribute
+        let mut attrs = args.names;
+        let paths = if attrs.is_empty() { Vec::new() } else { attrs.split_off(1) };
+        (attrs, paths)
+    };
+    if !args.all && attrs.is_empty() {
+        usage_error("No attribute specified", global_opts);
+    }
+    if paths.is_empty() {
+        usage_error("No file specified", global_opts);
+    }
+
+    let config = Config::load(&root, global_opts)?;
+    let quoting = PathQuoting::new(&config)?;
+    let mut attributes = Attributes::new(&root, &config, global_opts)?;
+    for path in &paths {
+        let absolute = normalize(&cwd.join(path));
+        let relative = absolute.strip_prefix(&root)
+            .map_err(|_| anyhow!("fatal: '{}' is outside repository at '{}'", path, root.to_string_lossy()))?;
+
+        let values = match args.all {
+            true => attributes.get_all(relative)?,
+            false => attrs.iter().map(|x| Ok((x.clone(), attributes.get(relative, x)?))).collect::<Result<_>>()?
+        };
+        for (name, value) in values {
+            let value = match value {
+                AttrValue::Unspecified => "unspecified",
+                AttrValue::Set => "set",
+                AttrValue::Unset => "unset",
+                AttrValue::Value(ref x) => x
+            };
+            println!("{}: {}: {}", quoting.quote(Path::new(path)), name, value);
+        }
+    }
+    Ok(())
+}
+
+// Reports a mistake in the arguments given, as Git does, with how the command is used
+fn usage_error(message: &str, global_opts: GlobalOpts) -> ! {
+    let program = if global_opts.git_mode { "git" } else { "grit" };
+    eprintln!("error: {}", message);
+    eprintln!("usage: {} check-attr [-a | --all | <attr>...] [--] <pathname>...", program);
+    process::exit(129);
+}
@@ -0,0 +1,59 @@
+// Debug gitignore and exclude files
+
+use std::{env, path::{Component, Path, PathBuf}};
+use anyhow::{anyhow, Result};
+use clap::Args;
+
+use crate::{GlobalOpts, repo_find, ignore::IgnoreRules};
+
+#[derive(Args)]
+pub struct CheckIgnoreArgs {
+    /// Also print the source file, line number and pattern that matched each path
+    #[arg(short, long)]
+    pub verbose: bool,
+    /// Also print paths that don't match any pattern. Only has an effect with --verbose
+    #[arg(short, long)]
+    pub non_matching: bool,
+    #[arg(required = true)]
+    pub paths: Vec<String>,
+}
+
+pub fn cmd_check_ignore(args: CheckIgnoreArgs, global_opts: GlobalOpts) -> Result<()> {
+    let cwd = env::current_dir().unwrap_or_else(|_| { panic!() });
+    let root = repo_find(&cwd, global_opts).unwrap_or_else(|| {
+        panic!("fatal: not a grit repository");
+    });
+
+    let mut rules = IgnoreRules::new(&root, global_opts)?;
+
+    for path in &args.paths {
+        let absolute = normalize(&cwd.join(path));
+        let relative = absolute.strip_prefix(&root)
+            .map_err(|_| anyhow!("fatal: {}: '{}' is outside repository at '{}'", path, path, root.to_string_lossy()))?;
+
+        let pattern = rules.matching_pattern(relative)?;
+        match (&pattern, args.verbose) {
+            (Some(p), true) => {
+                println!("{}:{}:{}\t{}", p.source.to_string_lossy(), p.line, p.text, path);
+            },
+            (Some(p), false) if !p.negated => println!("{}", path),
+            (None, true) if args.non_matching => println!("::\t{}", path),
+            _ => ()
+        }
+    }
+
+    Ok(())
+}
+
+/// Resolves `.` and `..` components without touching the filesystem, since the path need not exist
+pub fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => (),
+            Component::ParentDir => { normalized.pop(); },
+            c => normalized.push(c)
+        }
+    }
+    normalized
+}
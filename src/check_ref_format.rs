@@ -0,0 +1,49 @@
+// Check whether a name can be used for a reference
+
+use std::process;
+use anyhow::{anyhow, Result};
+use clap::Args;
+
+use crate::GlobalOpts;
+use crate::refs::{check_branch_name, check_ref_name};
+
+#[derive(Args)]
+pub struct CheckRefFormatArgs {
+    /// The full name to check, e.g. `refs/heads/topic`, or the short name of a branch with --branch
+    #[arg(allow_hyphen_values = true)]
+    refname: String,
+    /// Allow names with only one component, such as `HEAD`
+    #[arg(long)]
+    allow_onelevel: bool,
+    /// Remove a leading slash and repeated slashes from the name, then print it if it's valid
+    #[arg(long)]
+    normalize: bool,
+    /// Check the name as the short name of a branch, printing it if it's valid
+    #[arg(long, conflicts_with_all = ["allow_onelevel", "normalize"])]
+    branch: bool,
+}
+
+pub fn cmd_check_ref_format(args: CheckRefFormatArgs, _global_opts: GlobalOpts) -> Result<()> {
+    if args.branch {
+        check_branch_name(&args.refname)
+            .map_err(|_| anyhow!("fatal: '{}' is not a valid branch name", args.refname))?;
+        println!("{}", args.refname);
+        return Ok(());
+    }
+
+    let name = if args.normalize {
+        args.refname.split('/').filter(|x| !x.is_empty()).collect::<Vec<&str>>().join("/")
+            + if args.refname.ends_with('/') { "/" } else { "" }
+    } else {
+        args.refname.clone()
+    };
+
+    // As in Git, an invalid name is reported only through the exit status
+    if check_ref_name(&name).is_err() || (!args.allow_onelevel && !name.contains('/')) {
+        process::exit(1);
+    }
+    if args.normalize {
+        println!("{}", name);
+    }
+    Ok(())
+}
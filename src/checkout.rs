@@ -1,9 +1,12 @@
-use std::{fs, path::PathBuf, env};
+use std::{collections::{BTreeMap, BTreeSet}, fs, path::{Path, PathBuf}, env};
 use anyhow::{bail, Result};
 use clap::Args;
 
 use crate::{GlobalOpts, repo_find};
-use crate::objects::{get_object, Commit, Object, search_object, parse_hash, Tree};
+use crate::filter::Filters;
+use crate::index::{read_index, write_index, IndexItem};
+use crate::merge::TreeMerge;
+use crate::objects::{get_object, Blob, GitObject, Object, search_object, parse_hash, walk_tree, TreeEntry};
 
 #[derive(Args)]
 pub struct CheckoutArgs {
@@ -17,7 +20,7 @@ pub fn cmd_checkout(args: CheckoutArgs, global_opts: GlobalOpts) -> Result<()> {
     // Fail if the given directory is not empty
     let destination = PathBuf::from(args.directory);
     let contents = fs::read_dir(&destination)?;
-    
+
     if contents.into_iter().count() > 0 {
         bail!("Destination directory is not empty!");
     }
@@ -31,35 +34,300 @@ pub fn cmd_checkout(args: CheckoutArgs, global_opts: GlobalOpts) -> Result<()> {
 
     // Parse the given commit object
     match search_object(&root, &hash, global_opts.git_mode) {
-        Ok(Some(Object::Commit(c))) => checkout_commit(&root, c, &destination, global_opts.git_mode),
+        Ok(Some(Object::Commit(_))) => {
+            let mut filters = Filters::new(&root, global_opts)?;
+            for (path, entry) in commit_files(&root, &hash, global_opts.git_mode)? {
+                write_worktree_file(&root, &entry, &path, &destination.join(&path), &mut filters, global_opts.git_mode)?;
+            }
+            Ok(())
+        },
         Ok(Some(_)) => bail!("Requested object is not a commit"),
         Ok(None) => bail!("Commit object does not exist"),
         Err(e) => Err(e)
     }
 }
 
-fn checkout_commit(root: &PathBuf, commit: Commit, destination: &PathBuf, git_mode: bool) -> Result<()> {
-    match get_object(root, &commit.tree, git_mode) {
-        Ok(Object::Tree(t)) => checkout_tree(root, t, destination, git_mode),
-        Ok(_) => bail!("Commit references a tree that is not actually a tree"),
-        Err(e) => Err(e)
+/// Updates the index and working tree from the state of commit `from` (None for an unborn branch) to that of
+/// commit `to`. Only paths that differ between the two commits are touched, so local changes to other files are
+/// carried over. Fails without modifying anything if a path that would be touched has local changes.
+pub fn switch_commit(root: &Path, from: Option<[u8; 20]>, to: &[u8; 20], global_opts: GlobalOpts) -> Result<()> {
+    let git_mode = global_opts.git_mode;
+    let old_files = match from {
+        Some(hash) => commit_files(root, &hash, git_mode)?,
+        None => BTreeMap::new()
+    };
+    let new_files = commit_files(root, to, git_mode)?;
+    switch_files(root, &old_files, &new_files, global_opts)
+}
+
+/// Updates the index and working tree from one set of files to another, as `switch_commit` does for commits
+pub fn switch_files(
+    root: &Path,
+    old_files: &BTreeMap<PathBuf, TreeEntry>,
+    new_files: &BTreeMap<PathBuf, TreeEntry>,
+    global_opts: GlobalOpts
+) -> Result<()> {
+    let git_mode = global_opts.git_mode;
+    let mut index = read_index(root, global_opts)?;
+    let mut filters = Filters::new(root, global_opts)?;
+
+    let changed: BTreeSet<&PathBuf> = old_files.keys()
+        .chain(new_files.keys())
+        .filter(|path| old_files.get(*path).map(|x| (x.hash, x.mode)) != new_files.get(*path).map(|x| (x.hash, x.mode)))
+        .collect();
+
+    // Check that no local changes would be overwritten before touching anything
+    let mut modified = Vec::new();
+    let mut untracked = Vec::new();
+    for path in &changed {
+        let old_hash = old_files.get(*path).map(|x| x.hash);
+        let new_hash = new_files.get(*path).map(|x| x.hash);
+        let index_item = index.items.iter().find(|x| &x.path == *path);
+        let index_hash = index_item.map(|x| x.hash);
+        let mut worktree_hash = hash_worktree_file(root, path, &mut filters)?;
+        // A file marked skip-worktree that isn't in the working tree has no changes to lose
+        if worktree_hash.is_none() && index_item.is_some_and(|x| x.skip_worktree) {
+            worktree_hash = index_hash;
+        }
+
+        if index_hash == old_hash && worktree_hash == old_hash {
+            continue;
+        }
+        if index_hash == new_hash && worktree_hash == new_hash {
+            continue;
+        }
+        if old_hash.is_none() && index_hash.is_none() {
+            untracked.push(path.to_string_lossy().to_string());
+        } else {
+            modified.push(path.to_string_lossy().to_string());
+        }
+    }
+
+    if !modified.is_empty() {
+        bail!("error: Your local changes to the following files would be overwritten by checkout:\n\t{}\n\
+            Please commit your changes or stash them before you switch branches.\nAborting", modified.join("\n\t"));
+    }
+    if !untracked.is_empty() {
+        bail!("error: The following untracked working tree files would be overwritten by checkout:\n\t{}\n\
+            Please move or remove them before you switch branches.\nAborting", untracked.join("\n\t"));
+    }
+
+    // Update the working tree and the index together, keeping the skip-worktree marks of the entries replaced
+    let skipped: BTreeSet<PathBuf> = index.items.iter()
+        .filter(|x| x.skip_worktree && changed.contains(&x.path))
+        .map(|x| x.path.clone())
+        .collect();
+    index.items.retain(|x| !changed.contains(&x.path));
+    for path in &changed {
+        let file = root.join(path);
+        match new_files.get(*path) {
+            Some(entry) => {
+                write_worktree_file(root, entry, path, &file, &mut filters, git_mode)?;
+                let mut item = IndexItem::from_file(&file, path.to_path_buf(), entry.hash)?;
+                item.mode = entry.mode;
+                item.skip_worktree = skipped.contains(*path);
+                index.items.push(item);
+            },
+            None => {
+                if file.exists() {
+                    fs::remove_file(&file)?;
+                }
+                remove_empty_parents(root, &file)?;
+            }
+        }
+    }
+    index.items.sort_by(|a, b| a.path.to_string_lossy().as_bytes().cmp(b.path.to_string_lossy().as_bytes()));
+    write_index(&index, root, global_opts)?;
+
+    Ok(())
+}
+
+/// Updates the index and working tree from `our_files` to the result of merging them with other changes. Each
+/// conflicted file is left in the working tree with conflict markers, and every version of it is put in the index.
+/// Returns the files as they were left in the working tree.
+pub fn checkout_merge(
+    root: &Path,
+    our_files: &BTreeMap<PathBuf, TreeEntry>,
+    merge: &TreeMerge,
+    global_opts: GlobalOpts
+) -> Result<BTreeMap<PathBuf, TreeEntry>> {
+    switch_files(root, our_files, &merge.files, global_opts)?;
+    let mut worktree_files = merge.files.clone();
+    for conflict in &merge.conflicts {
+        if let Some(contents) = &conflict.contents {
+            fs::write(root.join(&conflict.path), contents)?;
+            let blob = Blob { bytes: contents.clone() };
+            blob.write(root, global_opts)?;
+            if let Some(entry) = worktree_files.get_mut(&conflict.path) {
+                entry.hash = blob.hash();
+            }
+        }
+    }
+    let mut index = read_index(root, global_opts)?;
+    index.items.retain(|x| !merge.conflicts.iter().any(|c| c.path == x.path));
+    for conflict in &merge.conflicts {
+        for (stage, entry) in (1..).zip(&conflict.stages) {
+            if let Some(entry) = entry {
+                index.items.push(IndexItem::without_stat(conflict.path.clone(), entry.mode, entry.hash, stage));
+            }
+        }
+    }
+    index.items.sort_by(|a, b| {
+        a.path.as_os_str().as_encoded_bytes().cmp(b.path.as_os_str().as_encoded_bytes()).then(a.stage.cmp(&b.stage))
+    });
+    write_index(&index, root, global_opts)?;
+    Ok(worktree_files)
+}
+
+/// Makes the index and working tree match the given commit, discarding any local changes to tracked files
+/// and any conflicts. Files in the index that aren't in the commit are removed.
+pub fn reset_hard(root: &Path, to: &[u8; 20], global_opts: GlobalOpts) -> Result<()> {
+    let git_mode = global_opts.git_mode;
+    let new_files = commit_files(root, to, git_mode)?;
+    let mut index = read_index(root, global_opts)?;
+    let mut filters = Filters::new(root, global_opts)?;
+
+    for item in &index.items {
+        if !new_files.contains_key(&item.path) {
+            let file = root.join(&item.path);
+            if file.exists() {
+                fs::remove_file(&file)?;
+            }
+            remove_empty_parents(root, &file)?;
+        }
+    }
+
+    index.items.clear();
+    for (path, entry) in &new_files {
+        let file = root.join(path);
+        write_worktree_file(root, entry, path, &file, &mut filters, git_mode)?;
+        let mut item = IndexItem::from_file(&file, path.to_path_buf(), entry.hash)?;
+        item.mode = entry.mode;
+        index.items.push(item);
     }
+    index.items.sort_by(|a, b| a.path.to_string_lossy().as_bytes().cmp(b.path.to_string_lossy().as_bytes()));
+    write_index(&index, root, global_opts)?;
+    Ok(())
 }
 
-fn checkout_tree(root: &PathBuf, tree: Tree, destination: &PathBuf, git_mode: bool) -> Result<()> {
-    for leaf in tree.children.into_iter() {
-        println!("Checking out following tree node...");
-        // println!("{}", leaf);
+/// Makes the index match the given commit, discarding any conflicts, as `git reset --merge` does. Only the files
+/// whose entries in the index differ from the commit are restored in the working tree, so local changes to other
+/// files are kept.
+pub fn reset_merge(root: &Path, to: &[u8; 20], global_opts: GlobalOpts) -> Result<()> {
+    let git_mode = global_opts.git_mode;
+    let new_files = commit_files(root, to, git_mode)?;
+    let mut index = read_index(root, global_opts)?;
+    let mut filters = Filters::new(root, global_opts)?;
 
-        let output_path = destination.join(&leaf.name);
+    let mut changed: BTreeSet<PathBuf> = index.items.iter()
+        .filter(|x| x.stage != 0 || new_files.get(&x.path).map(|y| (y.hash, y.mode)) != Some((x.hash, x.mode)))
+        .map(|x| x.path.clone())
+        .collect();
+    changed.extend(new_files.keys().filter(|x| !index.items.iter().any(|y| &y.path == *x)).cloned());
 
-        match get_object(root, &leaf.hash, git_mode) {
-            Ok(Object::Blob(b)) => { fs::write(output_path, b.bytes)?; },
-            Ok(Object::Tree(_)) => {}, // TODO: Recurse on the subtree
-            Ok(_) => bail!("Unexpected object found in tree. Expecting only blobs or trees"),
-            Err(e) => return Err(e)
+    index.items.retain(|x| !changed.contains(&x.path));
+    for path in &changed {
+        let file = root.join(path);
+        match new_files.get(path) {
+            Some(entry) => {
+                write_worktree_file(root, entry, path, &file, &mut filters, git_mode)?;
+                let mut item = IndexItem::from_file(&file, path.to_path_buf(), entry.hash)?;
+                item.mode = entry.mode;
+                index.items.push(item);
+            },
+            None => {
+                if file.exists() {
+                    fs::remove_file(&file)?;
+                }
+                remove_empty_parents(root, &file)?;
+            }
         }
     }
+    index.items.sort_by(|a, b| a.path.to_string_lossy().as_bytes().cmp(b.path.to_string_lossy().as_bytes()));
+    write_index(&index, root, global_opts)?;
+    Ok(())
+}
+
+/// Returns every file in the tree of the given commit, keyed by its path relative to the repository root.
+pub fn commit_files(root: &Path, commit_hash: &[u8; 20], git_mode: bool) -> Result<BTreeMap<PathBuf, TreeEntry>> {
+    let mut files = BTreeMap::new();
+    match get_object(root, commit_hash, git_mode) {
+        Ok(Object::Commit(c)) => tree_files(root, &c.tree, git_mode, &mut files)?,
+        Ok(_) => bail!("Requested object is not a commit"),
+        Err(e) => return Err(e)
+    }
+    Ok(files)
+}
+
+/// Adds every file in the given tree to `files`, keyed by its path relative to the tree
+pub fn tree_files(
+    root: &Path,
+    tree_hash: &[u8; 20],
+    git_mode: bool,
+    files: &mut BTreeMap<PathBuf, TreeEntry>
+) -> Result<()> {
+    walk_tree(root, tree_hash, git_mode, &mut |path, entry| {
+        files.insert(path, entry);
+        Ok(())
+    })
+}
+
+// Writes the blob of a tree entry to `output_path`, smudged as the file at `path` in the repository would be
+fn write_worktree_file(
+    root: &Path,
+    entry: &TreeEntry,
+    path: &Path,
+    output_path: &Path,
+    filters: &mut Filters,
+    git_mode: bool
+) -> Result<()> {
+    let bytes = match get_object(root, &entry.hash, git_mode) {
+        Ok(Object::Blob(b)) => filters.smudge(path, b.bytes)?,
+        Ok(_) => bail!("Unexpected object found in tree. Expecting only blobs or trees"),
+        Err(e) => return Err(e)
+    };
+
+    if let Some(parent) = output_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    // Replace rather than overwrite any existing file so that it doesn't keep its old permissions
+    if output_path.exists() {
+        fs::remove_file(output_path)?;
+    }
+    fs::write(output_path, bytes)?;
+
+    #[cfg(unix)]
+    if entry.mode == 0o100755 {
+        use std::os::unix::fs::PermissionsExt;
+        let mut permissions = fs::metadata(output_path)?.permissions();
+        permissions.set_mode(permissions.mode() | 0o111);
+        fs::set_permissions(output_path, permissions)?;
+    }
 
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Returns the hash the working tree file at the given path would have as a blob once cleaned,
+/// or None if there is no such file
+pub fn hash_worktree_file(root: &Path, path: &Path, filters: &mut Filters) -> Result<Option<[u8; 20]>> {
+    let file = root.join(path);
+    if !file.is_file() {
+        return Ok(None);
+    }
+    let blob = Blob { bytes: filters.clean(path, fs::read(file)?)? };
+    Ok(Some(blob.hash()))
+}
+
+// Removes the directories containing the given path for as long as they are empty, stopping at the repository root
+fn remove_empty_parents(root: &Path, path: &Path) -> Result<()> {
+    let mut dir = path.parent();
+    while let Some(d) = dir {
+        if d == root || !d.is_dir() || fs::read_dir(d)?.next().is_some() {
+            break;
+        }
+        fs::remove_dir(d)?;
+        dir = d.parent();
+    }
+    Ok(())
+}
@@ -1,15 +1,47 @@
-use std::{env, fs, path::PathBuf};
+use std::{env, fs, path::Path, process};
 
-use anyhow::Result;
+use anyhow::{anyhow, bail, Result};
 use clap::Args;
 
-use crate::{GlobalOpts, repo_find, git_dir_name, index::Index, cmd_status, StatusArgs, write_tree::write_tree, objects::Commit};
+use crate::{GlobalOpts, repo_find, git_dir_name, index::read_index, cmd_status, StatusArgs, write_tree::write_tree};
+use crate::checkout::hash_worktree_file;
+use crate::config::Config;
+use crate::date::{format_date, now_with_timezone, parse_date, DateFormat};
+use crate::diffstat::diff_stat;
+use crate::editor::{git_editor, launch_editor};
+use crate::filter::Filters;
+use crate::gpg::sign_payload;
+use crate::hooks::run_hook;
+use crate::merge::{read_merge_head, remove_merge_state};
+use crate::objects::{get_object, object_exists, short_hash, Commit, GitObject, Object, Signature};
+use crate::refs::{append_reflog, read_head, read_ref, write_head, write_ref, Head};
+use crate::stripspace::stripspace;
 
 
 #[derive(Args)]
 pub struct CommitArgs {
+    /// The commit message. Without it, the message is written in the editor.
     #[arg(short)]
-    pub message: String
+    pub message: Option<String>,
+    /// Replace the commit at HEAD with a new one made from the index, with the same parents and author. The old
+    /// commit's message is used unless another is given.
+    #[arg(long)]
+    pub amend: bool,
+    /// Make the commit even if its tree is the same as its parent's
+    #[arg(long)]
+    pub allow_empty: bool,
+    /// Don't run the pre-commit and commit-msg hooks
+    #[arg(short, long)]
+    pub no_verify: bool,
+    /// Sign the commit, with the given key or by default with `user.signingKey`
+    #[arg(short = 'S', long, value_name = "KEYID", num_args = 0..=1, default_missing_value = "")]
+    pub gpg_sign: Option<String>,
+    /// Don't sign the commit, even if `commit.gpgSign` is set
+    #[arg(long, conflicts_with = "gpg_sign")]
+    pub no_gpg_sign: bool,
+    /// Record this as the author date instead of the current time or `GIT_AUTHOR_DATE`
+    #[arg(long)]
+    pub date: Option<String>,
 }
 
 pub fn cmd_commit(args: CommitArgs, global_opts: GlobalOpts) -> Result<()> {
@@ -17,35 +49,332 @@ pub fn cmd_commit(args: CommitArgs, global_opts: GlobalOpts) -> Result<()> {
     let root = repo_find(&path, global_opts).unwrap_or_else(|| {
         panic!("fatal: not a grit repository");
     });
+    let git_mode = global_opts.git_mode;
 
-    let index = read_index(&root, global_opts)?;
+    let head = read_head(&root, global_opts)?;
+    let parent = match &head {
+        Head::Branch(name) => read_ref(&root, name, global_opts)?,
+        Head::Detached(hash) => Some(*hash)
+    };
+    if args.amend && parent.is_none() {
+        bail!("fatal: You have nothing to amend.");
+    }
+    // A merge that stopped with conflicts is concluded by committing its resolution, with the merged commit as
+    // another parent
+    let merge_head = read_merge_head(&root, global_opts)?;
+    if args.amend && merge_head.is_some() {
+        bail!("fatal: You are in the middle of a merge -- cannot amend.");
+    }
+
+    let mut index = read_index(&root, global_opts)?;
 
     // If nothing is staged, run `status` instead to prompt the user to `add` files
-    if index.items.len() == 0 {
-        let status_args = StatusArgs { untracked_files: None };
+    if index.items.is_empty() {
+        let status_args = StatusArgs { untracked_files: None, porcelain: false, z: false };
         return cmd_status(status_args, global_opts);
     }
 
-    let tree = write_tree(index, &root, global_opts)?;
+    // A conflicted path has several versions in the index, and can't be committed until one is chosen
+    if index.has_conflicts() {
+        let program = if git_mode { "git" } else { "grit" };
+        eprintln!(
+            "error: Committing is not possible because you have unmerged files.\n\
+            hint: Fix them up in the work tree, and then use '{} add/rm <file>'\n\
+            hint: as appropriate to mark resolution and make a commit.\n\
+            fatal: Exiting because of an unresolved conflict.",
+            program
+        );
+        process::exit(128);
+    }
+
+    // The new commit can't point at objects missing from the store, as that would leave it unreadable
+    if let Some(parent) = parent {
+        if !object_exists(&root, &parent, git_mode)? {
+            bail!("fatal: could not parse HEAD");
+        }
+    }
+    // An amended commit takes the place of the one at HEAD, and so has its parents and author
+    let amended = match parent {
+        Some(parent) if args.amend => match get_object(&root, &parent, git_mode)? {
+            Object::Commit(commit) => Some(commit),
+            _ => bail!("fatal: could not parse HEAD")
+        },
+        _ => None
+    };
 
-    let branch = "master"; // TODO
-    let parent = "root-commit"; // TODO
-    let hash = ""; // TODO
-    
-    println!("[{} ({}) {}] {}", branch, parent, hash, args.message);
+    let mut tree = write_tree(index, &root, global_opts)?;
 
-    // Print summary of changes
+    let config = Config::load(&root, global_opts)?;
+    let mut author = match &amended {
+        Some(commit) => Signature::parse(&commit.author)?,
+        None => signature(&config, "AUTHOR", global_opts)?
+    };
+    if let Some(date) = &args.date {
+        (author.timestamp, author.timezone) = parse_date(date)?;
+    }
+    let committer = signature(&config, "COMMITTER", global_opts)?;
+
+    // Hooks are given the same environment Git provides them
+    let index_file = format!("{}/index", git_dir_name(global_opts));
+    let hook_env = [
+        ("GIT_INDEX_FILE", index_file),
+        ("GIT_EDITOR", String::from(":")),
+        ("GIT_AUTHOR_NAME", author.name.clone()),
+        ("GIT_AUTHOR_EMAIL", author.email.clone()),
+        ("GIT_AUTHOR_DATE", format!("@{} {}", author.timestamp, author.timezone)),
+    ];
+
+    // The pre-commit hook can reject the commit. It may also have staged more changes, so the tree is rewritten.
+    if !args.no_verify {
+        if let Some(status) = run_hook(&root, "pre-commit", &[], &hook_env, &config, global_opts)? {
+            if !status.success() {
+                process::exit(1);
+            }
+            index = read_index(&root, global_opts)?;
+            tree = write_tree(index, &root, global_opts)?;
+        }
+    }
+
+    // As in Git, a commit that records no change is refused unless asked for. An amended commit is compared with its
+    // own parent, as that's what it will follow, except for a merge, which is allowed to change nothing.
+    if !args.allow_empty && merge_head.is_none() {
+        let base = match &amended {
+            Some(commit) if commit.parents.len() > 1 => None,
+            Some(commit) => commit.parents.first().copied(),
+            None => parent
+        };
+        if let Some(base) = base {
+            if commit_tree(&root, &base, git_mode)? == tree.hash() {
+                if args.amend {
+                    eprintln!(
+                        "You asked to amend the most recent commit, but doing so would make\n\
+                        it empty. You can repeat your command with --allow-empty, or you can\n\
+                        remove the commit entirely with \"{} reset HEAD^\".",
+                        if git_mode { "git" } else { "grit" }
+                    );
+                }
+                print_nothing_to_commit(&root, &head, args.amend, global_opts)?;
+                process::exit(1);
+            }
+        }
+    }
+
+    // Without a message, one is written in the editor, starting from the old message when amending, or the message
+    // the merge prepared when concluding one
+    let message_file = format!("{}/COMMIT_EDITMSG", git_dir_name(global_opts));
+    match &args.message {
+        Some(message) => fs::write(root.join(&message_file), stripspace(message, false))?,
+        None => {
+            let old_message = match merge_head {
+                Some(_) => format!(
+                    "{}#\n# It looks like you may be committing a merge.\n# If this is not correct, please run\n\
+                    #\t{} update-ref -d MERGE_HEAD\n# and try again.\n\n",
+                    fs::read_to_string(root.join(git_dir_name(global_opts)).join("MERGE_MSG"))?,
+                    if git_mode { "git" } else { "grit" }
+                ),
+                None => amended.as_ref().map(|x| x.message.clone()).unwrap_or_default()
+            };
+            fs::write(root.join(&message_file), format!(
+                "{}\n# Please enter the commit message for your changes. Lines starting\n\
+                # with '#' will be ignored, and an empty message aborts the commit.\n",
+                old_message
+            ))?;
+            launch_editor(&root, Path::new(&message_file), &git_editor(&config))?;
+        }
+    }
+
+    // The commit-msg hook is given the message in a file, which it can reject or edit
+    if !args.no_verify {
+        if let Some(status) = run_hook(&root, "commit-msg", &[&message_file], &hook_env, &config, global_opts)? {
+            if !status.success() {
+                process::exit(1);
+            }
+        }
+    }
+    let message = fs::read_to_string(root.join(&message_file))?;
+    // Comments are only removed from messages that were written in the editor
+    let message = stripspace(&message, args.message.is_none());
+    if message.is_empty() {
+        bail!("Aborting commit due to empty commit message.");
+    }
+
+    if !object_exists(&root, &tree.hash(), git_mode)? {
+        bail!("fatal: {} is not a valid 'tree' object", hex::encode(tree.hash()));
+    }
+
+    let mut commit = Commit {
+        tree: tree.hash(),
+        author: author.to_string(),
+        committer: committer.to_string(),
+        parents: match &amended {
+            Some(commit) => commit.parents.clone(),
+            None => parent.into_iter().chain(merge_head).collect()
+        },
+        message,
+        gpgsig: None,
+    };
+
+    // The signature covers the rest of the commit, and is added as a header after the committer
+    let sign = !args.no_gpg_sign && (args.gpg_sign.is_some() || config.get_bool("commit.gpgsign")?.unwrap_or(false));
+    if sign {
+        let key = args.gpg_sign.as_deref().filter(|x| !x.is_empty());
+        let identity = format!("{} <{}>", committer.name, committer.email);
+        let signature = sign_payload(&commit.content_bytes(), key, &identity, &config)
+            .map_err(|e| anyhow!("{}\nfatal: failed to write commit object", e))?;
+        commit.gpgsig = Some(signature.trim_end_matches('\n').to_string());
+    }
+    commit.write(&root, global_opts)?;
+    let hash = commit.hash();
+
+    match &head {
+        Head::Branch(name) => write_ref(&root, name, &hash, global_opts)?,
+        Head::Detached(_) => write_head(&root, &Head::Detached(hash), global_opts)?
+    }
+
+    // The change is recorded in the reflogs of HEAD and the branch, so that the previous commit can be found
+    let kind = if args.amend {
+        " (amend)"
+    } else if parent.is_none() {
+        " (initial)"
+    } else if merge_head.is_some() {
+        " (merge)"
+    } else {
+        ""
+    };
+    let reflog_message = format!("commit{}: {}", kind, commit.subject());
+    append_reflog(&root, "HEAD", parent, &hash, &committer, &reflog_message, global_opts)?;
+    // Amending can make the same commit again, which Git only records for HEAD
+    if let (Head::Branch(name), true) = (&head, parent != Some(hash)) {
+        append_reflog(&root, name, parent, &hash, &committer, &reflog_message, global_opts)?;
+    }
+
+    if merge_head.is_some() {
+        remove_merge_state(&root, global_opts)?;
+    }
+
+    // The commit has been made, so a failing post-commit hook is ignored
+    run_hook(&root, "post-commit", &[], &hook_env, &config, global_opts)?;
+
+    // As in Git, an author date that was given or kept from the amended commit is shown, so that it can be checked
+    let show_date = args.amend || args.date.is_some();
+    print_commit_summary(&root, &head, &hash, &commit, parent.is_none(), show_date, global_opts)?;
 
     Ok(())
 }
 
-// Returns the current index, or an empty index if one does not exist
-fn read_index(repo_root: &PathBuf, global_opts: GlobalOpts) -> Result<Index> {
-    let index_path = repo_root.join(format!("{}/index", git_dir_name(global_opts)));
-    if index_path.exists() {
-        let index_bytes = fs::read(index_path)?;
-        return Index::deserialize(index_bytes);
+// The tree recorded by a commit
+fn commit_tree(root: &Path, hash: &[u8; 20], git_mode: bool) -> Result<[u8; 20]> {
+    match get_object(root, hash, git_mode)? {
+        Object::Commit(commit) => Ok(commit.tree),
+        _ => bail!("fatal: {} is not a commit", hex::encode(hash))
+    }
+}
+
+// Explains that there's nothing to commit, listing the changes in the working tree that could be added
+fn print_nothing_to_commit(root: &Path, head: &Head, amend: bool, global_opts: GlobalOpts) -> Result<()> {
+    match head {
+        Head::Branch(name) => println!("On branch {}", name.strip_prefix("refs/heads/").unwrap_or(name)),
+        Head::Detached(hash) => println!("HEAD detached at {}", &hex::encode(hash)[..7])
+    }
+    if amend {
+        println!("No changes");
+        return Ok(());
+    }
+
+    let index = read_index(root, global_opts)?;
+    let mut filters = Filters::new(root, global_opts)?;
+    let mut changes = Vec::new();
+    for item in index.items.iter().filter(|x| x.stage == 0 && !x.skip_worktree) {
+        match hash_worktree_file(root, &item.path, &mut filters)? {
+            None => changes.push(("deleted", &item.path)),
+            Some(hash) if hash != item.hash => changes.push(("modified", &item.path)),
+            _ => ()
+        }
+    }
+    if !changes.is_empty() {
+        println!("Changes not staged for commit:");
+        if changes.iter().any(|(change, _)| *change == "deleted") {
+            println!("  (use \"git add/rm <file>...\" to update what will be committed)");
+        } else {
+            println!("  (use \"git add <file>...\" to update what will be committed)");
+        }
+        println!("  (use \"git restore <file>...\" to discard changes in working directory)");
+        for (change, path) in &changes {
+            println!("\t{:<12}{}", format!("{}:", change), path.to_string_lossy());
+        }
+        println!();
+        println!("no changes added to commit (use \"git add\" and/or \"git commit -a\")");
     } else {
-        return Ok(Index { version: 2, items: Vec::new() });
+        println!("nothing to commit, working tree clean");
+    }
+    Ok(())
+}
+
+/// Prints the line naming a new commit and the totals of its changes, e.g. `[master abc1234] Subject`.
+/// `head` is the state of HEAD the commit was made on, and `initial` whether that was a branch with no commits yet.
+/// When amending a commit or giving the author date, `show_date` should be set to show the author date.
+pub fn print_commit_summary(
+    root: &Path,
+    head: &Head,
+    hash: &[u8; 20],
+    commit: &Commit,
+    initial: bool,
+    show_date: bool,
+    global_opts: GlobalOpts
+) -> Result<()> {
+    let branch = match head {
+        Head::Branch(name) => name.strip_prefix("refs/heads/").unwrap_or(name).to_string(),
+        Head::Detached(_) => String::from("detached HEAD")
+    };
+    let parent = commit.parents.first();
+    let root_commit = if initial { " (root-commit)" } else { "" };
+    println!("[{}{} {}] {}", branch, root_commit, short_hash(root, hash, global_opts)?, commit.subject());
+    if show_date {
+        let author = Signature::parse(&commit.author)?;
+        println!(" Date: {}", format_date(author.timestamp, &author.timezone, DateFormat::Default));
+    }
+
+    // Git shows no totals for a merge, which changes different things relative to each parent, or for a commit
+    // that changes nothing
+    if commit.parents.len() > 1 {
+        return Ok(());
+    }
+    let stat = diff_stat(root, parent, hash, global_opts.git_mode)?;
+    if !stat.files.is_empty() {
+        println!("{}", stat.summary());
+    }
+    for line in stat.mode_changes {
+        println!("{}", line);
     }
-}
\ No newline at end of file
+    Ok(())
+}
+
+/// Builds the author or committer identity from the environment, falling back to the `user` section of the config.
+/// `role` is `AUTHOR` or `COMMITTER`, as in the names of the environment variables.
+pub fn signature(config: &Config, role: &str, global_opts: GlobalOpts) -> Result<Signature> {
+    let name = env::var(format!("GIT_{}_NAME", role)).ok().or(config.get("user.name"));
+    let email = env::var(format!("GIT_{}_EMAIL", role)).ok().or(config.get("user.email"));
+
+    let (Some(name), Some(email)) = (name, email) else {
+        let program = if global_opts.git_mode { "git" } else { "grit" };
+        let role = if role == "AUTHOR" { "Author" } else { "Committer" };
+        bail!(
+            "{} identity unknown\n\n\
+            *** Please tell me who you are.\n\n\
+            Run\n\n  \
+            {program} config --global user.email \"you@example.com\"\n  \
+            {program} config --global user.name \"Your Name\"\n\n\
+            to set your account's default identity.\n\
+            Omit --global to set the identity only in this repository.\n\n\
+            fatal: unable to auto-detect email address",
+            role
+        );
+    };
+
+    // The date can also be fixed from the environment, which makes commits reproducible
+    let (timestamp, timezone) = match env::var(format!("GIT_{}_DATE", role)) {
+        Ok(date) => parse_date(&date)?,
+        Err(_) => now_with_timezone()
+    };
+    Ok(Signature { name, email, timestamp, timezone })
+}
@@ -0,0 +1,359 @@
+// Reading and writing the commit-graph file, which caches the parents, tree and generation number of each commit.
+// See https://git-scm.com/docs/gitformat-commit-graph for the file format.
+
+use std::{collections::HashMap, env, fs, path::{Path, PathBuf}};
+use anyhow::{anyhow, bail, Result};
+use clap::{Args, Subcommand};
+use sha1::{Sha1, Digest};
+
+use crate::{GlobalOpts, repo_find};
+use crate::objects::{search_object, Object, Signature};
+use crate::refs::list_refs;
+
+const SIGNATURE: &[u8; 4] = b"CGPH";
+const NO_PARENT: u32 = 0x70000000;
+const EXTRA_EDGES: u32 = 0x80000000;
+const LAST_EDGE: u32 = 0x80000000;
+const GENERATION_OVERFLOW: u32 = 0x80000000;
+const MAX_TOPO_LEVEL: u64 = 0x3FFFFFFF;
+
+#[derive(Args)]
+pub struct CommitGraphArgs {
+    #[command(subcommand)]
+    pub command: CommitGraphCommand,
+}
+
+#[derive(Subcommand)]
+pub enum CommitGraphCommand {
+    /// Write a commit-graph file containing every commit reachable from a reference
+    Write {
+        /// Accepted for compatibility with Git. Commits are always found by walking from the references
+        #[arg(long)]
+        reachable: bool,
+    },
+}
+
+pub fn cmd_commit_graph(args: CommitGraphArgs, global_opts: GlobalOpts) -> Result<()> {
+    let path = env::current_dir().unwrap_or_else(|_| { panic!() });
+    let root = repo_find(&path, global_opts).unwrap_or_else(|| {
+        panic!("fatal: not a grit repository");
+    });
+
+    match args.command {
+        CommitGraphCommand::Write { .. } => {
+            let mut commits = HashMap::new();
+            let mut pending: Vec<[u8; 20]> = list_refs(&root, global_opts)?.into_iter().map(|(_, hash)| hash).collect();
+
+            while let Some(hash) = pending.pop() {
+                if commits.contains_key(&hash) {
+                    continue;
+                }
+                // References to objects other than commits, such as annotated tags, are followed through
+                match search_object(&root, &hash, global_opts.git_mode)? {
+                    Some(Object::Commit(c)) => {
+                        pending.extend(&c.parents);
+                        commits.insert(hash, GraphCommit {
+                            tree: c.tree,
+                            timestamp: Signature::parse(&c.committer)?.timestamp,
+                            parents: c.parents,
+                            generation: 0,
+                        });
+                    },
+                    Some(Object::Tag(t)) => pending.push(t.object),
+                    Some(_) => (),
+                    None => bail!("fatal: object {} not found", hex::encode(hash))
+                }
+            }
+
+            // Git doesn't write a file when there are no commits
+            if !commits.is_empty() {
+                write_commit_graph(&root, &commits, global_opts.git_mode)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// The details of a commit needed to walk history, as stored in the commit-graph
+#[derive(Clone, Debug)]
+pub struct GraphCommit {
+    pub tree: [u8; 20],
+    pub parents: Vec<[u8; 20]>,
+    /// The committer timestamp
+    pub timestamp: i64,
+    /// A number that is always greater than that of each of the commit's parents
+    pub generation: u64,
+}
+
+pub struct CommitGraph {
+    commits: HashMap<[u8; 20], GraphCommit>
+}
+
+impl CommitGraph {
+    pub fn get(&self, hash: &[u8; 20]) -> Option<&GraphCommit> {
+        self.commits.get(hash)
+    }
+}
+
+pub fn graph_path(root: &Path, git_mode: bool) -> PathBuf {
+    let git_dir = if git_mode { ".git" } else { ".grit" };
+    root.join(format!("{}/objects/info/commit-graph", git_dir))
+}
+
+/// Reads the repository's commit-graph file, or returns None if there isn't one
+pub fn read_commit_graph(root: &Path, git_mode: bool) -> Result<Option<CommitGraph>> {
+    let path = graph_path(root, git_mode);
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    let bytes = fs::read(&path)?;
+    let corrupt = || anyhow!("error: commit-graph file {} is corrupt", path.to_string_lossy());
+
+    if bytes.len() < 8 + 12 + 20 || &bytes[..4] != SIGNATURE {
+        return Err(corrupt());
+    }
+    if bytes[4] != 1 || bytes[5] != 1 {
+        bail!("error: commit-graph version {} or hash version {} is not supported", bytes[4], bytes[5]);
+    }
+
+    // The table of contents lists the start of each chunk, followed by an entry marking the end of the last one
+    let num_chunks = bytes[6] as usize;
+    let mut chunks = HashMap::new();
+    for i in 0..num_chunks {
+        let entry = 8 + i * 12;
+        let next = entry + 12;
+        if bytes.len() < next + 12 {
+            return Err(corrupt());
+        }
+        let id: [u8; 4] = bytes[entry..entry + 4].try_into().unwrap();
+        let start = read_u64(&bytes, entry + 4) as usize;
+        let end = read_u64(&bytes, next + 4) as usize;
+        if start > end || end > bytes.len() {
+            return Err(corrupt());
+        }
+        chunks.insert(id, &bytes[start..end]);
+    }
+
+    let oid_lookup = chunks.get(b"OIDL").ok_or_else(corrupt)?;
+    let commit_data = chunks.get(b"CDAT").ok_or_else(corrupt)?;
+    let extra_edges = chunks.get(b"EDGE").copied().unwrap_or(&[]);
+    let generation_data = chunks.get(b"GDA2").copied();
+    let generation_overflow = chunks.get(b"GDO2").copied().unwrap_or(&[]);
+
+    let count = oid_lookup.len() / 20;
+    if commit_data.len() < count * 36 {
+        return Err(corrupt());
+    }
+    let hashes: Vec<[u8; 20]> = (0..count).map(|i| oid_lookup[i * 20..i * 20 + 20].try_into().unwrap()).collect();
+    let hash_at = |position: u32| hashes.get(position as usize).copied().ok_or_else(corrupt);
+
+    let mut commits = HashMap::new();
+    for (i, hash) in hashes.iter().enumerate() {
+        let data = &commit_data[i * 36..i * 36 + 36];
+        let tree = data[..20].try_into().unwrap();
+
+        let mut parents = Vec::new();
+        let first = read_u32(data, 20);
+        let second = read_u32(data, 24);
+        if first != NO_PARENT {
+            parents.push(hash_at(first)?);
+        }
+        if second & EXTRA_EDGES != 0 {
+            // Octopus merges list their second and later parents in the extra edges chunk
+            let mut edge = (second & !EXTRA_EDGES) as usize;
+            loop {
+                if extra_edges.len() < (edge + 1) * 4 {
+                    return Err(corrupt());
+                }
+                let value = read_u32(extra_edges, edge * 4);
+                parents.push(hash_at(value & !LAST_EDGE)?);
+                if value & LAST_EDGE != 0 {
+                    break;
+                }
+                edge += 1;
+            }
+        } else if second != NO_PARENT {
+            parents.push(hash_at(second)?);
+        }
+
+        // The top 30 bits hold the topological level and the remaining 34 the commit time
+        let packed = read_u64(data, 28);
+        let topo_level = packed >> 34;
+        let timestamp = (packed & 0x3_FFFF_FFFF) as i64;
+
+        // Newer files also store the corrected commit date, which is a better generation number
+        let generation = match generation_data {
+            Some(offsets) => {
+                let offset = read_u32(offsets, i * 4);
+                let offset = if offset & GENERATION_OVERFLOW != 0 {
+                    let position = (offset & !GENERATION_OVERFLOW) as usize;
+                    if generation_overflow.len() < (position + 1) * 8 {
+                        return Err(corrupt());
+                    }
+                    read_u64(generation_overflow, position * 8)
+                } else {
+                    offset as u64
+                };
+                timestamp as u64 + offset
+            },
+            None => topo_level
+        };
+
+        commits.insert(*hash, GraphCommit { tree, parents, timestamp, generation });
+    }
+
+    Ok(Some(CommitGraph { commits }))
+}
+
+/// Writes a commit-graph file describing the given commits, replacing any existing one.
+/// Every parent of a commit must also be in the map.
+pub fn write_commit_graph(root: &Path, commits: &HashMap<[u8; 20], GraphCommit>, git_mode: bool) -> Result<()> {
+    let mut hashes: Vec<[u8; 20]> = commits.keys().copied().collect();
+    hashes.sort();
+    let positions: HashMap<[u8; 20], u32> = hashes.iter().enumerate().map(|(i, x)| (*x, i as u32)).collect();
+    let position = |hash: &[u8; 20]| positions.get(hash).copied()
+        .ok_or(anyhow!("fatal: commit {} is missing from the commit-graph", hex::encode(hash)));
+
+    // Each commit's topological level and corrected date depend on those of its parents
+    let mut topo_levels = HashMap::new();
+    let mut corrected_dates = HashMap::new();
+    for hash in &hashes {
+        compute_generations(hash, commits, &mut topo_levels, &mut corrected_dates)?;
+    }
+
+    let mut fanout = Vec::new();
+    for byte in 0..256 {
+        let count = hashes.iter().filter(|x| (x[0] as usize) <= byte).count() as u32;
+        fanout.extend(count.to_be_bytes());
+    }
+
+    let oid_lookup: Vec<u8> = hashes.concat();
+
+    let mut commit_data = Vec::new();
+    let mut extra_edges = Vec::new();
+    let mut generation_data = Vec::new();
+    let mut generation_overflow = Vec::new();
+    for hash in &hashes {
+        let commit = &commits[hash];
+        commit_data.extend(commit.tree);
+
+        let first = match commit.parents.first() {
+            Some(x) => position(x)?,
+            None => NO_PARENT
+        };
+        let second = match commit.parents.len() {
+            0 | 1 => NO_PARENT,
+            2 => position(&commit.parents[1])?,
+            _ => {
+                let start = (extra_edges.len() / 4) as u32;
+                let rest = &commit.parents[1..];
+                for (i, parent) in rest.iter().enumerate() {
+                    let mut value = position(parent)?;
+                    if i == rest.len() - 1 {
+                        value |= LAST_EDGE;
+                    }
+                    extra_edges.extend(value.to_be_bytes());
+                }
+                start | EXTRA_EDGES
+            }
+        };
+        commit_data.extend(first.to_be_bytes());
+        commit_data.extend(second.to_be_bytes());
+
+        let timestamp = commit.timestamp as u64 & 0x3_FFFF_FFFF;
+        let packed = (topo_levels[hash] << 34) | timestamp;
+        commit_data.extend(packed.to_be_bytes());
+
+        let offset = corrected_dates[hash] - commit.timestamp as u64;
+        if offset > (!GENERATION_OVERFLOW) as u64 {
+            let index = (generation_overflow.len() / 8) as u32;
+            generation_data.extend((index | GENERATION_OVERFLOW).to_be_bytes());
+            generation_overflow.extend(offset.to_be_bytes());
+        } else {
+            generation_data.extend((offset as u32).to_be_bytes());
+        }
+    }
+
+    let mut chunks: Vec<(&[u8; 4], Vec<u8>)> = vec![
+        (b"OIDF", fanout),
+        (b"OIDL", oid_lookup),
+        (b"CDAT", commit_data),
+        (b"GDA2", generation_data),
+    ];
+    if !generation_overflow.is_empty() {
+        chunks.push((b"GDO2", generation_overflow));
+    }
+    if !extra_edges.is_empty() {
+        chunks.push((b"EDGE", extra_edges));
+    }
+
+    let mut bytes = Vec::new();
+    bytes.extend(SIGNATURE);
+    bytes.extend([1, 1, chunks.len() as u8, 0]);
+
+    let mut offset = (8 + (chunks.len() + 1) * 12) as u64;
+    for (id, data) in &chunks {
+        bytes.extend(*id);
+        bytes.extend(offset.to_be_bytes());
+        offset += data.len() as u64;
+    }
+    bytes.extend([0; 4]);
+    bytes.extend(offset.to_be_bytes());
+
+    for (_, data) in &chunks {
+        bytes.extend(data);
+    }
+
+    let checksum: [u8; 20] = Sha1::digest(&bytes).into();
+    bytes.extend(checksum);
+
+    let path = graph_path(root, git_mode);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, bytes)?;
+    Ok(())
+}
+
+// Computes the topological level and corrected commit date of a commit and all its ancestors.
+// Uses an explicit stack rather than recursion, since histories can be very long.
+fn compute_generations(
+    start: &[u8; 20],
+    commits: &HashMap<[u8; 20], GraphCommit>,
+    topo_levels: &mut HashMap<[u8; 20], u64>,
+    corrected_dates: &mut HashMap<[u8; 20], u64>
+) -> Result<()> {
+    let mut stack = vec![*start];
+    while let Some(hash) = stack.last().copied() {
+        if topo_levels.contains_key(&hash) {
+            stack.pop();
+            continue;
+        }
+
+        let commit = commits.get(&hash)
+            .ok_or(anyhow!("fatal: commit {} is missing from the commit-graph", hex::encode(hash)))?;
+        let pending: Vec<[u8; 20]> = commit.parents.iter().filter(|x| !topo_levels.contains_key(*x)).copied().collect();
+        if !pending.is_empty() {
+            stack.extend(pending);
+            continue;
+        }
+
+        let topo_level = commit.parents.iter().map(|x| topo_levels[x]).max().unwrap_or(0) + 1;
+        let corrected_date = commit.parents.iter().map(|x| corrected_dates[x] + 1).max().unwrap_or(0)
+            .max(commit.timestamp as u64);
+        topo_levels.insert(hash, topo_level.min(MAX_TOPO_LEVEL));
+        corrected_dates.insert(hash, corrected_date);
+        stack.pop();
+    }
+    Ok(())
+}
+
+fn read_u32(bytes: &[u8], pos: usize) -> u32 {
+    u32::from_be_bytes(bytes[pos..pos + 4].try_into().unwrap())
+}
+
+fn read_u64(bytes: &[u8], pos: usize) -> u64 {
+    u64::from_be_bytes(bytes[pos..pos + 8].try_into().unwrap())
+}
@@ -0,0 +1,588 @@
+// Reading and writing configuration files
+
+use std::{env, fs, path::{Path, PathBuf}, process};
+use anyhow::{anyhow, bail, Result};
+use clap::{Args, ValueEnum};
+use regex::Regex;
+
+use crate::{GlobalOpts, repo_find, git_dir_name};
+use crate::ignore::wildmatch;
+
+// Like Git, includes are only followed so deep, so that a file that includes itself can't go on forever
+const MAX_INCLUDE_DEPTH: usize = 10;
+
+#[derive(Args)]
+pub struct ConfigArgs {
+    /// Print every value the option is given, one to a line, rather than only the last
+    #[arg(long, conflicts_with_all = ["get_regexp", "add"])]
+    pub get_all: bool,
+    /// Print the name and value of every option whose name matches the regular expression given in place of a name
+    #[arg(long, conflicts_with = "add")]
+    pub get_regexp: bool,
+    /// Give the option another value, keeping those it already has, as `remote.<name>.fetch` can have several
+    #[arg(long)]
+    pub add: bool,
+    /// Read and write values as booleans, printed as `true` or `false` however they're written
+    #[arg(long, conflicts_with = "value_type")]
+    pub bool: bool,
+    /// The type of the values read and written
+    #[arg(long = "type", value_enum)]
+    pub value_type: Option<ValueType>,
+    /// The name of the option, in the form `section.key` or `section.subsection.key`
+    pub name: String,
+    /// The value to set. If omitted, the current value is printed
+    pub value: Option<String>,
+}
+
+/// The types a value can be given with `--type`
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ValueType {
+    Bool,
+}
+
+pub fn cmd_config(args: ConfigArgs, global_opts: GlobalOpts) -> Result<()> {
+    let path = env::current_dir().unwrap_or_else(|_| { panic!() });
+    let root = repo_find(&path, global_opts).unwrap_or_else(|| {
+        panic!("fatal: not a grit repository");
+    });
+    let config_path = repo_config_path(&root, global_opts);
+    let as_bool = args.bool || args.value_type == Some(ValueType::Bool);
+
+    if args.get_all || args.get_regexp {
+        if args.value.is_some() {
+            bail!("fatal: value patterns are not supported");
+        }
+        let config = Config::load(&root, global_opts)?;
+        let entries = match args.get_regexp {
+            true => {
+                let pattern = Regex::new(&name_pattern(&args.name)).unwrap_or_else(|_| {
+                    eprintln!("error: invalid key pattern: {}", args.name);
+                    process::exit(6);
+                });
+                config.get_regexp(&pattern)
+            },
+            false => config.get_all(&args.name)
+        };
+        if entries.is_empty() {
+            process::exit(1);
+        }
+        for entry in entries {
+            // Like Git, a key written without `=` is shown without a value unless it's read as a boolean
+            match (shown_value(entry, as_bool)?, args.get_regexp) {
+                (Some(value), true) => println!("{} {}", entry.name(), value),
+                (None, true) => println!("{}", entry.name()),
+                (value, false) => println!("{}", value.unwrap_or_default())
+            }
+        }
+        return Ok(());
+    }
+
+    let value = match args.value {
+        Some(value) if as_bool => Some(parse_bool(&args.name, Some(&value))?.to_string()),
+        value => value
+    };
+    match value {
+        Some(value) if args.add => add_value(&config_path, &args.name, &value),
+        Some(value) => {
+            // Only one of the values of an option given more than once could be replaced, so like Git, none are
+            if count_values(&config_path, &args.name)? > 1 {
+                eprintln!("warning: {} has multiple values", args.name);
+                eprintln!("error: cannot overwrite multiple values with a single value");
+                eprintln!("       Use a regexp, --add or --replace-all to change {}.", args.name);
+                process::exit(5);
+            }
+            set_value(&config_path, &args.name, &value)
+        },
+        None if args.add => {
+            eprintln!("error: wrong number of arguments, should be 2");
+            process::exit(129);
+        },
+        None => {
+            // Like Git, a missing option is reported only through the exit status
+            match Config::load(&root, global_opts)?.get_all(&args.name).last() {
+                Some(entry) => println!("{}", shown_value(entry, as_bool)?.unwrap_or_default()),
+                None => process::exit(1)
+            }
+            Ok(())
+        }
+    }
+}
+
+// The value of an entry as `config` prints it, which is None for a key written without `=` unless it's read as a
+// boolean
+fn shown_value(entry: &ConfigEntry, as_bool: bool) -> Result<Option<String>> {
+    match as_bool {
+        true => Ok(Some(parse_bool(&entry.name(), entry.value.as_deref())?.to_string())),
+        false => Ok(entry.value.clone())
+    }
+}
+
+// Reads the value of the boolean option with the given name, which Git allows to be written as yes/no, on/off,
+// true/false or 1/0, or as a key without `=`, which means true
+fn parse_bool(name: &str, value: Option<&str>) -> Result<bool> {
+    let Some(value) = value else {
+        return Ok(true);
+    };
+    match value.to_lowercase().as_str() {
+        "true" | "yes" | "on" | "1" => Ok(true),
+        "false" | "no" | "off" | "0" | "" => Ok(false),
+        _ => bail!("fatal: bad boolean config value '{}' for '{}'", value, name)
+    }
+}
+
+/// A single `key = value` line of a configuration file
+#[derive(Clone, Debug)]
+pub struct ConfigEntry {
+    /// The section name, in lowercase
+    pub section: String,
+    /// The subsection name, which is case-sensitive, e.g. `origin` in `[remote "origin"]`
+    pub subsection: Option<String>,
+    /// The key name, in lowercase
+    pub key: String,
+    /// The value, or None for a key written without `=`, which means true
+    pub value: Option<String>,
+}
+
+impl ConfigEntry {
+    /// The full name of the option, e.g. `remote.origin.fetch`
+    pub fn name(&self) -> String {
+        match &self.subsection {
+            Some(subsection) => format!("{}.{}.{}", self.section, subsection, self.key),
+            None => format!("{}.{}", self.section, self.key)
+        }
+    }
+}
+
+/// The configuration of a repository, combining the user's global configuration with the repository's own
+pub struct Config {
+    entries: Vec<ConfigEntry>
+}
+
+impl Config {
+    /// Reads `~/.gitconfig` followed by the repository's config file, so the repository's values take precedence.
+    /// The files they include are read in place of their `include.path` and `includeIf.<condition>.path` options.
+    pub fn load(root: &Path, global_opts: GlobalOpts) -> Result<Config> {
+        let mut files = Vec::new();
+        if let Some(home) = env::var_os("HOME") {
+            files.push(PathBuf::from(home).join(".gitconfig"));
+        }
+        files.push(repo_config_path(root, global_opts));
+        Config::read_files(&files, Some(&root.join(git_dir_name(global_opts))))
+    }
+
+    /// Reads only `~/.gitconfig`, for use before there is a repository
+    pub fn load_global() -> Result<Config> {
+        match env::var_os("HOME") {
+            Some(home) => Config::read_files(&[PathBuf::from(home).join(".gitconfig")], None),
+            None => Ok(Config { entries: Vec::new() })
+        }
+    }
+
+    // Reads the files that exist in order, with the git directory of the repository, if there is one, that
+    // conditional includes are checked against
+    fn read_files(files: &[PathBuf], git_dir: Option<&Path>) -> Result<Config> {
+        let mut entries = Vec::new();
+        for file in files {
+            read_file(file, git_dir, 0, &mut entries)?;
+        }
+        Ok(Config { entries })
+    }
+
+    /// Returns the last value given for the option with the given name, e.g. `diff.pdf.textconv`
+    pub fn get(&self, name: &str) -> Option<String> {
+        self.get_all(name).last().map(|x| x.value.clone().unwrap_or(String::from("true")))
+    }
+
+    /// Returns every entry for the option with the given name, in the order they were read, for options such as
+    /// `remote.<name>.fetch` that can be given more than once
+    pub fn get_all(&self, name: &str) -> Vec<&ConfigEntry> {
+        let Ok((section, subsection, key)) = split_name(name) else {
+            return Vec::new();
+        };
+        self.entries.iter()
+            .filter(|x| x.section == section && x.subsection == subsection && x.key == key)
+            .collect()
+    }
+
+    /// Returns every entry whose full name, as `ConfigEntry::name` gives it, matches the regular expression
+    pub fn get_regexp(&self, pattern: &Regex) -> Vec<&ConfigEntry> {
+        self.entries.iter().filter(|x| pattern.is_match(&x.name())).collect()
+    }
+
+    /// Returns the names of the subsections of a section, in the order they first appear, e.g. the names of the
+    /// remotes for `remote`
+    pub fn subsections(&self, section: &str) -> Vec<String> {
+        let mut names: Vec<String> = Vec::new();
+        for entry in self.entries.iter().filter(|x| x.section == section) {
+            if let Some(name) = &entry.subsection {
+                if !names.contains(name) {
+                    names.push(name.clone());
+                }
+            }
+        }
+        names
+    }
+
+    /// Returns the value of a boolean option, which Git allows to be written as yes/no, on/off, true/false or 1/0
+    pub fn get_bool(&self, name: &str) -> Result<Option<bool>> {
+        self.get_all(name).last().map(|x| parse_bool(name, x.value.as_deref())).transpose()
+    }
+
+    /// Whether paths that differ only in case are taken to name the same file, as they do on case-insensitive
+    /// filesystems. Set by `core.ignoreCase`, which defaults to true on Windows and macOS, whose filesystems usually
+    /// ignore case.
+    pub fn ignore_case(&self) -> Result<bool> {
+        Ok(self.get_bool("core.ignorecase")?.unwrap_or(cfg!(any(windows, target_os = "macos"))))
+    }
+}
+
+pub fn repo_config_path(root: &Path, global_opts: GlobalOpts) -> PathBuf {
+    root.join(git_dir_name(global_opts)).join("config")
+}
+
+// Adds the entries of a configuration file if it exists, each followed by the entries of any file it includes.
+// `depth` is the number of includes that led to the file.
+fn read_file(file: &Path, git_dir: Option<&Path>, depth: usize, entries: &mut Vec<ConfigEntry>) -> Result<()> {
+    if !file.is_file() {
+        return Ok(());
+    }
+    let text = fs::read_to_string(file)?;
+    let file_entries = parse_config(&text)
+        .map_err(|e| anyhow!("fatal: bad config file {}: {}", file.to_string_lossy(), e))?;
+    for entry in file_entries {
+        let included = included_file(&entry, file, git_dir);
+        entries.push(entry);
+        if let Some(included) = included {
+            if depth == MAX_INCLUDE_DEPTH {
+                bail!(
+                    "fatal: exceeded maximum include depth ({}) while including\n\t{}\nfrom\n\t{}\n\
+                    This might be due to circular includes.",
+                    MAX_INCLUDE_DEPTH, included.to_string_lossy(), file.to_string_lossy()
+                );
+            }
+            read_file(&included, git_dir, depth + 1, entries)?;
+        }
+    }
+    Ok(())
+}
+
+// The file an entry of `file` includes, if it's `include.path`, or `includeIf.<condition>.path` with a condition
+// that holds. As in Git, a relative path is taken from the directory of the file including it.
+fn included_file(entry: &ConfigEntry, file: &Path, git_dir: Option<&Path>) -> Option<PathBuf> {
+    let included = match (entry.section.as_str(), &entry.subsection, entry.key.as_str()) {
+        ("include", None, "path") => entry.value.as_ref()?,
+        ("includeif", Some(condition), "path") if include_condition_holds(condition, file, git_dir) => {
+            entry.value.as_ref()?
+        },
+        _ => return None
+    };
+    Some(expand_path(included, file))
+}
+
+// Expands a leading `~/` to the home directory, and makes a relative path relative to the directory of `file`
+fn expand_path(path: &str, file: &Path) -> PathBuf {
+    if let (Some(rest), Some(home)) = (path.strip_prefix("~/"), env::var_os("HOME")) {
+        return PathBuf::from(home).join(rest);
+    }
+    file.parent().unwrap_or(Path::new("")).join(path)
+}
+
+// Whether the condition of an `includeIf` section holds. Git's conditions on the git directory, `gitdir:` and the
+// case-insensitive `gitdir/i:`, and on the branch checked out, `onbranch:`, are understood, and any other condition
+// is taken not to hold. Neither holds outside a repository.
+fn include_condition_holds(condition: &str, file: &Path, git_dir: Option<&Path>) -> bool {
+    let Some(git_dir) = git_dir else {
+        return false;
+    };
+    if let Some(pattern) = condition.strip_prefix("gitdir:") {
+        git_dir_matches(pattern, file, git_dir, false)
+    } else if let Some(pattern) = condition.strip_prefix("gitdir/i:") {
+        git_dir_matches(pattern, file, git_dir, true)
+    } else if let Some(pattern) = condition.strip_prefix("onbranch:") {
+        // Only a branch that HEAD refers to counts, not a detached HEAD
+        let Ok(head) = fs::read_to_string(git_dir.join("HEAD")) else {
+            return false;
+        };
+        let Some(branch) = head.trim_end().strip_prefix("ref: refs/heads/") else {
+            return false;
+        };
+        let pattern = if pattern.ends_with('/') { format!("{}**", pattern) } else { pattern.to_string() };
+        wildmatch(pattern.as_bytes(), branch.as_bytes())
+    } else {
+        false
+    }
+}
+
+// Whether the git directory matches the pattern of a `gitdir:` condition. As in Git, the pattern may start with
+// `~/` for the home directory or `./` for the directory of the file including it, a pattern that isn't then
+// absolute can match at any depth, and one ending with `/` matches everything within the directory.
+fn git_dir_matches(pattern: &str, file: &Path, git_dir: &Path, ignore_case: bool) -> bool {
+    let mut pattern = match pattern.strip_prefix("./") {
+        Some(rest) => expand_path(rest, file).to_string_lossy().to_string(),
+        None => expand_path(pattern, Path::new("")).to_string_lossy().to_string()
+    };
+    if !Path::new(&pattern).is_absolute() {
+        pattern = format!("**/{}", pattern);
+    }
+    if pattern.ends_with('/') {
+        pattern += "**";
+    }
+    if ignore_case {
+        pattern = pattern.to_lowercase();
+    }
+    // The git directory is tried with any symbolic links resolved, and then as it's given
+    [fs::canonicalize(git_dir).ok(), Some(git_dir.to_path_buf())].into_iter().flatten().any(|dir| {
+        let dir = dir.to_string_lossy();
+        let dir = if ignore_case { dir.to_lowercase() } else { dir.to_string() };
+        wildmatch(pattern.as_bytes(), dir.as_bytes())
+    })
+}
+
+/// Checks that grit understands the format of the repository: `core.repositoryformatversion` must be 0 or 1, and a
+/// version 1 repository may only use the extensions grit supports. Like Git, extensions are ignored in version 0.
+pub fn check_repository_format(root: &Path, global_opts: GlobalOpts) -> Result<()> {
+    let path = repo_config_path(root, global_opts);
+    if !path.is_file() {
+        return Ok(());
+    }
+    let entries = parse_config(&fs::read_to_string(&path)?)
+        .map_err(|e| anyhow!("fatal: bad config file {}: {}", path.to_string_lossy(), e))?;
+    let value = |section: &str, key: &str| entries.iter().rev()
+        .find(|x| x.section == section && x.subsection.is_none() && x.key == key)
+        .map(|x| x.value.clone().unwrap_or_default());
+
+    let version = match value("core", "repositoryformatversion") {
+        Some(version) => version.trim().parse::<u32>()
+            .map_err(|_| anyhow!("fatal: bad numeric config value '{}' for 'core.repositoryformatversion'", version))?,
+        None => 0
+    };
+    if version > 1 {
+        bail!("fatal: Expected git repo version <= 1, found {}", version);
+    }
+    if version == 0 {
+        return Ok(());
+    }
+
+    // Only SHA-1 object names are supported
+    let mut unknown: Vec<&str> = Vec::new();
+    for entry in entries.iter().filter(|x| x.section == "extensions" && x.subsection.is_none()) {
+        let supported = match entry.key.as_str() {
+            "noop" => true,
+            "objectformat" => value("extensions", "objectformat").is_some_and(|x| x.eq_ignore_ascii_case("sha1")),
+            _ => false
+        };
+        if !supported && !unknown.contains(&entry.key.as_str()) {
+            unknown.push(&entry.key);
+        }
+    }
+    if !unknown.is_empty() {
+        let noun = if unknown.len() == 1 { "extension" } else { "extensions" };
+        bail!("fatal: unknown repository {} found:\n\t{}", noun, unknown.join("\n\t"));
+    }
+    Ok(())
+}
+
+// Lowercases the parts of a pattern for option names before the first `.` and after the last, as Git does, so that
+// they match the section and key names, which are stored in lowercase
+fn name_pattern(pattern: &str) -> String {
+    match (pattern.find('.'), pattern.rfind('.')) {
+        (Some(first), Some(last)) => {
+            format!("{}{}{}", pattern[..first].to_lowercase(), &pattern[first..last], pattern[last..].to_lowercase())
+        },
+        _ => pattern.to_lowercase()
+    }
+}
+
+// Splits an option name into its lowercase section, subsection and lowercase key
+fn split_name(name: &str) -> Result<(String, Option<String>, String)> {
+    let (section, rest) = name.split_once('.').ok_or(anyhow!("error: key does not contain a section: {}", name))?;
+    let (subsection, key) = match rest.rsplit_once('.') {
+        Some((subsection, key)) => (Some(subsection.to_string()), key),
+        None => (None, rest)
+    };
+    if section.is_empty() || key.is_empty() {
+        bail!("error: invalid key: {}", name);
+    }
+    Ok((section.to_lowercase(), subsection, key.to_lowercase()))
+}
+
+/// Parses the text of a configuration file into its entries, in the order they appear
+pub fn parse_config(text: &str) -> Result<Vec<ConfigEntry>> {
+    let mut entries = Vec::new();
+    let mut section: Option<(String, Option<String>)> = None;
+
+    // A backslash at the end of a line continues the value onto the next line
+    let text = text.replace("\\\r\n", "").replace("\\\n", "");
+
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+
+        if line.starts_with('[') {
+            section = Some(parse_section_header(line).map_err(|e| anyhow!("line {}: {}", i + 1, e))?);
+            continue;
+        }
+
+        let (section, subsection) = section.clone().ok_or(anyhow!("line {}: option outside of a section", i + 1))?;
+        let (key, value) = match line.split_once('=') {
+            Some((key, value)) => (key.trim(), Some(parse_value(value)?)),
+            None => (line, None)
+        };
+        entries.push(ConfigEntry { section, subsection, key: key.to_lowercase(), value });
+    }
+
+    Ok(entries)
+}
+
+// Parses a line such as `[remote "origin"]` into the lowercase section name and the subsection name
+fn parse_section_header(line: &str) -> Result<(String, Option<String>)> {
+    let header = line.strip_prefix('[').unwrap_or(line);
+    let end = header.rfind(']').ok_or(anyhow!("section header not terminated"))?;
+    let header = &header[..end];
+
+    match header.split_once(' ') {
+        Some((name, subsection)) => {
+            let subsection = subsection.trim();
+            let subsection = subsection.strip_prefix('"').and_then(|x| x.strip_suffix('"'))
+                .ok_or(anyhow!("subsection name must be quoted"))?;
+            Ok((name.to_lowercase(), Some(subsection.replace("\\\"", "\"").replace("\\\\", "\\"))))
+        },
+        // The older `[section.subsection]` syntax is also accepted
+        None => match header.split_once('.') {
+            Some((name, subsection)) => Ok((name.to_lowercase(), Some(subsection.to_string()))),
+            None => Ok((header.to_lowercase(), None))
+        }
+    }
+}
+
+// Removes comments and surrounding whitespace from a value, interpreting quotes and escape sequences
+fn parse_value(raw: &str) -> Result<String> {
+    let mut value = String::new();
+    let mut in_quotes = false;
+    // Whitespace is only kept if something other than whitespace or a comment follows it
+    let mut pending_space = String::new();
+
+    let mut chars = raw.trim_start().chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                value.push_str(&pending_space);
+                pending_space.clear();
+                in_quotes = !in_quotes;
+            },
+            '#' | ';' if !in_quotes => break,
+            '\\' => {
+                value.push_str(&pending_space);
+                pending_space.clear();
+                match chars.next() {
+                    Some('n') => value.push('\n'),
+                    Some('t') => value.push('\t'),
+                    Some('b') => { value.pop(); },
+                    Some(x @ ('"' | '\\')) => value.push(x),
+                    _ => bail!("invalid escape sequence in value")
+                }
+                continue;
+            },
+            c if c.is_whitespace() && !in_quotes => {
+                pending_space.push(c);
+                continue;
+            },
+            c => {
+                value.push_str(&pending_space);
+                pending_space.clear();
+                value.push(c);
+            }
+        }
+    }
+
+    if in_quotes {
+        bail!("value has an unterminated quote");
+    }
+    Ok(value)
+}
+
+/// Sets the option with the given name in the configuration file at `path`, in the same layout Git uses.
+/// The last existing entry for the option is replaced, or else the option is added to the end of its section.
+pub fn set_value(path: &Path, name: &str, value: &str) -> Result<()> {
+    write_value(path, name, value, false)
+}
+
+/// Adds an entry for the option with the given name to the end of its section in the configuration file at `path`,
+/// keeping any it already has
+pub fn add_value(path: &Path, name: &str, value: &str) -> Result<()> {
+    write_value(path, name, value, true)
+}
+
+/// The number of entries for the option with the given name in the configuration file at `path`, not counting any
+/// in the files it includes
+pub fn count_values(path: &Path, name: &str) -> Result<usize> {
+    if !path.is_file() {
+        return Ok(0);
+    }
+    let (section, subsection, key) = split_name(name)?;
+    let entries = parse_config(&fs::read_to_string(path)?)
+        .map_err(|e| anyhow!("fatal: bad config file {}: {}", path.to_string_lossy(), e))?;
+    Ok(entries.iter().filter(|x| x.section == section && x.subsection == subsection && x.key == key).count())
+}
+
+// Writes an entry for the option, replacing the last one it has unless `add` is given
+fn write_value(path: &Path, name: &str, value: &str, add: bool) -> Result<()> {
+    let (section, subsection, key) = split_name(name)?;
+    let text = if path.exists() { fs::read_to_string(path)? } else { String::new() };
+    let mut lines: Vec<String> = text.lines().map(String::from).collect();
+
+    // Find the last line of the matching section, and any existing entry for the key within it
+    let mut in_section = false;
+    let mut section_end = None;
+    let mut existing = None;
+    for (i, line) in lines.iter().enumerate() {
+        let trimmed = line.trim();
+        if trimmed.starts_with('[') {
+            in_section = parse_section_header(trimmed).map(|x| x == (section.clone(), subsection.clone())).unwrap_or(false);
+            if in_section {
+                section_end = Some(i);
+            }
+            continue;
+        }
+        if in_section {
+            section_end = Some(i);
+            let line_key = trimmed.split('=').next().unwrap_or("").trim().to_lowercase();
+            if line_key == key && !add {
+                existing = Some(i);
+            }
+        }
+    }
+
+    let key_name = name.rsplit('.').next().unwrap_or(&key);
+    let entry = format!("\t{} = {}", key_name, quote_value(value));
+    match (existing, section_end) {
+        (Some(i), _) => lines[i] = entry,
+        (None, Some(i)) => lines.insert(i + 1, entry),
+        (None, None) => {
+            let header = match &subsection {
+                Some(x) => format!("[{} \"{}\"]", section, x.replace('\\', "\\\\").replace('"', "\\\"")),
+                None => format!("[{}]", section)
+            };
+            lines.push(header);
+            lines.push(entry);
+        }
+    }
+
+    let mut output = lines.join("\n");
+    output.push('\n');
+    fs::write(path, output)?;
+    Ok(())
+}
+
+// Quotes a value if it would otherwise be read back differently
+fn quote_value(value: &str) -> String {
+    let escaped = value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n").replace('\t', "\\t");
+    let needs_quotes = value.starts_with(' ') || value.ends_with(' ') || value.contains(['#', ';']);
+    if needs_quotes {
+        format!("\"{}\"", escaped)
+    } else {
+        escaped
+    }
+}
@@ -0,0 +1,119 @@
+// Count unpacked objects and their disk consumption
+
+use std::{collections::HashSet, env, fs, path::Path};
+use anyhow::Result;
+use clap::Args;
+
+use crate::{GlobalOpts, repo_find, git_dir_name, pack::read_index_hashes};
+
+#[derive(Args)]
+pub struct CountObjectsArgs {
+    /// Also report packed objects, packs, and garbage in the object store
+    #[arg(short, long)]
+    pub verbose: bool,
+}
+
+pub fn cmd_count_objects(args: CountObjectsArgs, global_opts: GlobalOpts) -> Result<()> {
+    let path = env::current_dir().unwrap_or_else(|_| { panic!() });
+    let root = repo_find(&path, global_opts).unwrap_or_else(|| {
+        panic!("fatal: not a grit repository");
+    });
+
+    let objects_dir = root.join(format!("{}/objects", git_dir_name(global_opts)));
+
+    let mut loose = Vec::new();
+    let mut loose_size = 0;
+    let mut garbage = Vec::new();
+    let mut garbage_size = 0;
+
+    // Loose objects are stored under a directory named by the first two hex digits of their hash.
+    // Other directories are not part of the store, but unexpected files within these directories are garbage.
+    for entry in fs::read_dir(&objects_dir)? {
+        let entry = entry?;
+        let dir_name = entry.file_name().to_string_lossy().to_string();
+        if dir_name.len() != 2 || !is_hex(&dir_name) || !entry.file_type()?.is_dir() {
+            continue;
+        }
+
+        for file in fs::read_dir(entry.path())? {
+            let file = file?;
+            let file_name = file.file_name().to_string_lossy().to_string();
+            if file_name.len() == 38 && is_hex(&file_name) {
+                loose.push(dir_name.clone() + &file_name);
+                loose_size += disk_usage(&file.path())?;
+            } else {
+                garbage_size += file.metadata()?.len();
+                garbage.push(file.path());
+            }
+        }
+    }
+
+    let mut packs = 0;
+    let mut packed = HashSet::new();
+    let mut pack_size = 0;
+
+    let pack_dir = objects_dir.join("pack");
+    if pack_dir.is_dir() {
+        for entry in fs::read_dir(&pack_dir)? {
+            let file = entry?.path();
+            let extension = file.extension().map(|x| x.to_string_lossy().to_string()).unwrap_or_default();
+            let size = fs::metadata(&file)?.len();
+
+            match extension.as_str() {
+                "pack" if file.with_extension("idx").exists() => {
+                    packs += 1;
+                    pack_size += size;
+                    for hash in read_index_hashes(&file.with_extension("idx"))? {
+                        packed.insert(hex::encode(hash));
+                    }
+                },
+                "idx" if file.with_extension("pack").exists() => pack_size += size,
+                "keep" | "bitmap" | "rev" | "promisor" | "mtimes" => (),
+                _ => {
+                    garbage.push(file);
+                    garbage_size += size;
+                }
+            }
+        }
+    }
+
+    if args.verbose {
+        for file in &garbage {
+            let display = file.strip_prefix(&root).unwrap_or(file);
+            eprintln!("warning: garbage found: {}", display.to_string_lossy());
+        }
+
+        let prune_packable = loose.iter().filter(|x| packed.contains(*x)).count();
+        println!("count: {}", loose.len());
+        println!("size: {}", loose_size / 1024);
+        println!("in-pack: {}", packed.len());
+        println!("packs: {}", packs);
+        println!("size-pack: {}", pack_size / 1024);
+        println!("prune-packable: {}", prune_packable);
+        println!("garbage: {}", garbage.len());
+        println!("size-garbage: {}", garbage_size / 1024);
+    } else {
+        println!("{} objects, {} kilobytes", loose.len(), loose_size / 1024);
+    }
+
+    Ok(())
+}
+
+fn is_hex(name: &str) -> bool {
+    name.chars().all(|c| c.is_ascii_hexdigit() && !c.is_ascii_uppercase())
+}
+
+// Returns the space the file takes up on disk, which may be more than its length.
+// Git reports loose objects by this measure, but packs and garbage by their length.
+fn disk_usage(path: &Path) -> Result<u64> {
+    let metadata = fs::metadata(path)?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        Ok(metadata.blocks() * 512)
+    }
+
+    #[cfg(not(unix))]
+    Ok(metadata.len())
+}
@@ -0,0 +1,140 @@
+// Walking the history of commits
+
+use std::{collections::HashSet, path::{Path, PathBuf}};
+use anyhow::{bail, Result};
+
+use crate::commit_graph::{read_commit_graph, CommitGraph, GraphCommit};
+use crate::objects::{get_object, Object, Signature};
+
+/// Looks up the parents of commits, using the commit-graph where possible
+/// and falling back to reading the commit objects for commits it doesn't contain.
+pub struct CommitDag {
+    root: PathBuf,
+    git_mode: bool,
+    graph: Option<CommitGraph>,
+}
+
+impl CommitDag {
+    pub fn open(root: &Path, git_mode: bool) -> Result<CommitDag> {
+        Ok(CommitDag {
+            root: root.to_path_buf(),
+            git_mode,
+            graph: read_commit_graph(root, git_mode)?,
+        })
+    }
+
+    /// Returns the parents, tree and timestamp of a commit. The generation number is
+    /// only known for commits in the commit-graph, and is zero otherwise.
+    pub fn commit(&self, hash: &[u8; 20]) -> Result<GraphCommit> {
+        if let Some(commit) = self.graph.as_ref().and_then(|x| x.get(hash)) {
+            return Ok(commit.clone());
+        }
+
+        match get_object(&self.root, hash, self.git_mode)? {
+            Object::Commit(c) => Ok(GraphCommit {
+                tree: c.tree,
+                timestamp: Signature::parse(&c.committer)?.timestamp,
+                parents: c.parents,
+                generation: 0,
+            }),
+            _ => bail!("fatal: object {} is not a commit", hex::encode(hash))
+        }
+    }
+
+    pub fn parents(&self, hash: &[u8; 20]) -> Result<Vec<[u8; 20]>> {
+        Ok(self.commit(hash)?.parents)
+    }
+
+    /// Returns the given commit and all of its ancestors
+    pub fn ancestors(&self, hash: &[u8; 20]) -> Result<HashSet<[u8; 20]>> {
+        self.ancestors_down_to(hash, 0)
+    }
+
+    /// Returns the given commit and its ancestors, without walking past commits whose generation number
+    /// is known to be below `min_generation`. None of their ancestors can have a generation at or above it.
+    pub fn ancestors_down_to(&self, hash: &[u8; 20], min_generation: u64) -> Result<HashSet<[u8; 20]>> {
+        let mut visited = HashSet::new();
+        let mut pending = vec![*hash];
+        while let Some(current) = pending.pop() {
+            if !visited.insert(current) {
+                continue;
+            }
+            let commit = self.commit(&current)?;
+            if commit.generation == 0 || commit.generation >= min_generation {
+                pending.extend(commit.parents);
+            }
+        }
+        Ok(visited)
+    }
+}
+
+/// Returns whether `ancestor` can be reached from `descendant` by following parents. A commit is its own ancestor.
+/// Commits with a generation number below the ancestor's can't lead to it, so their parents aren't walked.
+pub fn is_ancestor(dag: &CommitDag, ancestor: &[u8; 20], descendant: &[u8; 20]) -> Result<bool> {
+    let target_generation = dag.commit(ancestor)?.generation;
+
+    let mut visited = HashSet::new();
+    let mut pending = vec![*descendant];
+    while let Some(current) = pending.pop() {
+        if current == *ancestor {
+            return Ok(true);
+        }
+        if !visited.insert(current) {
+            continue;
+        }
+        let commit = dag.commit(&current)?;
+        if commit.generation == 0 || commit.generation >= target_generation {
+            pending.extend(commit.parents);
+        }
+    }
+    Ok(false)
+}
+
+/// Returns the best common ancestors of two commits: those common ancestors which are not
+/// themselves ancestors of another common ancestor. The most recent is first.
+pub fn merge_bases(dag: &CommitDag, a: &[u8; 20], b: &[u8; 20]) -> Result<Vec<[u8; 20]>> {
+    let ancestors_of_a = dag.ancestors(a)?;
+
+    // Walk back from b, stopping at the first common commits found along each path
+    let mut common = HashSet::new();
+    let mut visited = HashSet::new();
+    let mut pending = vec![*b];
+    while let Some(current) = pending.pop() {
+        if !visited.insert(current) {
+            continue;
+        }
+        if ancestors_of_a.contains(&current) {
+            common.insert(current);
+        } else {
+            pending.extend(dag.parents(&current)?);
+        }
+    }
+
+    // A common commit reachable from another common commit is not a best common ancestor.
+    // Commits with a generation below every common commit's can't lead to one, so needn't be walked.
+    let mut min_generation = u64::MAX;
+    for commit in &common {
+        min_generation = min_generation.min(dag.commit(commit)?.generation);
+    }
+
+    let mut redundant = HashSet::new();
+    for commit in &common {
+        for parent in dag.parents(commit)? {
+            if redundant.contains(&parent) {
+                continue;
+            }
+            for ancestor in dag.ancestors_down_to(&parent, min_generation)? {
+                if common.contains(&ancestor) {
+                    redundant.insert(ancestor);
+                }
+            }
+        }
+    }
+
+    let mut bases = Vec::new();
+    for hash in common.difference(&redundant) {
+        bases.push((dag.commit(hash)?.timestamp, *hash));
+    }
+    bases.sort_by(|x, y| y.cmp(x));
+    Ok(bases.into_iter().map(|(_, hash)| hash).collect())
+}
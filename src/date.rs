@@ -0,0 +1,324 @@
+// Formatting of the timestamps stored in commits
+
+use std::time::{SystemTime, UNIX_EPOCH};
+use anyhow::{anyhow, bail, Result};
+
+const WEEKDAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+/// The ways a commit date can be displayed, as chosen with `--date`
+#[derive(Clone, Copy)]
+pub enum DateFormat {
+    /// e.g. `Thu Apr 7 15:13:13 2005 -0700`
+    Default,
+    /// e.g. `2 days ago`
+    Relative,
+    /// e.g. `2005-04-07 15:13:13 -0700`
+    Iso,
+    /// e.g. `2005-04-07T15:13:13-07:00`
+    IsoStrict,
+    /// e.g. `Thu, 7 Apr 2005 15:13:13 -0700`
+    Rfc2822,
+    /// e.g. `2005-04-07`
+    Short,
+    /// Seconds since the epoch, e.g. `1112911993`
+    Unix,
+    /// Seconds since the epoch and the timezone, e.g. `1112911993 -0700`
+    Raw,
+}
+
+impl DateFormat {
+    pub fn parse(text: &str) -> Result<DateFormat> {
+        match text {
+            "default" => Ok(DateFormat::Default),
+            "relative" => Ok(DateFormat::Relative),
+            "iso" | "iso8601" => Ok(DateFormat::Iso),
+            "iso-strict" | "iso8601-strict" => Ok(DateFormat::IsoStrict),
+            "rfc" | "rfc2822" => Ok(DateFormat::Rfc2822),
+            "short" => Ok(DateFormat::Short),
+            "unix" => Ok(DateFormat::Unix),
+            "raw" => Ok(DateFormat::Raw),
+            _ => bail!("fatal: unknown date format {}", text)
+        }
+    }
+}
+
+/// Formats a timestamp in the given format. Apart from the Unix and relative formats, the time is shown in
+/// the given timezone, which is an offset in the form `+hhmm`.
+pub fn format_date(timestamp: i64, timezone: &str, format: DateFormat) -> String {
+    let offset = timezone_offset(timezone);
+    let local = timestamp + offset;
+    let days = local.div_euclid(86400);
+    let seconds = local.rem_euclid(86400);
+    let (year, month, day) = civil_from_days(days);
+    let time = format!("{:02}:{:02}:{:02}", seconds / 3600, (seconds / 60) % 60, seconds % 60);
+    let weekday = WEEKDAYS[(days + 4).rem_euclid(7) as usize];
+    let month_name = MONTHS[month as usize - 1];
+
+    match format {
+        DateFormat::Default => format!("{} {} {} {} {} {}", weekday, month_name, day, time, year, timezone),
+        DateFormat::Relative => {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|x| x.as_secs() as i64).unwrap_or(0);
+            format_relative(now - timestamp)
+        },
+        DateFormat::Iso => format!("{}-{:02}-{:02} {} {}", year, month, day, time, timezone),
+        DateFormat::IsoStrict => {
+            let tz = if timezone.len() == 5 {
+                format!("{}:{}", &timezone[..3], &timezone[3..])
+            } else {
+                String::from("Z")
+            };
+            format!("{}-{:02}-{:02}T{}{}", year, month, day, time, tz)
+        },
+        DateFormat::Rfc2822 => format!("{}, {} {} {} {} {}", weekday, day, month_name, year, time, timezone),
+        DateFormat::Short => format!("{}-{:02}-{:02}", year, month, day),
+        DateFormat::Unix => timestamp.to_string(),
+        DateFormat::Raw => format!("{} {}", timestamp, timezone),
+    }
+}
+
+/// Parses an expiry date as given to options like `prune --expire`, returning a Unix timestamp.
+/// Accepts `now`, `never`, a Unix timestamp, or a relative date such as `2.weeks.ago` or `3 days ago`.
+pub fn parse_expiry_date(text: &str) -> Result<i64> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|x| x.as_secs() as i64).unwrap_or(0);
+
+    match text {
+        "now" | "all" => return Ok(now),
+        "never" => return Ok(0),
+        _ => ()
+    }
+    if let Ok(timestamp) = text.parse::<i64>() {
+        return Ok(timestamp);
+    }
+
+    let words: Vec<&str> = text.split(['.', ' ']).filter(|x| !x.is_empty()).collect();
+    if let [count, unit, "ago"] = words[..] {
+        if let Ok(count) = count.parse::<i64>() {
+            let seconds = match unit.trim_end_matches('s') {
+                "second" => 1,
+                "minute" => 60,
+                "hour" => 3600,
+                "day" => 86400,
+                "week" => 7 * 86400,
+                "month" => 30 * 86400,
+                "year" => 365 * 86400,
+                _ => bail!("fatal: malformed expiration date '{}'", text)
+            };
+            return Ok(now - count * seconds);
+        }
+    }
+
+    bail!("fatal: malformed expiration date '{}'", text)
+}
+
+/// Parses a date in Git's internal format, a Unix timestamp and a timezone such as `1112911993 -0700`,
+/// optionally prefixed with `@`. Returns the timestamp and the timezone.
+pub fn parse_raw_date(text: &str) -> Result<(i64, String)> {
+    let text = text.trim();
+    let (timestamp, timezone) = text.split_once(' ').unwrap_or((text, "+0000"));
+    let timestamp = timestamp.strip_prefix('@').unwrap_or(timestamp).parse::<i64>();
+    let valid_timezone = timezone.len() == 5
+        && timezone.starts_with(['+', '-'])
+        && timezone[1..].chars().all(|c| c.is_ascii_digit());
+
+    match timestamp {
+        Ok(timestamp) if valid_timezone => Ok((timestamp, timezone.to_string())),
+        _ => bail!("fatal: invalid date format: {}", text)
+    }
+}
+
+/// Parses a date in the form used by email headers, e.g. `Thu, 7 Apr 2005 15:13:13 -0700`.
+/// The day of the week is optional. Returns the timestamp and the timezone.
+pub fn parse_rfc2822_date(text: &str) -> Result<(i64, String)> {
+    let text = text.trim();
+    let fields: Vec<&str> = text.split_once(", ").map(|x| x.1).unwrap_or(text).split_whitespace().collect();
+    let [day, month, year, time, timezone] = fields[..] else {
+        bail!("fatal: invalid date format: {}", text);
+    };
+
+    let month = MONTHS.iter().position(|x| x.eq_ignore_ascii_case(month));
+    let time: Vec<Option<i64>> = time.split(':').map(|x| x.parse().ok()).collect();
+    let valid_timezone = timezone.len() == 5
+        && timezone.starts_with(['+', '-'])
+        && timezone[1..].chars().all(|c| c.is_ascii_digit());
+    match (day.parse::<u32>(), month, year.parse::<i64>(), &time[..]) {
+        (Ok(day), Some(month), Ok(year), [Some(hours), Some(minutes), seconds @ ..]) if valid_timezone && seconds.len() <= 1 => {
+            let seconds = seconds.first().copied().flatten().unwrap_or(0);
+            let local = days_from_civil(year, month as u32 + 1, day) * 86400 + hours * 3600 + minutes * 60 + seconds;
+            Ok((local - timezone_offset(timezone), timezone.to_string()))
+        },
+        _ => bail!("fatal: invalid date format: {}", text)
+    }
+}
+
+/// Parses a date in any of the forms Git commonly accepts for commit dates: its internal format
+/// (`1112911993 -0700` or `@1112911993`), RFC 2822 (`Thu, 7 Apr 2005 15:13:13 -0700`) and ISO 8601
+/// (`2005-04-07T15:13:13-07:00` or `2005-04-07 15:13:13 -0700`). An ISO 8601 date without a timezone is taken to be
+/// in the local timezone. Returns the timestamp and the timezone.
+pub fn parse_date(text: &str) -> Result<(i64, String)> {
+    parse_raw_date(text)
+        .or_else(|_| parse_rfc2822_date(text))
+        .or_else(|_| parse_iso_date(text))
+        .map_err(|_| anyhow!("fatal: invalid date format: {}", text.trim()))
+}
+
+// Parses an ISO 8601 date, with the time separated from the date by a `T` or a space, optional seconds and
+// fractions of a second, and a timezone given as `Z`, `+hh:mm` or `+hhmm`, optionally after a space
+fn parse_iso_date(text: &str) -> Result<(i64, String)> {
+    let text = text.trim();
+    let invalid = || anyhow!("fatal: invalid date format: {}", text);
+    let (date, rest) = text.split_at(text.find(['T', ' ']).ok_or_else(invalid)?);
+    let rest = rest[1..].trim_start();
+
+    let date: Vec<Option<u32>> = date.split('-').map(|x| x.parse().ok()).collect();
+    let [Some(year), Some(month), Some(day)] = date[..] else {
+        bail!(invalid());
+    };
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        bail!(invalid());
+    }
+
+    // The time ends where the timezone starts
+    let time_end = rest.find(['Z', '+', '-', ' ']).unwrap_or(rest.len());
+    let (time, zone) = rest.split_at(time_end);
+    let time = time.split('.').next().unwrap_or_default();
+    let time: Vec<Option<i64>> = time.split(':').map(|x| x.parse().ok()).collect();
+    let (hours, minutes, seconds) = match time[..] {
+        [Some(hours), Some(minutes)] => (hours, minutes, 0),
+        [Some(hours), Some(minutes), Some(seconds)] => (hours, minutes, seconds),
+        _ => bail!(invalid())
+    };
+    if hours > 23 || minutes > 59 || seconds > 60 {
+        bail!(invalid());
+    }
+    let local = days_from_civil(year as i64, month, day) * 86400 + hours * 3600 + minutes * 60 + seconds;
+
+    let zone = zone.trim();
+    let timezone = match zone {
+        "" => {
+            // Found from the offset in effect at about that time, which is close enough across changes in
+            // daylight saving time
+            let offset = local_offset(local);
+            let sign = if offset < 0 { '-' } else { '+' };
+            let minutes = offset.abs() / 60;
+            format!("{}{:02}{:02}", sign, minutes / 60, minutes % 60)
+        },
+        "Z" => String::from("+0000"),
+        _ => zone.replace(':', "")
+    };
+    let valid_timezone = timezone.len() == 5
+        && timezone.starts_with(['+', '-'])
+        && timezone[1..].chars().all(|c| c.is_ascii_digit());
+    if !valid_timezone {
+        bail!(invalid());
+    }
+    Ok((local - timezone_offset(&timezone), timezone))
+}
+
+/// Returns the current time as a Unix timestamp with the local timezone in the form `+hhmm`, as recorded in new commits
+pub fn now_with_timezone() -> (i64, String) {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|x| x.as_secs() as i64).unwrap_or(0);
+
+    // The offset from UTC depends on the date, because of daylight saving time
+    let offset = local_offset(now);
+    let sign = if offset < 0 { '-' } else { '+' };
+    let minutes = offset.abs() / 60;
+    (now, format!("{}{:02}{:02}", sign, minutes / 60, minutes % 60))
+}
+
+// Returns the local timezone's offset from UTC in seconds at the given time
+fn local_offset(timestamp: i64) -> i64 {
+    unsafe {
+        let time = timestamp as libc::time_t;
+        let mut tm: libc::tm = std::mem::zeroed();
+        if libc::localtime_r(&time, &mut tm).is_null() { 0 } else { tm.tm_gmtoff }
+    }
+}
+
+/// Describes how long ago something happened, given the number of seconds since it happened.
+/// Uses the same rounding as Git, so e.g. 100 seconds is `2 minutes ago`.
+fn format_relative(seconds: i64) -> String {
+    if seconds < 0 {
+        return String::from("in the future");
+    }
+    if seconds < 90 {
+        return plural(seconds, "second") + " ago";
+    }
+
+    let minutes = (seconds + 30) / 60;
+    if minutes < 90 {
+        return plural(minutes, "minute") + " ago";
+    }
+
+    let hours = (minutes + 30) / 60;
+    if hours < 36 {
+        return plural(hours, "hour") + " ago";
+    }
+
+    let days = (hours + 12) / 24;
+    if days < 14 {
+        return plural(days, "day") + " ago";
+    }
+    if days < 70 {
+        return plural((days + 3) / 7, "week") + " ago";
+    }
+    if days < 365 {
+        return plural((days + 15) / 30, "month") + " ago";
+    }
+
+    // Under five years, show years and months
+    if days < 1825 {
+        let total_months = (days * 12 * 2 + 365) / (365 * 2);
+        let years = total_months / 12;
+        let months = total_months % 12;
+        if months > 0 {
+            return format!("{}, {} ago", plural(years, "year"), plural(months, "month"));
+        }
+        return plural(years, "year") + " ago";
+    }
+
+    plural((days + 183) / 365, "year") + " ago"
+}
+
+fn plural(count: i64, unit: &str) -> String {
+    if count == 1 {
+        format!("{} {}", count, unit)
+    } else {
+        format!("{} {}s", count, unit)
+    }
+}
+
+/// Converts a timezone in the form `+hhmm` to an offset in seconds from UTC
+fn timezone_offset(timezone: &str) -> i64 {
+    let sign = if timezone.starts_with('-') { -1 } else { 1 };
+    let digits = timezone.trim_start_matches(['+', '-']);
+    let value = digits.parse::<i64>().unwrap_or(0);
+    sign * ((value / 100) * 3600 + (value % 100) * 60)
+}
+
+/// Converts a number of days since 1970-01-01 to a (year, month, day) date in the proleptic Gregorian calendar.
+/// See http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// Converts a (year, month, day) date in the proleptic Gregorian calendar to a number of days since 1970-01-01.
+/// See http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let mp = if month > 2 { month - 3 } else { month + 9 } as i64;
+    let day_of_year = (153 * mp + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
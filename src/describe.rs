@@ -0,0 +1,210 @@
+// Name commits after the nearest tag they descend from
+
+use std::{collections::HashMap, env};
+use anyhow::{bail, Result};
+use clap::Args;
+
+use crate::{GlobalOpts, repo_find};
+use crate::dag::CommitDag;
+use crate::objects::{get_object, peel, short_hash, Object, Signature};
+use crate::refs::{list_refs, resolve_revision};
+
+// Like Git, the search stops once this many tags have been found
+const MAX_CANDIDATES: usize = 10;
+// The flag marking a commit as already queued. The other bits record which candidate tags can reach the commit.
+const SEEN: u32 = 1;
+
+#[derive(Args)]
+pub struct DescribeArgs {
+    /// The commits to describe, by default HEAD
+    pub commits: Vec<String>,
+    /// Use lightweight tags as well as annotated ones
+    #[arg(long)]
+    pub tags: bool,
+    /// Show the abbreviated hash of a commit that no tag can describe
+    #[arg(long)]
+    pub always: bool,
+}
+
+// A tag pointing at a commit
+struct TagName {
+    name: String,
+    annotated: bool,
+    /// When an annotated tag was made, used to choose between several tags of the same commit
+    date: i64,
+}
+
+// A tag found while walking back from the commit being described
+struct Candidate<'a> {
+    tag: &'a TagName,
+    /// The number of commits walked that the tag can't reach
+    depth: usize,
+    /// The flag marking commits this tag can reach
+    flag: u32,
+    found_order: usize,
+}
+
+pub fn cmd_describe(args: DescribeArgs, global_opts: GlobalOpts) -> Result<()> {
+    let path = env::current_dir().unwrap_or_else(|_| { panic!() });
+    let root = repo_find(&path, global_opts).unwrap_or_else(|| {
+        panic!("fatal: not a grit repository");
+    });
+    let git_mode = global_opts.git_mode;
+
+    // Find the commit each tag points at. Annotated tags are preferred when a commit has several,
+    // then the newest annotated tag, then the first in order of name.
+    let mut names: HashMap<[u8; 20], TagName> = HashMap::new();
+    for (ref_name, hash) in list_refs(&root, global_opts)? {
+        let Some(name) = ref_name.strip_prefix("refs/tags/") else { continue };
+        let target = peel(&root, &hash, git_mode)?;
+        let (annotated, date) = match get_object(&root, &hash, git_mode)? {
+            Object::Tag(t) => (true, t.tagger.map(|x| Signature::parse(&x)).transpose()?.map(|x| x.timestamp).unwrap_or(0)),
+            _ => (false, 0)
+        };
+
+        let replace = match names.get(&target) {
+            None => true,
+            Some(existing) => annotated && (!existing.annotated || existing.date < date)
+        };
+        if replace {
+            names.insert(target, TagName { name: name.to_string(), annotated, date });
+        }
+    }
+
+    let dag = CommitDag::open(&root, git_mode)?;
+    let commits = if args.commits.is_empty() { vec![String::from("HEAD")] } else { args.commits.clone() };
+    for rev in commits {
+        let hash = peel(&root, &resolve_revision(&root, &rev, global_opts)?, git_mode)?;
+        let short = short_hash(&root, &hash, global_opts)?;
+        println!("{}", describe(&dag, &hash, &short, &names, &args)?);
+    }
+
+    Ok(())
+}
+
+// Finds the tag reachable from the commit with the fewest commits between them, walking back in date order. The
+// commit is shown by its abbreviated hash, `short`, if it isn't tagged itself.
+fn describe(
+    dag: &CommitDag,
+    hash: &[u8; 20],
+    short: &str,
+    names: &HashMap<[u8; 20], TagName>,
+    args: &DescribeArgs
+) -> Result<String> {
+    if names.is_empty() && !args.always {
+        bail!("fatal: No names found, cannot describe anything.");
+    }
+
+    let usable = |x: &&TagName| args.tags || x.annotated;
+    if let Some(tag) = names.get(hash).filter(usable) {
+        return Ok(tag.name.clone());
+    }
+
+    let mut flags: HashMap<[u8; 20], u32> = HashMap::from([(*hash, SEEN)]);
+    let mut queue = vec![*hash];
+    let mut candidates: Vec<Candidate> = Vec::new();
+    let mut annotated_count = 0;
+    let mut unannotated_count = 0;
+    let mut seen_commits = 0;
+    let mut gave_up_on = None;
+
+    while !queue.is_empty() {
+        let current = queue.remove(0);
+        seen_commits += 1;
+
+        if let Some(tag) = names.get(&current) {
+            if !usable(&tag) {
+                unannotated_count += 1;
+            } else if candidates.len() < MAX_CANDIDATES {
+                let flag = 1 << (candidates.len() + 1);
+                candidates.push(Candidate { tag, depth: seen_commits - 1, flag, found_order: candidates.len() + 1 });
+                *flags.entry(current).or_default() |= flag;
+                if tag.annotated {
+                    annotated_count += 1;
+                }
+            } else {
+                gave_up_on = Some(current);
+                break;
+            }
+        }
+
+        let current_flags = flags[&current];
+        for candidate in candidates.iter_mut() {
+            if current_flags & candidate.flag == 0 {
+                candidate.depth += 1;
+            }
+        }
+
+        // Stop if the last remaining path is already covered by the best candidates
+        if annotated_count > 0 && queue.is_empty() {
+            break;
+        }
+        queue_parents(dag, &current, current_flags, &mut flags, &mut queue)?;
+    }
+
+    if candidates.is_empty() {
+        if args.always {
+            return Ok(short.to_string());
+        }
+        if unannotated_count > 0 {
+            bail!("fatal: No annotated tags can describe '{}'.\nHowever, there were unannotated tags: try --tags.", hex::encode(hash));
+        }
+        bail!("fatal: No tags can describe '{}'.\nTry --always, or create some tags.", hex::encode(hash));
+    }
+
+    candidates.sort_by_key(|x| (x.depth, x.found_order));
+    if let Some(commit) = gave_up_on {
+        insert_by_date(dag, &mut queue, commit)?;
+    }
+    let best = &mut candidates[0];
+    finish_depth(dag, best, &mut flags, &mut queue)?;
+
+    Ok(format!("{}-{}-g{}", best.tag.name, best.depth, short))
+}
+
+// Counts the remaining commits the best tag can't reach, until every queued commit is one it can reach
+fn finish_depth(dag: &CommitDag, best: &mut Candidate, flags: &mut HashMap<[u8; 20], u32>, queue: &mut Vec<[u8; 20]>) -> Result<()> {
+    while !queue.is_empty() {
+        let current = queue.remove(0);
+        let current_flags = flags[&current];
+        if current_flags & best.flag != 0 {
+            if queue.iter().all(|x| flags[x] & best.flag != 0) {
+                break;
+            }
+        } else {
+            best.depth += 1;
+        }
+        queue_parents(dag, &current, current_flags, flags, queue)?;
+    }
+    Ok(())
+}
+
+// Queues the parents of a commit that haven't been seen yet, and passes on the flags of the tags that reach it
+fn queue_parents(
+    dag: &CommitDag,
+    hash: &[u8; 20],
+    current_flags: u32,
+    flags: &mut HashMap<[u8; 20], u32>,
+    queue: &mut Vec<[u8; 20]>
+) -> Result<()> {
+    for parent in dag.parents(hash)? {
+        let parent_flags = flags.entry(parent).or_default();
+        let seen = *parent_flags & SEEN != 0;
+        *parent_flags |= current_flags;
+        if !seen {
+            insert_by_date(dag, queue, parent)?;
+        }
+    }
+    Ok(())
+}
+
+// Keeps the queue ordered newest first, with commits of the same date in the order they were queued
+fn insert_by_date(dag: &CommitDag, queue: &mut Vec<[u8; 20]>, hash: [u8; 20]) -> Result<()> {
+    let timestamp = dag.commit(&hash)?.timestamp;
+    let mut pos = 0;
+    while pos < queue.len() && dag.commit(&queue[pos])?.timestamp >= timestamp {
+        pos += 1;
+    }
+    queue.insert(pos, hash);
+    Ok(())
+}
@@ -0,0 +1,415 @@
+// Show changes between the index and the working tree, or between HEAD and the index, or between two files
+
+use std::{collections::{BTreeMap, BTreeSet}, env, fs, io::Write, path::Path, process::{self, Command}};
+use anyhow::{anyhow, bail, Result};
+use clap::Args;
+
+use crate::{GlobalOpts, repo_find};
+use crate::attributes::{AttrValue, Attributes};
+use crate::checkout::commit_files;
+use crate::config::Config;
+use crate::filter::Filters;
+use crate::index::{canonical_mode, index_path, read_index};
+use crate::line_diff::{unified_diff, DiffAlgorithm};
+use crate::objects::{get_object, Blob, GitObject, Object};
+use crate::refs::head_commit;
+use crate::word_diff::{word_diff, WordDiffMode};
+
+#[derive(Args)]
+pub struct DiffArgs {
+    /// Compare the index with HEAD rather than the working tree with the index
+    #[arg(long, visible_alias = "staged")]
+    pub cached: bool,
+    /// Mark the words that changed within lines, rather than showing whole lines as removed and added
+    #[arg(long, value_name = "MODE", require_equals = true, num_args = 0..=1, default_missing_value = "plain")]
+    pub word_diff: Option<WordDiffMode>,
+    /// The algorithm used to find the changed lines, overriding `diff.algorithm`
+    #[arg(long, value_name = "ALGORITHM")]
+    pub diff_algorithm: Option<DiffAlgorithm>,
+    /// Show the changes found by patience diff, the same as `--diff-algorithm=patience`
+    #[arg(long, conflicts_with = "diff_algorithm")]
+    pub patience: bool,
+    /// Compare the two files given as paths, or two directories file by file, rather than anything in a repository.
+    /// This is also what happens when two paths are given outside a repository.
+    #[arg(long, conflicts_with = "cached")]
+    pub no_index: bool,
+    /// Only show changes to these paths
+    pub paths: Vec<String>,
+}
+
+/// One version of a file being compared
+pub struct DiffSide {
+    pub hash: [u8; 20],
+    pub mode: u32,
+    pub contents: Vec<u8>,
+}
+
+pub fn cmd_diff(args: DiffArgs, global_opts: GlobalOpts) -> Result<()> {
+    let path = env::current_dir().unwrap_or_else(|_| { panic!() });
+    let root = repo_find(&path, global_opts);
+    if args.no_index || (root.is_none() && args.paths.len() == 2) {
+        let config = match &root {
+            Some(root) => Config::load(root, global_opts)?,
+            None => Config::load_global()?
+        };
+        // Inside a repository, Git names the paths in errors from the top of the working tree
+        let prefix = root.as_ref()
+            .and_then(|x| path.strip_prefix(x).ok())
+            .filter(|x| !x.as_os_str().is_empty())
+            .map(|x| format!("{}/", index_path(x).to_string_lossy()))
+            .unwrap_or_default();
+        return diff_no_index(&args, &config, &prefix, global_opts);
+    }
+    let root = root.unwrap_or_else(|| {
+        panic!("fatal: not a grit repository");
+    });
+    let git_mode = global_opts.git_mode;
+
+    let index = read_index(&root, global_opts)?;
+    let mut filters = Filters::new(&root, global_opts)?;
+    let mut old_files: BTreeMap<String, DiffSide> = BTreeMap::new();
+    let mut new_files: BTreeMap<String, DiffSide> = BTreeMap::new();
+
+    if args.cached {
+        if let Some(head) = head_commit(&root, global_opts)? {
+            for (path, entry) in commit_files(&root, &head, git_mode)? {
+                old_files.insert(path.to_string_lossy().to_string(), read_blob(&root, &entry.hash, entry.mode, git_mode)?);
+            }
+        }
+        for item in &index.items {
+            new_files.insert(item.path.to_string_lossy().to_string(), read_blob(&root, &item.hash, item.mode, git_mode)?);
+        }
+    } else {
+        for item in &index.items {
+            let name = item.path.to_string_lossy().to_string();
+            old_files.insert(name.clone(), read_blob(&root, &item.hash, item.mode, git_mode)?);
+
+            // Files removed from the working tree are shown as deleted
+            let file = root.join(&item.path);
+            if file.is_file() {
+                let contents = filters.clean(&item.path, fs::read(&file)?)?;
+                let hash = Blob { bytes: contents.clone() }.hash();
+                new_files.insert(name, DiffSide { hash, mode: file_mode(&file)?, contents });
+            }
+        }
+    }
+
+    let mut paths: Vec<&String> = old_files.keys().chain(new_files.keys()).collect();
+    paths.sort_by(|x, y| x.as_bytes().cmp(y.as_bytes()));
+    paths.dedup();
+
+    let config = Config::load(&root, global_opts)?;
+    let algorithm = chosen_algorithm(&args, &config)?;
+    let mut attributes = Attributes::new(&root, &config, global_opts)?;
+    let mut output = Vec::new();
+    for path in paths {
+        if !args.paths.is_empty() && !args.paths.iter().any(|x| path == x || path.starts_with(&format!("{}/", x.trim_end_matches('/')))) {
+            continue;
+        }
+        let old = old_files.get(path);
+        let new = new_files.get(path);
+        if old.map(|x| (x.hash, x.mode)) == new.map(|x| (x.hash, x.mode)) {
+            continue;
+        }
+        let patch = file_patch(&root, path, old, new, &config, &mut attributes, algorithm)?;
+        match args.word_diff {
+            Some(mode) => output.extend(word_diff(&patch, mode)),
+            None => output.extend(patch)
+        }
+    }
+
+    std::io::stdout().write_all(&output)?;
+    Ok(())
+}
+
+// Compares the two files or directories given as paths, as `diff --no-index` does, without reading anything from a
+// repository. Like Git, a file compared with a directory is compared with the file of the same name in the directory,
+// and the command exits with 1 if the files differ. Relative paths are named in errors with `prefix` before them.
+fn diff_no_index(args: &DiffArgs, config: &Config, prefix: &str, global_opts: GlobalOpts) -> Result<()> {
+    let [old, new] = &args.paths[..] else {
+        let program = if global_opts.git_mode { "git" } else { "grit" };
+        eprintln!("usage: {} diff --no-index [<options>] <path> <path>", program);
+        process::exit(129);
+    };
+    let in_dir = |dir: &str, file: &str| {
+        let name = Path::new(file).file_name().map(|x| x.to_string_lossy().to_string()).unwrap_or_default();
+        child_path(dir, &name)
+    };
+    let (old, new) = match (Path::new(old).is_dir(), Path::new(new).is_dir()) {
+        (true, false) => (in_dir(old, new), new.clone()),
+        (false, true) => (old.clone(), in_dir(new, old)),
+        _ => (old.clone(), new.clone())
+    };
+    for path in [&old, &new] {
+        if fs::symlink_metadata(path).is_err() {
+            bail!("error: Could not access '{}'", shown_path(prefix, path));
+        }
+    }
+
+    let algorithm = chosen_algorithm(args, config)?;
+    let mut patches = Vec::new();
+    no_index_patches(Some(&old), Some(&new), prefix, algorithm, &mut patches)?;
+    let mut stdout = std::io::stdout();
+    for patch in &patches {
+        match args.word_diff {
+            Some(mode) => stdout.write_all(&word_diff(patch, mode))?,
+            None => stdout.write_all(patch)?
+        }
+    }
+    if !patches.is_empty() {
+        stdout.flush()?;
+        process::exit(1);
+    }
+    Ok(())
+}
+
+// Adds a patch for each file that differs between two paths to `patches`, going through directories file by file in order of
+// name. A side is None where the other side has a file or directory that it doesn't. Where one side has a file and
+// the other a directory, the file is shown as removed or added before the files of the directory.
+fn no_index_patches(
+    old: Option<&str>,
+    new: Option<&str>,
+    prefix: &str,
+    algorithm: DiffAlgorithm,
+    patches: &mut Vec<Vec<u8>>
+) -> Result<()> {
+    let old_dir = old.filter(|x| Path::new(x).is_dir());
+    let new_dir = new.filter(|x| Path::new(x).is_dir());
+    if old_dir.is_none() && new_dir.is_none() {
+        let read = |path: Option<&str>| -> Result<Option<DiffSide>> {
+            let Some(path) = path else {
+                return Ok(None);
+            };
+            let contents = fs::read(path).map_err(|_| anyhow!("error: Could not access '{}'", shown_path(prefix, path)))?;
+            let hash = Blob { bytes: contents.clone() }.hash();
+            Ok(Some(DiffSide { hash, mode: file_mode(Path::new(path))?, contents }))
+        };
+        let (old_side, new_side) = (read(old)?, read(new)?);
+        if old_side.as_ref().map(|x| (x.hash, x.mode)) == new_side.as_ref().map(|x| (x.hash, x.mode)) {
+            return Ok(());
+        }
+
+        // As in Git, a file on only one side is named by its path on both sides, and the slash of an absolute path is
+        // left out after the `a/` or `b/`
+        let old_path = old.or(new).unwrap_or_default().trim_start_matches('/');
+        let new_path = new.or(old).unwrap_or_default().trim_start_matches('/');
+        patches.push(patch(old_path, new_path, old_side.as_ref(), new_side.as_ref(), Comparison::Detect, algorithm)?);
+        return Ok(());
+    }
+
+    if old_dir.is_none() && old.is_some() {
+        no_index_patches(old, None, prefix, algorithm, patches)?;
+    }
+    if new_dir.is_none() && new.is_some() {
+        no_index_patches(None, new, prefix, algorithm, patches)?;
+    }
+    let mut names = BTreeSet::new();
+    for dir in old_dir.iter().chain(new_dir.iter()) {
+        for entry in fs::read_dir(dir)? {
+            names.insert(entry?.file_name().to_string_lossy().to_string());
+        }
+    }
+    for name in names {
+        let child = |dir: Option<&str>| dir.map(|x| child_path(x, &name)).filter(|x| fs::symlink_metadata(x).is_ok());
+        no_index_patches(child(old_dir).as_deref(), child(new_dir).as_deref(), prefix, algorithm, patches)?;
+    }
+    Ok(())
+}
+
+// A path as Git names it in errors, with the prefix before it unless it's absolute
+fn shown_path(prefix: &str, path: &str) -> String {
+    match Path::new(path).is_absolute() {
+        true => path.to_string(),
+        false => format!("{}{}", prefix, path)
+    }
+}
+
+// The path of the file with the given name in a directory
+fn child_path(dir: &str, name: &str) -> String {
+    match dir.ends_with('/') {
+        true => format!("{}{}", dir, name),
+        false => format!("{}/{}", dir, name)
+    }
+}
+
+// The algorithm the options choose for finding changed lines, or else the one `diff.algorithm` sets
+fn chosen_algorithm(args: &DiffArgs, config: &Config) -> Result<DiffAlgorithm> {
+    match (args.patience, args.diff_algorithm) {
+        (true, _) => Ok(DiffAlgorithm::Patience),
+        (false, Some(algorithm)) => Ok(algorithm),
+        (false, None) => configured_algorithm(config)
+    }
+}
+
+/// Formats the changes to a single file as Git does, including the `diff --git` header.
+/// A side is None if the file doesn't exist in that version.
+pub fn file_patch(
+    root: &Path,
+    path: &str,
+    old: Option<&DiffSide>,
+    new: Option<&DiffSide>,
+    config: &Config,
+    attributes: &mut Attributes,
+    algorithm: DiffAlgorithm
+) -> Result<Vec<u8>> {
+    // The diff attribute can name a driver whose textconv program converts the files to text before comparing them
+    let comparison = match textconv_command(Path::new(path), config, attributes)? {
+        Some(command) => Comparison::Textconv { root, command },
+        None => match attributes.get(Path::new(path), "diff")? {
+            AttrValue::Set => Comparison::Text,
+            AttrValue::Unset => Comparison::Binary,
+            _ => Comparison::Detect
+        }
+    };
+    patch(path, path, old, new, comparison, algorithm)
+}
+
+// How the contents of two versions of a file are compared
+enum Comparison<'a> {
+    /// As the text a textconv program, run in the repository at `root`, converts them to
+    Textconv { root: &'a Path, command: String },
+    /// As text, whatever they hold
+    Text,
+    /// As binary, whatever they hold
+    Binary,
+    /// As binary if either looks binary, and otherwise as text
+    Detect,
+}
+
+// Formats the changes between two versions of a file, which may be at different paths, as Git does
+fn patch(
+    old_path: &str,
+    new_path: &str,
+    old: Option<&DiffSide>,
+    new: Option<&DiffSide>,
+    comparison: Comparison,
+    algorithm: DiffAlgorithm
+) -> Result<Vec<u8>> {
+    let mut output = format!("diff --git a/{} b/{}\n", old_path, new_path);
+
+    let old_hash = old.map(|x| short_hash(&x.hash)).unwrap_or(String::from("0000000"));
+    let new_hash = new.map(|x| short_hash(&x.hash)).unwrap_or(String::from("0000000"));
+    match (old, new) {
+        (None, Some(new)) => {
+            output += &format!("new file mode {:o}\nindex {}..{}\n", new.mode, old_hash, new_hash);
+        },
+        (Some(old), None) => {
+            output += &format!("deleted file mode {:o}\nindex {}..{}\n", old.mode, old_hash, new_hash);
+        },
+        (Some(old), Some(new)) => {
+            if old.mode != new.mode {
+                output += &format!("old mode {:o}\nnew mode {:o}\n", old.mode, new.mode);
+            }
+            if old.hash == new.hash {
+                return Ok(output.into_bytes());
+            }
+            output += &format!("index {}..{}", old_hash, new_hash);
+            if old.mode == new.mode {
+                output += &format!(" {:o}", new.mode);
+            }
+            output.push('\n');
+        },
+        (None, None) => bail!("no versions of {} to compare", old_path)
+    }
+
+    let old_name = if old.is_some() { format!("a/{}", old_path) } else { String::from("/dev/null") };
+    let new_name = if new.is_some() { format!("b/{}", new_path) } else { String::from("/dev/null") };
+    let empty = Vec::new();
+    let old_contents = old.map(|x| &x.contents).unwrap_or(&empty);
+    let new_contents = new.map(|x| &x.contents).unwrap_or(&empty);
+
+    let (old_text, new_text) = match comparison {
+        Comparison::Textconv { root, command } => (
+            if old.is_some() { run_textconv(root, &command, old_path, old_contents)? } else { Vec::new() },
+            if new.is_some() { run_textconv(root, &command, new_path, new_contents)? } else { Vec::new() },
+        ),
+        _ => {
+            let binary = match comparison {
+                Comparison::Text => false,
+                Comparison::Binary => true,
+                _ => is_binary(old_contents) || is_binary(new_contents)
+            };
+            if binary {
+                output += &format!("Binary files {} and {} differ\n", old_name, new_name);
+                return Ok(output.into_bytes());
+            }
+            (old_contents.clone(), new_contents.clone())
+        }
+    };
+
+    let hunks = unified_diff(&old_text, &new_text, 3, algorithm);
+    let mut output = output.into_bytes();
+    if !hunks.is_empty() {
+        output.extend(format!("--- {}\n+++ {}\n", old_name, new_name).into_bytes());
+        output.extend(hunks);
+    }
+    Ok(output)
+}
+
+/// The algorithm `diff.algorithm` sets for finding changed lines, which is Myers' if it isn't set
+pub fn configured_algorithm(config: &Config) -> Result<DiffAlgorithm> {
+    match config.get("diff.algorithm") {
+        Some(name) => DiffAlgorithm::parse(&name)
+            .ok_or(anyhow!("fatal: unknown value for config 'diff.algorithm': {}", name)),
+        None => Ok(DiffAlgorithm::default())
+    }
+}
+
+/// The textconv program of the diff driver that the `diff` attribute names for the file at `path`, if there is one
+pub fn textconv_command(path: &Path, config: &Config, attributes: &mut Attributes) -> Result<Option<String>> {
+    Ok(match attributes.get(path, "diff")? {
+        AttrValue::Value(name) => config.get(&format!("diff.{}.textconv", name)),
+        _ => None
+    })
+}
+
+/// Runs a textconv program on the contents of a file, which is passed to it as a temporary file.
+/// The program is run by the shell, so it may include arguments.
+pub fn run_textconv(root: &Path, command: &str, path: &str, contents: &[u8]) -> Result<Vec<u8>> {
+    let file_name = Path::new(path).file_name().map(|x| x.to_string_lossy().to_string()).unwrap_or_default();
+    let temp_file = env::temp_dir().join(format!("grit-{}-{}", process::id(), file_name));
+    fs::write(&temp_file, contents)?;
+
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{} \"$@\"", command))
+        .arg(command)
+        .arg(&temp_file)
+        .current_dir(root)
+        .output();
+    fs::remove_file(&temp_file)?;
+
+    let output = output.map_err(|e| anyhow!("error: cannot run {}: {}", command, e))?;
+    if !output.status.success() {
+        bail!("fatal: unable to read files to diff");
+    }
+    Ok(output.stdout)
+}
+
+// Git treats a file as binary if a NUL byte appears within its first 8000 bytes
+pub fn is_binary(contents: &[u8]) -> bool {
+    contents.iter().take(8000).any(|x| *x == 0)
+}
+
+fn short_hash(hash: &[u8; 20]) -> String {
+    hex::encode(hash)[..7].to_string()
+}
+
+/// Reads a blob as one side of a comparison
+pub fn read_blob(root: &Path, hash: &[u8; 20], mode: u32, git_mode: bool) -> Result<DiffSide> {
+    match get_object(root, hash, git_mode)? {
+        Object::Blob(blob) => Ok(DiffSide { hash: *hash, mode: canonical_mode(mode), contents: blob.bytes }),
+        _ => bail!("fatal: object {} is not a blob", hex::encode(hash))
+    }
+}
+
+fn file_mode(file: &Path) -> Result<u32> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        Ok(canonical_mode(fs::metadata(file)?.permissions().mode()))
+    }
+
+    #[cfg(not(unix))]
+    Ok(0o100644)
+}
@@ -0,0 +1,218 @@
+// Summarising the changes made by a commit, as shown after committing and when bisecting finishes
+
+use std::{collections::BTreeMap, path::Path};
+use anyhow::Result;
+
+use crate::checkout::commit_files;
+use crate::diff::is_binary;
+use crate::line_diff::{myers_diff, split_lines, Edit};
+use crate::objects::get_blob;
+
+/// The number of lines added to and removed from one file
+pub struct FileStat {
+    pub path: String,
+    pub insertions: usize,
+    pub deletions: usize,
+    /// The sizes in bytes before and after the change, if either version is binary
+    pub binary_sizes: Option<(usize, usize)>,
+}
+
+/// The changes between two commits, with a line for each file that was created, deleted or changed mode
+pub struct DiffStat {
+    pub files: Vec<FileStat>,
+    pub mode_changes: Vec<String>,
+}
+
+/// Compares the files of commit `new` with those of commit `old`, or with no files at all for a root commit
+pub fn diff_stat(root: &Path, old: Option<&[u8; 20]>, new: &[u8; 20], git_mode: bool) -> Result<DiffStat> {
+    let old_files = match old {
+        Some(old) => commit_files(root, old, git_mode)?,
+        None => BTreeMap::new()
+    };
+    let new_files = commit_files(root, new, git_mode)?;
+    let mut paths: Vec<_> = old_files.keys().chain(new_files.keys()).collect();
+    paths.sort_by(|x, y| x.as_os_str().as_encoded_bytes().cmp(y.as_os_str().as_encoded_bytes()));
+    paths.dedup();
+
+    let mut stat = DiffStat { files: Vec::new(), mode_changes: Vec::new() };
+    for path in paths {
+        let old = old_files.get(path);
+        let new = new_files.get(path);
+        if old.map(|x| (x.hash, x.mode)) == new.map(|x| (x.hash, x.mode)) {
+            continue;
+        }
+
+        let old_contents = match old { Some(x) => get_blob(root, &x.hash, git_mode)?, None => Vec::new() };
+        let new_contents = match new { Some(x) => get_blob(root, &x.hash, git_mode)?, None => Vec::new() };
+        let path = path.to_string_lossy().to_string();
+        let mut file = FileStat { path: path.clone(), insertions: 0, deletions: 0, binary_sizes: None };
+        if is_binary(&old_contents) || is_binary(&new_contents) {
+            file.binary_sizes = Some((old_contents.len(), new_contents.len()));
+        } else {
+            for edit in myers_diff(&split_lines(&old_contents), &split_lines(&new_contents)) {
+                match edit {
+                    Edit::Insert(_) => file.insertions += 1,
+                    Edit::Delete(_) => file.deletions += 1,
+                    Edit::Equal(..) => ()
+                }
+            }
+        }
+        stat.files.push(file);
+
+        match (old, new) {
+            (None, Some(new)) => stat.mode_changes.push(format!(" create mode {:o} {}", new.mode, path)),
+            (Some(old), None) => stat.mode_changes.push(format!(" delete mode {:o} {}", old.mode, path)),
+            (Some(old), Some(new)) if old.mode != new.mode => {
+                stat.mode_changes.push(format!(" mode change {:o} => {:o} {}", old.mode, new.mode, path))
+            },
+            _ => ()
+        }
+    }
+    Ok(stat)
+}
+
+/// A line for each file that differs between commit `old`, or no commit, and commit `new`, as `--raw` shows it:
+/// the old and new modes and abbreviated hashes, then a letter saying whether the file was added, deleted, modified
+/// or changed type, e.g. `:100644 100644 7898192 e61ef7b M\tfile.txt`
+pub fn raw_diff(root: &Path, old: Option<&[u8; 20]>, new: &[u8; 20], git_mode: bool) -> Result<Vec<String>> {
+    let old_files = match old {
+        Some(old) => commit_files(root, old, git_mode)?,
+        None => BTreeMap::new()
+    };
+    let new_files = commit_files(root, new, git_mode)?;
+    let mut paths: Vec<_> = old_files.keys().chain(new_files.keys()).collect();
+    paths.sort_by(|x, y| x.as_os_str().as_encoded_bytes().cmp(y.as_os_str().as_encoded_bytes()));
+    paths.dedup();
+
+    let mut lines = Vec::new();
+    for path in paths {
+        let old = old_files.get(path).map(|x| (x.mode, x.hash));
+        let new = new_files.get(path).map(|x| (x.mode, x.hash));
+        let status = match (old, new) {
+            (Some(old), Some(new)) if old == new => continue,
+            (None, _) => 'A',
+            (_, None) => 'D',
+            // The file type is in the upper bits of the mode
+            (Some((old_mode, _)), Some((new_mode, _))) if old_mode >> 12 != new_mode >> 12 => 'T',
+            _ => 'M'
+        };
+        let (old_mode, old_hash) = old.unwrap_or((0, [0; 20]));
+        let (new_mode, new_hash) = new.unwrap_or((0, [0; 20]));
+        lines.push(format!(
+            ":{:06o} {:06o} {} {} {}\t{}",
+            old_mode, new_mode, &hex::encode(old_hash)[..7], &hex::encode(new_hash)[..7], status, path.to_string_lossy()
+        ));
+    }
+    Ok(lines)
+}
+
+impl DiffStat {
+    /// The totals line, e.g. ` 2 files changed, 3 insertions(+), 1 deletion(-)`
+    pub fn summary(&self) -> String {
+        if self.files.is_empty() {
+            return String::from(" 0 files changed");
+        }
+        let insertions: usize = self.files.iter().map(|x| x.insertions).sum();
+        let deletions: usize = self.files.iter().map(|x| x.deletions).sum();
+
+        // Like Git, an insertion count is shown unless there are only deletions, and vice versa
+        let mut summary = format!(" {} file{} changed", self.files.len(), plural(self.files.len()));
+        if insertions > 0 || deletions == 0 {
+            summary += &format!(", {} insertion{}(+)", insertions, plural(insertions));
+        }
+        if deletions > 0 || insertions == 0 {
+            summary += &format!(", {} deletion{}(-)", deletions, plural(deletions));
+        }
+        summary
+    }
+
+    /// A line for each file with its number of changed lines and a graph of `+` and `-`, e.g. ` file.txt | 3 ++-`,
+    /// fitted into `width` columns in the same way as Git's `--stat`
+    pub fn graph(&self, width: usize) -> Vec<String> {
+        let text_files = self.files.iter().filter(|x| x.binary_sizes.is_none());
+        let max_change = text_files.map(|x| x.insertions + x.deletions).max().unwrap_or(0);
+        let max_name = self.files.iter().map(|x| x.path.chars().count()).max().unwrap_or(0);
+
+        // Binary files show "Bin XXX -> YYY bytes" in place of the count and graph
+        let mut number_width = max_change.to_string().len();
+        let mut bin_width = 0;
+        for (old_size, new_size) in self.files.iter().filter_map(|x| x.binary_sizes) {
+            bin_width = bin_width.max(14 + old_size.to_string().len() + new_size.to_string().len());
+            number_width = number_width.max(3);
+        }
+
+        let width = width.max(16 + 6 + number_width);
+        let mut graph_width = if max_change + 4 > bin_width { max_change } else { bin_width - 4 };
+        let mut name_width = max_name;
+        if name_width + number_width + 6 + graph_width > width {
+            if graph_width + number_width + 6 > width * 3 / 8 {
+                graph_width = (width * 3 / 8).saturating_sub(number_width + 6).max(6);
+            }
+            if name_width > width - number_width - 6 - graph_width {
+                name_width = width - number_width - 6 - graph_width;
+            } else {
+                graph_width = width - number_width - 6 - name_width;
+            }
+        }
+
+        let mut lines = Vec::new();
+        for file in &self.files {
+            // Names too long for the column are shortened from the start, at a directory boundary if possible
+            let mut name = file.path.clone();
+            let mut prefix = "";
+            if name.chars().count() > name_width {
+                prefix = "...";
+                let keep = name_width.saturating_sub(3);
+                name = name.chars().skip(name.chars().count() - keep).collect();
+                if let Some(slash) = name.find('/') {
+                    name = name[slash..].to_string();
+                }
+            }
+            let padding = name_width.saturating_sub(prefix.len() + name.chars().count());
+            let mut line = format!(" {}{}{} | ", prefix, name, " ".repeat(padding));
+
+            if let Some((old_size, new_size)) = file.binary_sizes {
+                line += &format!("{:>1$}", "Bin", number_width);
+                if old_size != 0 || new_size != 0 {
+                    line += &format!(" {} -> {} bytes", old_size, new_size);
+                }
+                lines.push(line);
+                continue;
+            }
+
+            let changes = file.insertions + file.deletions;
+            let (mut plus, mut minus) = (file.insertions, file.deletions);
+            if graph_width <= max_change {
+                let mut total = scale_linear(changes, graph_width, max_change);
+                if total < 2 && plus > 0 && minus > 0 {
+                    total = 2;
+                }
+                if plus < minus {
+                    plus = scale_linear(plus, graph_width, max_change);
+                    minus = total - plus;
+                } else {
+                    minus = scale_linear(minus, graph_width, max_change);
+                    plus = total - minus;
+                }
+            }
+            line += &format!("{:>1$}", changes, number_width);
+            if changes > 0 {
+                line += &format!(" {}{}", "+".repeat(plus), "-".repeat(minus));
+            }
+            lines.push(line);
+        }
+        lines
+    }
+}
+
+// Scales a count of changed lines down to the width of the graph, without letting a non-zero count disappear
+fn scale_linear(count: usize, width: usize, max_change: usize) -> usize {
+    if count == 0 {
+        return 0;
+    }
+    1 + count * (width - 1) / max_change
+}
+
+fn plural(count: usize) -> &'static str {
+    if count == 1 { "" } else { "s" }
+}
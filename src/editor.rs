@@ -0,0 +1,44 @@
+// Opening files in the user's editor, for writing messages and editing lists of commands
+
+use std::{env, path::Path, process::Command};
+use anyhow::{anyhow, bail, Result};
+
+use crate::config::Config;
+
+/// The editor to run, from `GIT_EDITOR`, `core.editor`, `VISUAL` or `EDITOR` in that order of preference
+pub fn git_editor(config: &Config) -> String {
+    env::var("GIT_EDITOR").ok()
+        .or(config.get("core.editor"))
+        .or(env::var("VISUAL").ok())
+        .or(env::var("EDITOR").ok())
+        .unwrap_or(String::from("vi"))
+}
+
+/// The editor to run on the todo list of an interactive rebase. `GIT_SEQUENCE_EDITOR` and `sequence.editor`
+/// choose a different editor to the one used for messages.
+pub fn sequence_editor(config: &Config) -> String {
+    env::var("GIT_SEQUENCE_EDITOR").ok()
+        .or(config.get("sequence.editor"))
+        .unwrap_or_else(|| git_editor(config))
+}
+
+/// Opens a file in the given editor and waits for it to exit. As in Git, the editor is run by the shell,
+/// so it can be given with arguments, and the editor `:` leaves the file as it is.
+pub fn launch_editor(root: &Path, file: &Path, editor: &str) -> Result<()> {
+    if editor == ":" {
+        return Ok(());
+    }
+
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{} \"$@\"", editor))
+        .arg(editor)
+        .arg(root.join(file))
+        .current_dir(root)
+        .status()
+        .map_err(|e| anyhow!("error: unable to start editor '{}': {}", editor, e))?;
+    if !status.success() {
+        bail!("error: There was a problem with the editor '{}'.", editor);
+    }
+    Ok(())
+}
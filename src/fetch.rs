@@ -0,0 +1,301 @@
+// Download objects and references from another repository
+
+use std::{env, fs, path::{Path, PathBuf}, process};
+use anyhow::{anyhow, bail, Result};
+use clap::Args;
+
+use crate::{GlobalOpts, git_dir_name, repo_find};
+use crate::commit::signature;
+use crate::config::Config;
+use crate::dag::{is_ancestor, CommitDag};
+use crate::http::DumbRemote;
+use crate::objects::{get_object, peel, Object};
+use crate::reachable::copy_objects;
+use crate::refs::{append_reflog, head_commit, list_refs, read_head, read_ref, write_ref, Head};
+use crate::remote::{default_remote, map_refspec, open_remote, remote_url};
+
+#[derive(Args)]
+pub struct FetchArgs {
+    /// The remote to fetch from, or the path or `file://` URL of a repository. Defaults to the current branch's
+    /// remote, or `origin` if it has none.
+    pub remote: Option<String>,
+    /// The references of the remote to fetch into FETCH_HEAD, e.g. branch names, rather than those its refspec gives
+    #[arg(requires = "remote")]
+    pub refs: Vec<String>,
+}
+
+// Whether a fetched reference is recorded in FETCH_HEAD, and if so whether pull should merge it. As in Git,
+// references are processed and recorded in this order.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum FetchHeadStatus {
+    Merge,
+    NotForMerge,
+    /// Fetched only to update its remote-tracking reference, as it's already recorded under another entry
+    Ignore,
+}
+
+// Where a remote's references and objects are read from
+enum Source {
+    /// A repository on the local file system, by its root
+    Local(PathBuf),
+    /// A repository served over HTTP without Git on the server
+    Http(DumbRemote),
+}
+
+// A reference of the remote being fetched
+struct FetchedRef {
+    /// The full name in the remote, e.g. `refs/heads/master`, or `HEAD`
+    remote_name: String,
+    /// The full name it's stored under locally, or None if it's only recorded in FETCH_HEAD
+    local_name: Option<String>,
+    hash: [u8; 20],
+    /// Whether the refspec allows the local reference to be updated even if it isn't a fast-forward
+    forced: bool,
+    status: FetchHeadStatus,
+}
+
+pub fn cmd_fetch(args: FetchArgs, global_opts: GlobalOpts) -> Result<()> {
+    let path = env::current_dir().unwrap_or_else(|_| { panic!() });
+    let root = repo_find(&path, global_opts).unwrap_or_else(|| {
+        panic!("fatal: not a grit repository");
+    });
+
+    // Like Git, the reflog entries are labelled with the command as it was given
+    let reflog_action = ["fetch"].into_iter()
+        .chain(args.remote.as_deref())
+        .chain(args.refs.iter().map(|x| x.as_str()))
+        .collect::<Vec<&str>>()
+        .join(" ");
+    let config = Config::load(&root, global_opts)?;
+    let remote = match args.remote {
+        Some(remote) => remote,
+        None => default_remote(&config, current_branch(&root, global_opts)?.as_deref())
+    };
+    if !fetch(&root, &path, &config, &remote, &args.refs, &reflog_action, global_opts)? {
+        process::exit(1);
+    }
+    Ok(())
+}
+
+/// Copies the objects and references of a remote into the repository, leaving local branches and the working tree
+/// alone. The remote's references are stored locally as its `remote.<name>.fetch` refspec says, and are all recorded
+/// in FETCH_HEAD, marking the one the current branch merges from. References given in `refs` are instead the only
+/// ones recorded in FETCH_HEAD, all marked for merging, though their remote-tracking references are still updated.
+/// Without either, only the remote's HEAD is fetched. Reflog entries are labelled with `reflog_action`.
+///
+/// Returns false if a reference couldn't be updated because the change wasn't a fast-forward and the refspec doesn't
+/// allow forcing it.
+pub fn fetch(
+    root: &Path,
+    cwd: &Path,
+    config: &Config,
+    remote: &str,
+    refs: &[String],
+    reflog_action: &str,
+    global_opts: GlobalOpts
+) -> Result<bool> {
+    // Like Git, FETCH_HEAD is emptied before the remote is looked at, so it's left empty if the fetch fails
+    let fetch_head_path = root.join(git_dir_name(global_opts)).join("FETCH_HEAD");
+    fs::write(&fetch_head_path, "")?;
+    let url = remote_url(config, remote);
+    let mut source = if url.starts_with("http://") || url.starts_with("https://") {
+        Source::Http(DumbRemote::open(&url)?)
+    } else {
+        Source::Local(open_remote(config, remote, cwd, global_opts)?.1)
+    };
+
+    let refspec = config.get(&format!("remote.{}.fetch", remote));
+    let forced = refspec.as_ref().is_some_and(|x| x.starts_with('+'));
+    let remote_refs = match &source {
+        Source::Local(remote_root) => list_refs(remote_root, global_opts)?,
+        Source::Http(remote) => remote.refs()?
+    };
+    let remote_head = match &source {
+        Source::Local(remote_root) => head_commit(remote_root, global_opts)?,
+        Source::Http(remote) => remote.head(&remote_refs)?
+    };
+    let mut fetched = Vec::new();
+    if !refs.is_empty() {
+        for name in refs {
+            let (full_name, hash) = match name.as_str() {
+                "HEAD" => remote_head.map(|x| (name.clone(), x)),
+                // Names are matched in the same order as revisions are resolved
+                _ => [name.clone(), format!("refs/{}", name), format!("refs/tags/{}", name), format!("refs/heads/{}", name)]
+                    .into_iter()
+                    .find_map(|x| remote_refs.iter().find(|(y, _)| *y == x).cloned())
+            }.ok_or(anyhow!("fatal: couldn't find remote ref {}", name))?;
+            fetched.push(FetchedRef { remote_name: full_name.clone(), local_name: None, hash, forced: false, status: FetchHeadStatus::Merge });
+            // Its remote-tracking reference is updated too, if the refspec gives one
+            if let Some(local_name) = refspec.as_ref().and_then(|x| map_refspec(x, &full_name)) {
+                fetched.push(FetchedRef { remote_name: full_name, local_name: Some(local_name), hash, forced, status: FetchHeadStatus::Ignore });
+            }
+        }
+    } else if let Some(refspec) = &refspec {
+        let merge = match current_branch(root, global_opts)? {
+            Some(branch) if config.get(&format!("branch.{}.remote", branch)).as_deref() == Some(remote) => {
+                config.get(&format!("branch.{}.merge", branch))
+            },
+            _ => None
+        };
+        for (name, hash) in remote_refs {
+            if let Some(local_name) = map_refspec(refspec, &name) {
+                let status = if merge.as_ref() == Some(&name) { FetchHeadStatus::Merge } else { FetchHeadStatus::NotForMerge };
+                fetched.push(FetchedRef { remote_name: name, local_name: Some(local_name), hash, forced, status });
+            }
+        }
+    } else if let Some(hash) = remote_head {
+        fetched.push(FetchedRef { remote_name: String::from("HEAD"), local_name: None, hash, forced: false, status: FetchHeadStatus::Merge });
+    }
+    fetched.sort_by_key(|x| x.status);
+
+    if let Head::Branch(current) = read_head(root, global_opts)? {
+        if fetched.iter().any(|x| x.local_name.as_ref() == Some(&current)) {
+            bail!("fatal: refusing to fetch into branch '{}' checked out at '{}'", current, root.to_string_lossy());
+        }
+    }
+
+    let hashes: Vec<[u8; 20]> = fetched.iter().map(|x| x.hash).collect();
+    match &mut source {
+        Source::Local(remote_root) => copy_objects(remote_root, &root.join(git_dir_name(global_opts)), &hashes, global_opts)?,
+        Source::Http(remote) => remote.download_objects(root, &hashes, global_opts)?
+    };
+
+    let dag = CommitDag::open(root, global_opts.git_mode)?;
+    let identity = signature(config, "COMMITTER", global_opts).ok();
+    let mut updates = Vec::new();
+    let mut success = true;
+    for x in &fetched {
+        let remote_short = short_name(&x.remote_name);
+        let Some(local_name) = &x.local_name else {
+            let (kind, name) = describe_ref(&x.remote_name);
+            let kind = if kind.is_empty() { "branch" } else { kind };
+            let name = if name.is_empty() { "HEAD" } else { name };
+            updates.push(('*', kind.to_string(), name.to_string(), String::from("FETCH_HEAD"), ""));
+            continue;
+        };
+        let old = read_ref(root, local_name, global_opts)?;
+        if old == Some(x.hash) {
+            continue;
+        }
+
+        let local_short = short_name(local_name);
+        let is_tag = local_name.starts_with("refs/tags/");
+        let stored = |kind: &str| {
+            let message = format!("storing {}", if kind == "branch" { "head" } else { kind });
+            ('*', format!("[new {}]", kind), message, "")
+        };
+        // As in Git, an existing tag is only moved when forced. Other references are compared by the commits they
+        // point at, and are simply stored if either isn't one.
+        let update = match old {
+            None => Some(stored(ref_kind(&x.remote_name))),
+            Some(_) if is_tag => x.forced.then(|| ('t', String::from("[tag update]"), String::from("updating tag"), "")),
+            Some(old) => match (peel_commit(root, &old, global_opts)?, peel_commit(root, &x.hash, global_opts)?) {
+                (Some(old_commit), Some(new_commit)) if is_ancestor(&dag, &old_commit, &new_commit)? => {
+                    let summary = format!("{}..{}", &hex::encode(old)[..7], &hex::encode(x.hash)[..7]);
+                    Some((' ', summary, String::from("fast-forward"), ""))
+                },
+                (Some(_), Some(_)) if x.forced => {
+                    let summary = format!("{}...{}", &hex::encode(old)[..7], &hex::encode(x.hash)[..7]);
+                    Some(('+', summary, String::from("forced-update"), "forced update"))
+                },
+                (Some(_), Some(_)) => None,
+                _ => Some(stored(ref_kind(local_name)))
+            }
+        };
+        let Some((code, summary, message, note)) = update else {
+            let note = if is_tag { "would clobber existing tag" } else { "non-fast-forward" };
+            updates.push(('!', String::from("[rejected]"), remote_short, local_short, note));
+            success = false;
+            continue;
+        };
+
+        write_ref(root, local_name, &x.hash, global_opts)?;
+        if let Some(identity) = &identity {
+            append_reflog(root, local_name, old, &x.hash, identity, &format!("{}: {}", reflog_action, message), global_opts)?;
+        }
+        updates.push((code, summary, remote_short, local_short, note));
+    }
+
+    // Like Git, the remote is shown without the `.git` many repository names end in
+    let trimmed = url.trim_end_matches('/');
+    let url = trimmed.strip_suffix(".git").filter(|_| trimmed.len() > 5).unwrap_or(trimmed);
+    fs::write(&fetch_head_path, fetch_head(url, &fetched))?;
+
+    if !updates.is_empty() {
+        eprintln!("From {}", url);
+    }
+    // As in Git, the remote names are lined up unless that would make a line too long for the terminal
+    let width = updates.iter()
+        .filter(|(_, _, remote, local, _)| 21 + remote.len() + 4 + local.len() < 80)
+        .map(|(_, _, remote, _, _)| remote.len())
+        .fold(10, usize::max);
+    for (code, summary, remote_short, local_short, note) in updates {
+        let note = if note.is_empty() { String::new() } else { format!("  ({})", note) };
+        eprintln!(" {} {:<17} {:<width$} -> {}{}", code, summary, remote_short, local_short, note);
+    }
+    Ok(success)
+}
+
+// The contents of FETCH_HEAD, which records the fetched references with whether they're to be merged
+fn fetch_head(url: &str, fetched: &[FetchedRef]) -> String {
+    let mut contents = String::new();
+    for x in fetched {
+        let description = match describe_ref(&x.remote_name) {
+            ("", "") => url.to_string(),
+            ("", name) => format!("'{}' of {}", name, url),
+            (kind, name) => format!("{} '{}' of {}", kind, name, url)
+        };
+        match x.status {
+            FetchHeadStatus::Merge => contents += &format!("{}\t\t{}\n", hex::encode(x.hash), description),
+            FetchHeadStatus::NotForMerge => contents += &format!("{}\tnot-for-merge\t{}\n", hex::encode(x.hash), description),
+            FetchHeadStatus::Ignore => ()
+        }
+    }
+    contents
+}
+
+// The name of the branch HEAD is on, without `refs/heads/`, or None if HEAD is detached
+fn current_branch(root: &Path, global_opts: GlobalOpts) -> Result<Option<String>> {
+    match read_head(root, global_opts)? {
+        Head::Branch(name) => Ok(Some(name.strip_prefix("refs/heads/").unwrap_or(&name).to_string())),
+        Head::Detached(_) => Ok(None)
+    }
+}
+
+// Follows tags to the commit they point at, returning None if it isn't a commit
+fn peel_commit(root: &Path, hash: &[u8; 20], global_opts: GlobalOpts) -> Result<Option<[u8; 20]>> {
+    let hash = peel(root, hash, global_opts.git_mode)?;
+    match get_object(root, &hash, global_opts.git_mode)? {
+        Object::Commit(_) => Ok(Some(hash)),
+        _ => Ok(None)
+    }
+}
+
+// Splits a reference name into the kind of reference it is and its short name, as FETCH_HEAD describes them, e.g.
+// `branch` and `master` for `refs/heads/master`. Other names have no kind, and HEAD has no name either.
+fn describe_ref(name: &str) -> (&'static str, &str) {
+    if name == "HEAD" {
+        return ("", "");
+    }
+    [("branch", "refs/heads/"), ("tag", "refs/tags/"), ("remote-tracking branch", "refs/remotes/")].iter()
+        .find_map(|(kind, prefix)| name.strip_prefix(prefix).map(|x| (*kind, x)))
+        .unwrap_or(("", name))
+}
+
+fn ref_kind(name: &str) -> &'static str {
+    if name.starts_with("refs/heads/") {
+        "branch"
+    } else if name.starts_with("refs/tags/") {
+        "tag"
+    } else {
+        "ref"
+    }
+}
+
+// Shortens a full reference name the way Git displays it, e.g. `refs/remotes/origin/master` to `origin/master`
+fn short_name(name: &str) -> String {
+    ["refs/heads/", "refs/tags/", "refs/remotes/"].iter()
+        .find_map(|x| name.strip_prefix(x))
+        .unwrap_or(name)
+        .to_string()
+}
@@ -0,0 +1,234 @@
+// Running the clean and smudge filters assigned to paths by the `filter` attribute, and converting line endings
+
+use std::{io::{self, Write}, path::{Path, PathBuf}, process::{Command, Output, Stdio}, thread};
+use anyhow::{anyhow, bail, Result};
+
+use crate::GlobalOpts;
+use crate::attributes::{AttrValue, Attributes};
+use crate::config::Config;
+
+/// Converts file contents between their form in the working tree and their form in the object store.
+/// A `[filter "<name>"]` section of the config gives the `clean` program, run when a file is stored,
+/// and the `smudge` program, run when a file is written to the working tree. Line endings are also converted
+/// as the `text` and `eol` attributes and `core.autocrlf` say, to LF when stored and possibly CRLF when written.
+pub struct Filters {
+    root: PathBuf,
+    config: Config,
+    attributes: Attributes,
+}
+
+impl Filters {
+    pub fn new(root: &Path, global_opts: GlobalOpts) -> Result<Filters> {
+        let config = Config::load(root, global_opts)?;
+        Ok(Filters {
+            root: root.to_path_buf(),
+            attributes: Attributes::new(root, &config, global_opts)?,
+            config,
+        })
+    }
+
+    /// Converts the contents of the working tree file at `path` (relative to the repository root) to be stored
+    pub fn clean(&mut self, path: &Path, contents: Vec<u8>) -> Result<Vec<u8>> {
+        let contents = self.apply("clean", path, contents)?;
+        let conversion = self.eol_conversion(path)?;
+        if conversion == EolConversion::None || (conversion.auto() && is_binary_text(&contents)) {
+            return Ok(contents);
+        }
+        Ok(crlf_to_lf(&contents))
+    }
+
+    /// Converts the stored contents of the file at `path` (relative to the repository root) for the working tree
+    pub fn smudge(&mut self, path: &Path, contents: Vec<u8>) -> Result<Vec<u8>> {
+        let conversion = self.eol_conversion(path)?;
+        let contents = if self.checkout_crlf(conversion)? && (!conversion.auto() || plain_lf_text(&contents)) {
+            lf_to_crlf(&contents)
+        } else {
+            contents
+        };
+        self.apply("smudge", path, contents)
+    }
+
+    // Works out from the attributes and config how the line endings of the file at `path` are converted, as Git does
+    fn eol_conversion(&mut self, path: &Path) -> Result<EolConversion> {
+        if let Some(conversion) = self.attribute_conversion(path)? {
+            return Ok(conversion);
+        }
+        // Without attributes saying otherwise, core.autocrlf turns on conversion for files that look like text
+        Ok(match self.config.get("core.autocrlf").as_deref() {
+            Some("input") => EolConversion::Auto { crlf: Some(false) },
+            _ if self.config.get_bool("core.autocrlf")? == Some(true) => EolConversion::Auto { crlf: Some(true) },
+            _ => EolConversion::None
+        })
+    }
+
+    // The conversion the `text` and `eol` attributes ask for, or None if they don't say
+    fn attribute_conversion(&mut self, path: &Path) -> Result<Option<EolConversion>> {
+        let text = self.attributes.get(path, "text")?;
+        let eol = match self.attributes.get(path, "eol")? {
+            AttrValue::Value(x) if x == "lf" => Some(false),
+            AttrValue::Value(x) if x == "crlf" => Some(true),
+            _ => None
+        };
+        Ok(match (text, eol) {
+            (AttrValue::Unset, _) => Some(EolConversion::None),
+            (AttrValue::Value(x), crlf) if x == "auto" => Some(EolConversion::Auto { crlf }),
+            (_, Some(crlf)) => Some(EolConversion::Text { crlf: Some(crlf) }),
+            (AttrValue::Set, None) => Some(EolConversion::Text { crlf: None }),
+            _ => None
+        })
+    }
+
+    /// Describes the line ending conversion the attributes ask for at `path`, as `ls-files --eol` does: `text`,
+    /// `-text` or `text=auto`, followed by the line endings if they're given, e.g. `text eol=crlf`. Empty if the
+    /// attributes don't say.
+    pub fn eol_attributes(&mut self, path: &Path) -> Result<&'static str> {
+        Ok(match self.attribute_conversion(path)? {
+            None => "",
+            Some(EolConversion::None) => "-text",
+            Some(EolConversion::Text { crlf: None }) => "text",
+            Some(EolConversion::Text { crlf: Some(false) }) => "text eol=lf",
+            Some(EolConversion::Text { crlf: Some(true) }) => "text eol=crlf",
+            Some(EolConversion::Auto { crlf: None }) => "text=auto",
+            Some(EolConversion::Auto { crlf: Some(false) }) => "text=auto eol=lf",
+            Some(EolConversion::Auto { crlf: Some(true) }) => "text=auto eol=crlf",
+        })
+    }
+
+    // Whether files with this conversion are written to the working tree with CRLF line endings. Where the
+    // attributes don't say, core.autocrlf decides, then core.eol.
+    fn checkout_crlf(&self, conversion: EolConversion) -> Result<bool> {
+        let (EolConversion::Text { crlf } | EolConversion::Auto { crlf }) = conversion else { return Ok(false) };
+        if let Some(crlf) = crlf {
+            return Ok(crlf);
+        }
+        Ok(match self.config.get("core.autocrlf").as_deref() {
+            Some("input") => false,
+            _ if self.config.get_bool("core.autocrlf")? == Some(true) => true,
+            _ => self.config.get("core.eol").as_deref() == Some("crlf")
+        })
+    }
+
+    fn apply(&mut self, kind: &str, path: &Path, contents: Vec<u8>) -> Result<Vec<u8>> {
+        let AttrValue::Value(name) = self.attributes.get(path, "filter")? else { return Ok(contents) };
+        let Some(command) = self.config.get(&format!("filter.{}.{}", name, kind)) else { return Ok(contents) };
+
+        // `%f` in the command is replaced with the path of the file being filtered
+        let path_str = path.to_string_lossy();
+        let command = command.replace("%f", &format!("'{}'", path_str.replace('\'', "'\\''")));
+
+        let output = run_filter(&self.root, &command, contents)
+            .map_err(|e| anyhow!("error: cannot run {}: {}", command, e))?;
+        if !output.status.success() {
+            bail!(
+                "error: external filter '{}' failed {}\nfatal: {}: {} filter '{}' failed",
+                command, output.status.code().unwrap_or(-1), path_str, kind, name
+            );
+        }
+        Ok(output.stdout)
+    }
+}
+
+// How a file's line endings are converted. With Auto, files that don't look like text are left alone. `crlf` says
+// whether they're written to the working tree with CRLF, or None if that's left to the config.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum EolConversion {
+    None,
+    Text { crlf: Option<bool> },
+    Auto { crlf: Option<bool> },
+}
+
+impl EolConversion {
+    fn auto(self) -> bool {
+        matches!(self, EolConversion::Auto { .. })
+    }
+}
+
+// Whether contents should be treated as binary rather than text when deciding if their line endings are converted:
+// if they have a NUL byte, a CR that doesn't start a CRLF, or too many other control characters
+fn is_binary_text(contents: &[u8]) -> bool {
+    let mut printable = 0;
+    let mut nonprintable = 0;
+    for (i, byte) in contents.iter().enumerate() {
+        match byte {
+            b'\r' if contents.get(i + 1) != Some(&b'\n') => return true,
+            0 => return true,
+            b'\r' | b'\n' => (),
+            // Backspace, tab, escape and form feed are common in text
+            8 | 9 | 27 | 12 => printable += 1,
+            0..=31 | 127 => nonprintable += 1,
+            _ => printable += 1,
+        }
+    }
+    (printable >> 7) < nonprintable
+}
+
+/// Describes the line endings of some contents, as `ls-files --eol` does: `lf`, `crlf` or `mixed`, `none` if there
+/// are no line ends, or `-text` if the contents don't look like text
+pub fn line_endings(contents: &[u8]) -> &'static str {
+    if is_binary_text(contents) {
+        return "-text";
+    }
+    let crlf = contents.windows(2).any(|x| x == b"\r\n");
+    let lone_lf = contents.iter().enumerate().any(|(i, x)| *x == b'\n' && (i == 0 || contents[i - 1] != b'\r'));
+    match (lone_lf, crlf) {
+        (true, false) => "lf",
+        (false, true) => "crlf",
+        (true, true) => "mixed",
+        (false, false) => "none"
+    }
+}
+
+// Whether automatic conversion would write the contents with CRLF: they must be text with at least one LF, and
+// none of their lines may already end with CR
+fn plain_lf_text(contents: &[u8]) -> bool {
+    contents.contains(&b'\n') && !contents.contains(&b'\r') && !is_binary_text(contents)
+}
+
+fn crlf_to_lf(contents: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(contents.len());
+    for (i, byte) in contents.iter().enumerate() {
+        if *byte != b'\r' || contents.get(i + 1) != Some(&b'\n') {
+            output.push(*byte);
+        }
+    }
+    output
+}
+
+// Puts a CR before each LF that doesn't already have one
+fn lf_to_crlf(contents: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(contents.len());
+    for (i, byte) in contents.iter().enumerate() {
+        if *byte == b'\n' && (i == 0 || contents[i - 1] != b'\r') {
+            output.push(b'\r');
+        }
+        output.push(*byte);
+    }
+    output
+}
+
+// Runs the command with the shell, passing the contents on its standard input
+fn run_filter(root: &Path, command: &str, contents: Vec<u8>) -> io::Result<Output> {
+    let mut shell = Command::new("sh");
+    shell.arg("-c").arg(command).current_dir(root);
+    run_with_input(&mut shell, contents)
+}
+
+/// Runs a program, writing the input to its standard input and collecting its standard output.
+/// Its standard error is collected too if the command was set up to pipe it.
+pub fn run_with_input(command: &mut Command, input: Vec<u8>) -> io::Result<Output> {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+
+    // Write from another thread, since the program may not read all its input before its output fills the pipe
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let writer = thread::spawn(move || {
+        // The program may exit without reading everything, which is reported through its exit status instead
+        let _ = stdin.write_all(&input);
+    });
+
+    let output = child.wait_with_output()?;
+    let _ = writer.join();
+    Ok(output)
+}
@@ -0,0 +1,141 @@
+// Show details of each reference in a given format
+
+use std::env;
+use anyhow::{bail, Result};
+use clap::Args;
+
+use crate::{GlobalOpts, repo_find};
+use crate::ignore::wildmatch;
+use crate::objects::{get_object, GitObject, Object};
+use crate::refs::list_refs;
+
+// What is shown for each reference when no format is given
+const DEFAULT_FORMAT: &str = "%(objectname) %(objecttype)\t%(refname)";
+
+#[derive(Args)]
+pub struct ForEachRefArgs {
+    /// Only show references whose names start with one of these, counting whole components, or match one as a glob
+    patterns: Vec<String>,
+    /// What to show for each reference, in which `%(refname)`, `%(objectname)` and `%(objecttype)` are replaced.
+    /// `:short` after refname or objectname shortens it, and a `*` before objectname or objecttype describes the
+    /// object an annotated tag points at.
+    #[arg(long, default_value = DEFAULT_FORMAT)]
+    format: String,
+    /// Stop after showing this many references
+    #[arg(long)]
+    count: Option<usize>,
+}
+
+// A piece of a format
+enum FormatPart {
+    Literal(String),
+    RefName { short: bool },
+    ObjectName { short: bool, dereference: bool },
+    ObjectType { dereference: bool },
+}
+
+pub fn cmd_for_each_ref(args: ForEachRefArgs, global_opts: GlobalOpts) -> Result<()> {
+    let path = env::current_dir().unwrap_or_else(|_| { panic!() });
+    let root = repo_find(&path, global_opts).unwrap_or_else(|| {
+        panic!("fatal: not a grit repository");
+    });
+
+    let parts = parse_format(&args.format)?;
+    let refs = list_refs(&root, global_opts)?.into_iter()
+        .filter(|(name, _)| args.patterns.is_empty() || args.patterns.iter().any(|x| matches_pattern(name, x)))
+        .take(args.count.unwrap_or(usize::MAX));
+    for (name, hash) in refs {
+        let object = get_object(&root, &hash, global_opts.git_mode)?;
+        // Only an annotated tag can be dereferenced, and only by one step
+        let target = match &object {
+            Object::Tag(tag) => Some((tag.object, get_object(&root, &tag.object, global_opts.git_mode)?)),
+            _ => None
+        };
+
+        let mut line = String::new();
+        for part in &parts {
+            match part {
+                FormatPart::Literal(text) => line += text,
+                FormatPart::RefName { short: false } => line += &name,
+                FormatPart::RefName { short: true } => line += short_ref_name(&name),
+                FormatPart::ObjectName { short, dereference } => {
+                    let hash = if *dereference { target.as_ref().map(|(x, _)| *x) } else { Some(hash) };
+                    if let Some(hash) = hash {
+                        line += &hex::encode(hash)[..if *short { 7 } else { 40 }];
+                    }
+                },
+                FormatPart::ObjectType { dereference: false } => line += object.object_type().as_str(),
+                FormatPart::ObjectType { dereference: true } => {
+                    if let Some((_, target)) = &target {
+                        line += target.object_type().as_str();
+                    }
+                }
+            }
+        }
+        println!("{}", line);
+    }
+    Ok(())
+}
+
+// Whether a reference name is selected by a pattern: the pattern is the name or a leading run of its components, or
+// matches it as a glob in which `*` doesn't cross a slash
+fn matches_pattern(name: &str, pattern: &str) -> bool {
+    let prefix_matches = name.strip_prefix(pattern)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/') || pattern.ends_with('/'));
+    prefix_matches || wildmatch(pattern.as_bytes(), name.as_bytes())
+}
+
+// The name of a reference without the part that says what kind it is, e.g. `master` for `refs/heads/master`
+fn short_ref_name(name: &str) -> &str {
+    ["refs/heads/", "refs/tags/", "refs/remotes/", "refs/"].iter()
+        .find_map(|x| name.strip_prefix(x))
+        .unwrap_or(name)
+}
+
+// Splits a format into literal text and the fields to be replaced. As in Git, `%%` stands for `%`, and a `%` followed
+// by anything but a parenthesis is left as it is.
+fn parse_format(format: &str) -> Result<Vec<FormatPart>> {
+    let mut parts = Vec::new();
+    let mut literal = String::new();
+    let mut rest = format;
+    while let Some(start) = rest.find('%') {
+        literal.push_str(&rest[..start]);
+        rest = &rest[start + 1..];
+        if let Some(after) = rest.strip_prefix('%') {
+            literal.push('%');
+            rest = after;
+            continue;
+        }
+        if !rest.starts_with('(') {
+            literal.push('%');
+            continue;
+        }
+        let Some(end) = rest.find(')') else {
+            bail!("error: malformed format string %{}", rest);
+        };
+        let field = &rest[1..end];
+        let (dereference, name) = match field.strip_prefix('*') {
+            Some(name) => (true, name),
+            None => (false, field)
+        };
+        let part = match name {
+            "refname" if !dereference => FormatPart::RefName { short: false },
+            "refname:short" if !dereference => FormatPart::RefName { short: true },
+            "objectname" => FormatPart::ObjectName { short: false, dereference },
+            "objectname:short" => FormatPart::ObjectName { short: true, dereference },
+            "objecttype" => FormatPart::ObjectType { dereference },
+            _ => bail!("fatal: unknown field name: {}", field)
+        };
+        if !literal.is_empty() {
+            parts.push(FormatPart::Literal(literal.clone()));
+            literal.clear();
+        }
+        parts.push(part);
+        rest = &rest[end + 1..];
+    }
+    literal.push_str(rest);
+    if !literal.is_empty() {
+        parts.push(FormatPart::Literal(literal));
+    }
+    Ok(parts)
+}
@@ -0,0 +1,261 @@
+// Formatting commits as emails, one file per commit, which can be sent to a mailing list and applied with `am`
+
+use std::{env, fs, io::Write, path::{Path, PathBuf}};
+use anyhow::{bail, Result};
+use clap::Args;
+
+use crate::{GlobalOpts, repo_find};
+use crate::attributes::Attributes;
+use crate::checkout::commit_files;
+use crate::config::Config;
+use crate::dag::CommitDag;
+use crate::date::{format_date, DateFormat};
+use crate::diff::{configured_algorithm, file_patch, read_blob};
+use crate::diffstat::diff_stat;
+use crate::objects::{get_object, peel, Commit, Object, Signature};
+use crate::rebase::commits_to_replay;
+use crate::refs::resolve_revision;
+
+// Like Git, the diffstat is fitted to the width of a typical email
+const MAIL_WRAP: usize = 72;
+// Header lines longer than this are folded onto continuation lines
+const HEADER_WIDTH: usize = 78;
+// Words encoded as in RFC 2047 have a stricter limit
+const ENCODED_HEADER_WIDTH: usize = 76;
+// The longest name a patch file is given, including its number and extension
+const PATCH_NAME_MAX: usize = 64;
+
+#[derive(Args)]
+pub struct FormatPatchArgs {
+    /// The commits to format: `<since>` for the commits on HEAD since then, or `<since>..<until>`
+    revision_range: String,
+    /// Write the patch files to this directory rather than the current one
+    #[arg(short, long)]
+    output_directory: Option<String>,
+    /// Print the patches to standard output rather than writing them to files
+    #[arg(long)]
+    stdout: bool,
+}
+
+pub fn cmd_format_patch(args: FormatPatchArgs, global_opts: GlobalOpts) -> Result<()> {
+    let path = env::current_dir().unwrap_or_else(|_| { panic!() });
+    let root = repo_find(&path, global_opts).unwrap_or_else(|| {
+        panic!("fatal: not a grit repository");
+    });
+    let git_mode = global_opts.git_mode;
+
+    let (since, until) = match args.revision_range.split_once("..") {
+        Some((since, until)) => (since, until),
+        None => (args.revision_range.as_str(), "HEAD")
+    };
+    let since = resolve_commit(&root, if since.is_empty() { "HEAD" } else { since }, global_opts)?;
+    let until = resolve_commit(&root, if until.is_empty() { "HEAD" } else { until }, global_opts)?;
+    let dag = CommitDag::open(&root, git_mode)?;
+    let commits = commits_to_replay(&dag, &since, &until)?;
+
+    let config = Config::load(&root, global_opts)?;
+    let mut attributes = Attributes::new(&root, &config, global_opts)?;
+    let signature = config.get("format.signature").unwrap_or(String::from(env!("CARGO_PKG_VERSION")));
+    let output_dir = args.output_directory.as_deref().map(Path::new).unwrap_or(Path::new(""));
+    if !args.stdout && !output_dir.as_os_str().is_empty() {
+        fs::create_dir_all(path.join(output_dir))?;
+    }
+
+    for (i, hash) in commits.iter().enumerate() {
+        let commit = match get_object(&root, hash, git_mode)? {
+            Object::Commit(c) => c,
+            _ => bail!("fatal: object {} is not a commit", hex::encode(hash))
+        };
+        let number = if commits.len() > 1 { Some((i + 1, commits.len())) } else { None };
+        let mut patch = format_email(&root, hash, &commit, number, &config, &mut attributes, git_mode)?;
+        patch.extend(format!("-- \n{}\n\n", signature).into_bytes());
+
+        if args.stdout {
+            // Patches printed together are separated by a blank line, as in an mbox file
+            if i > 0 {
+                println!();
+            }
+            std::io::stdout().write_all(&patch)?;
+        } else {
+            let file = output_dir.join(patch_file_name(i + 1, &commit.subject()));
+            fs::write(path.join(&file), patch)?;
+            println!("{}", file.to_string_lossy());
+        }
+    }
+    Ok(())
+}
+
+fn resolve_commit(root: &Path, rev: &str, global_opts: GlobalOpts) -> Result<[u8; 20]> {
+    let hash = peel(root, &resolve_revision(root, rev, global_opts)?, global_opts.git_mode)?;
+    match get_object(root, &hash, global_opts.git_mode)? {
+        Object::Commit(_) => Ok(hash),
+        _ => bail!("fatal: object {} is not a commit", hex::encode(hash))
+    }
+}
+
+// Formats a commit as an email in mbox format: the headers, the message, a diffstat and the changes to each file.
+// `number` is the position of the patch in the series and the length of the series, if there's more than one.
+fn format_email(
+    root: &Path,
+    hash: &[u8; 20],
+    commit: &Commit,
+    number: Option<(usize, usize)>,
+    config: &Config,
+    attributes: &mut Attributes,
+    git_mode: bool
+) -> Result<Vec<u8>> {
+    let author = Signature::parse(&commit.author)?;
+    let mut email = format!("From {} Mon Sep 17 00:00:00 2001\n", hex::encode(hash));
+
+    let mut from = String::from("From: ");
+    if needs_rfc2047_encoding(&author.name) {
+        from += &encode_rfc2047(&author.name, from.len(), true);
+    } else if needs_rfc822_quoting(&author.name) {
+        from += &format!("\"{}\"", author.name.replace('\\', "\\\\").replace('"', "\\\""));
+    } else {
+        from += &author.name;
+    }
+    // The address is moved to a line of its own if it won't fit on the line with the name
+    let last_line = from.rsplit('\n').next().unwrap_or_default().len();
+    if last_line + author.email.len() + 3 > HEADER_WIDTH {
+        from.push('\n');
+    }
+    email += &format!("{} <{}>\n", from, author.email);
+    email += &format!("Date: {}\n", format_date(author.timestamp, &author.timezone, DateFormat::Rfc2822));
+
+    let mut subject = match number {
+        Some((n, total)) => format!("Subject: [PATCH {}/{}] ", n, total),
+        None => String::from("Subject: [PATCH] ")
+    };
+    if needs_rfc2047_encoding(&commit.subject()) {
+        subject += &encode_rfc2047(&commit.subject(), subject.len(), false);
+    } else {
+        fold_header(&mut subject, &commit.subject());
+    }
+    email += &subject;
+    email.push('\n');
+    if !commit.message.is_ascii() {
+        email += "MIME-Version: 1.0\nContent-Type: text/plain; charset=UTF-8\nContent-Transfer-Encoding: 8bit\n";
+    }
+    email += &format!("\n{}---\n", commit.body());
+
+    let parent = commit.parents.first();
+    let stat = diff_stat(root, parent, hash, git_mode)?;
+    for line in stat.graph(MAIL_WRAP) {
+        email += &format!("{}\n", line);
+    }
+    email += &format!("{}\n", stat.summary());
+    for line in stat.mode_changes {
+        email += &format!("{}\n", line);
+    }
+    email.push('\n');
+
+    let old_files = match parent {
+        Some(parent) => commit_files(root, parent, git_mode)?,
+        None => Default::default()
+    };
+    let new_files = commit_files(root, hash, git_mode)?;
+    let mut paths: Vec<&PathBuf> = old_files.keys().chain(new_files.keys()).collect();
+    paths.sort_by(|x, y| x.as_os_str().as_encoded_bytes().cmp(y.as_os_str().as_encoded_bytes()));
+    paths.dedup();
+
+    let algorithm = configured_algorithm(config)?;
+    let mut email = email.into_bytes();
+    for path in paths {
+        let old = old_files.get(path).map(|x| read_blob(root, &x.hash, x.mode, git_mode)).transpose()?;
+        let new = new_files.get(path).map(|x| read_blob(root, &x.hash, x.mode, git_mode)).transpose()?;
+        if old.as_ref().map(|x| (x.hash, x.mode)) == new.as_ref().map(|x| (x.hash, x.mode)) {
+            continue;
+        }
+        let name = path.to_string_lossy();
+        email.extend(file_patch(root, &name, old.as_ref(), new.as_ref(), config, attributes, algorithm)?);
+    }
+    Ok(email)
+}
+
+// Names the file for the nth patch after its subject, e.g. `0001-Fix-the-thing.patch`. As in Git, runs of characters
+// other than letters, digits, `.` and `_` become a single `-`, and the name is cut short if the subject is long.
+fn patch_file_name(n: usize, subject: &str) -> String {
+    let mut name = format!("{:04}-", n);
+    let start = name.len();
+    let mut separated = false;
+    let mut previous = None;
+    for c in subject.chars() {
+        if c.is_ascii_alphanumeric() || c == '.' || c == '_' {
+            if separated && name.len() > start {
+                name.push('-');
+            }
+            separated = false;
+            // Runs of dots are collapsed, so that the name can't contain `..`
+            if !(c == '.' && previous == Some('.')) {
+                name.push(c);
+            }
+        } else {
+            separated = true;
+        }
+        previous = Some(c);
+    }
+    while name.len() > start && name.ends_with(['.', '-']) {
+        name.pop();
+    }
+    name.truncate(PATCH_NAME_MAX - ".patch".len() - 1);
+    name + ".patch"
+}
+
+// Appends words to a header line, starting a continuation line whenever the next word wouldn't fit
+fn fold_header(header: &mut String, text: &str) {
+    let mut width = header.chars().count();
+    for (i, word) in text.split(' ').enumerate() {
+        let length = word.chars().count();
+        if i == 0 {
+            width += length;
+        } else if width + 1 + length > HEADER_WIDTH {
+            header.push_str("\n ");
+            width = 1 + length;
+        } else {
+            header.push(' ');
+            width += 1 + length;
+        }
+        header.push_str(word);
+    }
+}
+
+// Text that isn't ASCII can only be put in a header once encoded
+fn needs_rfc2047_encoding(text: &str) -> bool {
+    !text.is_ascii() || text.contains('\n') || text.contains("=?")
+}
+
+// Names containing characters that are special in email addresses must be quoted
+fn needs_rfc822_quoting(name: &str) -> bool {
+    name.chars().any(|c| !c.is_ascii_alphanumeric() && c != ' ' && !"!#$%&'*+-/=?^_`{|}~".contains(c))
+}
+
+// Encodes text as quoted-printable words as described in RFC 2047, e.g. `=?UTF-8?q?Caf=C3=A9?=`, starting new
+// words on continuation lines to keep within the line length. `line_length` is the length of the line so far.
+// Fewer characters can be left as they are in an address than in other headers.
+fn encode_rfc2047(text: &str, line_length: usize, address: bool) -> String {
+    let mut encoded = String::from("=?UTF-8?q?");
+    let mut line_length = line_length + encoded.len();
+    for c in text.chars() {
+        let mut buffer = [0; 4];
+        let bytes = c.encode_utf8(&mut buffer).as_bytes();
+        let special = bytes.len() > 1 || is_rfc2047_special(bytes[0], address);
+        let piece = if special { bytes.iter().map(|x| format!("={:02X}", x)).collect() } else { c.to_string() };
+
+        // Each word must be closed with `?=` within the line, and can't split a character
+        if line_length + piece.len() + 2 > ENCODED_HEADER_WIDTH {
+            encoded += "?=\n =?UTF-8?q?";
+            line_length = " =?UTF-8?q?".len();
+        }
+        encoded += &piece;
+        line_length += piece.len();
+    }
+    encoded + "?="
+}
+
+fn is_rfc2047_special(byte: u8, address: bool) -> bool {
+    if !byte.is_ascii_graphic() || matches!(byte, b'=' | b'?' | b'_') {
+        return true;
+    }
+    address && !(byte.is_ascii_alphanumeric() || matches!(byte, b'!' | b'*' | b'+' | b'-' | b'/'))
+}
@@ -0,0 +1,227 @@
+// Signing commits and tags with GPG or SSH, and checking their signatures
+
+use std::{env, fs, path::{Path, PathBuf}, process::{self, Command, Stdio}};
+use anyhow::{anyhow, bail, Result};
+
+use crate::config::Config;
+use crate::filter::run_with_input;
+
+/// The kinds of signature Git supports, told apart by the first line of the signature
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SignatureFormat {
+    OpenPgp,
+    X509,
+    Ssh,
+}
+
+const SIGNATURE_HEADERS: [(&str, SignatureFormat); 4] = [
+    ("-----BEGIN PGP SIGNATURE-----", SignatureFormat::OpenPgp),
+    ("-----BEGIN PGP MESSAGE-----", SignatureFormat::OpenPgp),
+    ("-----BEGIN SIGNED MESSAGE-----", SignatureFormat::X509),
+    ("-----BEGIN SSH SIGNATURE-----", SignatureFormat::Ssh),
+];
+
+/// The result of checking a signature
+pub struct SignatureCheck {
+    /// Whether the signature is valid and was made by a trusted key
+    pub good: bool,
+    /// The messages from the signing program describing the signature, to be shown to the user
+    pub output: String,
+}
+
+/// Returns the format of the given signature, or None if it isn't a signature Git recognises
+pub fn signature_format(signature: &str) -> Option<SignatureFormat> {
+    SIGNATURE_HEADERS.iter().find(|(header, _)| signature.starts_with(header)).map(|(_, format)| *format)
+}
+
+/// Returns the position of the signature appended to the message of a signed tag, if it has one
+pub fn signature_start(message: &str) -> Option<usize> {
+    let mut pos = 0;
+    for line in message.split_inclusive('\n') {
+        if signature_format(line).is_some() {
+            return Some(pos);
+        }
+        pos += line.len();
+    }
+    None
+}
+
+/// Signs the payload with the configured program, chosen by `gpg.format`, returning the ASCII-armored signature.
+/// The key defaults to `user.signingKey`, or for GPG to the committer's identity, e.g. `Name <email>`.
+pub fn sign_payload(payload: &[u8], key: Option<&str>, committer: &str, config: &Config) -> Result<String> {
+    let format = match config.get("gpg.format").as_deref() {
+        None | Some("openpgp") => SignatureFormat::OpenPgp,
+        Some("x509") => SignatureFormat::X509,
+        Some("ssh") => SignatureFormat::Ssh,
+        Some(other) => bail!("error: unsupported value for gpg.format: {}", other)
+    };
+    let key = key.map(String::from).or(config.get("user.signingkey"));
+
+    let signature = match format {
+        SignatureFormat::Ssh => {
+            let key = key.ok_or(anyhow!("error: user.signingKey needs to be set for ssh signing"))?;
+            sign_ssh(payload, &key, config)?
+        },
+        _ => sign_gpg(payload, &key.unwrap_or(committer.to_string()), format, config)?
+    };
+
+    // Signatures made on Windows may have CRLF line endings
+    Ok(signature.replace("\r\n", "\n"))
+}
+
+// GPG writes a detached signature to standard output and confirms it was made on the status file descriptor
+fn sign_gpg(payload: &[u8], key: &str, format: SignatureFormat, config: &Config) -> Result<String> {
+    let program = gpg_program(format, config);
+    let mut command = Command::new(&program);
+    command.args(["--status-fd=2", "-bsau", key]).stderr(Stdio::piped());
+    let output = run_with_input(&mut command, payload.to_vec())
+        .map_err(|e| anyhow!("error: cannot run {}: {}", program, e))?;
+
+    let status = String::from_utf8_lossy(&output.stderr);
+    if !output.status.success() || !status.lines().any(|x| x.starts_with("[GNUPG:] SIG_CREATED ")) {
+        eprint!("{}", status);
+        bail!("error: gpg failed to sign the data");
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+// ssh-keygen signs a file, writing the signature alongside it. The key is either the path of a key file,
+// or a public key given literally, e.g. `key::ssh-ed25519 AAAA...`, whose private key is held by ssh-agent.
+fn sign_ssh(payload: &[u8], key: &str, config: &Config) -> Result<String> {
+    let program = config.get("gpg.ssh.program").unwrap_or(String::from("ssh-keygen"));
+    let literal_key = key.strip_prefix("key::").or(if key.starts_with("ssh-") { Some(key) } else { None });
+
+    let temp_prefix = env::temp_dir().join(format!("grit-{}", process::id()));
+    let key_file = match literal_key {
+        Some(literal) => {
+            let key_file = temp_prefix.with_extension("signing-key");
+            fs::write(&key_file, literal)?;
+            key_file
+        },
+        None => PathBuf::from(key)
+    };
+    let buffer_file = temp_prefix.with_extension("signing-buffer");
+    fs::write(&buffer_file, payload)?;
+
+    let mut command = Command::new(&program);
+    command.args(["-Y", "sign", "-n", "git", "-f"]).arg(&key_file);
+    if literal_key.is_some() {
+        command.arg("-U");
+    }
+    let output = command.arg(&buffer_file).output();
+
+    let signature_file = temp_prefix.with_extension("signing-buffer.sig");
+    let signature = fs::read_to_string(&signature_file);
+    let _ = fs::remove_file(&signature_file);
+    fs::remove_file(&buffer_file)?;
+    if literal_key.is_some() {
+        fs::remove_file(&key_file)?;
+    }
+
+    let output = output.map_err(|e| anyhow!("error: cannot run {}: {}", program, e))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if stderr.contains("usage:") {
+            eprintln!("error: ssh-keygen -Y sign is needed for ssh signing (available in openssh version 8.2p1+)");
+        }
+        bail!("error: {}", stderr.trim_end());
+    }
+    signature.map_err(|e| anyhow!("error: failed reading ssh signing data buffer from '{}': {}", signature_file.to_string_lossy(), e))
+}
+
+/// Checks that the signature was made over the payload, using the program configured for its format
+pub fn verify_signature(payload: &[u8], signature: &str, config: &Config) -> Result<SignatureCheck> {
+    let format = signature_format(signature).ok_or(anyhow!("error: unknown signature format"))?;
+
+    // The programs read the signature from a file and the payload from standard input
+    let signature_file = env::temp_dir().join(format!("grit-{}-signature", process::id()));
+    fs::write(&signature_file, signature)?;
+    let result = match format {
+        SignatureFormat::Ssh => verify_ssh(payload, &signature_file, config),
+        _ => verify_gpg(payload, &signature_file, format, config)
+    };
+    fs::remove_file(&signature_file)?;
+    result
+}
+
+// GPG reports whether the signature is good on the file descriptor given by `--status-fd`,
+// and describes the signature to the user on standard error
+fn verify_gpg(payload: &[u8], signature_file: &Path, format: SignatureFormat, config: &Config) -> Result<SignatureCheck> {
+    let program = gpg_program(format, config);
+    let mut command = Command::new(&program);
+    command.arg("--status-fd=1").arg("--verify").arg(signature_file).arg("-").stderr(Stdio::piped());
+    let output = run_with_input(&mut command, payload.to_vec())
+        .map_err(|e| anyhow!("error: cannot run {}: {}", program, e))?;
+
+    let status = String::from_utf8_lossy(&output.stdout);
+    let good_signature = status.lines().any(|x| x.starts_with("[GNUPG:] GOODSIG "));
+    let bad_signature = status.lines().any(|x| x.starts_with("[GNUPG:] BADSIG ") || x.starts_with("[GNUPG:] ERRSIG "));
+
+    Ok(SignatureCheck {
+        good: output.status.success() && good_signature && !bad_signature,
+        output: String::from_utf8_lossy(&output.stderr).to_string(),
+    })
+}
+
+// SSH signatures are checked against the keys listed in the allowed signers file. The principals
+// whose keys could have made the signature are found first, then the signature is verified for each.
+fn verify_ssh(payload: &[u8], signature_file: &Path, config: &Config) -> Result<SignatureCheck> {
+    let program = config.get("gpg.ssh.program").unwrap_or(String::from("ssh-keygen"));
+    let Some(allowed_signers) = config.get("gpg.ssh.allowedsignersfile") else {
+        bail!("error: gpg.ssh.allowedSignersFile needs to be configured and exist for ssh signature verification");
+    };
+
+    let principals = Command::new(&program)
+        .args(["-Y", "find-principals", "-f", &allowed_signers, "-s"])
+        .arg(signature_file)
+        .output()
+        .map_err(|e| anyhow!("error: cannot run {}: {}", program, e))?;
+    let principals_out = String::from_utf8_lossy(&principals.stdout).to_string();
+    let principals_err = String::from_utf8_lossy(&principals.stderr).to_string();
+
+    let mut good = false;
+    let mut out = String::new();
+    let mut err = String::new();
+    if !principals.status.success() || principals_out.trim().is_empty() {
+        // Without a known signer the signature can only be checked for validity, so it's never reported as good
+        let mut command = Command::new(&program);
+        command.args(["-Y", "check-novalidate", "-n", "git", "-s"]).arg(signature_file).stderr(Stdio::piped());
+        let output = run_with_input(&mut command, payload.to_vec())
+            .map_err(|e| anyhow!("error: cannot run {}: {}", program, e))?;
+        out = String::from_utf8_lossy(&output.stdout).to_string();
+        err = String::from_utf8_lossy(&output.stderr).to_string() + &principals_err;
+    } else {
+        for principal in principals_out.lines().filter(|x| !x.is_empty()) {
+            let mut command = Command::new(&program);
+            command.args(["-Y", "verify", "-n", "git", "-f", &allowed_signers, "-I", principal, "-s"])
+                .arg(signature_file)
+                .stderr(Stdio::piped());
+            let output = run_with_input(&mut command, payload.to_vec())
+                .map_err(|e| anyhow!("error: cannot run {}: {}", program, e))?;
+            out = String::from_utf8_lossy(&output.stdout).to_string();
+            err = String::from_utf8_lossy(&output.stderr).to_string();
+            good = output.status.success() && out.starts_with("Good");
+            if good {
+                break;
+            }
+        }
+    }
+
+    Ok(SignatureCheck { good, output: tidy_output(&out) + &tidy_output(&err) })
+}
+
+fn gpg_program(format: SignatureFormat, config: &Config) -> String {
+    match format {
+        SignatureFormat::X509 => config.get("gpg.x509.program").unwrap_or(String::from("gpgsm")),
+        _ => config.get("gpg.openpgp.program").or(config.get("gpg.program")).unwrap_or(String::from("gpg"))
+    }
+}
+
+// Removes blank lines and trailing whitespace, leaving each remaining line terminated by a new line
+fn tidy_output(text: &str) -> String {
+    text.lines()
+        .map(|x| x.trim_end())
+        .filter(|x| !x.is_empty())
+        .map(|x| format!("{}\n", x))
+        .collect()
+}
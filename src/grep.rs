@@ -0,0 +1,189 @@
+// Search the contents of tracked files for lines matching a pattern
+
+use std::{collections::BTreeMap, env, fs, io::{self, Write}, path::PathBuf, process};
+use anyhow::{anyhow, bail, Result};
+use clap::Args;
+use regex::bytes::{Regex, RegexBuilder};
+
+use crate::{GlobalOpts, repo_find};
+use crate::checkout::tree_files;
+use crate::diff::is_binary;
+use crate::index::read_index;
+use crate::objects::{get_object, peel, Object};
+use crate::refs::resolve_revision;
+
+#[derive(Args)]
+pub struct GrepArgs {
+    /// The pattern to search for, a basic regular expression unless -E or -F is given
+    pattern: String,
+    /// Search the files of this tree, or of the commit or tag pointing at one, instead of the working tree
+    tree_ish: Option<String>,
+    /// Search the blobs staged in the index instead of the working tree
+    #[arg(long, conflicts_with = "tree_ish")]
+    cached: bool,
+    /// Ignore case differences between the pattern and the files
+    #[arg(short, long)]
+    ignore_case: bool,
+    /// Prefix each matching line with its line number
+    #[arg(short = 'n', long)]
+    line_number: bool,
+    /// Only show the names of the files that have a match
+    #[arg(short = 'l', long)]
+    files_with_matches: bool,
+    /// Treat the pattern as an extended regular expression
+    #[arg(short = 'E', long)]
+    extended_regexp: bool,
+    /// Treat the pattern as a fixed string rather than a regular expression
+    #[arg(short = 'F', long)]
+    fixed_strings: bool,
+}
+
+pub fn cmd_grep(args: GrepArgs, global_opts: GlobalOpts) -> Result<()> {
+    let path = env::current_dir().unwrap_or_else(|_| { panic!() });
+    let root = repo_find(&path, global_opts).unwrap_or_else(|| {
+        panic!("fatal: not a grit repository");
+    });
+    let git_mode = global_opts.git_mode;
+
+    let pattern = if args.fixed_strings {
+        regex::escape(&args.pattern)
+    } else if args.extended_regexp {
+        args.pattern.clone()
+    } else {
+        basic_to_extended(&args.pattern)
+    };
+    let regex = RegexBuilder::new(&pattern)
+        .case_insensitive(args.ignore_case)
+        .build()
+        .map_err(|e| anyhow!("fatal: command line, '{}': {}", args.pattern, e))?;
+
+    // Each file to search, with the name it's shown by and where its contents come from
+    let mut files: Vec<(String, Source)> = Vec::new();
+    if let Some(tree_ish) = &args.tree_ish {
+        let hash = resolve_revision(&root, tree_ish, global_opts)
+            .map_err(|_| anyhow!(
+                "fatal: ambiguous argument '{}': unknown revision or path not in the working tree.\n\
+                Use '--' to separate paths from revisions, like this:\n\
+                '{} <command> [<revision>...] -- [<file>...]'", tree_ish, if git_mode { "git" } else { "grit" }
+            ))?;
+        let tree = match get_object(&root, &peel(&root, &hash, git_mode)?, git_mode)? {
+            Object::Tree(_) => peel(&root, &hash, git_mode)?,
+            Object::Commit(commit) => commit.tree,
+            _ => bail!("fatal: unable to read tree ({})", hex::encode(hash))
+        };
+        let mut entries = BTreeMap::new();
+        tree_files(&root, &tree, git_mode, &mut entries)?;
+        for (path, entry) in entries {
+            if is_regular_file(entry.mode) {
+                files.push((format!("{}:{}", tree_ish, path.to_string_lossy()), Source::Blob(entry.hash)));
+            }
+        }
+    } else {
+        let index = read_index(&root, global_opts)?;
+        for item in &index.items {
+            if !is_regular_file(item.mode) {
+                continue;
+            }
+            // As in Git, the blobs of paths in conflict aren't searched, but their file in the working tree is,
+            // once. Files marked skip-worktree are searched in the index, as they may not be in the working tree.
+            let name = item.path.to_string_lossy().to_string();
+            if args.cached || item.skip_worktree {
+                if item.stage == 0 {
+                    files.push((name, Source::Blob(item.hash)));
+                }
+            } else if files.last().map(|(x, _)| x) != Some(&name) {
+                files.push((name, Source::File(item.path.clone())));
+            }
+        }
+    }
+
+    let mut stdout = io::stdout().lock();
+    let mut found = false;
+    for (name, source) in files {
+        let contents = match source {
+            Source::Blob(hash) => match get_object(&root, &hash, git_mode)? {
+                Object::Blob(blob) => blob.bytes,
+                _ => bail!("fatal: '{}' is not a blob", name)
+            },
+            // Files missing from the working tree have nothing to search
+            Source::File(path) => match fs::read(root.join(path)) {
+                Ok(contents) => contents,
+                Err(_) => continue
+            }
+        };
+        found |= grep_file(&mut stdout, &regex, &name, &contents, &args)?;
+    }
+
+    if !found {
+        process::exit(1);
+    }
+    Ok(())
+}
+
+// Where the contents of a file to search are read from
+enum Source {
+    Blob([u8; 20]),
+    File(PathBuf),
+}
+
+// Prints the lines of a file that match, or just its name, returning whether there were any. Binary files aren't
+// shown line by line, but are said to match.
+fn grep_file(out: &mut impl Write, regex: &Regex, name: &str, contents: &[u8], args: &GrepArgs) -> Result<bool> {
+    let mut lines = contents.split(|x| *x == b'\n');
+    // The empty line after a final newline isn't part of the file
+    if contents.ends_with(b"\n") || contents.is_empty() {
+        lines.next_back();
+    }
+    let mut found = false;
+    for (number, line) in (1..).zip(lines) {
+        if !regex.is_match(line) {
+            continue;
+        }
+        found = true;
+        if args.files_with_matches {
+            writeln!(out, "{}", name)?;
+            break;
+        }
+        if is_binary(contents) {
+            writeln!(out, "Binary file {} matches", name)?;
+            break;
+        }
+        write!(out, "{}:", name)?;
+        if args.line_number {
+            write!(out, "{}:", number)?;
+        }
+        out.write_all(line)?;
+        out.write_all(b"\n")?;
+    }
+    Ok(found)
+}
+
+// Only regular files are searched, not symbolic links or submodules
+fn is_regular_file(mode: u32) -> bool {
+    mode & 0o170000 == 0o100000
+}
+
+// Rewrites a POSIX basic regular expression in the syntax of the regex engine. In a basic expression `?`, `+`, `|`,
+// braces and parentheses stand for themselves, and are only special when escaped.
+fn basic_to_extended(pattern: &str) -> String {
+    let mut result = String::new();
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(next @ ('?' | '+' | '|' | '{' | '}' | '(' | ')')) => result.push(next),
+                Some(next) => {
+                    result.push('\\');
+                    result.push(next);
+                },
+                None => result.push_str("\\\\")
+            },
+            '?' | '+' | '|' | '{' | '}' | '(' | ')' => {
+                result.push('\\');
+                result.push(c);
+            },
+            _ => result.push(c)
+        }
+    }
+    result
+}
@@ -1,8 +1,8 @@
 use std::{fs, env};
-use anyhow::Result;
-use clap::{arg, Args};
+use anyhow::{bail, Result};
+use clap::Args;
 
-use crate::{GlobalOpts, repo_find, objects::{Blob, GitObject}};
+use crate::{GlobalOpts, repo_find, objects::{Blob, GitObject, Object, ObjectType, parse_commit, parse_tag, parse_tree}};
 
 #[derive(Args)]
 pub struct HashObjectArgs {
@@ -11,15 +11,25 @@ pub struct HashObjectArgs {
     pub r#type: String,
     #[arg(short)]
     pub write: bool,
+    /// Hash the contents as they are, with the type given, without checking that they make a valid object of it
+    #[arg(long)]
+    pub literally: bool,
 }
 
 pub fn cmd_hash_object(args: HashObjectArgs, global_opts: GlobalOpts) -> Result<()> {
     // Read the file at the given path
     let Ok(content_bytes) = fs::read(&args.path) else { panic!() };
-    
-    // Assume the object is a blob for now
-    let blob = Blob { bytes: content_bytes };
-    let hash = blob.hash();
+
+    // Objects other than blobs must be well-formed, so they are parsed before being hashed, unless written literally
+    let object = match ObjectType::from_bytes(args.r#type.as_bytes()) {
+        object_type if args.literally => Object::Unknown { object_type, bytes: content_bytes },
+        ObjectType::Blob => Object::Blob(Blob { bytes: content_bytes }),
+        ObjectType::Commit => Object::Commit(parse_commit(&String::from_utf8_lossy(&content_bytes))?),
+        ObjectType::Tree => Object::Tree(parse_tree(&content_bytes)?),
+        ObjectType::Tag => Object::Tag(parse_tag(&String::from_utf8_lossy(&content_bytes))?),
+        ObjectType::Unknown(x) => bail!("fatal: invalid object type \"{}\"", x)
+    };
+    let hash = object.hash();
 
     let hash_str = hex::encode(hash);
     println!("{}", hash_str);
@@ -30,7 +40,7 @@ pub fn cmd_hash_object(args: HashObjectArgs, global_opts: GlobalOpts) -> Result<
             panic!("fatal: not a grit repository");
         });
 
-        blob.write(&root, global_opts)?;
+        object.write(&root, global_opts)?;
     }
 
     Ok(())
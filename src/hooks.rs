@@ -0,0 +1,60 @@
+// Running the hook programs a repository configures to customise commands
+
+use std::{io, path::{Path, PathBuf}, process::{Command, ExitStatus, Stdio}};
+use anyhow::{anyhow, Result};
+
+use crate::{GlobalOpts, git_dir_name};
+use crate::config::Config;
+
+/// Runs the named hook, e.g. `pre-commit`, from the repository's hooks directory, or from the directory
+/// given by `core.hooksPath`. The hook is run in the root of the working tree with the given arguments and
+/// extra environment variables, and its output is shown on standard error.
+/// Returns None if the hook doesn't exist or isn't executable, in which case it is skipped.
+pub fn run_hook(
+    root: &Path,
+    name: &str,
+    args: &[&str],
+    env: &[(&str, String)],
+    config: &Config,
+    global_opts: GlobalOpts
+) -> Result<Option<ExitStatus>> {
+    let hooks_dir = match config.get("core.hookspath") {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(git_dir_name(global_opts)).join("hooks")
+    };
+    let hook = hooks_dir.join(name);
+    let hook_path = root.join(&hook);
+    if !hook_path.is_file() {
+        return Ok(None);
+    }
+
+    if !is_executable(&hook_path)? {
+        if config.get_bool("advice.ignoredhook")?.unwrap_or(true) {
+            eprintln!("hint: The '{}' hook was ignored because it's not set as executable.", hook.to_string_lossy());
+            eprintln!("hint: You can disable this warning with `git config advice.ignoredHook false`.");
+        }
+        return Ok(None);
+    }
+
+    // As in Git, anything the hook prints goes to standard error so it can't be mistaken for the command's output
+    let status = Command::new(&hook_path)
+        .args(args)
+        .envs(env.iter().map(|(key, value)| (key, value)))
+        .current_dir(root)
+        .stdin(Stdio::null())
+        .stdout(io::stderr())
+        .status()
+        .map_err(|e| anyhow!("error: cannot exec '{}': {}", hook.to_string_lossy(), e))?;
+    Ok(Some(status))
+}
+
+fn is_executable(path: &Path) -> Result<bool> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        Ok(path.metadata()?.permissions().mode() & 0o111 != 0)
+    }
+
+    #[cfg(not(unix))]
+    Ok(true)
+}
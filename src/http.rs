@@ -0,0 +1,251 @@
+// Fetching from repositories served as plain files over HTTP, with what Git calls the dumb protocol
+
+use std::{collections::{HashMap, HashSet}, fs, io::{Read, Write}, net::TcpStream, path::Path};
+use anyhow::{anyhow, bail, Result};
+use flate2::bufread::ZlibDecoder;
+use sha1::{Digest, Sha1};
+
+use crate::{GlobalOpts, git_dir_name};
+use crate::objects::{object_path, parse_hash, search_object, Object};
+use crate::pack::{check_pack, index_contains};
+use crate::refs::check_ref_name;
+
+/// A repository served over HTTP by a server that knows nothing of Git, which makes the files of its git directory
+/// available as they are. The server must have been prepared with `git update-server-info`, which lists the
+/// references in `info/refs` and the packs in `objects/info/packs`.
+pub struct DumbRemote {
+    url: String,
+    host: String,
+    port: u16,
+    /// The path of the repository on the server, with a trailing slash
+    path: String,
+    /// The names of the remote's packs, once they have been listed
+    packs: Option<Vec<String>>,
+    /// The indexes of the remote's packs that have been downloaded, or None for those the server didn't have
+    indexes: HashMap<String, Option<Vec<u8>>>,
+    /// The packs already downloaded
+    downloaded: HashSet<String>,
+}
+
+impl DumbRemote {
+    /// Connects to nothing yet, but checks that the URL is one that can be fetched from. Only `http://` URLs are
+    /// supported, as there's no TLS.
+    pub fn open(url: &str) -> Result<DumbRemote> {
+        let Some(rest) = url.strip_prefix("http://") else {
+            bail!("fatal: unable to access '{}': only plain http is supported", url);
+        };
+        let (authority, path) = rest.split_once('/').unwrap_or((rest, ""));
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse().map_err(|_| anyhow!("fatal: invalid port in '{}'", url))?),
+            None => (authority, 80)
+        };
+        let path = format!("/{}", path.trim_end_matches('/'));
+        Ok(DumbRemote {
+            url: url.to_string(),
+            host: host.to_string(),
+            port,
+            path: if path == "/" { path } else { path + "/" },
+            packs: None,
+            indexes: HashMap::new(),
+            downloaded: HashSet::new(),
+        })
+    }
+
+    /// Returns every reference of the remote with the object it points at, as listed in `info/refs`
+    pub fn refs(&self) -> Result<Vec<(String, [u8; 20])>> {
+        let Some(body) = self.get("info/refs")? else {
+            bail!("fatal: repository '{}/' not found", self.url.trim_end_matches('/'));
+        };
+        let mut refs = Vec::new();
+        for line in String::from_utf8_lossy(&body).lines() {
+            let Some((hash, name)) = line.split_once('\t') else {
+                continue;
+            };
+            // The objects tags point at are listed too, which aren't references in their own right
+            if name.ends_with("^{}") {
+                continue;
+            }
+            // Names are used as paths once fetched, so one that isn't valid could be used to write anywhere
+            if check_ref_name(name).is_err() {
+                bail!("fatal: remote sent an invalid ref name '{}'", name);
+            }
+            refs.push((name.to_string(), parse_hash(hash)?));
+        }
+        refs.sort();
+        Ok(refs)
+    }
+
+    /// Returns the commit the remote's HEAD points at, or None if it's on a branch with no commits
+    pub fn head(&self, refs: &[(String, [u8; 20])]) -> Result<Option<[u8; 20]>> {
+        let Some(body) = self.get("HEAD")? else {
+            return Ok(None);
+        };
+        let contents = String::from_utf8_lossy(&body);
+        let contents = contents.trim_end();
+        match contents.strip_prefix("ref: ") {
+            Some(name) => Ok(refs.iter().find(|(x, _)| x == name).map(|(_, hash)| *hash)),
+            None => Ok(Some(parse_hash(contents)?))
+        }
+    }
+
+    /// Downloads the objects reachable from the given roots that the local repository doesn't have. Each object is
+    /// looked for as a loose object first, then in the remote's packs, which are downloaded whole when they have an
+    /// object that's needed. Returns the number of objects downloaded.
+    pub fn download_objects(&mut self, root: &Path, roots: &[[u8; 20]], global_opts: GlobalOpts) -> Result<usize> {
+        let git_mode = global_opts.git_mode;
+        let mut seen = HashSet::new();
+        let mut downloaded = 0;
+        let mut pending = roots.to_vec();
+
+        while let Some(hash) = pending.pop() {
+            // As when copying from a local repository, objects already present are assumed to have everything they
+            // reference
+            if !seen.insert(hash) || search_object(root, &hash, git_mode)?.is_some() {
+                continue;
+            }
+            if !self.download_loose(root, &hash, global_opts)? && !self.download_pack(root, &hash, global_opts)? {
+                bail!("fatal: unable to find {} on the remote", hex::encode(hash));
+            }
+            downloaded += 1;
+
+            match search_object(root, &hash, git_mode)? {
+                Some(Object::Commit(commit)) => {
+                    pending.push(commit.tree);
+                    pending.extend(commit.parents);
+                },
+                Some(Object::Tree(tree)) => {
+                    pending.extend(tree.children.iter().filter(|x| x.mode != 0o160000).map(|x| x.hash));
+                },
+                Some(Object::Tag(tag)) => pending.push(tag.object),
+                _ => ()
+            }
+        }
+        Ok(downloaded)
+    }
+
+    // Downloads a loose object into the local store, returning false if the remote doesn't have it loose
+    fn download_loose(&self, root: &Path, hash: &[u8; 20], global_opts: GlobalOpts) -> Result<bool> {
+        let hex = hex::encode(hash);
+        let Some(compressed) = self.get(&format!("objects/{}/{}", &hex[..2], &hex[2..]))? else {
+            return Ok(false);
+        };
+
+        // The object is checked before it's stored, since a server could send anything
+        let mut contents = Vec::new();
+        ZlibDecoder::new(&compressed[..]).read_to_end(&mut contents)
+            .map_err(|_| anyhow!("fatal: object file {} is corrupt", hex))?;
+        let actual: [u8; 20] = Sha1::digest(&contents).into();
+        if actual != *hash {
+            bail!("fatal: object {} has the wrong hash {}", hex, hex::encode(actual));
+        }
+
+        let path = object_path(root, hash, global_opts.git_mode);
+        fs::create_dir_all(path.parent().unwrap())?;
+        fs::write(path, compressed)?;
+        Ok(true)
+    }
+
+    // Downloads the pack that has the given object into the local store, returning false if no pack has it
+    fn download_pack(&mut self, root: &Path, hash: &[u8; 20], global_opts: GlobalOpts) -> Result<bool> {
+        for pack in self.packs()? {
+            if self.downloaded.contains(&pack) {
+                continue;
+            }
+            // Each pack's index is only downloaded once, to find out which objects it has
+            if !self.indexes.contains_key(&pack) {
+                let idx = self.get(&format!("objects/pack/{}.idx", pack))?;
+                self.indexes.insert(pack.clone(), idx);
+            }
+            let Some(idx) = &self.indexes[&pack] else {
+                continue;
+            };
+            if !index_contains(idx, hash)? {
+                continue;
+            }
+
+            let Some(data) = self.get(&format!("objects/pack/{}.pack", pack))? else {
+                bail!("fatal: unable to get pack file {}.pack", pack);
+            };
+            check_pack(&pack, &data, idx)?;
+            // The pack is only used once it has its index, so that's written last
+            let pack_dir = root.join(git_dir_name(global_opts)).join("objects/pack");
+            fs::create_dir_all(&pack_dir)?;
+            fs::write(pack_dir.join(format!("{}.pack", pack)), data)?;
+            fs::write(pack_dir.join(format!("{}.idx", pack)), idx)?;
+            self.downloaded.insert(pack);
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    // The names of the remote's packs, without extensions, read from `objects/info/packs` the first time they're
+    // needed. Names are used as paths, so only those of the form Git gives packs, `pack-<checksum>`, are accepted.
+    fn packs(&mut self) -> Result<Vec<String>> {
+        if self.packs.is_none() {
+            let body = self.get("objects/info/packs")?.unwrap_or_default();
+            let packs = String::from_utf8_lossy(&body).lines()
+                .filter_map(|x| x.strip_prefix("P "))
+                .filter_map(|x| x.strip_suffix(".pack"))
+                .filter(|x| is_pack_name(x))
+                .map(|x| x.to_string())
+                .collect();
+            self.packs = Some(packs);
+        }
+        Ok(self.packs.clone().unwrap_or_default())
+    }
+
+    // Requests a file of the repository, returning None if the server doesn't have it
+    fn get(&self, file: &str) -> Result<Option<Vec<u8>>> {
+        let unable = |e: &dyn std::fmt::Display| anyhow!("fatal: unable to access '{}/': {}", self.url.trim_end_matches('/'), e);
+        let mut stream = TcpStream::connect((self.host.as_str(), self.port)).map_err(|e| unable(&e))?;
+        write!(
+            stream,
+            "GET {}{} HTTP/1.1\r\nHost: {}\r\nUser-Agent: grit/{}\r\nAccept: */*\r\nConnection: close\r\n\r\n",
+            self.path, file, self.host, env!("CARGO_PKG_VERSION")
+        ).map_err(|e| unable(&e))?;
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).map_err(|e| unable(&e))?;
+
+        let header_end = response.windows(4).position(|x| x == b"\r\n\r\n")
+            .ok_or(unable(&"malformed response"))?;
+        let headers = String::from_utf8_lossy(&response[..header_end]).to_string();
+        let body = &response[header_end + 4..];
+        let status: u16 = headers.split(' ').nth(1).and_then(|x| x.parse().ok())
+            .ok_or(unable(&"malformed response"))?;
+        match status {
+            200 => (),
+            404 | 410 => return Ok(None),
+            _ => return Err(unable(&format!("The requested URL returned error: {}", status)))
+        }
+
+        let chunked = headers.lines().any(|x| {
+            let x = x.to_ascii_lowercase();
+            x.starts_with("transfer-encoding:") && x.contains("chunked")
+        });
+        if chunked {
+            return Ok(Some(dechunk(body).ok_or(unable(&"malformed chunked response"))?));
+        }
+        Ok(Some(body.to_vec()))
+    }
+}
+
+// Whether a name is one Git gives a pack, `pack-` followed by a hash in lowercase hex
+fn is_pack_name(name: &str) -> bool {
+    name.strip_prefix("pack-").is_some_and(|x| x.len() == 40 && x.bytes().all(|x| matches!(x, b'0'..=b'9' | b'a'..=b'f')))
+}
+
+// Joins the chunks of a body sent with chunked transfer encoding, each of which is preceded by its size in hex
+fn dechunk(mut body: &[u8]) -> Option<Vec<u8>> {
+    let mut data = Vec::new();
+    loop {
+        let line_end = body.windows(2).position(|x| x == b"\r\n")?;
+        let size_field = String::from_utf8_lossy(&body[..line_end]);
+        let size = usize::from_str_radix(size_field.split(';').next()?.trim(), 16).ok()?;
+        body = &body[line_end + 2..];
+        if size == 0 {
+            return Some(data);
+        }
+        data.extend_from_slice(body.get(..size)?);
+        body = body.get(size + 2..)?;
+    }
+}
@@ -0,0 +1,300 @@
+// Matching of paths against the patterns in .gitignore files and info/exclude
+
+use std::{collections::HashMap, fs, path::{Path, PathBuf}};
+use anyhow::Result;
+
+use crate::{GlobalOpts, git_dir_name};
+
+/// A single pattern read from an ignore file, along with where it came from
+#[derive(Clone, Debug)]
+pub struct IgnorePattern {
+    /// The pattern as written in the file
+    pub text: String,
+    /// The file the pattern was read from, relative to the repository root
+    pub source: PathBuf,
+    /// The line number of the pattern within its source file, starting from 1
+    pub line: usize,
+    /// Whether the pattern re-includes paths rather than excluding them
+    pub negated: bool,
+    glob: String,
+    dir_only: bool,
+    anchored: bool,
+    /// The directory containing the source file, relative to the repository root
+    base: PathBuf,
+}
+
+impl IgnorePattern {
+    fn parse(line: &str, line_number: usize, source: &Path, base: &Path) -> Option<IgnorePattern> {
+        // Trailing spaces are ignored unless escaped with a backslash
+        let mut glob = line.trim_end_matches(' ');
+        if glob.ends_with('\\') && line.len() > glob.len() {
+            glob = &line[..glob.len() + 1];
+        }
+        if glob.is_empty() || glob.starts_with('#') {
+            return None;
+        }
+
+        // A leading backslash escapes a literal `!` or `#`
+        let negated = glob.starts_with('!');
+        if negated || glob.starts_with("\\!") || glob.starts_with("\\#") {
+            glob = &glob[1..];
+        }
+
+        let dir_only = glob.ends_with('/');
+        let glob = glob.trim_end_matches('/');
+
+        // A pattern containing a slash anywhere but the end is matched relative to its source directory,
+        // rather than against the file name at any depth.
+        let anchored = glob.contains('/');
+        let glob = glob.strip_prefix('/').unwrap_or(glob);
+
+        if glob.is_empty() {
+            return None;
+        }
+
+        Some(IgnorePattern {
+            text: line.trim_end_matches(' ').to_string(),
+            source: source.to_path_buf(),
+            line: line_number,
+            negated,
+            glob: glob.to_string(),
+            dir_only,
+            anchored,
+            base: base.to_path_buf(),
+        })
+    }
+
+    fn matches(&self, path: &Path, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+
+        let Ok(relative) = path.strip_prefix(&self.base) else { return false };
+
+        if self.anchored {
+            wildmatch(self.glob.as_bytes(), relative.to_string_lossy().as_bytes())
+        } else {
+            match relative.file_name() {
+                Some(name) => wildmatch(self.glob.as_bytes(), name.to_string_lossy().as_bytes()),
+                None => false
+            }
+        }
+    }
+}
+
+/// The ignore rules of a repository. The `.gitignore` file of each directory is read the first time
+/// a path within that directory is checked.
+pub struct IgnoreRules {
+    root: PathBuf,
+    exclude: Vec<IgnorePattern>,
+    per_directory: HashMap<PathBuf, Vec<IgnorePattern>>,
+}
+
+impl IgnoreRules {
+    pub fn new(root: &Path, global_opts: GlobalOpts) -> Result<IgnoreRules> {
+        let exclude_source = PathBuf::from(format!("{}/info/exclude", git_dir_name(global_opts)));
+        let exclude = read_patterns(&root.join(&exclude_source), &exclude_source, Path::new(""))?;
+
+        Ok(IgnoreRules {
+            root: root.to_path_buf(),
+            exclude,
+            per_directory: HashMap::new(),
+        })
+    }
+
+    /// Returns the pattern that decides whether the given path (relative to the repository root) is ignored,
+    /// or None if no pattern matches it. The returned pattern may be a negation, in which case the path is
+    /// explicitly not ignored. A path inside an ignored directory is reported as matching the directory's pattern.
+    pub fn matching_pattern(&mut self, path: &Path) -> Result<Option<IgnorePattern>> {
+        // Nothing inside an excluded directory can be re-included, so check the leading directories first
+        let mut prefix = PathBuf::new();
+        let components: Vec<_> = path.components().collect();
+        for (i, component) in components.iter().enumerate() {
+            prefix.push(component);
+            let is_dir = i + 1 < components.len() || self.root.join(&prefix).is_dir();
+            let found = self.last_match(&prefix, is_dir)?;
+            if let Some(pattern) = found {
+                if !pattern.negated || i + 1 == components.len() {
+                    return Ok(Some(pattern));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    // Finds the last matching pattern, with patterns from deeper directories taking precedence over those from
+    // shallower ones, and any .gitignore taking precedence over info/exclude.
+    fn last_match(&mut self, path: &Path, is_dir: bool) -> Result<Option<IgnorePattern>> {
+        let mut dirs = vec![PathBuf::new()];
+        if let Some(parent) = path.parent() {
+            let mut dir = PathBuf::new();
+            for component in parent.components() {
+                dir.push(component);
+                dirs.push(dir.clone());
+            }
+        }
+
+        for dir in dirs.iter().rev() {
+            let patterns = self.directory_patterns(dir)?;
+            if let Some(pattern) = patterns.iter().rev().find(|x| x.matches(path, is_dir)) {
+                return Ok(Some(pattern.clone()));
+            }
+        }
+
+        Ok(self.exclude.iter().rev().find(|x| x.matches(path, is_dir)).cloned())
+    }
+
+    fn directory_patterns(&mut self, dir: &Path) -> Result<&Vec<IgnorePattern>> {
+        if !self.per_directory.contains_key(dir) {
+            let source = dir.join(".gitignore");
+            let patterns = read_patterns(&self.root.join(&source), &source, dir)?;
+            self.per_directory.insert(dir.to_path_buf(), patterns);
+        }
+        Ok(&self.per_directory[dir])
+    }
+}
+
+fn read_patterns(file: &Path, source: &Path, base: &Path) -> Result<Vec<IgnorePattern>> {
+    if !file.is_file() {
+        return Ok(Vec::new());
+    }
+
+    let contents = fs::read_to_string(file)?;
+    Ok(contents
+        .lines()
+        .enumerate()
+        .filter_map(|(i, line)| IgnorePattern::parse(line, i + 1, source, base))
+        .collect())
+}
+
+/// Matches text against a glob pattern with Git's wildmatch semantics. `*` and `?` never match a slash,
+/// while `**` between slashes matches any number of directories.
+pub fn wildmatch(pattern: &[u8], text: &[u8]) -> bool {
+    match_from(pattern, 0, text, 0)
+}
+
+fn match_from(pattern: &[u8], mut p: usize, text: &[u8], mut t: usize) -> bool {
+    while p < pattern.len() {
+        match pattern[p] {
+            b'*' => {
+                let double = pattern.get(p + 1) == Some(&b'*');
+                let at_segment_start = p == 0 || pattern[p - 1] == b'/';
+
+                if double && at_segment_start {
+                    let rest = p + 2;
+                    if rest == pattern.len() {
+                        return true;
+                    }
+                    if pattern[rest] == b'/' {
+                        // `**/` matches zero or more leading directories
+                        if match_from(pattern, rest + 1, text, t) {
+                            return true;
+                        }
+                        for i in t..text.len() {
+                            if text[i] == b'/' && match_from(pattern, rest + 1, text, i + 1) {
+                                return true;
+                            }
+                        }
+                        return false;
+                    }
+                }
+
+                // A single star matches any run of characters within one path segment
+                let rest = if double { p + 2 } else { p + 1 };
+                let mut i = t;
+                loop {
+                    if match_from(pattern, rest, text, i) {
+                        return true;
+                    }
+                    if i == text.len() || text[i] == b'/' {
+                        return false;
+                    }
+                    i += 1;
+                }
+            },
+            b'?' => {
+                if t == text.len() || text[t] == b'/' {
+                    return false;
+                }
+                p += 1;
+                t += 1;
+            },
+            b'[' => {
+                if t == text.len() || text[t] == b'/' {
+                    return false;
+                }
+                match match_class(pattern, p, text[t]) {
+                    Some((true, end)) => {
+                        p = end;
+                        t += 1;
+                    },
+                    Some((false, _)) => return false,
+                    None => {
+                        // An unterminated class is matched literally
+                        if text[t] != b'[' {
+                            return false;
+                        }
+                        p += 1;
+                        t += 1;
+                    }
+                }
+            },
+            b'\\' if p + 1 < pattern.len() => {
+                if t == text.len() || text[t] != pattern[p + 1] {
+                    return false;
+                }
+                p += 2;
+                t += 1;
+            },
+            c => {
+                if t == text.len() || text[t] != c {
+                    return false;
+                }
+                p += 1;
+                t += 1;
+            }
+        }
+    }
+
+    t == text.len()
+}
+
+// Matches a single byte against the bracket expression starting at `start`. Returns whether it matched and
+// the position just past the closing bracket, or None if the expression is not terminated.
+fn match_class(pattern: &[u8], start: usize, c: u8) -> Option<(bool, usize)> {
+    let mut p = start + 1;
+    let negated = matches!(pattern.get(p), Some(b'!') | Some(b'^'));
+    if negated {
+        p += 1;
+    }
+
+    let mut matched = false;
+    let mut first = true;
+    while p < pattern.len() {
+        let mut lo = pattern[p];
+        if lo == b']' && !first {
+            return Some((matched != negated, p + 1));
+        }
+        first = false;
+
+        if lo == b'\\' && p + 1 < pattern.len() {
+            p += 1;
+            lo = pattern[p];
+        }
+
+        if pattern.get(p + 1) == Some(&b'-') && pattern.get(p + 2).is_some_and(|x| *x != b']') {
+            let hi = pattern[p + 2];
+            if lo <= c && c <= hi {
+                matched = true;
+            }
+            p += 3;
+        } else {
+            if lo == c {
+                matched = true;
+            }
+            p += 1;
+        }
+    }
+
+    None
+}
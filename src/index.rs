@@ -1,7 +1,18 @@
-use std::path::PathBuf;
-use anyhow::Result;
+use std::{ffi::CString, fs, io, mem, path::{Path, PathBuf}};
+use anyhow::{anyhow, bail, Result};
 use sha1::{Sha1, Digest};
 
+use crate::{GlobalOpts, git_dir_name};
+
+// Version 4 compresses paths against the previous entry, which isn't supported
+const SUPPORTED_VERSIONS: [u32; 2] = [2, 3];
+// Set in the flags of an entry that has a second set of flags following them, from version 3
+const EXTENDED_FLAG: u16 = 0x4000;
+// Set in the extended flags of an entry whose file is to be left alone in the working tree
+const SKIP_WORKTREE_FLAG: u16 = 0x4000;
+// The largest path length the flags of an entry can hold
+const PATH_LENGTH_MAX: u16 = 0xFFF;
+
 #[derive(Debug)]
 pub struct Index {
     pub version: u32,
@@ -24,71 +35,147 @@ pub struct IndexItem {
     pub gid: u32,
     pub size: u32,
     pub hash: [u8; 20],
-    pub path: PathBuf
+    pub path: PathBuf,
+    /// Zero for a merged entry. While a merge is in conflict, a path instead has entries for the versions
+    /// in the common ancestor (1), our side (2) and their side (3).
+    pub stage: u16,
+    /// Whether the file is left out of the working tree, as in a sparse checkout. The working tree is assumed to
+    /// match the entry, whatever it has at the path.
+    pub skip_worktree: bool
+}
+
+impl IndexItem {
+    /// Builds an index entry for the file at `file`, to be stored under `path` relative to the repository root.
+    pub fn from_file(file: &Path, path: PathBuf, hash: [u8; 20]) -> Result<IndexItem> {
+        // Get status information on the file by calling the C standard library. As in Git, symbolic links are
+        // described themselves rather than the file they point at.
+        let c_path = CString::new(file.to_string_lossy().as_bytes())?;
+        let stat = unsafe {
+            let mut stat: libc::stat = mem::zeroed();
+            if libc::lstat(c_path.as_ptr(), &mut stat) != 0 {
+                return Err(io::Error::last_os_error().into());
+            }
+            stat
+        };
+
+        // The index only has 32 bits for each field, so like Git, larger values are truncated to their low bits.
+        // The nanosecond fields only hold the part of the time after the whole second, which always fits.
+        Ok(IndexItem {
+            ctime: stat.st_ctime as u32,
+            ctime_nsec: stat.st_ctime_nsec as u32,
+            mtime: stat.st_mtime as u32,
+            mtime_nsec: stat.st_mtime_nsec as u32,
+            dev: stat.st_dev as u32,
+            ino: stat.st_ino as u32,
+            mode: canonical_mode(stat.st_mode),
+            uid: stat.st_uid,
+            gid: stat.st_gid,
+            size: stat.st_size as u32,
+            hash,
+            path,
+            stage: 0,
+            skip_worktree: false
+        })
+    }
+
+    /// Builds an entry with no file information, for a version of a path that isn't in the working tree,
+    /// such as one side of a conflict or a file in a tree that hasn't been checked out.
+    pub fn without_stat(path: PathBuf, mode: u32, hash: [u8; 20], stage: u16) -> IndexItem {
+        IndexItem {
+            ctime: 0,
+            ctime_nsec: 0,
+            mtime: 0,
+            mtime_nsec: 0,
+            dev: 0,
+            ino: 0,
+            mode,
+            uid: 0,
+            gid: 0,
+            size: 0,
+            hash,
+            path,
+            stage,
+            skip_worktree: false
+        }
+    }
+}
+
+/// Changes a file's mode to the closest of the modes Git records, as Git does: symbolic links and submodules are kept,
+/// directories become submodules, and any other file is executable or not depending on the owner's execute bit.
+pub fn canonical_mode(mode: u32) -> u32 {
+    match mode & 0o170000 {
+        0o120000 => 0o120000,
+        0o040000 | 0o160000 => 0o160000,
+        _ if mode & 0o100 != 0 => 0o100755,
+        _ => 0o100644
+    }
 }
 
 impl Index {
     pub fn deserialize(bytes: Vec<u8>) -> Result<Index> {
-        let _signature = String::from_utf8(bytes[..4].to_vec())?;
-        let mut pos = 4;
-        let version = read_u32(&bytes, &mut pos);
-        let num_entries = read_u32(&bytes, &mut pos);
-
-        let mut items = Vec::new();
-        for _ in 0..num_entries {
-            let mut item_pos = 0;
-            let item_bytes = &bytes[pos..];
-            let ctime = read_u32(item_bytes, &mut item_pos);
-            let ctime_nsec = read_u32(item_bytes, &mut item_pos);
-            let mtime = read_u32(item_bytes, &mut item_pos);
-            let mtime_nsec = read_u32(item_bytes, &mut item_pos);
-            let dev = read_u32(item_bytes, &mut item_pos);
-            let ino = read_u32(item_bytes, &mut item_pos);
-            let mode = read_u32(item_bytes, &mut item_pos);
-            let uid = read_u32(item_bytes, &mut item_pos);
-            let gid = read_u32(item_bytes, &mut item_pos);
-            let size = read_u32(item_bytes, &mut item_pos);
-            let hash = read_hash(item_bytes, &mut item_pos);
-
-            let flags = u16::from_be_bytes(item_bytes[item_pos..(item_pos+2)].try_into().unwrap());
-            item_pos += 2;
-
-            let path_len: usize = (0xFFF & flags).into();
-            let path_bytes = item_bytes[item_pos..(item_pos+path_len)].into();
-            let path_str = String::from_utf8_lossy(path_bytes).to_string();
-            let path = PathBuf::from(&path_str);
-            item_pos += path_len;
-
-            // Shift pos to account for NUL-padding of path name
-            let npad = 8 - ((item_pos) % 8);
-            let item_len = item_pos + npad;
-            pos += item_len;
-
-            items.push(IndexItem {
-                ctime,
-                ctime_nsec,
-                mtime,
-                mtime_nsec,
-                dev,
-                ino,
-                mode,
-                uid,
-                gid,
-                size,
-                hash,
-                path
-            });
-        }
-
+        let reader = IndexReader::new(&bytes)?;
+        let version = reader.version;
+        let items = reader.collect::<Result<Vec<IndexItem>>>()?;
         Ok(Index{version, items})
     }
+
+    /// Adds an entry in its place in the sorted order, replacing any entries the index already has for its path
+    pub fn add_item(&mut self, mut item: IndexItem) {
+        item.path = index_path(&item.path);
+        self.items.retain(|x| x.path != item.path);
+        let key = item.path.as_os_str().as_encoded_bytes();
+        let position = self.items.partition_point(|x| x.path.as_os_str().as_encoded_bytes() < key);
+        self.items.insert(position, item);
+    }
+
+    /// Returns the path as the index already spells it, for when case is ignored. A path that matches an entry but
+    /// for case takes the entry's spelling, as do directories that match those of an entry, so that a file is added
+    /// to a directory under the name the index already has for it.
+    pub fn existing_case(&self, path: &Path) -> PathBuf {
+        let text = path.to_string_lossy().to_string();
+        let same = |x: &IndexItem, end: usize| {
+            x.path.to_string_lossy().get(..end).is_some_and(|x| x.eq_ignore_ascii_case(&text[..end]))
+        };
+        if let Some(item) = self.items.iter().find(|x| x.path.as_os_str().len() == text.len() && same(x, text.len())) {
+            return item.path.clone();
+        }
+
+        // Changing case leaves every character the same length, so the positions of the separators stay the same
+        let mut result = text.clone();
+        for (i, _) in text.match_indices('/') {
+            if let Some(item) = self.items.iter().find(|x| same(x, i + 1)) {
+                result.replace_range(..=i, &item.path.to_string_lossy()[..=i]);
+            }
+        }
+        PathBuf::from(result)
+    }
+
+    /// Removes the entries for a path, including every version of it while in conflict.
+    /// Returns whether there were any.
+    pub fn remove_path(&mut self, path: &Path) -> bool {
+        let count = self.items.len();
+        self.items.retain(|x| x.path != path);
+        self.items.len() != count
+    }
+
+    /// Whether any path is in conflict, with entries for several versions rather than one merged entry
+    pub fn has_conflicts(&self) -> bool {
+        self.items.iter().any(|x| x.stage != 0)
+    }
     
 
     pub fn serialize(&self) -> Result<Vec<u8>> {
         let mut bytes = Vec::<u8>::new();
 
         append_string(&mut bytes, String::from("DIRC"));
-        append_u32(&mut bytes, self.version.try_into().unwrap());
+        // Only version 3 can hold extended flags, and Git only writes it when some entry needs them
+        let extended = self.items.iter().any(|x| x.skip_worktree);
+        let version = match self.version {
+            2 | 3 if extended => 3,
+            2 | 3 => 2,
+            version => version
+        };
+        append_u32(&mut bytes, version);
 
         let num_entries = self.items.len().try_into()?;
         append_u32(&mut bytes, num_entries);
@@ -96,27 +183,36 @@ impl Index {
         for item in &self.items {
             let mut entry_bytes = Vec::<u8>::new();
 
-            append_u32(&mut entry_bytes, u32::try_from(item.ctime).unwrap());
-            append_u32(&mut entry_bytes, u32::try_from(item.ctime_nsec).unwrap());
-            append_u32(&mut entry_bytes, u32::try_from(item.mtime).unwrap());
-            append_u32(&mut entry_bytes, u32::try_from(item.mtime_nsec).unwrap());
-            append_u32(&mut entry_bytes, u32::try_from(item.dev).unwrap());
-            append_u32(&mut entry_bytes, u32::try_from(item.ino).unwrap());
+            append_u32(&mut entry_bytes, item.ctime);
+            append_u32(&mut entry_bytes, item.ctime_nsec);
+            append_u32(&mut entry_bytes, item.mtime);
+            append_u32(&mut entry_bytes, item.mtime_nsec);
+            append_u32(&mut entry_bytes, item.dev);
+            append_u32(&mut entry_bytes, item.ino);
             append_u32(&mut entry_bytes, item.mode);
             append_u32(&mut entry_bytes, item.uid);
             append_u32(&mut entry_bytes, item.gid);
-            append_u32(&mut entry_bytes, u32::try_from(item.size).unwrap());
+            append_u32(&mut entry_bytes, item.size);
             entry_bytes.append(&mut item.hash.into());
 
-            let path_str = item.path.to_string_lossy();
+            let path_str = index_path(&item.path).to_string_lossy().to_string();
             let path_bytes = path_str.as_bytes();
 
             // TODO: Handle "assume-valid" flag
-            let flags: u16 = std::cmp::min(0xFFF, path_bytes.len()).try_into().unwrap();
+            // Longer paths are given the largest length, and read up to the first byte of padding instead
+            let path_len: u16 = std::cmp::min(PATH_LENGTH_MAX.into(), path_bytes.len()).try_into().unwrap();
+            let mut flags = (item.stage << 12) | path_len;
+            if item.skip_worktree {
+                flags |= EXTENDED_FLAG;
+            }
             entry_bytes.append(&mut u16::to_be_bytes(flags).to_vec());
+            if item.skip_worktree {
+                entry_bytes.append(&mut u16::to_be_bytes(SKIP_WORKTREE_FLAG).to_vec());
+            }
             entry_bytes.append(&mut path_bytes.into());
 
-            // Pad with 1-8 NUL bytes so total length is a multiple of 8.
+            // Pad with 1-8 NUL bytes so total length is a multiple of 8. There is always at least one, which ends
+            // the path.
             let npad = 8 - (entry_bytes.len() % 8);
             entry_bytes.append(&mut vec![0; npad]);
 
@@ -135,16 +231,229 @@ impl Index {
     }
 }
 
-fn read_u32(bytes: &[u8], pos: &mut usize) -> u32 {
-    let val = u32::from_be_bytes(bytes[*pos..(*pos+4)].try_into().unwrap());
+/// Reads the entries of an index file one at a time, without holding them all in memory.
+/// Each entry is checked as it's read, and reading stops at the first that is malformed.
+pub struct IndexReader<'a> {
+    /// The header and entries of the file, without the checksum at the end
+    bytes: &'a [u8],
+    pub version: u32,
+    remaining: u32,
+    pos: usize,
+}
+
+impl<'a> IndexReader<'a> {
+    /// Checks the header of an index file, returning a reader for the entries that follow it
+    pub fn new(bytes: &'a [u8]) -> Result<IndexReader<'a>> {
+        // The header is a signature, version and entry count, and the file ends with a checksum
+        if bytes.len() < 12 + 20 {
+            bail!("fatal: index file smaller than expected");
+        }
+        let bytes = &bytes[..bytes.len() - 20];
+        let mut pos = 0;
+        let signature = read_u32(bytes, &mut pos)?;
+        if &bytes[..4] != b"DIRC" {
+            bail!("error: bad signature 0x{:08x}\nfatal: index file corrupt", signature);
+        }
+        let version = read_u32(bytes, &mut pos)?;
+        if !SUPPORTED_VERSIONS.contains(&version) {
+            bail!("error: bad index version {}\nfatal: index file corrupt", version);
+        }
+        let remaining = read_u32(bytes, &mut pos)?;
+        Ok(IndexReader { bytes, version, remaining, pos })
+    }
+
+    fn read_item(&mut self) -> Result<IndexItem> {
+        let mut item_pos = 0;
+        let item_bytes = &self.bytes[self.pos..];
+        let ctime = read_u32(item_bytes, &mut item_pos)?;
+        let ctime_nsec = read_u32(item_bytes, &mut item_pos)?;
+        let mtime = read_u32(item_bytes, &mut item_pos)?;
+        let mtime_nsec = read_u32(item_bytes, &mut item_pos)?;
+        let dev = read_u32(item_bytes, &mut item_pos)?;
+        let ino = read_u32(item_bytes, &mut item_pos)?;
+        let mode = read_u32(item_bytes, &mut item_pos)?;
+        let uid = read_u32(item_bytes, &mut item_pos)?;
+        let gid = read_u32(item_bytes, &mut item_pos)?;
+        let size = read_u32(item_bytes, &mut item_pos)?;
+        let hash = read_hash(item_bytes, &mut item_pos)?;
+
+        let flags = read_u16(item_bytes, &mut item_pos)?;
+        let mut skip_worktree = false;
+        if self.version >= 3 && flags & EXTENDED_FLAG != 0 {
+            skip_worktree = read_u16(item_bytes, &mut item_pos)? & SKIP_WORKTREE_FLAG != 0;
+        }
+
+        let stage = (flags >> 12) & 0x3;
+        // A path too long for the length field is instead found by the NUL that ends it
+        let mut path_len: usize = (PATH_LENGTH_MAX & flags).into();
+        if path_len == PATH_LENGTH_MAX.into() {
+            path_len = item_bytes[item_pos..].iter().position(|x| *x == 0).ok_or(corrupt())?;
+        }
+        let path_bytes = item_bytes.get(item_pos..(item_pos+path_len)).ok_or(corrupt())?;
+        let path_str = String::from_utf8_lossy(path_bytes).to_string();
+        let path = PathBuf::from(&path_str);
+        item_pos += path_len;
+
+        // Shift pos to account for NUL-padding of path name
+        let npad = 8 - ((item_pos) % 8);
+        let item_len = item_pos + npad;
+        if item_len > item_bytes.len() {
+            return Err(corrupt());
+        }
+        self.pos += item_len;
+
+        Ok(IndexItem {
+            ctime,
+            ctime_nsec,
+            mtime,
+            mtime_nsec,
+            dev,
+            ino,
+            mode,
+            uid,
+            gid,
+            size,
+            hash,
+            path,
+            stage,
+            skip_worktree
+        })
+    }
+}
+
+impl Iterator for IndexReader<'_> {
+    type Item = Result<IndexItem>;
+
+    fn next(&mut self) -> Option<Result<IndexItem>> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        let item = self.read_item();
+        // The entries after a malformed one can't be found
+        if item.is_err() {
+            self.remaining = 0;
+        }
+        Some(item)
+    }
+}
+
+/// Converts a path relative to the repository root to the form Git keeps in the index and in trees, with `/` between
+/// its components on every platform. On Windows, where `\\` is the separator, it's replaced.
+pub fn index_path(path: &Path) -> PathBuf {
+    if cfg!(windows) {
+        PathBuf::from(path.to_string_lossy().replace('\\', "/"))
+    } else {
+        path.to_path_buf()
+    }
+}
+
+// Returns the current index, or an empty index if one does not exist
+pub fn read_index(repo_root: &Path, global_opts: GlobalOpts) -> Result<Index> {
+    let index_path = repo_root.join(format!("{}/index", git_dir_name(global_opts)));
+    if index_path.exists() {
+        let index_bytes = fs::read(index_path)?;
+        // Git gives no advice on a corrupt index, so its messages are kept as they are in compatibility mode
+        if global_opts.git_mode {
+            return Index::deserialize(index_bytes);
+        }
+        Index::deserialize(index_bytes).map_err(|e| corrupt_index_hint(e, repo_root, global_opts))
+    } else {
+        Ok(Index { version: 2, items: Vec::new() })
+    }
+}
+
+// Saves a copy of an index that couldn't be read, so that it can be looked at later, and adds advice on starting
+// again from an empty index to the error
+fn corrupt_index_hint(error: anyhow::Error, repo_root: &Path, global_opts: GlobalOpts) -> anyhow::Error {
+    let git_dir = git_dir_name(global_opts);
+    let index_path = repo_root.join(format!("{}/index", git_dir));
+    let backup = match fs::copy(index_path, repo_root.join(format!("{}/index.corrupt", git_dir))) {
+        Ok(_) => format!("hint: A copy of it has been saved as {}/index.corrupt.\n", git_dir),
+        Err(_) => String::new()
+    };
+    anyhow!(
+        "{}\n{}\
+        hint: To start again from an empty index, remove it with 'rm {}/index'\n\
+        hint: and add the files to be committed again.",
+        error, backup, git_dir
+    )
+}
+
+/// Writes the index, holding the lock on it while it's written. See `IndexLock`.
+pub fn write_index(index: &Index, repo_root: &Path, global_opts: GlobalOpts) -> Result<()> {
+    lock_index(repo_root, global_opts)?.commit(index)
+}
+
+/// The lock on the index, held by creating `index.lock` beside it as Git does. The new index is written to the lock
+/// file and then renamed over the old one, so a command that fails part way through leaves the old index whole.
+/// Dropping the lock without committing it removes the lock file.
+pub struct IndexLock {
+    lock_path: PathBuf,
+    index_path: PathBuf,
+    file: fs::File,
+    committed: bool,
+}
+
+/// Takes the lock on the index, failing if another process holds it or a crashed one left it behind
+pub fn lock_index(repo_root: &Path, global_opts: GlobalOpts) -> Result<IndexLock> {
+    let index_path = repo_root.join(format!("{}/index", git_dir_name(global_opts)));
+    let lock_path = repo_root.join(format!("{}/index.lock", git_dir_name(global_opts)));
+    let file = fs::OpenOptions::new().write(true).create_new(true).open(&lock_path).map_err(|e| match e.kind() {
+        io::ErrorKind::AlreadyExists => anyhow!(
+            "fatal: Unable to create '{}': File exists.\n\n\
+            Another git process seems to be running in this repository, e.g.\n\
+            an editor opened by 'git commit'. Please make sure all processes\n\
+            are terminated then try again. If it still fails, a git process\n\
+            may have crashed in this repository earlier:\n\
+            remove the file manually to continue.",
+            lock_path.to_string_lossy()
+        ),
+        _ => anyhow!("fatal: Unable to create '{}': {}", lock_path.to_string_lossy(), e)
+    })?;
+    Ok(IndexLock { lock_path, index_path, file, committed: false })
+}
+
+impl IndexLock {
+    /// Replaces the index with the given one and releases the lock
+    pub fn commit(mut self, index: &Index) -> Result<()> {
+        io::Write::write_all(&mut self.file, &index.serialize()?)?;
+        self.file.sync_all()?;
+        fs::rename(&self.lock_path, &self.index_path)?;
+        self.committed = true;
+        Ok(())
+    }
+}
+
+impl Drop for IndexLock {
+    fn drop(&mut self) {
+        // Once committed, the lock file has become the index, and a lock file there now belongs to someone else
+        if !self.committed {
+            let _ = fs::remove_file(&self.lock_path);
+        }
+    }
+}
+
+fn corrupt() -> anyhow::Error {
+    anyhow!("fatal: index file corrupt")
+}
+
+fn read_u16(bytes: &[u8], pos: &mut usize) -> Result<u16> {
+    let val = u16::from_be_bytes(bytes.get(*pos..(*pos+2)).ok_or(corrupt())?.try_into()?);
+    *pos += 2;
+    Ok(val)
+}
+
+fn read_u32(bytes: &[u8], pos: &mut usize) -> Result<u32> {
+    let val = u32::from_be_bytes(bytes.get(*pos..(*pos+4)).ok_or(corrupt())?.try_into()?);
     *pos += 4;
-    val
+    Ok(val)
 }
 
-fn read_hash(bytes: &[u8], pos: &mut usize) -> [u8; 20] {
-    let val: [u8; 20] = bytes[*pos..(*pos+20)].try_into().unwrap();
+fn read_hash(bytes: &[u8], pos: &mut usize) -> Result<[u8; 20]> {
+    let val: [u8; 20] = bytes.get(*pos..(*pos+20)).ok_or(corrupt())?.try_into()?;
     *pos += 20;
-    val
+    Ok(val)
 }
 
 fn append_string(current: &mut Vec::<u8>, val: String) {
@@ -155,4 +464,32 @@ fn append_string(current: &mut Vec::<u8>, val: String) {
 fn append_u32(current: &mut Vec::<u8>, val: u32) {
     let mut bytes = u32::to_be_bytes(val).to_vec();
     current.append(&mut bytes);
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn index_path_separates_components_with_forward_slashes() {
+        // Joining uses the platform's separator, which is `\` on Windows
+        let path = Path::new("src").join("bin").join("main.rs");
+        assert_eq!(index_path(&path), PathBuf::from("src/bin/main.rs"));
+        assert_eq!(index_path(Path::new("src/bin/main.rs")), PathBuf::from("src/bin/main.rs"));
+    }
+
+    #[test]
+    #[cfg(windows)]
+    fn index_path_replaces_backslashes_on_windows() {
+        assert_eq!(index_path(Path::new("src\\bin\\main.rs")), PathBuf::from("src/bin/main.rs"));
+    }
+
+    #[test]
+    fn add_item_stores_paths_with_forward_slashes() {
+        let mut index = Index { version: 2, items: Vec::new() };
+        index.add_item(IndexItem::without_stat(Path::new("docs").join("a.txt"), 0o100644, [0; 20], 0));
+        index.add_item(IndexItem::without_stat(PathBuf::from("docs/a.txt"), 0o100644, [1; 20], 0));
+        assert_eq!(index.items.len(), 1);
+        assert_eq!(index.items[0].path, PathBuf::from("docs/a.txt"));
+        assert_eq!(index.items[0].hash, [1; 20]);
+    }
+}
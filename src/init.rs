@@ -1,10 +1,37 @@
-use std::{path::{PathBuf, Path}, env, fs};
-use anyhow::Result;
+use std::{path::PathBuf, env, fs};
+use anyhow::{anyhow, Result};
+use clap::Args;
 use crate::{GlobalOpts, git_dir_name, program_name};
+use crate::config::Config;
+use crate::refs::check_ref_name;
 
+#[derive(Args)]
+pub struct InitArgs {
+    /// The directory to create the repository in. Defaults to the current directory.
+    path: Option<String>,
+    /// Create a repository without a working tree, with its contents directly in the directory
+    #[arg(long)]
+    bare: bool,
+    /// The branch HEAD starts on. Defaults to `init.defaultBranch` from the global configuration, or `master`.
+    #[arg(short = 'b', long)]
+    initial_branch: Option<String>,
+}
 
-pub fn cmd_init(path: Option<String>, global_opts: GlobalOpts) -> Result<()> {
-    let git_dir_name = git_dir_name(global_opts.clone());
+pub fn cmd_init(args: InitArgs, global_opts: GlobalOpts) -> Result<()> {
+    let default_branch = Config::load_global()?.get("init.defaultBranch");
+    let branch = match (&args.initial_branch, &default_branch) {
+        (Some(name), _) => {
+            check_ref_name(&format!("refs/heads/{}", name))
+                .map_err(|_| anyhow!("fatal: invalid initial branch name: '{}'", name))?;
+            name.clone()
+        },
+        (None, Some(name)) => {
+            check_ref_name(&format!("refs/heads/{}", name))
+                .map_err(|_| anyhow!("fatal: invalid branch name: init.defaultBranch = {}", name))?;
+            name.clone()
+        },
+        (None, None) => String::from("master")
+    };
 
     let git_dirs: Vec<PathBuf> = vec![
         "branches",
@@ -14,27 +41,25 @@ pub fn cmd_init(path: Option<String>, global_opts: GlobalOpts) -> Result<()> {
         "objects/pack",
         "refs/heads",
         "refs/tags"
-    ].into_iter().map(|s| PathBuf::from(s)).collect();
+    ].into_iter().map(PathBuf::from).collect();
 
-    let root = path
-        .map(|p| Path::new(&p).to_path_buf())
-        .unwrap_or(env::current_dir().unwrap());
+    // Git reports where the repository is as an absolute path
+    let cwd = env::current_dir()?;
+    let root = args.path.map(|p| cwd.join(p)).unwrap_or(cwd);
 
-    let gitdir = root.join(git_dir_name); 
+    let gitdir = if args.bare { root } else { root.join(git_dir_name(global_opts)) };
     for p in git_dirs {
         let path = gitdir.join(&p);
         fs::create_dir_all(&path)?;
     }
 
     // Create default files
-    fs::write(gitdir.join("config"), repo_default_config())?;
+    fs::write(gitdir.join("config"), repo_default_config(args.bare))?;
     fs::write(gitdir.join("description"), repo_default_description())?;
     fs::write(gitdir.join("info/exclude"), repo_default_exclude())?;
 
-    // Create a HEAD file pointing to the master branch
-    let head_path = gitdir.join("HEAD");
-    let head_contents = "ref: refs/heads/master\n";
-    fs::write(head_path, head_contents)?;
+    // Create a HEAD file pointing to the initial branch
+    fs::write(gitdir.join("HEAD"), format!("ref: refs/heads/{}\n", branch))?;
 
     // Add trailing slash if a directory name to match Git
     let mut gitdir_str: String = gitdir.to_string_lossy().into();
@@ -43,6 +68,11 @@ pub fn cmd_init(path: Option<String>, global_opts: GlobalOpts) -> Result<()> {
     }
 
     println!("Initialized empty {} repository in {}", program_name(global_opts), gitdir_str);
+
+    // The hint is only given when the branch name wasn't chosen
+    if args.initial_branch.is_some() || default_branch.is_some() {
+        return Ok(());
+    }
     eprintln!("hint: Using 'master' as the name for the initial branch. This default branch name");
     eprintln!("hint: is subject to change. To configure the initial branch name to use in all");
     eprintln!("hint: of your new repositories, which will suppress this warning, call:");
@@ -56,7 +86,16 @@ pub fn cmd_init(path: Option<String>, global_opts: GlobalOpts) -> Result<()> {
     Ok(())
 }
 
-fn repo_default_config() -> String {
+// Bare repositories have no working tree for reflogs to record changes made in, so reflogs are only kept by default
+// in other repositories
+fn repo_default_config(bare: bool) -> String {
+    if bare {
+        return String::from(
+"[core]
+\trepositoryformatversion = 0
+\tfilemode = true
+\tbare = true\n");
+    }
     String::from(
 "[core]
 \trepositoryformatversion = 0
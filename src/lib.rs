@@ -1,35 +1,129 @@
 // INTERFACE
 
+pub mod date;
+pub mod index;
 pub mod objects;
+pub mod reachable;
 
 pub use crate::add::{AddArgs, cmd_add};
+pub use crate::am::{AmArgs, cmd_am};
+pub use crate::bisect::{BisectArgs, cmd_bisect};
 pub use crate::checkout::{CheckoutArgs, cmd_checkout};
 pub use crate::cat_file::{CatFileArgs, cmd_cat_file};
+pub use crate::check_attr::{CheckAttrArgs, cmd_check_attr};
+pub use crate::check_ignore::{CheckIgnoreArgs, cmd_check_ignore};
+pub use crate::check_ref_format::{CheckRefFormatArgs, cmd_check_ref_format};
 pub use crate::commit::{CommitArgs, cmd_commit};
+pub use crate::commit_graph::{CommitGraphArgs, cmd_commit_graph};
+pub use crate::config::{ConfigArgs, cmd_config};
+pub use crate::count_objects::{CountObjectsArgs, cmd_count_objects};
+pub use crate::describe::{DescribeArgs, cmd_describe};
+pub use crate::diff::{DiffArgs, cmd_diff};
+pub use crate::fetch::{FetchArgs, cmd_fetch};
+pub use crate::for_each_ref::{ForEachRefArgs, cmd_for_each_ref};
+pub use crate::format_patch::{FormatPatchArgs, cmd_format_patch};
+pub use crate::grep::{GrepArgs, cmd_grep};
 pub use crate::hash_object::{HashObjectArgs, cmd_hash_object};
-pub use crate::init::cmd_init;
+pub use crate::init::{InitArgs, cmd_init};
 pub use crate::log::{LogArgs, cmd_log};
 pub use crate::ls_files::{LsFilesArgs, cmd_ls_files};
+pub use crate::ls_tree::{LsTreeArgs, cmd_ls_tree};
+pub use crate::merge::{MergeArgs, cmd_merge};
+pub use crate::merge_base::{MergeBaseArgs, cmd_merge_base};
+pub use crate::mktag::{MktagArgs, cmd_mktag};
+pub use crate::mktree::{MktreeArgs, cmd_mktree};
+pub use crate::name_rev::{NameRevArgs, cmd_name_rev};
+pub use crate::prune::{PruneArgs, cmd_prune};
+pub use crate::pull::{PullArgs, cmd_pull};
+pub use crate::push::{PushArgs, cmd_push};
+pub use crate::rebase::{RebaseArgs, cmd_rebase};
+pub use crate::reflog::{ReflogArgs, cmd_reflog};
+pub use crate::repack::{RepackArgs, cmd_repack};
+pub use crate::rev_parse::{RevParseArgs, cmd_rev_parse};
+pub use crate::show_branch::{ShowBranchArgs, cmd_show_branch};
+pub use crate::show_ref::{ShowRefArgs, cmd_show_ref};
+pub use crate::stash::{StashArgs, cmd_stash};
 pub use crate::status::{StatusArgs, cmd_status};
-pub use crate::write_tree::cmd_write_tree;
+pub use crate::stripspace::{StripspaceArgs, cmd_stripspace};
+pub use crate::switch::{SwitchArgs, cmd_switch};
+pub use crate::tag::{TagArgs, cmd_tag};
+pub use crate::update_index::{UpdateIndexArgs, cmd_update_index};
+pub use crate::verify_commit::{VerifyCommitArgs, cmd_verify_commit};
+pub use crate::verify_tag::{VerifyTagArgs, cmd_verify_tag};
+pub use crate::write_tree::{WriteTreeArgs, cmd_write_tree};
 
 // END INTERFACE
 
 mod add;
+mod add_patch;
+mod am;
+mod apply;
+mod attributes;
+mod bisect;
 mod cat_file;
+mod check_attr;
+mod check_ignore;
+mod check_ref_format;
 mod checkout;
 mod commit;
+mod commit_graph;
+mod config;
+mod count_objects;
+mod dag;
+mod describe;
+mod diff;
+mod diffstat;
+mod editor;
+mod filter;
+mod fetch;
+mod for_each_ref;
+mod format_patch;
+mod gpg;
+mod grep;
 mod hash_object;
-mod index;
+mod hooks;
+mod http;
+mod ignore;
 mod init;
+mod line_diff;
 mod log;
+mod mailmap;
 mod ls_files;
+mod ls_tree;
+mod merge;
+mod merge_base;
+mod mktag;
+mod mktree;
+mod name_rev;
+mod pack;
+mod prune;
+mod pull;
+mod push;
+mod quote;
+mod rebase;
+mod reflog;
+mod refs;
+mod remote;
+mod repack;
+mod rev_parse;
+mod show_branch;
+mod show_ref;
+mod stash;
 mod status;
+mod stripspace;
+mod switch;
+mod tag;
+mod update_index;
+mod verify_commit;
+mod verify_tag;
+mod word_diff;
 mod write_tree;
 
 use clap::Args;
-use clap::{Parser, Subcommand, ValueEnum};
-use std::path::{Path, PathBuf};
+use clap::{Parser, Subcommand};
+use std::{path::{Path, PathBuf}, process};
+
+use crate::config::check_repository_format;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
@@ -45,15 +139,51 @@ pub struct Cli {
 #[derive(Subcommand)]
 pub enum Command {
     Add(AddArgs),
-    Init { path: Option<String> },
+    Am(AmArgs),
+    Bisect(BisectArgs),
+    Init(InitArgs),
     HashObject(HashObjectArgs),
     CatFile(CatFileArgs),
+    CheckAttr(CheckAttrArgs),
+    CheckIgnore(CheckIgnoreArgs),
+    CheckRefFormat(CheckRefFormatArgs),
     Checkout(CheckoutArgs),
     Commit(CommitArgs),
+    CommitGraph(CommitGraphArgs),
+    Config(ConfigArgs),
+    CountObjects(CountObjectsArgs),
+    Describe(DescribeArgs),
+    Diff(DiffArgs),
+    Fetch(FetchArgs),
+    ForEachRef(ForEachRefArgs),
+    FormatPatch(FormatPatchArgs),
+    Grep(GrepArgs),
     Log(LogArgs),
     LsFiles(LsFilesArgs),
+    LsTree(LsTreeArgs),
+    Merge(MergeArgs),
+    MergeBase(MergeBaseArgs),
+    Mktag(MktagArgs),
+    Mktree(MktreeArgs),
+    NameRev(NameRevArgs),
+    Prune(PruneArgs),
+    Pull(PullArgs),
+    Push(PushArgs),
+    Rebase(RebaseArgs),
+    Reflog(ReflogArgs),
+    Repack(RepackArgs),
+    RevParse(RevParseArgs),
+    ShowBranch(ShowBranchArgs),
+    ShowRef(ShowRefArgs),
+    Stash(StashArgs),
     Status(StatusArgs),
-    WriteTree
+    Stripspace(StripspaceArgs),
+    Switch(SwitchArgs),
+    Tag(TagArgs),
+    UpdateIndex(UpdateIndexArgs),
+    VerifyCommit(VerifyCommitArgs),
+    VerifyTag(VerifyTagArgs),
+    WriteTree(WriteTreeArgs),
 }
 
 #[derive(Args, Clone, Copy)]
@@ -62,28 +192,41 @@ pub struct GlobalOpts {
     pub git_mode: bool
 }
 
-#[derive(Copy, Clone, Eq, PartialEq, ValueEnum)]
-enum ObjectTypeExternal {
-    Blob,
-    Tree,
-    Commit,
-    Tag
-}
+// The most directories `repo_find` looks in. Paths can't be nearly this deep in practice, so reaching it means
+// something is wrong with the path.
+const REPO_SEARCH_DEPTH_MAX: usize = 1024;
 
-// Returns the path to the root of the repository at the given path.
+// Returns the path to the root of the repository at the given path, or None if neither it nor any directory above it
+// has one. The search goes no further than `REPO_SEARCH_DEPTH_MAX` directories up.
+// Exits if the repository has a format grit doesn't understand, since it could be damaged by trying to use it.
 fn repo_find(path: &Path, global_opts: GlobalOpts) -> Option<PathBuf> {
     let git_dir = git_dir_name(global_opts);
 
-    if path.join(git_dir).exists() {
-        return Some(path.to_path_buf());
-    }
+    // The ancestors end at the root of the filesystem, or of the drive on Windows, or with the empty path for a
+    // relative path, which isn't searched
+    for dir in path.ancestors().take(REPO_SEARCH_DEPTH_MAX).filter(|x| !x.as_os_str().is_empty()) {
+        if dir.join(&git_dir).exists() {
+            if let Err(e) = check_repository_format(dir, global_opts) {
+                eprintln!("{}", e);
+                process::exit(128);
+            }
+            return Some(dir.to_path_buf());
+        }
 
-    let parent = path.parent();
-    if parent == None || parent == Some(Path::new("")) {
-        return None
+        // Commands find the repository's files within the git directory under the root, so a bare repository, which
+        // is made of those files alone, has no root they can use. Carrying on to the parent could find an unrelated
+        // repository instead.
+        if is_bare_repository(dir) {
+            eprintln!("fatal: this operation must be run in a work tree");
+            process::exit(128);
+        }
     }
+    None
+}
 
-    repo_find(parent.unwrap(), global_opts)
+// Whether the directory has the files of a repository directly in it, as a bare repository does
+fn is_bare_repository(path: &Path) -> bool {
+    path.join("HEAD").is_file() && path.join("objects").is_dir() && path.join("refs").is_dir()
 }
 
 pub fn git_dir_name(global_opts: GlobalOpts) -> String {
@@ -92,4 +235,21 @@ pub fn git_dir_name(global_opts: GlobalOpts) -> String {
 
 pub fn program_name(global_opts: GlobalOpts) -> String { 
     if global_opts.git_mode { String::from("Git") } else { String::from("Grit") }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use super::*;
+
+    #[test]
+    fn repo_find_stops_searching_a_deep_path_outside_any_repository() {
+        // The directories needn't exist. Only the deepest REPO_SEARCH_DEPTH_MAX are looked in, so whatever is above
+        // the temporary directory can't be found.
+        let mut path = env::temp_dir();
+        for _ in 0..100_000 {
+            path.push("a");
+        }
+        assert_eq!(repo_find(&path, GlobalOpts { git_mode: true }), None);
+        assert_eq!(repo_find(&path, GlobalOpts { git_mode: false }), None);
+    }
+}
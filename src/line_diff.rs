@@ -0,0 +1,657 @@
+// Line-based differences between two files, and their display as unified diff hunks
+
+use std::{collections::HashMap, ops::Range};
+use clap::ValueEnum;
+
+/// One step in transforming the old file into the new one. Indices are zero-based line numbers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Edit {
+    /// The line at the first index of the old file is the same as the line at the second index of the new file
+    Equal(usize, usize),
+    /// The line at this index of the old file was removed
+    Delete(usize),
+    /// The line at this index of the new file was added
+    Insert(usize),
+}
+
+/// Splits text into lines, each including its terminating new line if it has one
+pub fn split_lines(text: &[u8]) -> Vec<&[u8]> {
+    let mut lines = Vec::new();
+    let mut start = 0;
+    for (i, byte) in text.iter().enumerate() {
+        if *byte == b'\n' {
+            lines.push(&text[start..=i]);
+            start = i + 1;
+        }
+    }
+    if start < text.len() {
+        lines.push(&text[start..]);
+    }
+    lines
+}
+
+/// The algorithm used to find the differences between two files
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum DiffAlgorithm {
+    /// Myers' algorithm, which finds a shortest sequence of edits
+    #[default]
+    Myers,
+    /// The same as Myers, whose result is always minimal here
+    Minimal,
+    /// Patience diff, which matches the lines that appear once in each file first and so keeps blocks of code
+    /// together
+    Patience,
+}
+
+impl DiffAlgorithm {
+    /// Parses an algorithm by the name Git gives it, as in the `diff.algorithm` setting
+    pub fn parse(name: &str) -> Option<DiffAlgorithm> {
+        DiffAlgorithm::from_str(name, true).ok()
+    }
+}
+
+/// Finds a sequence of edits turning `old` into `new` with the given algorithm. As in Git, each group of changed lines
+/// is then slid up or down to where it's easiest to read, whichever algorithm found it.
+pub fn diff_lines(old: &[&[u8]], new: &[&[u8]], algorithm: DiffAlgorithm) -> Vec<Edit> {
+    let mut old_changed = vec![false; old.len()];
+    let mut new_changed = vec![false; new.len()];
+    match algorithm {
+        DiffAlgorithm::Myers | DiffAlgorithm::Minimal => myers_changes(old, new, &mut old_changed, &mut new_changed),
+        DiffAlgorithm::Patience => {
+            patience_range(old, new, 0..old.len(), 0..new.len(), &mut old_changed, &mut new_changed)
+        }
+    }
+    compact_changes(old, &mut old_changed, &new_changed);
+    compact_changes(new, &mut new_changed, &old_changed);
+    changes_to_edits(&old_changed, &new_changed)
+}
+
+// Lists the edits that turn one file into another, given which lines of each change. As in Git, the lines removed from
+// each changed region come before those added.
+fn changes_to_edits(old_changed: &[bool], new_changed: &[bool]) -> Vec<Edit> {
+    let mut edits = Vec::new();
+    let (mut x, mut y) = (0, 0);
+    while x < old_changed.len() || y < new_changed.len() {
+        if x < old_changed.len() && old_changed[x] {
+            edits.push(Edit::Delete(x));
+            x += 1;
+        } else if y < new_changed.len() && new_changed[y] {
+            edits.push(Edit::Insert(y));
+            y += 1;
+        } else {
+            edits.push(Edit::Equal(x, y));
+            x += 1;
+            y += 1;
+        }
+    }
+    edits
+}
+
+/// Finds a shortest sequence of edits turning `old` into `new`, using Myers' algorithm
+pub fn myers_diff(old: &[&[u8]], new: &[&[u8]]) -> Vec<Edit> {
+    diff_lines(old, new, DiffAlgorithm::Myers)
+}
+
+// Marks the lines that change between two files by Myers' algorithm. A line that appears nowhere in the other file
+// must change, so as in Git those lines are set aside first and only the rest are searched.
+fn myers_changes(old: &[&[u8]], new: &[&[u8]], old_changed: &mut [bool], new_changed: &mut [bool]) {
+    // Each distinct line is numbered, so the search compares numbers rather than lines
+    let mut numbers: HashMap<&[u8], usize> = HashMap::new();
+    let [old_numbers, new_numbers] = [old, new].map(|lines| -> Vec<usize> {
+        lines.iter().map(|x| {
+            let next = numbers.len();
+            *numbers.entry(x).or_insert(next)
+        }).collect()
+    });
+    let old_distinct = old_numbers.iter().max().map_or(0, |x| x + 1);
+    let mut in_new = vec![false; old_distinct];
+    for n in new_numbers.iter().filter(|n| **n < old_distinct) {
+        in_new[*n] = true;
+    }
+
+    let old_kept: Vec<usize> = (0..old.len()).filter(|x| in_new[old_numbers[*x]]).collect();
+    let new_kept: Vec<usize> = (0..new.len()).filter(|y| new_numbers[*y] < old_distinct).collect();
+    let a: Vec<usize> = old_kept.iter().map(|x| old_numbers[*x]).collect();
+    let b: Vec<usize> = new_kept.iter().map(|y| new_numbers[*y]).collect();
+    let mut a_changed = vec![false; a.len()];
+    let mut b_changed = vec![false; b.len()];
+    let mut forward = vec![0; 2 * (a.len() + b.len()) + 3];
+    let mut backward = forward.clone();
+    myers_range(&a, &b, &mut a_changed, &mut b_changed, &mut forward, &mut backward);
+
+    old_changed.fill(true);
+    new_changed.fill(true);
+    for (x, changed) in old_kept.into_iter().zip(a_changed) {
+        old_changed[x] = changed;
+    }
+    for (y, changed) in new_kept.into_iter().zip(b_changed) {
+        new_changed[y] = changed;
+    }
+}
+
+// Marks the lines that change between `a` and `b` by the linear space form of Myers' algorithm: a point in the middle
+// of a shortest path of edits is found, and the parts before and after it are diffed in the same way.
+// See "An O(ND) Difference Algorithm and Its Variations", Eugene W. Myers, 1986. The furthest reaching point on each
+// diagonal is kept in `forward` and `backward`, which are shared by every part to save allocating them again.
+fn myers_range(
+    a: &[usize],
+    b: &[usize],
+    a_changed: &mut [bool],
+    b_changed: &mut [bool],
+    forward: &mut [isize],
+    backward: &mut [isize]
+) {
+    // Lines common to the start or end of both parts needn't be searched
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    let (a, b) = (&a[prefix..], &b[prefix..]);
+    let suffix = a.iter().rev().zip(b.iter().rev()).take_while(|(x, y)| x == y).count();
+    let (a, b) = (&a[..a.len() - suffix], &b[..b.len() - suffix]);
+    let a_changed = &mut a_changed[prefix..prefix + a.len()];
+    let b_changed = &mut b_changed[prefix..prefix + b.len()];
+    if a.is_empty() || b.is_empty() {
+        a_changed.fill(true);
+        b_changed.fill(true);
+        return;
+    }
+
+    let (x, y) = middle_split(a, b, forward, backward);
+    myers_range(&a[..x], &b[..y], &mut a_changed[..x], &mut b_changed[..y], forward, backward);
+    myers_range(&a[x..], &b[y..], &mut a_changed[x..], &mut b_changed[y..], forward, backward);
+}
+
+// Finds a point in the middle of a shortest path of edits turning `a` into `b`, by searching from the start and from
+// the end at once until the two searches meet. Diagonals are searched in the same order as Git does, so that where
+// several paths are as short the same one is taken. Neither `a` nor `b` may be empty.
+fn middle_split(
+    a: &[usize],
+    b: &[usize],
+    forward: &mut [isize],
+    backward: &mut [isize]
+) -> (usize, usize) {
+    let n = a.len() as isize;
+    let m = b.len() as isize;
+    // The diagonal the searches from either end are centred on, as seen from the start
+    let delta = n - m;
+    let offset = (forward.len() / 2) as isize;
+    forward[(offset + 1) as usize] = 0;
+    backward[(offset + 1) as usize] = 0;
+
+    // Both searches work forwards, the one from the end along the reversed files. A point on diagonal `k` is `x`
+    // lines into `a` and `x - k` lines into `b`, and only the diagonals that cross the files are searched.
+    let furthest = |v: &[isize], d: isize, k: isize| {
+        let i = (k + offset) as usize;
+        let down = k == -d || k == -m || (k != d && k != n && v[i - 1] < v[i + 1]);
+        let x = if down { v[i + 1] } else { v[i - 1] + 1 };
+        x.min(n).min(m + k)
+    };
+    for d in 0..=(n + m) {
+        for k in (-d..=d).rev().step_by(2).filter(|k| (-m..=n).contains(k)) {
+            let mut x = furthest(forward, d, k);
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            forward[(k + offset) as usize] = x;
+            // With an odd delta the searches can first meet on the search from the start
+            let back_k = delta - k;
+            if delta % 2 != 0 && (1 - d..d).contains(&back_k) && x + backward[(back_k + offset) as usize] >= n {
+                return (x as usize, y as usize);
+            }
+        }
+        for k in (-d..=d).step_by(2).filter(|k| (-m..=n).contains(k)) {
+            let mut u = furthest(backward, d, k);
+            let mut w = u - k;
+            while u < n && w < m && a[(n - u - 1) as usize] == b[(m - w - 1) as usize] {
+                u += 1;
+                w += 1;
+            }
+            backward[(k + offset) as usize] = u;
+            let forward_k = delta - k;
+            if delta % 2 == 0 && (-d..=d).contains(&forward_k) && forward[(forward_k + offset) as usize] + u >= n {
+                return ((n - u) as usize, (m - w) as usize);
+            }
+        }
+    }
+    unreachable!("the searches from either end always meet")
+}
+
+// Marks the lines that change within a range of each file by patience diff, as Git does. The lines that appear exactly
+// once in each range are matched up, keeping the longest sequence of them that's in the same order in both, and the
+// lines between those are diffed in the same way in turn. Where there are no unique lines to match, Myers' algorithm
+// is used instead.
+fn patience_range(
+    old: &[&[u8]],
+    new: &[&[u8]],
+    old_range: Range<usize>,
+    new_range: Range<usize>,
+    old_changed: &mut [bool],
+    new_changed: &mut [bool]
+) {
+    if old_range.is_empty() || new_range.is_empty() {
+        old_changed[old_range].fill(true);
+        new_changed[new_range].fill(true);
+        return;
+    }
+
+    // The number of times each line of the old range appears in each range, with where it's found in the new one
+    let mut counts: HashMap<&[u8], (usize, usize, usize)> = HashMap::new();
+    for x in old_range.clone() {
+        counts.entry(old[x]).or_default().0 += 1;
+    }
+    for y in new_range.clone() {
+        if let Some(entry) = counts.get_mut(new[y]) {
+            entry.1 += 1;
+            entry.2 = y;
+        }
+    }
+    let unique: Vec<(usize, usize)> = old_range.clone()
+        .filter_map(|x| counts.get(old[x]).filter(|e| e.0 == 1 && e.1 == 1).map(|e| (x, e.2)))
+        .collect();
+
+    // The longest run of unique lines in the same order in both files, found by patience sorting: each line goes on
+    // the leftmost pile whose top is later in the new file, and remembers the top of the pile before it
+    let mut piles: Vec<usize> = Vec::new();
+    let mut previous: Vec<Option<usize>> = Vec::with_capacity(unique.len());
+    for (i, (_, y)) in unique.iter().enumerate() {
+        let pile = piles.partition_point(|top| unique[*top].1 < *y);
+        previous.push(pile.checked_sub(1).map(|x| piles[x]));
+        match piles.get_mut(pile) {
+            Some(top) => *top = i,
+            None => piles.push(i)
+        }
+    }
+    let Some(mut last) = piles.last().copied() else {
+        // With no unique lines in common, this part of the files is left to Myers' algorithm
+        myers_changes(&old[old_range.clone()], &new[new_range.clone()], &mut old_changed[old_range],
+            &mut new_changed[new_range]);
+        return;
+    };
+    let mut matches = vec![unique[last]];
+    while let Some(before) = previous[last] {
+        matches.push(unique[before]);
+        last = before;
+    }
+    matches.reverse();
+
+    // Between the matched lines, the lines that are the same next to them are matched too, and the rest is diffed
+    // again. Runs of matched lines next to each other are taken together.
+    let (mut x, mut y) = (old_range.start, new_range.start);
+    let mut i = 0;
+    loop {
+        let (mut next_x, mut next_y) = matches.get(i).copied().unwrap_or((old_range.end, new_range.end));
+        if i < matches.len() {
+            while next_x > x && next_y > y && old[next_x - 1] == new[next_y - 1] {
+                next_x -= 1;
+                next_y -= 1;
+            }
+        }
+        while x < next_x && y < next_y && old[x] == new[y] {
+            x += 1;
+            y += 1;
+        }
+        if next_x > x || next_y > y {
+            patience_range(old, new, x..next_x, y..next_y, old_changed, new_changed);
+        }
+        if i == matches.len() {
+            return;
+        }
+        while i + 1 < matches.len() && matches[i + 1] == (matches[i].0 + 1, matches[i].1 + 1) {
+            i += 1;
+        }
+        (x, y) = (matches[i].0 + 1, matches[i].1 + 1);
+        i += 1;
+    }
+}
+
+// How far the indent heuristic looks for a better place for a group of changes, and the weights it gives to what's
+// around each place, all as in Git
+const INDENT_HEURISTIC_MAX_SLIDING: usize = 100;
+const MAX_INDENT: isize = 200;
+const MAX_BLANKS: isize = 20;
+const START_OF_FILE_PENALTY: isize = 1;
+const END_OF_FILE_PENALTY: isize = 21;
+const TOTAL_BLANK_WEIGHT: isize = -30;
+const POST_BLANK_WEIGHT: isize = 6;
+const RELATIVE_INDENT_PENALTY: isize = -4;
+const RELATIVE_INDENT_WITH_BLANK_PENALTY: isize = 10;
+const RELATIVE_OUTDENT_PENALTY: isize = 24;
+const RELATIVE_OUTDENT_WITH_BLANK_PENALTY: isize = 17;
+const RELATIVE_DEDENT_PENALTY: isize = 23;
+const RELATIVE_DEDENT_WITH_BLANK_PENALTY: isize = 17;
+const INDENT_WEIGHT: isize = 60;
+
+// A run of changed lines of one file, from `start` up to but not including `end`. An empty group stands for the
+// place before the line at `start`, where the other file has changes.
+struct Group {
+    start: usize,
+    end: usize,
+}
+
+impl Group {
+    // The first group of a file, which is empty unless the file starts with a change
+    fn first(changed: &[bool]) -> Group {
+        let end = changed.iter().take_while(|x| **x).count();
+        Group { start: 0, end }
+    }
+
+    // Moves to the next group, returning false at the end of the file
+    fn next(&mut self, changed: &[bool]) -> bool {
+        if self.end == changed.len() {
+            return false;
+        }
+        self.start = self.end + 1;
+        self.end = self.start + changed[self.start..].iter().take_while(|x| **x).count();
+        true
+    }
+
+    // Moves to the previous group, returning false at the start of the file
+    fn previous(&mut self, changed: &[bool]) -> bool {
+        if self.start == 0 {
+            return false;
+        }
+        self.end = self.start - 1;
+        self.start = self.end - changed[..self.end].iter().rev().take_while(|x| **x).count();
+        true
+    }
+
+    // Moves the changes one line down if the line after them is the same as their first line, merging with the
+    // group after if they meet
+    fn slide_down(&mut self, lines: &[&[u8]], changed: &mut [bool]) -> bool {
+        if self.end == lines.len() || lines[self.start] != lines[self.end] {
+            return false;
+        }
+        changed[self.start] = false;
+        changed[self.end] = true;
+        self.start += 1;
+        self.end += 1 + changed[self.end + 1..].iter().take_while(|x| **x).count();
+        true
+    }
+
+    // Moves the changes one line up if the line before them is the same as their last line, merging with the group
+    // before if they meet
+    fn slide_up(&mut self, lines: &[&[u8]], changed: &mut [bool]) -> bool {
+        if self.start == 0 || lines[self.start - 1] != lines[self.end - 1] {
+            return false;
+        }
+        self.start -= 1;
+        self.end -= 1;
+        changed[self.start] = true;
+        changed[self.end] = false;
+        self.start -= changed[..self.start].iter().rev().take_while(|x| **x).count();
+        true
+    }
+}
+
+// Slides each group of changed lines of a file up or down where the lines at either end are the same, which doesn't
+// change the diff's meaning but can make it easier to read. As in Git, a group is lined up with changes in the other
+// file if it can be, and otherwise placed where the indentation around it suggests a block of code begins and ends.
+fn compact_changes(lines: &[&[u8]], changed: &mut [bool], other_changed: &[bool]) {
+    let mut group = Group::first(changed);
+    let mut other = Group::first(other_changed);
+    loop {
+        if group.end != group.start {
+            // Slide the group as far up and then down as it goes, until it stops growing by merging with others
+            let mut earliest_end;
+            let mut end_matching_other;
+            loop {
+                let size = group.end - group.start;
+                end_matching_other = None;
+                while group.slide_up(lines, changed) {
+                    other.previous(other_changed);
+                }
+                earliest_end = group.end;
+                if other.end > other.start {
+                    end_matching_other = Some(group.end);
+                }
+                while group.slide_down(lines, changed) {
+                    other.next(other_changed);
+                    if other.end > other.start {
+                        end_matching_other = Some(group.end);
+                    }
+                }
+                if size == group.end - group.start {
+                    break;
+                }
+            }
+
+            if group.end == earliest_end {
+                // The group can't move
+            } else if end_matching_other.is_some() {
+                while other.end == other.start {
+                    group.slide_up(lines, changed);
+                    other.previous(other_changed);
+                }
+            } else {
+                // Try each place the group could go, and keep the highest of those with the best score
+                let size = group.end - group.start;
+                let lowest = earliest_end.max((group.end + 1).saturating_sub(size + 2))
+                    .max(group.end.saturating_sub(INDENT_HEURISTIC_MAX_SLIDING));
+                let mut best: Option<(usize, (isize, isize))> = None;
+                for shift in lowest..=group.end {
+                    let score = add_split_scores(measure_split(lines, shift), measure_split(lines, shift - size));
+                    if best.is_none_or(|(_, best_score)| compare_scores(score, best_score) <= 0) {
+                        best = Some((shift, score));
+                    }
+                }
+                let best_shift = best.map_or(group.end, |(shift, _)| shift);
+                while group.end > best_shift {
+                    group.slide_up(lines, changed);
+                    other.previous(other_changed);
+                }
+            }
+        }
+
+        if !group.next(changed) {
+            break;
+        }
+        other.next(other_changed);
+    }
+}
+
+// What's around the place between two lines that a group of changes could start or end at, for the indent heuristic
+struct SplitMeasurement {
+    end_of_file: bool,
+    /// The indent of the line after the split, or -1 if it's blank
+    indent: isize,
+    /// The number of blank lines just before the split
+    pre_blank: isize,
+    /// The indent of the nearest line before the split that isn't blank, or -1 if there's none
+    pre_indent: isize,
+    /// The number of blank lines after the line after the split
+    post_blank: isize,
+    /// The indent of the nearest line after the line after the split that isn't blank, or -1 if there's none
+    post_indent: isize,
+}
+
+// Measures the split before the line at `split`
+fn measure_split(lines: &[&[u8]], split: usize) -> SplitMeasurement {
+    let mut m = SplitMeasurement {
+        end_of_file: split >= lines.len(),
+        indent: lines.get(split).map_or(-1, |x| indent(x)),
+        pre_blank: 0,
+        pre_indent: -1,
+        post_blank: 0,
+        post_indent: -1,
+    };
+    for line in lines[..split.min(lines.len())].iter().rev() {
+        m.pre_indent = indent(line);
+        if m.pre_indent != -1 {
+            break;
+        }
+        m.pre_blank += 1;
+        if m.pre_blank == MAX_BLANKS {
+            m.pre_indent = 0;
+            break;
+        }
+    }
+    for line in lines.iter().skip(split + 1) {
+        m.post_indent = indent(line);
+        if m.post_indent != -1 {
+            break;
+        }
+        m.post_blank += 1;
+        if m.post_blank == MAX_BLANKS {
+            m.post_indent = 0;
+            break;
+        }
+    }
+    m
+}
+
+// The width of the white space a line starts with, with tabs to multiples of eight, or -1 if the line is blank
+fn indent(line: &[u8]) -> isize {
+    let mut width = 0;
+    for byte in line {
+        if !byte.is_ascii_whitespace() {
+            return width;
+        }
+        match byte {
+            b' ' => width += 1,
+            b'\t' => width += 8 - width % 8,
+            _ => ()
+        }
+        if width >= MAX_INDENT {
+            return MAX_INDENT;
+        }
+    }
+    -1
+}
+
+// Scores the two splits a group of changes makes, as the effective indent and a penalty, lower being better
+fn add_split_scores(first: SplitMeasurement, second: SplitMeasurement) -> (isize, isize) {
+    let mut effective_indent = 0;
+    let mut penalty = 0;
+    for m in [first, second] {
+        if m.pre_indent == -1 && m.pre_blank == 0 {
+            penalty += START_OF_FILE_PENALTY;
+        }
+        if m.end_of_file {
+            penalty += END_OF_FILE_PENALTY;
+        }
+        let post_blank = if m.indent == -1 { 1 + m.post_blank } else { 0 };
+        let total_blank = m.pre_blank + post_blank;
+        penalty += TOTAL_BLANK_WEIGHT * total_blank + POST_BLANK_WEIGHT * post_blank;
+
+        let indent = if m.indent != -1 { m.indent } else { m.post_indent };
+        let any_blanks = total_blank != 0;
+        effective_indent += indent;
+        if indent == -1 || m.pre_indent == -1 || indent == m.pre_indent {
+            continue;
+        }
+        penalty += if indent > m.pre_indent {
+            if any_blanks { RELATIVE_INDENT_WITH_BLANK_PENALTY } else { RELATIVE_INDENT_PENALTY }
+        } else if m.post_indent != -1 && m.post_indent > indent {
+            // A line indented less than the one before, but followed by one indented more, likely starts a block
+            if any_blanks { RELATIVE_OUTDENT_WITH_BLANK_PENALTY } else { RELATIVE_OUTDENT_PENALTY }
+        } else {
+            if any_blanks { RELATIVE_DEDENT_WITH_BLANK_PENALTY } else { RELATIVE_DEDENT_PENALTY }
+        };
+    }
+    (effective_indent, penalty)
+}
+
+fn compare_scores(a: (isize, isize), b: (isize, isize)) -> isize {
+    INDENT_WEIGHT * (a.0 - b.0).signum() + a.1 - b.1
+}
+
+/// A group of nearby edits with the unchanged lines around them
+#[derive(Debug)]
+pub struct Hunk {
+    /// The zero-based line of the old file the hunk starts at
+    pub old_start: usize,
+    pub old_count: usize,
+    /// The zero-based line of the new file the hunk starts at
+    pub new_start: usize,
+    pub new_count: usize,
+    pub edits: Vec<Edit>,
+}
+
+/// Groups edits into hunks, each with up to `context` unchanged lines either side.
+/// Changes separated by no more than twice that many unchanged lines share a hunk.
+pub fn hunks(edits: &[Edit], context: usize) -> Vec<Hunk> {
+    let changes: Vec<usize> = edits.iter().enumerate()
+        .filter(|(_, x)| !matches!(x, Edit::Equal(..)))
+        .map(|(i, _)| i)
+        .collect();
+
+    let mut groups: Vec<(usize, usize)> = Vec::new();
+    for i in changes {
+        match groups.last_mut() {
+            Some((_, end)) if i - *end <= 2 * context + 1 => *end = i,
+            _ => groups.push((i, i))
+        }
+    }
+
+    let mut result = Vec::new();
+    for (first, last) in groups {
+        let start = first.saturating_sub(context);
+        let end = (last + context + 1).min(edits.len());
+        let hunk_edits = edits[start..end].to_vec();
+
+        // The position of the hunk in each file is the number of that file's lines before it
+        let old_start = edits[..start].iter().filter(|x| !matches!(x, Edit::Insert(_))).count();
+        let new_start = edits[..start].iter().filter(|x| !matches!(x, Edit::Delete(_))).count();
+        let old_count = hunk_edits.iter().filter(|x| !matches!(x, Edit::Insert(_))).count();
+        let new_count = hunk_edits.iter().filter(|x| !matches!(x, Edit::Delete(_))).count();
+
+        result.push(Hunk { old_start, old_count, new_start, new_count, edits: hunk_edits });
+    }
+    result
+}
+
+/// Formats the differences between two texts as the hunks of a unified diff, as Git prints them.
+/// Returns nothing if the texts are the same.
+pub fn unified_diff(old: &[u8], new: &[u8], context: usize, algorithm: DiffAlgorithm) -> Vec<u8> {
+    let old_lines = split_lines(old);
+    let new_lines = split_lines(new);
+    let edits = diff_lines(&old_lines, &new_lines, algorithm);
+
+    let mut output = Vec::new();
+    for hunk in hunks(&edits, context) {
+        output.extend(format!(
+            "@@ -{} +{} @@",
+            hunk_range(hunk.old_start, hunk.old_count),
+            hunk_range(hunk.new_start, hunk.new_count)
+        ).into_bytes());
+        if let Some(function) = function_name(&old_lines[..hunk.old_start]) {
+            output.push(b' ');
+            output.extend(function);
+        }
+        output.push(b'\n');
+
+        for edit in &hunk.edits {
+            let (marker, line) = match edit {
+                Edit::Equal(x, _) => (b' ', old_lines[*x]),
+                Edit::Delete(x) => (b'-', old_lines[*x]),
+                Edit::Insert(y) => (b'+', new_lines[*y]),
+            };
+            output.push(marker);
+            output.extend(line);
+            if !line.ends_with(b"\n") {
+                output.extend(b"\n\\ No newline at end of file\n");
+            }
+        }
+    }
+    output
+}
+
+// A range is shown as `start,count`, with a count of one left out. An empty range starts at the line before it.
+fn hunk_range(start: usize, count: usize) -> String {
+    match count {
+        0 => format!("{},0", start),
+        1 => format!("{}", start + 1),
+        _ => format!("{},{}", start + 1, count)
+    }
+}
+
+// Git shows the nearest line before a hunk that looks like the start of a function,
+// which by default is any line starting with a letter, underscore or dollar sign.
+fn function_name<'a>(preceding: &[&'a [u8]]) -> Option<&'a [u8]> {
+    let line = preceding.iter().rev()
+        .find(|x| x.first().map(|c| c.is_ascii_alphabetic() || *c == b'_' || *c == b'$').unwrap_or(false))?;
+    let line = &line[..line.len().min(80)];
+    let end = line.iter().rposition(|x| !x.is_ascii_whitespace()).map(|x| x + 1).unwrap_or(0);
+    Some(&line[..end])
+}
+
@@ -1,13 +1,75 @@
 use std::env;
-use anyhow::{anyhow, Result};
+use anyhow::{bail, Result};
 use clap::Args;
 
-use crate::{GlobalOpts, repo_find, objects::{parse_hash, parse_commit, Commit, read_object_raw}};
+use crate::{GlobalOpts, repo_find};
+use crate::config::Config;
+use crate::date::{format_date, DateFormat};
+use crate::diffstat::raw_diff;
+use crate::mailmap::Mailmap;
+use crate::objects::{get_object, short_hash, Commit, Object, Signature};
+use crate::refs::resolve_revision;
 
 
 #[derive(Args)]
 pub struct LogArgs {
-    commit_hash: String,
+    /// The commit to start from. Defaults to HEAD
+    commit: Option<String>,
+    /// How to print each commit: oneline, short, medium, full, format:<template> or tformat:<template>
+    #[arg(long, visible_alias = "format", default_value = "medium")]
+    pretty: String,
+    /// How to show dates: default, relative, iso, iso-strict, rfc2822, short, unix or raw
+    #[arg(long, default_value = "default")]
+    date: String,
+    /// Show authors and committers by the canonical names and emails given in .mailmap. The default, unless
+    /// `log.mailmap` is false
+    #[arg(long, visible_alias = "mailmap", overrides_with = "no_use_mailmap")]
+    use_mailmap: bool,
+    /// Show authors and committers as they were recorded
+    #[arg(long, visible_alias = "no-mailmap")]
+    no_use_mailmap: bool,
+    /// After each commit, show a line for each file it changed with the old and new modes and blob hashes
+    #[arg(long)]
+    raw: bool,
+    /// Show each commit by the shortest prefix of its hash that's unambiguous, rather than in full
+    #[arg(long)]
+    abbrev_commit: bool,
+    /// Show each commit on one line, as with `--pretty=oneline --abbrev-commit`
+    #[arg(long)]
+    oneline: bool,
+}
+
+/// The layout used to print each commit
+pub enum PrettyFormat {
+    Oneline,
+    Short,
+    Medium,
+    Full,
+    /// A user-supplied template. `terminate` is true if each commit's output is followed by a new line,
+    /// rather than new lines only appearing between commits.
+    Template { template: String, terminate: bool }
+}
+
+impl PrettyFormat {
+    fn parse(text: &str) -> Result<PrettyFormat> {
+        match text {
+            "oneline" => Ok(PrettyFormat::Oneline),
+            "short" => Ok(PrettyFormat::Short),
+            "medium" => Ok(PrettyFormat::Medium),
+            "full" => Ok(PrettyFormat::Full),
+            _ => {
+                if let Some(template) = text.strip_prefix("format:") {
+                    Ok(PrettyFormat::Template { template: template.to_string(), terminate: false })
+                } else if let Some(template) = text.strip_prefix("tformat:") {
+                    Ok(PrettyFormat::Template { template: template.to_string(), terminate: true })
+                } else if text.contains('%') {
+                    Ok(PrettyFormat::Template { template: text.to_string(), terminate: true })
+                } else {
+                    bail!("fatal: invalid --pretty format: {}", text)
+                }
+            }
+        }
+    }
 }
 
 pub fn cmd_log(args: LogArgs, global_opts: GlobalOpts) -> Result<()> {
@@ -16,31 +78,177 @@ pub fn cmd_log(args: LogArgs, global_opts: GlobalOpts) -> Result<()> {
         panic!("fatal: not a grit repository");
     });
 
-    let mut current_hash = Some(parse_hash(&args.commit_hash)?);
+    let format = if args.oneline { PrettyFormat::Oneline } else { PrettyFormat::parse(&args.pretty)? };
+    let abbrev_commit = args.abbrev_commit || args.oneline;
+    let date_format = DateFormat::parse(&args.date)?;
+    let start = args.commit.as_deref().unwrap_or("HEAD");
+
+    let config = Config::load(&root, global_opts)?;
+    let use_mailmap = !args.no_use_mailmap && (args.use_mailmap || config.get_bool("log.mailmap")?.unwrap_or(true));
+    let mailmap = Mailmap::load(&root, &config)?;
+
+    let mut current_hash = Some(resolve_revision(&root, start, global_opts)?);
+    let mut first = true;
+    // Whether the last commit's changes were shown, which end the line a template leaves unfinished
+    let mut changes_shown = false;
     while let Some(hash) = current_hash {
-        match read_object_raw(&root, &hash, global_opts.git_mode) {
-            Ok(Some(bytes)) => {
-                let commit_text = String::from_utf8_lossy(&bytes).to_string();
-                let commit = parse_commit(&commit_text)?;
-                print_commit(&commit, &args.commit_hash);
-
-                // TODO: Handle multiple parents due to merges
-                current_hash = commit.parent;
-            },
-            Ok(None) => { return Err(anyhow!("object {} not found in store", args.commit_hash)); },
-            Err(e) => { return Err(e) }
+        let commit = match get_object(&root, &hash, global_opts.git_mode)? {
+            Object::Commit(c) => c,
+            _ => bail!("fatal: object {} is not a commit", hex::encode(hash))
+        };
+
+        // Formats other than these are separated by a new line between commits
+        let terminated = matches!(format, PrettyFormat::Oneline | PrettyFormat::Template { terminate: true, .. })
+            || (changes_shown && matches!(format, PrettyFormat::Template { .. }));
+        if !terminated && !first {
+            println!();
+        }
+        let short = short_hash(&root, &hash, global_opts)?;
+        print_commit(&commit, &hash, &short, abbrev_commit, &format, date_format, &mailmap, use_mailmap)?;
+        first = false;
+
+        // Like Git, merges aren't compared with their parents, and the changes are set apart from the message by a
+        // blank line in every format but oneline
+        changes_shown = false;
+        if args.raw && commit.parents.len() < 2 {
+            let lines = raw_diff(&root, commit.parents.first(), &hash, global_opts.git_mode)?;
+            changes_shown = !lines.is_empty();
+            if changes_shown {
+                match format {
+                    PrettyFormat::Oneline => (),
+                    PrettyFormat::Template { terminate: false, .. } => print!("\n\n"),
+                    _ => println!()
+                }
+                for line in lines {
+                    println!("{}", line);
+                }
+            }
         }
+
+        // TODO: Handle multiple parents due to merges
+        current_hash = commit.parents.first().copied();
     }
     Ok(())
 }
 
-fn print_commit(commit: &Commit, hash: &String) {
-    println!("commit {}", hash);
-    println!("Author: {}", commit.committer);
-    if let Some(date) = &commit.date {
-        println!("Date: {}", date);
+/// Prints a commit to standard output in the given format, as `log` does. Identities are shown as the mailmap says
+/// if `use_mailmap` is true, though templates can ask for either form whatever it is. `short` is the abbreviated
+/// hash, which templates can ask for and which is shown in place of the full hash if `abbrev_commit` is true.
+#[allow(clippy::too_many_arguments)]
+pub fn print_commit(
+    commit: &Commit,
+    hash: &[u8; 20],
+    short: &str,
+    abbrev_commit: bool,
+    format: &PrettyFormat,
+    date_format: DateFormat,
+    mailmap: &Mailmap,
+    use_mailmap: bool
+) -> Result<()> {
+    let hash_str = if abbrev_commit { short.to_string() } else { hex::encode(hash) };
+
+    match format {
+        PrettyFormat::Oneline => {
+            println!("{} {}", hash_str, commit.subject());
+        },
+        PrettyFormat::Template { template, terminate } => {
+            let expanded = expand_template(template, commit, hash, short, date_format, mailmap)?;
+            if *terminate {
+                println!("{}", expanded);
+            } else {
+                print!("{}", expanded);
+            }
+        },
+        PrettyFormat::Short | PrettyFormat::Medium | PrettyFormat::Full => {
+            let author = Signature::parse(&commit.author)?;
+            println!("commit {}", hash_str);
+            if commit.parents.len() > 1 {
+                let parents: Vec<String> = commit.parents.iter().map(|x| hex::encode(x)[..7].to_string()).collect();
+                println!("Merge: {}", parents.join(" "));
+            }
+            let (author_name, author_email) = show_identity(&author, mailmap, use_mailmap);
+            println!("Author: {} <{}>", author_name, author_email);
+            if let PrettyFormat::Medium = format {
+                println!("Date:   {}", format_date(author.timestamp, &author.timezone, date_format));
+            }
+            if let PrettyFormat::Full = format {
+                let committer = Signature::parse(&commit.committer)?;
+                let (committer_name, committer_email) = show_identity(&committer, mailmap, use_mailmap);
+                println!("Commit: {} <{}>", committer_name, committer_email);
+            }
+            println!();
+
+            // The short format only shows the subject paragraph of the message
+            let lines = commit.message.lines();
+            let lines: Vec<&str> = match format {
+                PrettyFormat::Short => lines.take_while(|x| !x.trim().is_empty()).collect(),
+                _ => lines.collect()
+            };
+            for line in lines {
+                println!("    {}", line);
+            }
+        }
     }
-    println!();
-    println!("\t{}", commit.message);
-    println!();
+
+    Ok(())
+}
+
+// Returns the name and email to show for a signature, which are as recorded unless the mailmap is used
+fn show_identity(signature: &Signature, mailmap: &Mailmap, use_mailmap: bool) -> (String, String) {
+    if use_mailmap {
+        mailmap.map(&signature.name, &signature.email)
+    } else {
+        (signature.name.clone(), signature.email.clone())
+    }
+}
+
+/// Replaces the placeholders in a `format:` template with details of the given commit
+fn expand_template(
+    template: &str,
+    commit: &Commit,
+    hash: &[u8; 20],
+    short: &str,
+    date_format: DateFormat,
+    mailmap: &Mailmap
+) -> Result<String> {
+    let author = Signature::parse(&commit.author)?;
+    // The upper-case placeholders respect the mailmap, even when it isn't used elsewhere
+    let (mapped_name, mapped_email) = mailmap.map(&author.name, &author.email);
+    let hash_str = hex::encode(hash);
+
+    let mut output = String::new();
+    let mut chars = template.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            output.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('H') => output.push_str(&hash_str),
+            Some('h') => output.push_str(short),
+            Some('s') => output.push_str(&commit.subject()),
+            Some('b') => output.push_str(&commit.body()),
+            Some('n') => output.push('\n'),
+            Some('%') => output.push('%'),
+            Some('P') => {
+                let parents: Vec<String> = commit.parents.iter().map(hex::encode).collect();
+                output.push_str(&parents.join(" "));
+            },
+            Some('a') => match chars.next() {
+                Some('n') => output.push_str(&author.name),
+                Some('e') => output.push_str(&author.email),
+                Some('N') => output.push_str(&mapped_name),
+                Some('E') => output.push_str(&mapped_email),
+                Some('d') => output.push_str(&format_date(author.timestamp, &author.timezone, date_format)),
+                Some(x) => { output.push_str("%a"); output.push(x); },
+                None => output.push_str("%a")
+            },
+            // Unknown placeholders are printed as they are
+            Some(x) => { output.push('%'); output.push(x); },
+            None => output.push('%')
+        }
+    }
+
+    Ok(output)
 }
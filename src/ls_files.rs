@@ -1,28 +1,168 @@
 // Show information about files in the index and the working tree
 
-use std::{env, fs};
-use anyhow::Result;
+use std::{env, fs, io::{self, Write}, path::Path};
+use anyhow::{bail, Result};
 use clap::Args;
 
-use crate::{GlobalOpts, repo_find, git_dir_name, index::Index};
+use crate::{GlobalOpts, repo_find, index::{read_index, IndexItem}};
+use crate::config::Config;
+use crate::filter::{line_endings, Filters};
+use crate::objects::get_blob;
+use crate::quote::PathQuoting;
 
 #[derive(Args)]
 pub struct LsFilesArgs {
+    /// Show the status of each file before its name: `S` for files marked skip-worktree and `H` for the others
+    #[arg(short)]
+    v: bool,
+    /// What to show for each file, in which `%(path)`, `%(objectname)`, `%(objectmode)`, `%(stage)`,
+    /// `%(eolinfo:index)`, `%(eolinfo:worktree)` and `%(eolattr)` are replaced, `%%` stands for `%` and `%xNN` for
+    /// the byte with the hexadecimal value NN
+    #[arg(long, conflicts_with = "v")]
+    format: Option<String>,
+    /// End each file's line with a NUL byte rather than a new line
+    #[arg(short)]
+    z: bool,
 }
 
-pub fn cmd_ls_files(_args: LsFilesArgs, global_opts: GlobalOpts) -> Result<()> {
+// A piece of a --format format
+enum FormatPart {
+    Literal(Vec<u8>),
+    Path,
+    ObjectName,
+    ObjectMode,
+    Stage,
+    /// The line endings of the file's blob in the index
+    IndexEol,
+    /// The line endings of the file in the working tree
+    WorktreeEol,
+    /// The line ending conversion the attributes ask for
+    EolAttributes,
+}
+
+pub fn cmd_ls_files(args: LsFilesArgs, global_opts: GlobalOpts) -> Result<()> {
     let path = env::current_dir().unwrap_or_else(|_| { panic!() });
     let root = repo_find(&path, global_opts).unwrap_or_else(|| {
         panic!("fatal: not a grit repository");
     });
 
-    let index_path = root.join(format!("{}/index", git_dir_name(global_opts)));
-    let index_bytes = fs::read(index_path)?;
-    let index = Index::deserialize(index_bytes)?;
+    let format = args.format.as_deref().map(parse_format).transpose()?;
+    let index = read_index(&root, global_opts)?;
+    let mut filters = Filters::new(&root, global_opts)?;
+    let terminator = if args.z { b'\0' } else { b'\n' };
+    // Paths are quoted as needed unless entries are ended with NUL, when they can be given exactly
+    let quoting = match args.z {
+        true => None,
+        false => Some(PathQuoting::new(&Config::load(&root, global_opts)?)?)
+    };
+    let mut stdout = io::stdout().lock();
 
-    for item in index.items {
-        println!("{}", item.path.to_string_lossy());
+    for item in &index.items {
+        if let Some(format) = &format {
+            write_formatted(&mut stdout, format, &root, item, quoting, &mut filters, global_opts)?;
+        } else {
+            if args.v {
+                write!(stdout, "{} ", if item.skip_worktree { "S" } else { "H" })?;
+            }
+            write_path(&mut stdout, &item.path, quoting)?;
+        }
+        stdout.write_all(&[terminator])?;
     }
 
     Ok(())
-}
\ No newline at end of file
+}
+
+// Writes what --format shows for a file. As in Git, line endings are only described for regular files, and for the
+// working tree only if the file is there.
+fn write_formatted(
+    out: &mut impl Write,
+    format: &[FormatPart],
+    root: &Path,
+    item: &IndexItem,
+    quoting: Option<PathQuoting>,
+    filters: &mut Filters,
+    global_opts: GlobalOpts
+) -> Result<()> {
+    let regular = item.mode & 0o170000 == 0o100000;
+    for part in format {
+        match part {
+            FormatPart::Literal(bytes) => out.write_all(bytes)?,
+            FormatPart::Path => write_path(out, &item.path, quoting)?,
+            FormatPart::ObjectName => write!(out, "{}", hex::encode(item.hash))?,
+            FormatPart::ObjectMode => write!(out, "{:06o}", item.mode)?,
+            FormatPart::Stage => write!(out, "{}", item.stage)?,
+            FormatPart::IndexEol => {
+                if regular {
+                    write!(out, "{}", line_endings(&get_blob(root, &item.hash, global_opts.git_mode)?))?;
+                }
+            },
+            FormatPart::WorktreeEol => {
+                let file = root.join(&item.path);
+                if regular && fs::symlink_metadata(&file).is_ok_and(|x| x.is_file()) {
+                    write!(out, "{}", line_endings(&fs::read(&file)?))?;
+                }
+            },
+            FormatPart::EolAttributes => write!(out, "{}", filters.eol_attributes(&item.path)?)?
+        }
+    }
+    Ok(())
+}
+
+// Writes a path quoted as needed, or exactly as it is without quoting
+fn write_path(out: &mut impl Write, path: &Path, quoting: Option<PathQuoting>) -> Result<()> {
+    match quoting {
+        Some(quoting) => out.write_all(quoting.quote(path).as_bytes())?,
+        None => out.write_all(path.as_os_str().as_encoded_bytes())?
+    }
+    Ok(())
+}
+
+// Splits a --format format into literal text and the fields to be replaced
+fn parse_format(format: &str) -> Result<Vec<FormatPart>> {
+    let mut parts = Vec::new();
+    let mut literal = Vec::new();
+    let mut rest = format;
+    while let Some(start) = rest.find('%') {
+        literal.extend(&rest.as_bytes()[..start]);
+        rest = &rest[start + 1..];
+        if let Some(after) = rest.strip_prefix('%') {
+            literal.push(b'%');
+            rest = after;
+            continue;
+        }
+        let hex_digits = rest.strip_prefix('x').and_then(|x| x.get(..2))
+            .filter(|x| x.bytes().all(|b| b.is_ascii_hexdigit()));
+        if let Some(digits) = hex_digits {
+            literal.push(u8::from_str_radix(digits, 16)?);
+            rest = &rest[3..];
+            continue;
+        }
+        if !rest.starts_with('(') {
+            bail!("fatal: bad ls-files format: element '{}' does not start with '('", rest);
+        }
+        let Some(end) = rest.find(')') else {
+            bail!("fatal: bad ls-files format: element '{}' does not end in ')'", rest);
+        };
+        let part = match &rest[1..end] {
+            "path" => FormatPart::Path,
+            "objectname" => FormatPart::ObjectName,
+            "objectmode" => FormatPart::ObjectMode,
+            "stage" => FormatPart::Stage,
+            "eolinfo:index" => FormatPart::IndexEol,
+            "eolinfo:worktree" => FormatPart::WorktreeEol,
+            "eolattr" => FormatPart::EolAttributes,
+            _ => bail!("fatal: bad ls-files format: %{}", &rest[..=end])
+        };
+        if !literal.is_empty() {
+            parts.push(FormatPart::Literal(literal.clone()));
+            literal.clear();
+        }
+        parts.push(part);
+        rest = &rest[end + 1..];
+    }
+    literal.extend(rest.as_bytes());
+    if !literal.is_empty() {
+        parts.push(FormatPart::Literal(literal));
+    }
+    Ok(parts)
+}
@@ -0,0 +1,80 @@
+// List the contents of a tree object
+
+use std::{env, path::Path};
+use anyhow::{anyhow, bail, Result};
+use clap::Args;
+
+use crate::{GlobalOpts, repo_find};
+use crate::config::Config;
+use crate::objects::{get_object, peel, Object, ObjectType, Tree};
+use crate::quote::PathQuoting;
+use crate::refs::resolve_revision;
+
+#[derive(Args)]
+pub struct LsTreeArgs {
+    /// The tree to list, or a commit or tag pointing at one
+    tree_ish: String,
+    /// Recurse into subtrees, listing the files within them by their full paths
+    #[arg(short)]
+    r: bool,
+    /// Only show subtrees
+    #[arg(short)]
+    d: bool,
+    /// Only show the name of each entry
+    #[arg(long)]
+    name_only: bool,
+}
+
+pub fn cmd_ls_tree(args: LsTreeArgs, global_opts: GlobalOpts) -> Result<()> {
+    let path = env::current_dir().unwrap_or_else(|_| { panic!() });
+    let root = repo_find(&path, global_opts).unwrap_or_else(|| {
+        panic!("fatal: not a grit repository");
+    });
+    let git_mode = global_opts.git_mode;
+
+    let hash = resolve_revision(&root, &args.tree_ish, global_opts)
+        .map_err(|_| anyhow!("fatal: Not a valid object name {}", args.tree_ish))?;
+    let tree = match get_object(&root, &peel(&root, &hash, git_mode)?, git_mode)? {
+        Object::Tree(tree) => tree,
+        Object::Commit(commit) => match get_object(&root, &commit.tree, git_mode)? {
+            Object::Tree(tree) => tree,
+            _ => bail!("fatal: not a tree object")
+        },
+        _ => bail!("fatal: not a tree object")
+    };
+    let quoting = PathQuoting::new(&Config::load(&root, global_opts)?)?;
+    list_tree(&root, &tree, "", &args, quoting, git_mode)
+}
+
+// Prints the entries of a tree, whose path within the top-level tree is `prefix`
+fn list_tree(
+    root: &Path,
+    tree: &Tree,
+    prefix: &str,
+    args: &LsTreeArgs,
+    quoting: PathQuoting,
+    git_mode: bool
+) -> Result<()> {
+    for entry in &tree.children {
+        let path = format!("{}{}", prefix, entry.name);
+        let is_tree = entry.object_type() == ObjectType::Tree;
+
+        // When recursing, subtrees are listed in place of the tree itself, unless only trees are wanted
+        let show = if args.d { is_tree } else { !(is_tree && args.r) };
+        let quoted = quoting.quote(Path::new(&path));
+        if show && args.name_only {
+            println!("{}", quoted);
+        } else if show {
+            println!("{:06o} {} {}\t{}", entry.mode, entry.object_type(), hex::encode(entry.hash), quoted);
+        }
+        if is_tree && args.r {
+            match get_object(root, &entry.hash, git_mode)? {
+                Object::Tree(subtree) => {
+                    list_tree(root, &subtree, &format!("{}/", path), args, quoting, git_mode)?
+                },
+                _ => bail!("fatal: object {} is not a tree", hex::encode(entry.hash))
+            }
+        }
+    }
+    Ok(())
+}
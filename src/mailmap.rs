@@ -0,0 +1,116 @@
+// Mapping the names and emails recorded in commits to canonical ones, as listed in .mailmap files
+
+use std::{collections::HashMap, env, fs, path::Path};
+use anyhow::Result;
+
+use crate::config::Config;
+
+/// The identities a project has asked to be shown in place of those in its commits
+#[derive(Default)]
+pub struct Mailmap {
+    /// Keyed by the email found in commits, in lower case as emails are matched regardless of case
+    entries: HashMap<String, MailmapEntry>,
+}
+
+// The replacements for an email found in commits
+#[derive(Default)]
+struct MailmapEntry {
+    /// The replacement for any identity with the email that has no more specific one
+    default: Replacement,
+    /// Replacements that only apply to identities with a particular name, keyed by that name in lower case
+    by_name: HashMap<String, Replacement>,
+}
+
+// The canonical name and email to show, either of which may be left as it was
+#[derive(Clone, Default)]
+struct Replacement {
+    name: Option<String>,
+    email: Option<String>,
+}
+
+impl Mailmap {
+    /// Reads the `.mailmap` file at the root of the working tree, followed by the file named by `mailmap.file`. Later
+    /// lines take precedence over earlier ones for the same identity.
+    pub fn load(root: &Path, config: &Config) -> Result<Mailmap> {
+        let mut mailmap = Mailmap::default();
+        if let Ok(text) = fs::read_to_string(root.join(".mailmap")) {
+            mailmap.add_lines(&text);
+        }
+        if let Some(file) = config.get("mailmap.file") {
+            let path = match file.strip_prefix("~/") {
+                Some(rest) => env::var_os("HOME").map(|x| Path::new(&x).join(rest)).unwrap_or(file.clone().into()),
+                None => file.clone().into()
+            };
+            if let Ok(text) = fs::read_to_string(path) {
+                mailmap.add_lines(&text);
+            }
+        }
+        Ok(mailmap)
+    }
+
+    /// Adds the mappings in the text of a mailmap file. Each line has one of four forms:
+    ///
+    /// - `Proper Name <commit@email>` replaces the name of identities with the email
+    /// - `<proper@email> <commit@email>` replaces the email
+    /// - `Proper Name <proper@email> <commit@email>` replaces both
+    /// - `Proper Name <proper@email> Commit Name <commit@email>` replaces both, only for the identity with that
+    ///   name and email
+    ///
+    /// Lines that start with `#` are comments, and lines that don't match any form are ignored.
+    pub fn add_lines(&mut self, text: &str) {
+        for line in text.lines() {
+            if line.starts_with('#') {
+                continue;
+            }
+            let Some((name1, email1, rest)) = parse_name_and_email(line) else {
+                continue;
+            };
+            // The first email must be given, but the second may be empty
+            if email1.is_empty() {
+                continue;
+            }
+            match parse_name_and_email(rest) {
+                Some((name2, email2, _)) => self.add(name1, Some(email1), name2, email2),
+                None => self.add(name1, None, None, email1)
+            }
+        }
+    }
+
+    // Adds the mapping of the identity with `old_email`, and `old_name` if given, to a new name and email
+    fn add(&mut self, new_name: Option<&str>, new_email: Option<&str>, old_name: Option<&str>, old_email: &str) {
+        let entry = self.entries.entry(old_email.to_lowercase()).or_default();
+        let replacement = match old_name {
+            Some(name) => entry.by_name.entry(name.to_lowercase()).or_default(),
+            None => &mut entry.default
+        };
+        // A later line for the same identity only replaces what it gives
+        if let Some(name) = new_name {
+            replacement.name = Some(name.to_string());
+        }
+        if let Some(email) = new_email {
+            replacement.email = Some(email.to_string());
+        }
+    }
+
+    /// Returns the canonical name and email for an identity found in a commit
+    pub fn map(&self, name: &str, email: &str) -> (String, String) {
+        let Some(entry) = self.entries.get(&email.to_lowercase()) else {
+            return (name.to_string(), email.to_string());
+        };
+        let replacement = entry.by_name.get(&name.to_lowercase()).unwrap_or(&entry.default);
+        (
+            replacement.name.clone().unwrap_or(name.to_string()),
+            replacement.email.clone().unwrap_or(email.to_string())
+        )
+    }
+}
+
+// Splits a name followed by an email in angle brackets off the start of the text, returning the name if there is one,
+// the email and the rest of the text
+fn parse_name_and_email(text: &str) -> Option<(Option<&str>, &str, &str)> {
+    let left = text.find('<')?;
+    let right = left + 1 + text[left + 1..].find('>')?;
+    let name = text[..left].trim();
+    let name = if name.is_empty() { None } else { Some(name) };
+    Some((name, &text[left + 1..right], &text[right + 1..]))
+}
@@ -1,16 +1,53 @@
 use clap::Parser;
+use std::process;
 
 use grit::{Cli,
     Command,
     cmd_add,
+    cmd_am,
+    cmd_bisect,
     cmd_init,
     cmd_hash_object,
     cmd_cat_file,
+    cmd_check_attr,
+    cmd_check_ignore,
+    cmd_check_ref_format,
     cmd_checkout,
     cmd_commit,
+    cmd_commit_graph,
+    cmd_config,
+    cmd_count_objects,
+    cmd_describe,
+    cmd_diff,
+    cmd_fetch,
+    cmd_for_each_ref,
+    cmd_format_patch,
+    cmd_grep,
     cmd_log,
     cmd_ls_files,
+    cmd_ls_tree,
+    cmd_merge,
+    cmd_merge_base,
+    cmd_mktag,
+    cmd_mktree,
+    cmd_name_rev,
+    cmd_prune,
+    cmd_pull,
+    cmd_push,
+    cmd_rebase,
+    cmd_reflog,
+    cmd_repack,
+    cmd_rev_parse,
+    cmd_show_branch,
+    cmd_show_ref,
+    cmd_stash,
     cmd_status,
+    cmd_stripspace,
+    cmd_switch,
+    cmd_tag,
+    cmd_update_index,
+    cmd_verify_commit,
+    cmd_verify_tag,
     cmd_write_tree
 };
 
@@ -20,18 +57,57 @@ fn main() {
 
     let result = match args.command {
         Command::Add(args) => cmd_add(args, global_opts),
-        Command::Init { path } => cmd_init(path, global_opts),
+        Command::Am(args) => cmd_am(args, global_opts),
+        Command::Bisect(args) => cmd_bisect(args, global_opts),
+        Command::Init(args) => cmd_init(args, global_opts),
         Command::HashObject(args) => cmd_hash_object(args, global_opts),
         Command::CatFile(args) => cmd_cat_file(args, global_opts),
+        Command::CheckAttr(args) => cmd_check_attr(args, global_opts),
+        Command::CheckIgnore(args) => cmd_check_ignore(args, global_opts),
+        Command::CheckRefFormat(args) => cmd_check_ref_format(args, global_opts),
         Command::Checkout(args) => cmd_checkout(args, global_opts),
         Command::Commit(args) => cmd_commit(args, global_opts),
+        Command::CommitGraph(args) => cmd_commit_graph(args, global_opts),
+        Command::Config(args) => cmd_config(args, global_opts),
+        Command::CountObjects(args) => cmd_count_objects(args, global_opts),
+        Command::Describe(args) => cmd_describe(args, global_opts),
+        Command::Diff(args) => cmd_diff(args, global_opts),
+        Command::Fetch(args) => cmd_fetch(args, global_opts),
+        Command::ForEachRef(args) => cmd_for_each_ref(args, global_opts),
+        Command::FormatPatch(args) => cmd_format_patch(args, global_opts),
+        Command::Grep(args) => cmd_grep(args, global_opts),
         Command::Log(args) => cmd_log(args, global_opts),
         Command::LsFiles(args) => cmd_ls_files(args, global_opts),
+        Command::LsTree(args) => cmd_ls_tree(args, global_opts),
+        Command::Merge(args) => cmd_merge(args, global_opts),
+        Command::MergeBase(args) => cmd_merge_base(args, global_opts),
+        Command::Mktag(args) => cmd_mktag(args, global_opts),
+        Command::Mktree(args) => cmd_mktree(args, global_opts),
+        Command::NameRev(args) => cmd_name_rev(args, global_opts),
+        Command::Prune(args) => cmd_prune(args, global_opts),
+        Command::Pull(args) => cmd_pull(args, global_opts),
+        Command::Push(args) => cmd_push(args, global_opts),
+        Command::Rebase(args) => cmd_rebase(args, global_opts),
+        Command::Reflog(args) => cmd_reflog(args, global_opts),
+        Command::Repack(args) => cmd_repack(args, global_opts),
+        Command::RevParse(args) => cmd_rev_parse(args, global_opts),
+        Command::ShowBranch(args) => cmd_show_branch(args, global_opts),
+        Command::ShowRef(args) => cmd_show_ref(args, global_opts),
+        Command::Stash(args) => cmd_stash(args, global_opts),
         Command::Status(args) => cmd_status(args, global_opts),
-        Command::WriteTree => cmd_write_tree(global_opts)
+        Command::Stripspace(args) => cmd_stripspace(args, global_opts),
+        Command::Switch(args) => cmd_switch(args, global_opts),
+        Command::Tag(args) => cmd_tag(args, global_opts),
+        Command::UpdateIndex(args) => cmd_update_index(args, global_opts),
+        Command::VerifyCommit(args) => cmd_verify_commit(args, global_opts),
+        Command::VerifyTag(args) => cmd_verify_tag(args, global_opts),
+        Command::WriteTree(args) => cmd_write_tree(args, global_opts),
     };
 
     if let Some(err) = result.err() {
         eprintln!("{}", err);
+        // Like Git, exit with 128 after a fatal error and 1 after any other
+        let fatal = err.to_string().lines().any(|x| x.starts_with("fatal: "));
+        process::exit(if fatal { 128 } else { 1 });
     }
 }
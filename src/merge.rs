@@ -0,0 +1,601 @@
+// Join another commit's history into the current branch, and the three-way merges of files and trees this relies on,
+// combining the changes two sides made to a common ancestor
+
+use std::{collections::BTreeMap, env, fs, io::ErrorKind, path::{Path, PathBuf}, process};
+use anyhow::{bail, Result};
+use clap::Args;
+
+use crate::{GlobalOpts, repo_find, git_dir_name};
+use crate::checkout::{checkout_merge, commit_files, reset_merge, switch_commit, switch_files};
+use crate::commit::signature;
+use crate::config::Config;
+use crate::dag::{is_ancestor, merge_bases, CommitDag};
+use crate::diff::is_binary;
+use crate::diffstat::diff_stat;
+use crate::index::read_index;
+use crate::line_diff::{hunks, myers_diff, split_lines};
+use crate::objects::{get_blob, get_object, parse_hash, peel, Blob, Commit, GitObject, Object, TreeEntry};
+use crate::refs::{append_reflog, full_ref_name, head_commit, read_head, resolve_revision, write_head, write_ref, Head};
+use crate::write_tree::files_tree;
+
+// The length of the markers around a conflict, e.g. `<<<<<<<`
+const MARKER_SIZE: usize = 7;
+// Conflicts with no more than this many unchanged lines between them are shown as one
+const CONFLICT_GAP: usize = 3;
+// The width the summary of the changes merged in is fitted to, as Git uses when not writing to a terminal
+const STAT_WIDTH: usize = 80;
+// The files in the git directory that record a merge stopped by conflicts
+const MERGE_STATE_FILES: [&str; 4] = ["MERGE_HEAD", "MERGE_MSG", "MERGE_MODE", "AUTO_MERGE"];
+
+#[derive(Args)]
+pub struct MergeArgs {
+    /// The commit to merge into the current branch
+    #[arg(required_unless_present = "abort")]
+    commit: Option<String>,
+    /// Abandon a merge that stopped with conflicts, putting back the index and the files it changed as they were
+    #[arg(long, conflicts_with = "commit")]
+    abort: bool,
+}
+
+pub fn cmd_merge(args: MergeArgs, global_opts: GlobalOpts) -> Result<()> {
+    let path = env::current_dir().unwrap_or_else(|_| { panic!() });
+    let root = repo_find(&path, global_opts).unwrap_or_else(|| {
+        panic!("fatal: not a grit repository");
+    });
+    let git_dir = root.join(git_dir_name(global_opts));
+
+    if args.abort {
+        let head = head_commit(&root, global_opts)?;
+        let Some(head) = head.filter(|_| git_dir.join("MERGE_HEAD").exists()) else {
+            bail!("fatal: There is no merge to abort (MERGE_HEAD missing).");
+        };
+        reset_merge(&root, &head, global_opts)?;
+        return remove_merge_state(&root, global_opts);
+    }
+    if read_index(&root, global_opts)?.has_conflicts() {
+        let program = if global_opts.git_mode { "git" } else { "grit" };
+        bail!(
+            "error: Merging is not possible because you have unmerged files.\n\
+            hint: Fix them up in the work tree, and then use '{} add/rm <file>'\n\
+            hint: as appropriate to mark resolution and make a commit.\n\
+            fatal: Exiting because of an unresolved conflict.", program
+        );
+    }
+    if git_dir.join("MERGE_HEAD").exists() {
+        bail!("fatal: You have not concluded your merge (MERGE_HEAD exists).\n\
+            Please, commit your changes before you merge.");
+    }
+
+    let name = args.commit.unwrap_or_default();
+    let Ok(tagged) = resolve_revision(&root, &name, global_opts) else {
+        eprintln!("merge: {} - not something we can merge", name);
+        process::exit(1);
+    };
+    let target = peel(&root, &tagged, global_opts.git_mode)?;
+    if !matches!(get_object(&root, &target, global_opts.git_mode)?, Object::Commit(_)) {
+        eprintln!("merge: {} - not something we can merge", name);
+        process::exit(1);
+    }
+    let reflog_action = format!("merge {}", name);
+
+    let config = Config::load(&root, global_opts)?;
+    let head = read_head(&root, global_opts)?;
+    let branch = match &head {
+        Head::Branch(name) => Some(name.strip_prefix("refs/heads/").unwrap_or(name).to_string()),
+        Head::Detached(_) => None
+    };
+    let Some(current) = head_commit(&root, global_opts)? else {
+        // A branch with no commits yet simply starts from the merged commit, which Git records as a pull
+        switch_commit(&root, None, &target, global_opts)?;
+        return update_head(&root, &head, None, &target, &config, "initial pull", global_opts);
+    };
+    let dag = CommitDag::open(&root, global_opts.git_mode)?;
+    if is_ancestor(&dag, &target, &current)? {
+        println!("Already up to date.");
+        return Ok(());
+    }
+    if is_ancestor(&dag, &current, &target)? {
+        return fast_forward(&root, &head, &current, &target, &config, &reflog_action, global_opts);
+    }
+
+    // As `git fmt-merge-msg` does, the message says what kind of reference was merged, and includes the message of
+    // an annotated tag
+    let description = match full_ref_name(&root, &name, global_opts)? {
+        Some(full_name) if full_name.starts_with("refs/heads/") => format!("branch '{}'", name),
+        Some(full_name) if full_name.starts_with("refs/remotes/") => format!("remote-tracking branch '{}'", name),
+        Some(full_name) if full_name.starts_with("refs/tags/") => format!("tag '{}'", name),
+        _ => format!("commit '{}'", name)
+    };
+    let mut message = merge_message(&description, branch.as_deref());
+    if let Object::Tag(tag) = get_object(&root, &tagged, global_opts.git_mode)? {
+        message += &format!("\n\n{}", tag.message.trim_end());
+    }
+    merge(&root, &head, &current, &target, &name, &message, &config, &reflog_action, global_opts)
+}
+
+/// The commit being merged by a merge that stopped with conflicts, if there is one
+pub fn read_merge_head(root: &Path, global_opts: GlobalOpts) -> Result<Option<[u8; 20]>> {
+    match fs::read_to_string(root.join(git_dir_name(global_opts)).join("MERGE_HEAD")) {
+        Ok(contents) => Ok(Some(parse_hash(contents.trim_end())?)),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into())
+    }
+}
+
+/// Removes the files that record a merge stopped by conflicts, once it has been committed or abandoned
+pub fn remove_merge_state(root: &Path, global_opts: GlobalOpts) -> Result<()> {
+    let git_dir = root.join(git_dir_name(global_opts));
+    for name in MERGE_STATE_FILES {
+        if git_dir.join(name).exists() {
+            fs::remove_file(git_dir.join(name))?;
+        }
+    }
+    Ok(())
+}
+
+/// The message of a merge commit, made from the description of where the merged commit came from as
+/// `git fmt-merge-msg` does, e.g. `Merge branch 'topic' of ../upstream into feature`
+pub fn merge_message(description: &str, branch: Option<&str>) -> String {
+    // Commits from the repository itself don't say where they came from
+    let mut message = format!("Merge {}", description.strip_suffix(" of .").unwrap_or(description));
+    if let Some(branch) = branch.filter(|x| *x != "master" && *x != "main") {
+        message += &format!(" into {}", branch);
+    }
+    message
+}
+
+/// Moves HEAD forward to a commit it's an ancestor of, updating the index and working tree and printing the changes
+pub fn fast_forward(
+    root: &Path,
+    head: &Head,
+    current: &[u8; 20],
+    target: &[u8; 20],
+    config: &Config,
+    reflog_action: &str,
+    global_opts: GlobalOpts
+) -> Result<()> {
+    println!("Updating {}..{}", &hex::encode(current)[..7], &hex::encode(target)[..7]);
+    switch_commit(root, Some(*current), target, global_opts)?;
+    fs::write(root.join(git_dir_name(global_opts)).join("ORIG_HEAD"), format!("{}\n", hex::encode(current)))?;
+    update_head(root, head, Some(*current), target, config, &format!("{}: Fast-forward", reflog_action), global_opts)?;
+    println!("Fast-forward");
+    print_stat(root, current, target, global_opts)
+}
+
+/// Merges a commit into HEAD with a merge commit, labelling its side of any conflict with `their_label`. On a
+/// conflict the merged files are left in the working tree, the state of the merge is recorded for it to be committed
+/// or abandoned, and the process exits.
+#[allow(clippy::too_many_arguments)]
+pub fn merge(
+    root: &Path,
+    head: &Head,
+    current: &[u8; 20],
+    target: &[u8; 20],
+    their_label: &str,
+    message: &str,
+    config: &Config,
+    reflog_action: &str,
+    global_opts: GlobalOpts
+) -> Result<()> {
+    let git_mode = global_opts.git_mode;
+    let git_dir = root.join(git_dir_name(global_opts));
+    let dag = CommitDag::open(root, git_mode)?;
+    let base_files = match merge_bases(&dag, current, target)?.first() {
+        Some(base) => commit_files(root, base, git_mode)?,
+        None => Default::default()
+    };
+    let our_files = commit_files(root, current, git_mode)?;
+    let their_files = commit_files(root, target, git_mode)?;
+    let merge = merge_trees(root, &base_files, &our_files, &their_files, "HEAD", their_label, global_opts)?;
+    fs::write(git_dir.join("ORIG_HEAD"), format!("{}\n", hex::encode(current)))?;
+
+    if !merge.conflicts.is_empty() {
+        let worktree_files = checkout_merge(root, &our_files, &merge, global_opts)?;
+        let tree = files_tree(root, &worktree_files, global_opts)?;
+        fs::write(git_dir.join("AUTO_MERGE"), format!("{}\n", hex::encode(tree)))?;
+        fs::write(git_dir.join("MERGE_HEAD"), format!("{}\n", hex::encode(target)))?;
+        fs::write(git_dir.join("MERGE_MODE"), "")?;
+        let mut merge_msg = format!("{}\n\n# Conflicts:\n", message);
+        for conflict in &merge.conflicts {
+            merge_msg += &format!("#\t{}\n", conflict.path.to_string_lossy());
+        }
+        fs::write(git_dir.join("MERGE_MSG"), merge_msg)?;
+
+        for message in &merge.messages {
+            println!("{}", message);
+        }
+        println!("Automatic merge failed; fix conflicts and then commit the result.");
+        process::exit(1);
+    }
+
+    switch_files(root, &our_files, &merge.files, global_opts)?;
+    let commit = Commit {
+        tree: files_tree(root, &merge.files, global_opts)?,
+        author: signature(config, "AUTHOR", global_opts)?.to_string(),
+        committer: signature(config, "COMMITTER", global_opts)?.to_string(),
+        parents: vec![*current, *target],
+        message: format!("{}\n", message),
+        gpgsig: None,
+    };
+    commit.write(root, global_opts)?;
+    let strategy = "Merge made by the 'ort' strategy.";
+    update_head(root, head, Some(*current), &commit.hash(), config, &format!("{}: {}", reflog_action, strategy), global_opts)?;
+
+    for message in &merge.messages {
+        println!("{}", message);
+    }
+    println!("{}", strategy);
+    print_stat(root, current, &commit.hash(), global_opts)
+}
+
+/// Moves the current branch, or HEAD itself if it's detached, to a new commit, recording the move in the reflogs
+pub fn update_head(
+    root: &Path,
+    head: &Head,
+    old: Option<[u8; 20]>,
+    new: &[u8; 20],
+    config: &Config,
+    message: &str,
+    global_opts: GlobalOpts
+) -> Result<()> {
+    match head {
+        Head::Branch(name) => write_ref(root, name, new, global_opts)?,
+        Head::Detached(_) => write_head(root, &Head::Detached(*new), global_opts)?
+    }
+    // Merging is still possible without an identity, but isn't recorded in the reflog
+    if let Ok(identity) = signature(config, "COMMITTER", global_opts) {
+        append_reflog(root, "HEAD", old, new, &identity, message, global_opts)?;
+        if let Head::Branch(name) = head {
+            append_reflog(root, name, old, new, &identity, message, global_opts)?;
+        }
+    }
+    Ok(())
+}
+
+// Prints the changes between two commits, as Git does after a merge
+fn print_stat(root: &Path, old: &[u8; 20], new: &[u8; 20], global_opts: GlobalOpts) -> Result<()> {
+    let stat = diff_stat(root, Some(old), new, global_opts.git_mode)?;
+    for line in stat.graph(STAT_WIDTH) {
+        println!("{}", line);
+    }
+    println!("{}", stat.summary());
+    for line in stat.mode_changes {
+        println!("{}", line);
+    }
+    Ok(())
+}
+
+/// A path whose changes couldn't be merged
+pub struct Conflict {
+    pub path: PathBuf,
+    /// The versions in the common ancestor, our side and their side, where the path exists in them
+    pub stages: [Option<TreeEntry>; 3],
+    /// The file to leave in the working tree, with conflict markers, if the contents were merged
+    pub contents: Option<Vec<u8>>,
+}
+
+/// The result of merging two trees
+pub struct TreeMerge {
+    /// The merged files, keyed by path. A conflicted path holds the version left in the working tree.
+    pub files: BTreeMap<PathBuf, TreeEntry>,
+    pub conflicts: Vec<Conflict>,
+    /// Messages describing the merge, e.g. `Auto-merging file.txt`
+    pub messages: Vec<String>,
+}
+
+// A region of the merged file taken from one side, or in conflict. Positions are line numbers in the ancestor (0),
+// our side (1) and their side (2).
+#[derive(Clone, Copy)]
+struct Region {
+    kind: RegionKind,
+    i0: isize,
+    chg0: isize,
+    i1: isize,
+    chg1: isize,
+    i2: isize,
+    chg2: isize,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum RegionKind {
+    Conflict,
+    Ours,
+    Theirs,
+    /// A conflict found to have the same changes on both sides
+    Same,
+}
+
+/// Merges the lines of two files changed from a common ancestor, as Git does by default. Where the sides changed
+/// the same lines differently, both versions are kept between conflict markers labelled with the given names.
+/// Returns the merged file and whether there were conflicts.
+pub fn merge_file(base: &[u8], ours: &[u8], theirs: &[u8], our_label: &str, their_label: &str) -> (Vec<u8>, bool) {
+    let base_lines = split_lines(base);
+    let our_lines = split_lines(ours);
+    let their_lines = split_lines(theirs);
+    let our_changes = hunks(&myers_diff(&base_lines, &our_lines), 0);
+    let their_changes = hunks(&myers_diff(&base_lines, &their_lines), 0);
+
+    // Walk the changes of both sides in order of their position in the ancestor. Changes that touch or
+    // overlap conflict, unless both sides made the same change.
+    let mut regions: Vec<Region> = Vec::new();
+    let (mut x, mut y) = (0, 0);
+    while x < our_changes.len() && y < their_changes.len() {
+        let ours = &our_changes[x];
+        let theirs = &their_changes[y];
+        let (o_start, o_count, o_new, o_new_count) =
+            (ours.old_start as isize, ours.old_count as isize, ours.new_start as isize, ours.new_count as isize);
+        let (t_start, t_count, t_new, t_new_count) =
+            (theirs.old_start as isize, theirs.old_count as isize, theirs.new_start as isize, theirs.new_count as isize);
+
+        if o_start + o_count < t_start {
+            append_region(&mut regions, Region {
+                kind: RegionKind::Ours,
+                i0: o_start, chg0: o_count,
+                i1: o_new, chg1: o_new_count,
+                i2: t_new - t_start + o_start, chg2: o_count,
+            });
+            x += 1;
+            continue;
+        }
+        if t_start + t_count < o_start {
+            append_region(&mut regions, Region {
+                kind: RegionKind::Theirs,
+                i0: t_start, chg0: t_count,
+                i1: o_new - o_start + t_start, chg1: t_count,
+                i2: t_new, chg2: t_new_count,
+            });
+            y += 1;
+            continue;
+        }
+
+        let same = o_start == t_start && o_count == t_count
+            && our_lines[ours.new_start..ours.new_start + ours.new_count] == their_lines[theirs.new_start..theirs.new_start + theirs.new_count];
+        if !same {
+            // The conflict covers both changes, extending each side's range to match the other's in the ancestor
+            let off = o_start - t_start;
+            let ffo = off + o_count - t_count;
+            let (mut i0, mut i1, mut i2) = (o_start, o_new, t_new);
+            if off > 0 {
+                i0 -= off;
+                i1 -= off;
+            } else {
+                i2 += off;
+            }
+            let mut chg0 = o_start + o_count - i0;
+            let mut chg1 = o_new + o_new_count - i1;
+            let mut chg2 = t_new + t_new_count - i2;
+            if ffo < 0 {
+                chg0 -= ffo;
+                chg1 -= ffo;
+            } else {
+                chg2 += ffo;
+            }
+            append_region(&mut regions, Region { kind: RegionKind::Conflict, i0, chg0, i1, chg1, i2, chg2 });
+        }
+
+        let our_end = o_start + o_count;
+        let their_end = t_start + t_count;
+        if our_end >= their_end {
+            y += 1;
+        }
+        if their_end >= our_end {
+            x += 1;
+        }
+    }
+    let our_offset = our_lines.len() as isize - base_lines.len() as isize;
+    let their_offset = their_lines.len() as isize - base_lines.len() as isize;
+    for ours in &our_changes[x..] {
+        append_region(&mut regions, Region {
+            kind: RegionKind::Ours,
+            i0: ours.old_start as isize, chg0: ours.old_count as isize,
+            i1: ours.new_start as isize, chg1: ours.new_count as isize,
+            i2: ours.old_start as isize + their_offset, chg2: ours.old_count as isize,
+        });
+    }
+    for theirs in &their_changes[y..] {
+        append_region(&mut regions, Region {
+            kind: RegionKind::Theirs,
+            i0: theirs.old_start as isize, chg0: theirs.old_count as isize,
+            i1: theirs.old_start as isize + our_offset, chg1: theirs.old_count as isize,
+            i2: theirs.new_start as isize, chg2: theirs.new_count as isize,
+        });
+    }
+
+    let regions = join_close_conflicts(refine_conflicts(regions, &our_lines, &their_lines));
+
+    let mut output = Vec::new();
+    let mut conflicted = false;
+    let mut pos = 0;
+    let copy = |output: &mut Vec<u8>, lines: &[&[u8]], start: isize, count: isize, add_newline: bool| {
+        let chunk = &lines[start as usize..(start + count) as usize];
+        for line in chunk {
+            output.extend(*line);
+        }
+        if add_newline && chunk.last().is_some_and(|x| !x.ends_with(b"\n")) {
+            output.push(b'\n');
+        }
+    };
+    for region in &regions {
+        match region.kind {
+            RegionKind::Conflict => {
+                conflicted = true;
+                copy(&mut output, &our_lines, pos, region.i1 - pos, false);
+                output.extend(format!("{} {}\n", "<".repeat(MARKER_SIZE), our_label).into_bytes());
+                copy(&mut output, &our_lines, region.i1, region.chg1, true);
+                output.extend(format!("{}\n", "=".repeat(MARKER_SIZE)).into_bytes());
+                copy(&mut output, &their_lines, region.i2, region.chg2, true);
+                output.extend(format!("{} {}\n", ">".repeat(MARKER_SIZE), their_label).into_bytes());
+            },
+            RegionKind::Ours => {
+                copy(&mut output, &our_lines, pos, region.i1 - pos, false);
+                copy(&mut output, &our_lines, region.i1, region.chg1, false);
+            },
+            RegionKind::Theirs => {
+                copy(&mut output, &our_lines, pos, region.i1 - pos, false);
+                copy(&mut output, &their_lines, region.i2, region.chg2, false);
+            },
+            // Our side is copied along with the unchanged lines after it
+            RegionKind::Same => continue
+        }
+        pos = region.i1 + region.chg1;
+    }
+    copy(&mut output, &our_lines, pos, our_lines.len() as isize - pos, false);
+    (output, conflicted)
+}
+
+// Adds a region to the merge, combining it with the previous region if they overlap.
+// Regions from different sides that overlap are in conflict.
+fn append_region(regions: &mut Vec<Region>, region: Region) {
+    if let Some(last) = regions.last_mut() {
+        if region.i1 <= last.i1 + last.chg1 || region.i2 <= last.i2 + last.chg2 {
+            if region.kind != last.kind {
+                last.kind = RegionKind::Conflict;
+            }
+            last.chg0 = region.i0 + region.chg0 - last.i0;
+            last.chg1 = region.i1 + region.chg1 - last.i1;
+            last.chg2 = region.i2 + region.chg2 - last.i2;
+            return;
+        }
+    }
+    regions.push(region);
+}
+
+// Narrows each conflict down to the lines that differ between the two sides, which may split it into several
+fn refine_conflicts(regions: Vec<Region>, our_lines: &[&[u8]], their_lines: &[&[u8]]) -> Vec<Region> {
+    let mut refined = Vec::new();
+    for region in regions {
+        if region.kind != RegionKind::Conflict || region.chg1 == 0 || region.chg2 == 0 {
+            refined.push(region);
+            continue;
+        }
+
+        let ours = &our_lines[region.i1 as usize..(region.i1 + region.chg1) as usize];
+        let theirs = &their_lines[region.i2 as usize..(region.i2 + region.chg2) as usize];
+        let changes = hunks(&myers_diff(ours, theirs), 0);
+        if changes.is_empty() {
+            refined.push(Region { kind: RegionKind::Same, ..region });
+            continue;
+        }
+        for change in changes {
+            refined.push(Region {
+                i1: region.i1 + change.old_start as isize,
+                chg1: change.old_count as isize,
+                i2: region.i2 + change.new_start as isize,
+                chg2: change.new_count as isize,
+                ..region
+            });
+        }
+    }
+    refined
+}
+
+// Joins conflicts separated by only a few unchanged lines, which are clearer shown together
+fn join_close_conflicts(regions: Vec<Region>) -> Vec<Region> {
+    let mut joined: Vec<Region> = Vec::new();
+    for region in regions {
+        if let Some(last) = joined.last_mut() {
+            let gap = region.i1 - (last.i1 + last.chg1);
+            if last.kind == RegionKind::Conflict && region.kind == RegionKind::Conflict && gap <= CONFLICT_GAP as isize {
+                last.chg0 = region.i0 + region.chg0 - last.i0;
+                last.chg1 = region.i1 + region.chg1 - last.i1;
+                last.chg2 = region.i2 + region.chg2 - last.i2;
+                continue;
+            }
+        }
+        joined.push(region);
+    }
+    joined
+}
+
+/// Merges the changes made by two sides to the files of a common ancestor. Files both sides changed are merged
+/// line by line, with the merged contents written to the object store. The labels name each side in messages
+/// and conflict markers, e.g. `HEAD` and `abc1234 (Commit subject)`.
+pub fn merge_trees(
+    root: &Path,
+    base: &BTreeMap<PathBuf, TreeEntry>,
+    ours: &BTreeMap<PathBuf, TreeEntry>,
+    theirs: &BTreeMap<PathBuf, TreeEntry>,
+    our_label: &str,
+    their_label: &str,
+    global_opts: GlobalOpts
+) -> Result<TreeMerge> {
+    let git_mode = global_opts.git_mode;
+    let mut paths: Vec<&PathBuf> = base.keys().chain(ours.keys()).chain(theirs.keys()).collect();
+    paths.sort_by(|x, y| x.as_os_str().as_encoded_bytes().cmp(y.as_os_str().as_encoded_bytes()));
+    paths.dedup();
+
+    let mut result = TreeMerge { files: BTreeMap::new(), conflicts: Vec::new(), messages: Vec::new() };
+    let same = |x: Option<&TreeEntry>, y: Option<&TreeEntry>| x.map(|e| (e.hash, e.mode)) == y.map(|e| (e.hash, e.mode));
+    for path in paths {
+        let (b, o, t) = (base.get(path), ours.get(path), theirs.get(path));
+        let name = path.to_string_lossy();
+
+        // Where only one side changed the file, that side's version is taken
+        let taken = if same(o, t) || same(b, t) {
+            Some(o)
+        } else if same(b, o) {
+            Some(t)
+        } else {
+            None
+        };
+        if let Some(entry) = taken {
+            if let Some(entry) = entry {
+                result.files.insert(path.clone(), entry.clone());
+            }
+            continue;
+        }
+
+        let stages = [b.cloned(), o.cloned(), t.cloned()];
+        match (o, t) {
+            (Some(o), Some(t)) => {
+                result.messages.push(format!("Auto-merging {}", name));
+                let base_contents = match b { Some(b) => get_blob(root, &b.hash, git_mode)?, None => Vec::new() };
+                let our_contents = get_blob(root, &o.hash, git_mode)?;
+                let their_contents = get_blob(root, &t.hash, git_mode)?;
+                let kind = if b.is_some() { "content" } else { "add/add" };
+
+                if is_binary(&base_contents) || is_binary(&our_contents) || is_binary(&their_contents) {
+                    result.messages.push(format!("warning: Cannot merge binary files: {} ({} vs. {})", name, our_label, their_label));
+                    result.messages.push(format!("CONFLICT ({}): Merge conflict in {}", kind, name));
+                    result.files.insert(path.clone(), o.clone());
+                    result.conflicts.push(Conflict { path: path.clone(), stages, contents: None });
+                    continue;
+                }
+
+                let (merged, conflicted) = merge_file(&base_contents, &our_contents, &their_contents, our_label, their_label);
+                // An executable bit changed by only one side is kept
+                let mode = match b {
+                    Some(b) if b.mode == o.mode => t.mode,
+                    _ => o.mode
+                };
+                let blob = Blob { bytes: merged };
+                if conflicted {
+                    result.messages.push(format!("CONFLICT ({}): Merge conflict in {}", kind, name));
+                    result.files.insert(path.clone(), o.clone());
+                    result.conflicts.push(Conflict { path: path.clone(), stages, contents: Some(blob.bytes) });
+                } else {
+                    blob.write(root, global_opts)?;
+                    result.files.insert(path.clone(), TreeEntry { mode, name: o.name.clone(), hash: blob.hash() });
+                }
+            },
+            (Some(o), None) => {
+                result.messages.push(format!(
+                    "CONFLICT (modify/delete): {name} deleted in {their_label} and modified in {our_label}.  \
+                    Version {our_label} of {name} left in tree."
+                ));
+                result.files.insert(path.clone(), o.clone());
+                result.conflicts.push(Conflict { path: path.clone(), stages, contents: None });
+            },
+            (None, Some(t)) => {
+                result.messages.push(format!(
+                    "CONFLICT (modify/delete): {name} deleted in {our_label} and modified in {their_label}.  \
+                    Version {their_label} of {name} left in tree."
+                ));
+                result.files.insert(path.clone(), t.clone());
+                result.conflicts.push(Conflict { path: path.clone(), stages, contents: None });
+            },
+            (None, None) => ()
+        }
+    }
+    Ok(result)
+}
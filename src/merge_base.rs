@@ -0,0 +1,45 @@
+// Find the best common ancestors of two commits
+
+use std::{env, process};
+use anyhow::Result;
+use clap::Args;
+
+use crate::{GlobalOpts, repo_find};
+use crate::dag::{is_ancestor, merge_bases, CommitDag};
+use crate::refs::resolve_revision;
+
+#[derive(Args)]
+pub struct MergeBaseArgs {
+    pub first: String,
+    pub second: String,
+    /// Print all best common ancestors rather than just one
+    #[arg(short, long)]
+    pub all: bool,
+    /// Print nothing, but exit with status 0 if the first commit is an ancestor of the second and 1 otherwise
+    #[arg(long)]
+    pub is_ancestor: bool,
+}
+
+pub fn cmd_merge_base(args: MergeBaseArgs, global_opts: GlobalOpts) -> Result<()> {
+    let path = env::current_dir().unwrap_or_else(|_| { panic!() });
+    let root = repo_find(&path, global_opts).unwrap_or_else(|| {
+        panic!("fatal: not a grit repository");
+    });
+
+    let first = resolve_revision(&root, &args.first, global_opts)?;
+    let second = resolve_revision(&root, &args.second, global_opts)?;
+
+    let dag = CommitDag::open(&root, global_opts.git_mode)?;
+    if args.is_ancestor {
+        let result = is_ancestor(&dag, &first, &second)?;
+        process::exit(if result { 0 } else { 1 });
+    }
+
+    let bases = merge_bases(&dag, &first, &second)?;
+    let shown = if args.all { bases.len() } else { 1 };
+    for base in bases.iter().take(shown) {
+        println!("{}", hex::encode(base));
+    }
+
+    Ok(())
+}
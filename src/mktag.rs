@@ -0,0 +1,116 @@
+// Build a tag object from its text, checking it as strictly as Git does
+
+use std::{env, io::{self, Read}};
+use anyhow::{anyhow, bail, Result};
+use clap::Args;
+
+use crate::{GlobalOpts, repo_find};
+use crate::objects::{parse_hash, parse_tag, read_object_header, GitObject, ObjectType};
+use crate::refs::check_ref_name;
+
+#[derive(Args)]
+pub struct MktagArgs {}
+
+pub fn cmd_mktag(_args: MktagArgs, global_opts: GlobalOpts) -> Result<()> {
+    let path = env::current_dir().unwrap_or_else(|_| { panic!() });
+    let root = repo_find(&path, global_opts).unwrap_or_else(|| {
+        panic!("fatal: not a grit repository");
+    });
+
+    let mut input = Vec::new();
+    io::stdin().read_to_end(&mut input)?;
+    let input = String::from_utf8_lossy(&input);
+    if let Err(e) = check_tag(&input) {
+        bail!("error: tag input does not pass fsck: {}\nfatal: tag on stdin did not pass our strict fsck check", e);
+    }
+
+    let tag = parse_tag(&input)?;
+    let object = hex::encode(tag.object);
+    let Some((object_type, _)) = read_object_header(&root, &tag.object, global_opts.git_mode)? else {
+        bail!("fatal: could not read tagged object '{}'", object);
+    };
+    if object_type != tag.object_type {
+        bail!("fatal: object '{}' tagged as '{}', but is a '{}' type", object, tag.object_type, object_type);
+    }
+
+    // As with hash-object, a tag given without a message is written with the blank line that would separate it
+    tag.write(&root, global_opts)?;
+    println!("{}", hex::encode(tag.hash()));
+    Ok(())
+}
+
+// Checks that the text of a tag has the headers Git requires, in order, and nothing more, giving the problem found
+// as Git's fsck describes it
+fn check_tag(text: &str) -> Result<()> {
+    let headers = match text.split_once("\n\n") {
+        Some((headers, _)) => headers,
+        None if text.ends_with('\n') => text.trim_end_matches('\n'),
+        None => bail!("unterminatedHeader: unterminated header")
+    };
+    let mut lines = headers.split('\n');
+
+    let object = lines.next().and_then(|x| x.strip_prefix("object "))
+        .ok_or(anyhow!("missingObject: invalid format - expected 'object' line"))?;
+    parse_hash(object).map_err(|_| anyhow!("badObjectSha1: invalid 'object' line format - bad sha1"))?;
+
+    let object_type = lines.next().and_then(|x| x.strip_prefix("type "))
+        .ok_or(anyhow!("missingTypeEntry: invalid format - expected 'type' line"))?;
+    if matches!(ObjectType::from_bytes(object_type.as_bytes()), ObjectType::Unknown(_)) {
+        bail!("badType: invalid 'type' value");
+    }
+
+    let name = lines.next().and_then(|x| x.strip_prefix("tag "))
+        .ok_or(anyhow!("missingTagEntry: invalid format - expected 'tag' line"))?;
+    if check_ref_name(&format!("refs/tags/{}", name)).is_err() {
+        bail!("badTagName: invalid 'tag' name: {}", name);
+    }
+
+    let tagger = lines.next().and_then(|x| x.strip_prefix("tagger "))
+        .ok_or(anyhow!("missingTaggerEntry: invalid format - expected 'tagger' line"))?;
+    check_ident(tagger)?;
+
+    if lines.next().is_some() {
+        bail!("extraHeaderEntry: invalid format - extra header(s) after 'tagger'");
+    }
+    Ok(())
+}
+
+// Checks that an identity has the form `Name <email> timestamp timezone`, making the same checks as Git, in the same
+// order
+fn check_ident(ident: &str) -> Result<()> {
+    let problem = |id: &str, description: &str| anyhow!("{}: invalid author/committer line - {}", id, description);
+    if ident.starts_with('<') {
+        return Err(problem("missingNameBeforeEmail", "missing space before email"));
+    }
+    let email_start = ident.find(['<', '>']);
+    match email_start.map(|x| &ident[x..x + 1]) {
+        Some(">") => return Err(problem("badName", "bad name")),
+        None => return Err(problem("missingEmail", "missing email")),
+        _ => ()
+    }
+    let email_start = email_start.unwrap_or_default();
+    if !ident[..email_start].ends_with(' ') {
+        return Err(problem("missingSpaceBeforeEmail", "missing space before email"));
+    }
+    let rest = &ident[email_start + 1..];
+    let email_end = rest.find(['<', '>']).filter(|x| &rest[*x..x + 1] == ">")
+        .ok_or_else(|| problem("badEmail", "bad email"))?;
+
+    let date = rest[email_end + 1..].strip_prefix(' ')
+        .ok_or_else(|| problem("missingSpaceBeforeDate", "missing space before date"))?;
+    if date.starts_with('0') && !date.starts_with("0 ") {
+        return Err(problem("zeroPaddedDate", "zero-padded date"));
+    }
+    let digits = date.len() - date.trim_start_matches(|x: char| x.is_ascii_digit()).len();
+    let timezone = date[digits..].strip_prefix(' ').filter(|_| digits > 0)
+        .ok_or_else(|| problem("badDate", "bad date"))?;
+    if date[..digits].parse::<i64>().is_err() {
+        return Err(problem("badDateOverflow", "date causes integer overflow"));
+    }
+
+    let bytes = timezone.as_bytes();
+    if bytes.len() != 5 || !matches!(bytes[0], b'+' | b'-') || !bytes[1..].iter().all(|x| x.is_ascii_digit()) {
+        return Err(problem("badTimezone", "bad time zone"));
+    }
+    Ok(())
+}
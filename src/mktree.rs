@@ -0,0 +1,93 @@
+// Build a tree object from a listing of its entries, in the format ls-tree prints
+
+use std::{env, io::{self, Read}, path::Path};
+use anyhow::{anyhow, bail, Result};
+use clap::Args;
+
+use crate::{GlobalOpts, repo_find};
+use crate::objects::{parse_hash, read_object_header, GitObject, ObjectType, Tree, TreeEntry};
+use crate::quote::unquote;
+
+#[derive(Args)]
+pub struct MktreeArgs {
+    /// Read entries ended by NUL rather than newline, with their names unquoted
+    #[arg(short = 'z')]
+    nul_terminated: bool,
+    /// Allow entries for objects that aren't in the store
+    #[arg(long)]
+    missing: bool,
+}
+
+pub fn cmd_mktree(args: MktreeArgs, global_opts: GlobalOpts) -> Result<()> {
+    let path = env::current_dir().unwrap_or_else(|_| { panic!() });
+    let root = repo_find(&path, global_opts).unwrap_or_else(|| {
+        panic!("fatal: not a grit repository");
+    });
+
+    let mut input = Vec::new();
+    io::stdin().read_to_end(&mut input)?;
+    let input = String::from_utf8_lossy(&input);
+    let terminator = if args.nul_terminated { '\0' } else { '\n' };
+
+    let mut children = Vec::new();
+    for line in input.split_terminator(terminator) {
+        if line.is_empty() {
+            bail!("fatal: input format error: (blank line only valid in batch mode)");
+        }
+        children.push(parse_entry(&root, line, args.nul_terminated, args.missing, global_opts)?);
+    }
+
+    // The entries are put in the order Git keeps them in when the tree is written
+    let tree = Tree { children };
+    tree.write(&root, global_opts)?;
+    println!("{}", hex::encode(tree.hash()));
+    Ok(())
+}
+
+// Reads an entry given as `<mode> SP <type> SP <hash> TAB <name>`, checking that the type agrees with the mode and
+// with the object in the store, which must have it unless `missing` is given. Like Git, a submodule's commit need not
+// be in the store.
+fn parse_entry(
+    root: &Path,
+    line: &str,
+    nul_terminated: bool,
+    missing: bool,
+    global_opts: GlobalOpts
+) -> Result<TreeEntry> {
+    let format_error = || anyhow!("fatal: input format error: {}", line);
+    let (mode, rest) = line.split_once(' ').ok_or_else(format_error)?;
+    let (type_name, rest) = rest.split_once(' ').ok_or_else(format_error)?;
+    let (hash, name) = rest.split_once('\t').ok_or_else(format_error)?;
+    let mode = u32::from_str_radix(mode, 8).map_err(|_| format_error())?;
+    let hash = parse_hash(hash).map_err(|_| format_error())?;
+
+    // Names are quoted as ls-tree quotes them unless entries are separated by NUL
+    let name = match name.starts_with('"') && !nul_terminated {
+        true => unquote(name).ok_or(anyhow!("fatal: invalid quoting"))?,
+        false => name.to_string()
+    };
+    if name.contains('/') {
+        bail!("fatal: path {} contains slash", name);
+    }
+
+    let object_type = ObjectType::from_bytes(type_name.as_bytes());
+    if matches!(object_type, ObjectType::Unknown(_)) {
+        bail!("fatal: invalid object type \"{}\"", type_name);
+    }
+    let entry = TreeEntry { mode, name, hash };
+    let mode_type = entry.object_type();
+    if object_type != mode_type {
+        bail!("fatal: entry '{}' object type ({}) doesn't match mode type ({})", entry.name, type_name, mode_type);
+    }
+    match read_object_header(root, &hash, global_opts.git_mode)? {
+        Some((stored_type, _)) if stored_type != mode_type => bail!(
+            "fatal: entry '{}' object {} is a {} but specified type was ({})",
+            entry.name, hex::encode(hash), stored_type, type_name
+        ),
+        None if !missing && mode_type != ObjectType::Commit => {
+            bail!("fatal: entry '{}' object {} is unavailable", entry.name, hex::encode(hash))
+        },
+        _ => ()
+    }
+    Ok(entry)
+}
@@ -0,0 +1,261 @@
+// Name commits after the references they can be reached from
+
+use std::{collections::HashMap, env, io::{BufRead, Write}, path::Path};
+use anyhow::Result;
+use clap::Args;
+
+use crate::{GlobalOpts, repo_find};
+use crate::dag::CommitDag;
+use crate::objects::{search_object, Object, Signature};
+use crate::refs::{list_refs, resolve_revision};
+
+// Going through a merge's other parents counts as this many steps, so that names along first parents are preferred
+const MERGE_TRAVERSAL_WEIGHT: usize = 65535;
+
+#[derive(Args)]
+pub struct NameRevArgs {
+    /// The commits to name
+    commits: Vec<String>,
+    /// Print only the names, rather than each commit followed by its name
+    #[arg(long)]
+    name_only: bool,
+    /// Copy standard input to standard output, adding the name after each full commit hash
+    #[arg(long)]
+    annotate_stdin: bool,
+    /// The old name for --annotate-stdin
+    #[arg(long)]
+    stdin: bool,
+}
+
+// The best name found so far for a commit
+struct RevName {
+    /// The reference the name starts from, e.g. `master` or `tags/v1.0^0`, and any merge parents taken since
+    tip_name: String,
+    /// When the tag was made, or the date of the commit the reference points at
+    tagger_date: i64,
+    /// The number of first parents back from the tip
+    generation: usize,
+    /// The number of steps back from the reference, weighting merges heavily
+    distance: usize,
+    from_tag: bool,
+}
+
+impl RevName {
+    // Like Git, names from tags are preferred, then the oldest tags, then the closest references
+    fn is_worse_than(&self, tagger_date: i64, generation: usize, distance: usize, from_tag: bool) -> bool {
+        let ours = effective_distance(self.generation, self.distance);
+        let theirs = effective_distance(generation, distance);
+        if from_tag && self.from_tag {
+            return self.tagger_date > tagger_date || (self.tagger_date == tagger_date && ours > theirs);
+        }
+        if self.from_tag != from_tag {
+            return from_tag;
+        }
+        if ours != theirs {
+            return ours > theirs;
+        }
+        self.tagger_date > tagger_date
+    }
+
+    fn display(&self) -> String {
+        if self.generation == 0 {
+            return self.tip_name.clone();
+        }
+        format!("{}~{}", self.tip_name.strip_suffix("^0").unwrap_or(&self.tip_name), self.generation)
+    }
+}
+
+// As in Git, names ending in `~<n>` count as having gone through a merge, so that a name like `topic^2` is preferred
+// over `master~1`
+fn effective_distance(generation: usize, distance: usize) -> usize {
+    if generation > 0 { distance + MERGE_TRAVERSAL_WEIGHT } else { distance }
+}
+
+// A reference that names start from
+struct Tip {
+    /// The reference name without `refs/`, or `refs/heads/` for branches
+    name: String,
+    /// The object the reference points at, which may be a tag
+    hash: [u8; 20],
+    /// The commit the reference points at, once tags are peeled
+    commit: Option<[u8; 20]>,
+    tagger_date: i64,
+    from_tag: bool,
+    /// Whether the reference is a tag object rather than pointing straight at the commit
+    deref: bool,
+}
+
+pub fn cmd_name_rev(args: NameRevArgs, global_opts: GlobalOpts) -> Result<()> {
+    let path = env::current_dir().unwrap_or_else(|_| { panic!() });
+    let root = repo_find(&path, global_opts).unwrap_or_else(|| {
+        panic!("fatal: not a grit repository");
+    });
+    let git_mode = global_opts.git_mode;
+
+    let dag = CommitDag::open(&root, git_mode)?;
+    let tips = read_tips(&root, global_opts)?;
+    let mut names: HashMap<[u8; 20], RevName> = HashMap::new();
+    for tip in &tips {
+        if let Some(commit) = &tip.commit {
+            name_rev(&dag, commit, tip, &mut names)?;
+        }
+    }
+
+    if args.stdin || args.annotate_stdin {
+        if args.stdin {
+            eprintln!("warning: --stdin is deprecated. Please use --annotate-stdin instead, which is functionally equivalent.");
+            eprintln!("This option will be removed in a future release.");
+        }
+        let mut stdout = std::io::stdout();
+        for line in std::io::stdin().lock().lines() {
+            stdout.write_all(annotate_line(&format!("{}\n", line?), &names, args.name_only).as_bytes())?;
+        }
+        return Ok(());
+    }
+
+    for rev in &args.commits {
+        let Ok(hash) = resolve_revision(&root, rev, global_opts) else {
+            eprintln!("Could not get sha1 for {}. Skipping.", rev);
+            continue;
+        };
+        // Objects other than commits are only named if a reference points straight at them
+        let name = match search_object(&root, &hash, git_mode)? {
+            Some(Object::Commit(_)) => names.get(&hash).map(|x| x.display()),
+            _ => tips.iter().find(|x| x.hash == hash).map(|x| x.name.clone())
+        };
+        let name = name.unwrap_or(String::from("undefined"));
+        if args.name_only {
+            println!("{}", name);
+        } else {
+            println!("{} {}", rev, name);
+        }
+    }
+    Ok(())
+}
+
+// Reads the references, ordered so that the best names are given first: tags before other references, and older
+// before newer
+fn read_tips(root: &Path, global_opts: GlobalOpts) -> Result<Vec<Tip>> {
+    let mut tips = Vec::new();
+    for (full_name, hash) in list_refs(root, global_opts)? {
+        let name = full_name.strip_prefix("refs/heads/").or(full_name.strip_prefix("refs/")).unwrap_or(&full_name);
+        let mut tip = Tip {
+            name: name.to_string(),
+            hash,
+            commit: None,
+            tagger_date: i64::MAX,
+            from_tag: false,
+            deref: false,
+        };
+
+        let mut current = hash;
+        loop {
+            match search_object(root, &current, global_opts.git_mode)? {
+                Some(Object::Tag(tag)) => {
+                    tip.deref = true;
+                    tip.tagger_date = match &tag.tagger {
+                        Some(tagger) => Signature::parse(tagger)?.timestamp,
+                        None => 0
+                    };
+                    current = tag.object;
+                },
+                Some(Object::Commit(commit)) => {
+                    tip.commit = Some(current);
+                    tip.from_tag = full_name.starts_with("refs/tags/");
+                    if tip.tagger_date == i64::MAX {
+                        tip.tagger_date = Signature::parse(&commit.committer)?.timestamp;
+                    }
+                    break;
+                },
+                _ => break
+            }
+        }
+        tips.push(tip);
+    }
+    tips.sort_by(|a, b| b.from_tag.cmp(&a.from_tag).then(a.tagger_date.cmp(&b.tagger_date)));
+    Ok(tips)
+}
+
+// Walks back from a reference, giving each commit a name from it if that's better than the name it has
+fn name_rev(dag: &CommitDag, start: &[u8; 20], tip: &Tip, names: &mut HashMap<[u8; 20], RevName>) -> Result<()> {
+    if names.get(start).is_some_and(|x| !x.is_worse_than(tip.tagger_date, 0, 0, tip.from_tag)) {
+        return Ok(());
+    }
+    let tip_name = if tip.deref { format!("{}^0", tip.name) } else { tip.name.clone() };
+    names.insert(*start, RevName { tip_name, tagger_date: tip.tagger_date, generation: 0, distance: 0, from_tag: tip.from_tag });
+
+    let mut stack = vec![*start];
+    while let Some(hash) = stack.pop() {
+        let (tip_name, generation, distance) = {
+            let name = &names[&hash];
+            (name.tip_name.clone(), name.generation, name.distance)
+        };
+
+        let mut to_visit = Vec::new();
+        for (i, parent) in dag.parents(&hash)?.into_iter().enumerate() {
+            let (parent_generation, parent_distance) = if i > 0 {
+                (0, distance + MERGE_TRAVERSAL_WEIGHT)
+            } else {
+                (generation + 1, distance + 1)
+            };
+            if names.get(&parent).is_some_and(|x| !x.is_worse_than(tip.tagger_date, parent_generation, parent_distance, tip.from_tag)) {
+                continue;
+            }
+
+            // A name through a merge's other parent records the path taken, e.g. `master~2^2`
+            let parent_tip_name = if i == 0 {
+                tip_name.clone()
+            } else {
+                let base = tip_name.strip_suffix("^0").unwrap_or(&tip_name);
+                match generation {
+                    0 => format!("{}^{}", base, i + 1),
+                    n => format!("{}~{}^{}", base, n, i + 1)
+                }
+            };
+            names.insert(parent, RevName {
+                tip_name: parent_tip_name,
+                tagger_date: tip.tagger_date,
+                generation: parent_generation,
+                distance: parent_distance,
+                from_tag: tip.from_tag,
+            });
+            to_visit.push(parent);
+        }
+        // The first parent is visited first
+        stack.extend(to_visit.into_iter().rev());
+    }
+    Ok(())
+}
+
+// Adds the name of each full commit hash in a line after it, or replaces the hash with its name if `name_only`.
+// Hashes of commits without names are left as they are.
+fn annotate_line(line: &str, names: &HashMap<[u8; 20], RevName>, name_only: bool) -> String {
+    let bytes = line.as_bytes();
+    let is_hex = |x: u8| x.is_ascii_digit() || (b'a'..=b'f').contains(&x);
+    let mut output = String::new();
+    let mut start = 0;
+    let mut run = 0;
+    for i in 0..bytes.len() {
+        if !is_hex(bytes[i]) {
+            run = 0;
+            continue;
+        }
+        run += 1;
+        if run != 40 || bytes.get(i + 1).is_some_and(|x| is_hex(*x)) {
+            continue;
+        }
+        let hex_start = i + 1 - 40;
+        let Some(name) = hex::decode(&line[hex_start..=i]).ok()
+            .and_then(|x| <[u8; 20]>::try_from(x).ok())
+            .and_then(|x| names.get(&x)) else {
+            continue;
+        };
+        if name_only {
+            output += &format!("{}{}", &line[start..hex_start], name.display());
+        } else {
+            output += &format!("{} ({})", &line[start..=i], name.display());
+        }
+        start = i + 1;
+    }
+    output + &line[start..]
+}
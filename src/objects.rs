@@ -1,30 +1,86 @@
-use std::{path::PathBuf, fs::{self, File}, io::{Write, Read}, collections::HashMap, fmt};
+use std::{path::{Path, PathBuf}, fs::{self, File}, io::{BufReader, Write, Read}, collections::{BTreeMap, HashMap}, fmt, cell::{OnceCell, RefCell}};
 use anyhow::{anyhow, bail, Result};
 use flate2::{bufread::ZlibDecoder, write::ZlibEncoder, Compression};
 use sha1::{Sha1, Digest};
 
-use crate::{git_dir_name, GlobalOpts};
+use crate::GlobalOpts;
+use crate::config::Config;
+use crate::pack::{open_packs, packed_objects, DeltaBaseCache, PackFile, PackedEntry};
+
+/// The length hashes are abbreviated to when no more is needed to tell objects apart
+const DEFAULT_ABBREV: usize = 7;
+/// The shortest `core.abbrev` Git accepts
+const MIN_ABBREV: usize = 4;
+/// How many alternates deep objects are looked for, as in Git
+const MAX_ALTERNATE_DEPTH: usize = 5;
+
+/// The hash of the tree with no entries, which can be read whether or not it's in the store
+pub const EMPTY_TREE_HASH: [u8; 20] = [
+    0x4b, 0x82, 0x5d, 0xc6, 0x42, 0xcb, 0x6e, 0xb9, 0xa0, 0x60,
+    0xe5, 0x4b, 0xf8, 0xd6, 0x92, 0x88, 0xfb, 0xee, 0x49, 0x04
+];
+/// The hash of the blob with no contents, which can be read whether or not it's in the store
+pub const EMPTY_BLOB_HASH: [u8; 20] = [
+    0xe6, 0x9d, 0xe2, 0x9b, 0xb2, 0xd1, 0xd6, 0x43, 0x4b, 0x8b,
+    0x29, 0xae, 0x77, 0x5a, 0xd8, 0xc2, 0xe4, 0x8c, 0x53, 0x91
+];
+
+/// The type of an object, as its header names it
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ObjectType {
+    Blob,
+    Tree,
+    Commit,
+    Tag,
+    /// A type other than the four Git uses, which only `hash-object --literally` writes
+    Unknown(String),
+}
+
+impl ObjectType {
+    /// The name of the type as headers give it
+    pub fn as_str(&self) -> &str {
+        match self {
+            ObjectType::Blob => "blob",
+            ObjectType::Tree => "tree",
+            ObjectType::Commit => "commit",
+            ObjectType::Tag => "tag",
+            ObjectType::Unknown(name) => name,
+        }
+    }
+
+    /// Reads the name of a type as headers give it. Any name but the four Git uses gives an unknown type.
+    pub fn from_bytes(name: &[u8]) -> ObjectType {
+        match name {
+            b"blob" => ObjectType::Blob,
+            b"tree" => ObjectType::Tree,
+            b"commit" => ObjectType::Commit,
+            b"tag" => ObjectType::Tag,
+            _ => ObjectType::Unknown(String::from_utf8_lossy(name).to_string()),
+        }
+    }
+}
+
+impl fmt::Display for ObjectType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
 
 // All object types implement this trait which provides common functionality.
 // All objects can be hashed, compressed, and written to the object store.
 pub trait GitObject {
-    fn type_name(&self) -> String;
+    fn object_type(&self) -> ObjectType;
     fn content_bytes(&self) -> Vec<u8>;
 
     fn content_with_header(&self) -> Vec<u8> {
         let content = self.content_bytes();
-        let header_str = self.type_name() + " " + &self.content_bytes().len().to_string() + "\0";
+        let header_str = format!("{} {}\0", self.object_type(), content.len());
         let header_bytes = header_str.as_bytes();
-        let bytes = [header_bytes, &content].concat();
-        bytes
+        [header_bytes, &content].concat()
     }
 
     fn compress(&self) -> Result<Vec<u8>> {
-        let bytes = self.content_with_header();
-        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::fast());
-        encoder.write_all(&bytes).map_err(|_| anyhow!("Object compression failed"))?;
-        let compressed_bytes = encoder.finish().map_err(|_| anyhow!("Object compression failed"))?;
-        Ok(compressed_bytes)
+        compress(&self.content_with_header())
     }
 
     fn hash(&self) -> [u8; 20] {
@@ -34,26 +90,233 @@ pub trait GitObject {
         hasher.finalize().into()
     }
 
-    fn write(&self, repo_root: &PathBuf, global_opts: GlobalOpts) -> Result<()> {
-        let hash = self.hash();
-        let compressed_bytes = self.compress()?;
+    fn write(&self, repo_root: &Path, global_opts: GlobalOpts) -> Result<bool> {
+        FileStore::new(repo_root, global_opts.git_mode)?.write(&self.hash(), &self.content_with_header())
+    }
+}
 
-        // The first two characters of the SHA1 hash are used to name a directory. The remaining 14 name the file within
-        // that directory. This is just for practical reasons, because most operating systems slow down on directories
-        // with loads of files.
+// Compresses the bytes of an object as they're kept in a loose object file
+fn compress(bytes: &[u8]) -> Result<Vec<u8>> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::fast());
+    encoder.write_all(bytes).map_err(|_| anyhow!("Object compression failed"))?;
+    let compressed_bytes = encoder.finish().map_err(|_| anyhow!("Object compression failed"))?;
+    Ok(compressed_bytes)
+}
+
+/// Somewhere objects are kept. Objects are stored as the bytes they're hashed from: a header giving their type and
+/// size, followed by their contents.
+pub trait ObjectStore {
+    /// Reads the object with the given hash, or returns None if the store doesn't have it
+    fn read_raw(&self, hash: &[u8; 20]) -> Result<Option<Vec<u8>>>;
+    /// Stores the object with the given hash and bytes unless the store already has it, returning whether it was
+    /// stored
+    fn write(&self, hash: &[u8; 20], bytes: &[u8]) -> Result<bool>;
+    /// Whether the store has the object with the given hash, without reading it
+    fn contains(&self, hash: &[u8; 20]) -> Result<bool>;
+    /// The hashes of every object in the store, in order and without duplicates
+    fn iter(&self) -> Result<Box<dyn Iterator<Item = [u8; 20]>>>;
+}
+
+/// The objects in the git directory of a repository. Each object is written to its own compressed file, as a loose
+/// object, and is read from there or from the packs. Objects the repository doesn't have are read from the stores
+/// `objects/info/alternates` lists, as a repository shared with another does. The packs are opened the first time
+/// they're needed and kept open, so a store kept for many reads opens each pack only once.
+pub struct FileStore {
+    root: PathBuf,
+    git_mode: bool,
+    objects_dir: PathBuf,
+    alternates: Vec<FileStore>,
+    packs: OnceCell<Vec<PackFile>>,
+    delta_bases: RefCell<DeltaBaseCache>,
+}
+
+impl FileStore {
+    pub fn new(root: &Path, git_mode: bool) -> Result<FileStore> {
+        let git_dir = if git_mode { ".git" } else { ".grit" };
+        FileStore::open(root, git_mode, root.join(git_dir).join("objects"), 0)
+    }
+
+    // Opens the store in an objects directory, along with its alternates, which are listed one to a line as paths
+    // either absolute or relative to the directory. Like Git, alternates that don't exist are skipped, and alternates
+    // of alternates are only followed so deep, so that a cycle can't go on forever.
+    fn open(root: &Path, git_mode: bool, objects_dir: PathBuf, depth: usize) -> Result<FileStore> {
+        let mut alternates = Vec::new();
+        let list = objects_dir.join("info").join("alternates");
+        if depth < MAX_ALTERNATE_DEPTH && list.is_file() {
+            for line in fs::read_to_string(&list)?.lines() {
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                let dir = objects_dir.join(line);
+                if dir.is_dir() {
+                    alternates.push(FileStore::open(root, git_mode, dir, depth + 1)?);
+                }
+            }
+        }
+        Ok(FileStore {
+            root: root.to_path_buf(),
+            git_mode,
+            objects_dir,
+            alternates,
+            packs: OnceCell::new(),
+            delta_bases: RefCell::new(DeltaBaseCache::default()),
+        })
+    }
+
+    /// Reads the type and size of the object with the given hash as its header declares them, without reading or
+    /// parsing the rest of it where that can be avoided. Returns None if the store doesn't have the object.
+    pub fn read_header(&self, hash: &[u8; 20]) -> Result<Option<(ObjectType, usize)>> {
+        // Only as much of a loose object in this store as holds the header is decompressed
+        let path = self.loose_path(hash);
+        let bytes = if path.exists() {
+            let mut header = Vec::new();
+            let mut decoder = ZlibDecoder::new(BufReader::new(File::open(path)?));
+            let mut byte = [0];
+            while !header.contains(&0) && decoder.read(&mut byte)? == 1 {
+                header.push(byte[0]);
+            }
+            header
+        } else {
+            match self.read_raw(hash)? {
+                Some(bytes) => bytes,
+                None if *hash == EMPTY_TREE_HASH => return Ok(Some((ObjectType::Tree, 0))),
+                None if *hash == EMPTY_BLOB_HASH => return Ok(Some((ObjectType::Blob, 0))),
+                None => return Ok(None)
+            }
+        };
+        let (object_type, size, _) = parse_object_header(&bytes)?;
+        Ok(Some((ObjectType::from_bytes(object_type), size)))
+    }
+
+    /// Describes how the first of this store's own packs that has an object keeps it, or returns None if none has it
+    pub fn packed_entry(&self, hash: &[u8; 20]) -> Result<Option<PackedEntry>> {
+        for pack in self.packs()? {
+            if let Some(entry) = pack.entry(hash)? {
+                return Ok(Some(entry));
+            }
+        }
+        Ok(None)
+    }
+
+    // This store's own packs, opened the first time they're needed
+    fn packs(&self) -> Result<&[PackFile]> {
+        if self.packs.get().is_none() {
+            let _ = self.packs.set(open_packs(&self.objects_dir.join("pack"))?);
+        }
+        Ok(self.packs.get().unwrap())
+    }
+
+    // The path at which the loose object with the given hash is stored in this store, whether or not it exists. The
+    // first two characters of the SHA1 hash are used to name a directory. The remaining 38 name the file within that
+    // directory. This is just for practical reasons, because most operating systems slow down on directories with
+    // loads of files.
+    fn loose_path(&self, hash: &[u8; 20]) -> PathBuf {
         let hash_str = hex::encode(hash);
-        let dir_name = &hash_str[..2];
-        let file_name = &hash_str[2..];
+        self.objects_dir.join(&hash_str[..2]).join(&hash_str[2..])
+    }
+}
 
-        let dir = repo_root.join(format!("{}/objects/{}", git_dir_name(global_opts), dir_name));
+impl ObjectStore for FileStore {
+    fn read_raw(&self, hash: &[u8; 20]) -> Result<Option<Vec<u8>>> {
+        let full_path = self.loose_path(hash);
+        if full_path.exists() {
+            // Read and decompress the requested file
+            let bytes = fs::read(full_path)?;
+            let mut z = ZlibDecoder::new(&bytes[..]);
 
-        fs::create_dir_all(&dir).and_then(|()| {
-            File::create(dir.join(file_name))
+            let mut buf = Vec::<u8>::new();
+            z.read_to_end(&mut buf)?;
+            return Ok(Some(buf));
+        }
+
+        for pack in self.packs()? {
+            let packed = pack.read_object(&self.root, hash, self.git_mode, &mut self.delta_bases.borrow_mut())?;
+            if packed.is_some() {
+                return Ok(packed);
+            }
+        }
+        for alternate in &self.alternates {
+            if let Some(bytes) = alternate.read_raw(hash)? {
+                return Ok(Some(bytes));
+            }
+        }
+        Ok(None)
+    }
+
+    fn write(&self, hash: &[u8; 20], bytes: &[u8]) -> Result<bool> {
+        if self.contains(hash)? {
+            return Ok(false);
+        }
+        let compressed_bytes = compress(bytes)?;
+        let path = self.loose_path(hash);
+        fs::create_dir_all(path.parent().unwrap()).and_then(|()| {
+            File::create(&path)
         }).and_then(|mut f| {
             f.write_all(&compressed_bytes)
         })?;
 
-        Ok(())
+        Ok(true)
+    }
+
+    fn contains(&self, hash: &[u8; 20]) -> Result<bool> {
+        if self.loose_path(hash).exists() {
+            return Ok(true);
+        }
+        for pack in self.packs()? {
+            if pack.contains(hash)? {
+                return Ok(true);
+            }
+        }
+        for alternate in &self.alternates {
+            if alternate.contains(hash)? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    fn iter(&self) -> Result<Box<dyn Iterator<Item = [u8; 20]>>> {
+        let mut hashes = loose_objects_in(&self.objects_dir)?;
+        for pack in self.packs()? {
+            hashes.extend(pack.hashes()?);
+        }
+        for alternate in &self.alternates {
+            hashes.extend(alternate.iter()?);
+        }
+        hashes.sort();
+        hashes.dedup();
+        Ok(Box::new(hashes.into_iter()))
+    }
+}
+
+/// Objects kept in memory rather than in a repository, for tests that need a store without setting one up on disk.
+/// Objects are kept uncompressed and are lost when the store is dropped.
+#[derive(Default)]
+pub struct MemoryStore {
+    objects: RefCell<BTreeMap<[u8; 20], Vec<u8>>>,
+}
+
+impl ObjectStore for MemoryStore {
+    fn read_raw(&self, hash: &[u8; 20]) -> Result<Option<Vec<u8>>> {
+        Ok(self.objects.borrow().get(hash).cloned())
+    }
+
+    fn write(&self, hash: &[u8; 20], bytes: &[u8]) -> Result<bool> {
+        let mut objects = self.objects.borrow_mut();
+        if objects.contains_key(hash) {
+            return Ok(false);
+        }
+        objects.insert(*hash, bytes.to_vec());
+        Ok(true)
+    }
+
+    fn contains(&self, hash: &[u8; 20]) -> Result<bool> {
+        Ok(self.objects.borrow().contains_key(hash))
+    }
+
+    fn iter(&self) -> Result<Box<dyn Iterator<Item = [u8; 20]>>> {
+        let hashes: Vec<[u8; 20]> = self.objects.borrow().keys().copied().collect();
+        Ok(Box::new(hashes.into_iter()))
     }
 }
 
@@ -65,8 +328,8 @@ pub struct Blob {
 }
 
 impl GitObject for Blob {
-    fn type_name(&self) -> String {
-        String::from("blob")
+    fn object_type(&self) -> ObjectType {
+        ObjectType::Blob
     }
     fn content_bytes(&self) -> Vec<u8> {
         self.bytes.clone()
@@ -79,31 +342,104 @@ pub struct Commit {
     pub tree: [u8; 20],
     pub author: String,
     pub committer: String,
-    pub date: Option<String>,
-    /// The SHA1 hash of the commit's parent if it has one
-    pub parent: Option<[u8; 20]>,
+    /// The SHA1 hashes of the commit's parents. The root commit has none and a merge commit has several.
+    pub parents: Vec<[u8; 20]>,
     pub message: String,
+    /// The signature over the rest of the commit, if it was signed, e.g. an ASCII-armored PGP signature
+    pub gpgsig: Option<String>,
+}
+
+impl Commit {
+    /// The first paragraph of the message, joined into a single line
+    pub fn subject(&self) -> String {
+        self.message
+            .lines()
+            .take_while(|x| !x.trim().is_empty())
+            .map(|x| x.trim())
+            .collect::<Vec<&str>>()
+            .join(" ")
+    }
+
+    /// The message following the subject paragraph
+    pub fn body(&self) -> String {
+        let lines = self.message.lines().skip_while(|x| !x.trim().is_empty()).skip_while(|x| x.trim().is_empty());
+        let mut body = String::new();
+        for line in lines {
+            body.push_str(line);
+            body.push('\n');
+        }
+        body
+    }
+}
+
+/// The identity and timestamp recorded in the author and committer fields of a commit
+pub struct Signature {
+    pub name: String,
+    pub email: String,
+    /// Seconds since the Unix epoch
+    pub timestamp: i64,
+    /// The author's UTC offset in the form `+hhmm`
+    pub timezone: String,
+}
+
+impl Signature {
+    /// Parses a signature in the form `Name <email> 1234567890 +0100`
+    pub fn parse(text: &str) -> Result<Signature> {
+        let email_start = text.find('<').ok_or(anyhow!("error parsing signature: missing email"))?;
+        let email_end = text.rfind('>').ok_or(anyhow!("error parsing signature: email not terminated"))?;
+        if email_end < email_start {
+            bail!("error parsing signature: malformed email");
+        }
+
+        let mut date_fields = text[email_end + 1..].split_whitespace();
+        let timestamp = date_fields.next().unwrap_or("0").parse::<i64>()
+            .map_err(|_| anyhow!("error parsing signature: invalid timestamp"))?;
+        let timezone = date_fields.next().unwrap_or("+0000").to_string();
+
+        Ok(Signature {
+            name: text[..email_start].trim().to_string(),
+            email: text[email_start + 1..email_end].to_string(),
+            timestamp,
+            timezone,
+        })
+    }
+}
+
+impl fmt::Display for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} <{}> {} {}", self.name, self.email, self.timestamp, self.timezone)
+    }
 }
 
 impl GitObject for Commit {
-    fn type_name(&self) -> String {
-        String::from("commit")
+    fn object_type(&self) -> ObjectType {
+        ObjectType::Commit
     }
     fn content_bytes(&self) -> Vec<u8> {
-        // TODO
-        vec![0]
+        let mut text = format!("tree {}\n", hex::encode(self.tree));
+        for parent in &self.parents {
+            text += &format!("parent {}\n", hex::encode(parent));
+        }
+        text += &format!("author {}\ncommitter {}\n", self.author, self.committer);
+        // A multi-line header value is continued on lines starting with a space
+        if let Some(gpgsig) = &self.gpgsig {
+            text += &format!("gpgsig {}\n", gpgsig.replace('\n', "\n "));
+        }
+        text += "\n";
+        text += &self.message;
+        text.into_bytes()
     }
 }
 
 impl fmt::Display for Commit {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        writeln!(f, "tree: {}", hex::encode(&self.tree))?;
-        if let Some(parent) = &self.parent {
+        writeln!(f, "tree: {}", hex::encode(self.tree))?;
+        for parent in &self.parents {
             writeln!(f, "parent: {}", hex::encode(parent))?;
-        } 
+        }
         writeln!(f, "author: {}", &self.author)?;
         writeln!(f, "committer: {}", &self.committer)?;
-        writeln!(f, "")?;
+        writeln!(f)?;
         writeln!(f, "{}", &self.message)
     }
 }
@@ -124,13 +460,30 @@ pub struct TreeEntry {
     pub hash: [u8; 20]
 }
 
+impl TreeEntry {
+    /// The type of the object the entry points at, which is given by its mode. Submodules are recorded as the
+    /// commit they're checked out at.
+    pub fn object_type(&self) -> ObjectType {
+        match self.mode {
+            0o40000 => ObjectType::Tree,
+            0o160000 => ObjectType::Commit,
+            _ => ObjectType::Blob
+        }
+    }
+}
+
 impl GitObject for Tree {
-    fn type_name(&self) -> String {
-        String::from("tree")
+    fn object_type(&self) -> ObjectType {
+        ObjectType::Tree
     }
     fn content_bytes(&self) -> Vec<u8> {
+        // Git orders entries by name, comparing the names of subtrees as if they ended in `/`, so that `foo-bar`
+        // comes before the directory `foo` but after a file of that name
+        let mut children: Vec<&TreeEntry> = self.children.iter().collect();
+        children.sort_by_cached_key(|x| tree_sort_key(x));
+
         let mut bytes = Vec::new();
-        for child in &self.children {
+        for child in children {
             // Convert mode from integer to an ASCII representation of the octal value
             let mode_str = format!("{:o}", child.mode);
             let mut mode = mode_str.as_bytes().to_vec();
@@ -148,17 +501,38 @@ impl GitObject for Tree {
     }
 }
 
+// The name an entry is sorted by within its tree
+fn tree_sort_key(entry: &TreeEntry) -> Vec<u8> {
+    let mut key = entry.name.as_bytes().to_vec();
+    if entry.mode == 0o40000 {
+        key.push(b'/');
+    }
+    key
+}
+
 
 pub struct Tag {
-    name: String
+    /// The SHA1 hash of the tagged object
+    pub object: [u8; 20],
+    /// The type of the tagged object
+    pub object_type: ObjectType,
+    pub name: String,
+    pub tagger: Option<String>,
+    pub message: String,
 }
 
 impl GitObject for Tag {
-    fn type_name(&self) -> String {
-        String::from("tag")
+    fn object_type(&self) -> ObjectType {
+        ObjectType::Tag
     }
     fn content_bytes(&self) -> Vec<u8> {
-        self.name.as_bytes().to_vec()
+        let mut text = format!("object {}\ntype {}\ntag {}\n", hex::encode(self.object), self.object_type, self.name);
+        if let Some(tagger) = &self.tagger {
+            text += &format!("tagger {}\n", tagger);
+        }
+        text += "\n";
+        text += &self.message;
+        text.into_bytes()
     }
 }
 
@@ -168,16 +542,20 @@ pub enum Object {
     Blob(Blob),
     Commit(Commit),
     Tree(Tree),
-    Tag(Tag)
+    Tag(Tag),
+    /// An object whose type isn't one of the four Git uses, or any object written by `hash-object --literally`.
+    /// Its contents are kept as they are.
+    Unknown { object_type: ObjectType, bytes: Vec<u8> }
 }
 
 impl GitObject for Object {
-    fn type_name(&self) -> String {
+    fn object_type(&self) -> ObjectType {
         match self {
-            Object::Blob(x) => x.type_name(),
-            Object::Commit(x) => x.type_name(),
-            Object::Tree(x) => x.type_name(),
-            Object::Tag(x) => x.type_name(),
+            Object::Blob(x) => x.object_type(),
+            Object::Commit(x) => x.object_type(),
+            Object::Tree(x) => x.object_type(),
+            Object::Tag(x) => x.object_type(),
+            Object::Unknown { object_type, .. } => object_type.clone(),
         }
     }
 
@@ -187,56 +565,80 @@ impl GitObject for Object {
             Object::Commit(x) => x.content_bytes(),
             Object::Tree(x) => x.content_bytes(),
             Object::Tag(x) => x.content_bytes(),
+            Object::Unknown { bytes, .. } => bytes.clone(),
         }
     }
 }
 
 /// Attempts to interpret the given string as a 20-byte SHA1 hash
-pub fn parse_hash(hash: &String) -> Result<[u8; 20]> {
+pub fn parse_hash(hash: &str) -> Result<[u8; 20]> {
     let bytes = hex::decode(hash)?;
     let result: [u8; 20] = bytes.as_slice().try_into()?;
     Ok(result)
 }
 
-pub fn search_object(root: &PathBuf, hash: &[u8; 20], git_mode: bool) -> Result<Option<Object>> {
+pub fn search_object(root: &Path, hash: &[u8; 20], git_mode: bool) -> Result<Option<Object>> {
     match read_object_raw(root, hash, git_mode) {
         Ok(Some(bytes)) => {
-            let type_end = bytes.iter().position(|x| x == &b' ')
-                .ok_or(anyhow!("error parsing object: `type` field not terminated"))?;
+            let (object_type, size, header_len) = parse_object_header(&bytes)?;
+            let contents = &bytes[header_len..];
+            // Only a loose object can disagree with itself, as the header of a packed one is made from its contents
+            if contents.len() != size {
+                let git_dir = if git_mode { ".git" } else { ".grit" };
+                let hash_str = hex::encode(hash);
+                bail!(
+                    "error: corrupt loose object '{}'\nfatal: loose object {} (stored in {}/objects/{}/{}) is corrupt",
+                    hash_str, hash_str, git_dir, &hash_str[..2], &hash_str[2..]
+                );
+            }
 
-            let file_size_end = (type_end + 1) + bytes[type_end+1..].iter().position(|x| x == &0)
-                .ok_or(anyhow!("error parsing object: `size` field not terminated"))?;
-
-            let object_type = &bytes[..type_end];
-            let _file_size = &bytes[type_end+1..file_size_end];
-            let contents = &bytes[file_size_end+1..];
-
-            match object_type {
-                b"blob" => Ok(Some(Object::Blob(Blob { bytes: contents.to_vec() }))),
-                b"tree" => {
-                    match parse_tree(contents) {
-                        Ok(t) => Ok(Some(Object::Tree(t))),
-                        Err(e) => Err(e)
-                    }
-                }
-                b"tag" => Ok(Some(Object::Tag(Tag { name: String::from("TODO: Read name")}))),
-                b"commit" => {
-                    match parse_commit(&String::from_utf8_lossy(&contents).to_string()) {
-                        Ok(c) => Ok(Some(Object::Commit(c))),
-                        Err(e) => Err(e)
-                    }
-                }
-                _ => bail!("unrecognised object type")
+            match ObjectType::from_bytes(object_type) {
+                ObjectType::Blob => Ok(Some(Object::Blob(Blob { bytes: contents.to_vec() }))),
+                ObjectType::Tree => Ok(Some(Object::Tree(parse_tree(contents)?))),
+                ObjectType::Tag => Ok(Some(Object::Tag(parse_tag(&String::from_utf8_lossy(contents))?))),
+                ObjectType::Commit => Ok(Some(Object::Commit(parse_commit(&String::from_utf8_lossy(contents))?))),
+                object_type @ ObjectType::Unknown(_) => Ok(Some(Object::Unknown { object_type, bytes: contents.to_vec() }))
             }
         },
+        // Like Git, the empty tree and blob are known without being written to the store
+        Ok(None) if *hash == EMPTY_TREE_HASH => Ok(Some(Object::Tree(Tree { children: Vec::new() }))),
+        Ok(None) if *hash == EMPTY_BLOB_HASH => Ok(Some(Object::Blob(Blob { bytes: Vec::new() }))),
         Ok(None) => Ok(None),
         Err(e) => Err(e)
     }
 }
 
+/// Reads the type and size of the object with the given hash as its header declares them, without reading or
+/// parsing the rest of it where that can be avoided. Returns None if the store doesn't have the object.
+pub fn read_object_header(root: &Path, hash: &[u8; 20], git_mode: bool) -> Result<Option<(ObjectType, usize)>> {
+    FileStore::new(root, git_mode)?.read_header(hash)
+}
+
+/// Splits the decompressed bytes of an object at the end of its header, returning the type and size it declares and
+/// the length of the header, after which the contents start
+pub fn parse_object_header(bytes: &[u8]) -> Result<(&[u8], usize, usize)> {
+    let type_end = bytes.iter().position(|x| x == &b' ')
+        .ok_or(anyhow!("error parsing object: `type` field not terminated"))?;
+    let size_end = (type_end + 1) + bytes[type_end + 1..].iter().position(|x| x == &0)
+        .ok_or(anyhow!("error parsing object: `size` field not terminated"))?;
+    let size = std::str::from_utf8(&bytes[type_end + 1..size_end]).ok().and_then(|x| x.parse().ok())
+        .ok_or(anyhow!("error parsing object: `size` field is not a number"))?;
+    Ok((&bytes[..type_end], size, size_end + 1))
+}
+
+/// Follows annotated tags, including tags of tags, to the object they point at.
+/// Returns the given hash if it isn't a tag.
+pub fn peel(root: &Path, hash: &[u8; 20], git_mode: bool) -> Result<[u8; 20]> {
+    let mut current = *hash;
+    while let Object::Tag(tag) = get_object(root, &current, git_mode)? {
+        current = tag.object;
+    }
+    Ok(current)
+}
+
 /// Retrieves the object with the given hash from the store, or an Err if it doesn't exist.
 /// Use this when the object is referenced by a different object, so it's absence suggests the store is corrupted.
-pub fn get_object(root: &PathBuf, hash: &[u8; 20], git_mode: bool) -> Result<Object> {
+pub fn get_object(root: &Path, hash: &[u8; 20], git_mode: bool) -> Result<Object> {
     match search_object(root, hash, git_mode) {
         Ok(Some(x)) => Ok(x),
         Ok(None) => bail!("Object {} not found in store", String::from_utf8_lossy(hash)),
@@ -244,37 +646,156 @@ pub fn get_object(root: &PathBuf, hash: &[u8; 20], git_mode: bool) -> Result<Obj
     }
 }
 
+/// Whether the store has the object with the given hash, loose or in a pack, without reading it
+pub fn object_exists(root: &Path, hash: &[u8; 20], git_mode: bool) -> Result<bool> {
+    if *hash == EMPTY_TREE_HASH || *hash == EMPTY_BLOB_HASH {
+        return Ok(true);
+    }
+    FileStore::new(root, git_mode)?.contains(hash)
+}
 
-// Returns the decompressed contents of the object with the given hash, or None
+/// Retrieves the contents of the blob with the given hash, or an Err if it doesn't exist or isn't a blob
+pub fn get_blob(root: &Path, hash: &[u8; 20], git_mode: bool) -> Result<Vec<u8>> {
+    match get_object(root, hash, git_mode)? {
+        Object::Blob(blob) => Ok(blob.bytes),
+        _ => bail!("fatal: object {} is not a blob", hex::encode(hash))
+    }
+}
+
+
+// Returns the decompressed contents of the object with the given hash, whether it's loose or in a pack, or None
 // if the object does not exist, or an error if the object exists but decompression fails
-pub fn read_object_raw(root: &PathBuf, hash: &[u8; 20], git_mode: bool) -> Result<Option<Vec<u8>>> {
-    if hash.len() < 3 {
-        return Ok(None);
+pub fn read_object_raw(root: &Path, hash: &[u8; 20], git_mode: bool) -> Result<Option<Vec<u8>>> {
+    FileStore::new(root, git_mode)?.read_raw(hash)
+}
+
+/// Returns the path at which the loose object with the given hash is stored, whether or not it exists
+pub fn object_path(root: &Path, hash: &[u8; 20], git_mode: bool) -> PathBuf {
+    let git_dir = if git_mode { ".git" } else { ".grit" };
+    let hash_str = hex::encode(hash);
+    root.join(format!("{}/objects/{}/{}", git_dir, &hash_str[0..2], &hash_str[2..]))
+}
+
+/// Returns the hashes of all loose objects in the store
+pub fn loose_objects(root: &Path, git_mode: bool) -> Result<Vec<[u8; 20]>> {
+    let git_dir = if git_mode { ".git" } else { ".grit" };
+    loose_objects_in(&root.join(format!("{}/objects", git_dir)))
+}
+
+// Returns the hashes of the loose objects in an objects directory
+fn loose_objects_in(objects_dir: &Path) -> Result<Vec<[u8; 20]>> {
+    let mut hashes = Vec::new();
+    for entry in fs::read_dir(objects_dir)? {
+        let entry = entry?;
+        let dir_name = entry.file_name().to_string_lossy().to_string();
+        if dir_name.len() != 2 || !entry.file_type()?.is_dir() {
+            continue;
+        }
+        for file in fs::read_dir(entry.path())? {
+            let file_name = file?.file_name().to_string_lossy().to_string();
+            if let Ok(hash) = parse_hash(&format!("{}{}", dir_name, file_name)) {
+                hashes.push(hash);
+            }
+        }
+    }
+
+    Ok(hashes)
+}
+
+/// Returns the hashes of every object in the store, whether loose or packed, in order and without duplicates
+pub fn all_objects(root: &Path, git_mode: bool) -> Result<Vec<[u8; 20]>> {
+    Ok(FileStore::new(root, git_mode)?.iter()?.collect())
+}
+
+/// Calls `visit` with the path and entry of every file in the tree with the given hash, in the order the tree lists
+/// them, descending into each subtree in its place. Paths are relative to the tree.
+pub fn walk_tree(
+    root: &Path,
+    tree_hash: &[u8; 20],
+    git_mode: bool,
+    visit: &mut impl FnMut(PathBuf, TreeEntry) -> Result<()>
+) -> Result<()> {
+    walk_subtree(root, tree_hash, Path::new(""), git_mode, visit)
+}
+
+// Visits the files of a tree for `walk_tree`, where `prefix` is the tree's path within the tree being walked
+fn walk_subtree(
+    root: &Path,
+    tree_hash: &[u8; 20],
+    prefix: &Path,
+    git_mode: bool,
+    visit: &mut impl FnMut(PathBuf, TreeEntry) -> Result<()>
+) -> Result<()> {
+    let Object::Tree(tree) = get_object(root, tree_hash, git_mode)? else {
+        bail!("fatal: object {} is not a tree", hex::encode(tree_hash));
+    };
+    for entry in tree.children {
+        let path = prefix.join(&entry.name);
+        if entry.mode == 0o40000 {
+            walk_subtree(root, &entry.hash, &path, git_mode, visit)?;
+        } else {
+            visit(path, entry)?;
+        }
     }
+    Ok(())
+}
 
+/// Abbreviates a hash to the shortest prefix that no other object in the store starts with, and no shorter than
+/// `core.abbrev`, which can be `no` for the full hash. Like Git, the length defaults to one that grows with the number
+/// of objects in packs, from 7.
+pub fn short_hash(root: &Path, hash: &[u8; 20], global_opts: GlobalOpts) -> Result<String> {
+    let git_mode = global_opts.git_mode;
     let git_dir = if git_mode { ".git" } else { ".grit" };
+    let packed = packed_objects(&root.join(git_dir).join("objects").join("pack"))?;
+    let min_length = match Config::load(root, global_opts)?.get("core.abbrev").as_deref() {
+        // Prefixes of n objects are expected to collide at about log2(n) / 2 bits, with 4 bits to a hex digit
+        None | Some("auto") => {
+            let bits = (usize::BITS - packed.len().leading_zeros()) as usize;
+            bits.div_ceil(2).max(DEFAULT_ABBREV)
+        },
+        Some(value) if value.eq_ignore_ascii_case("no") => 40,
+        Some(value) => match value.parse::<usize>() {
+            Ok(length) if length >= MIN_ABBREV => length.min(40),
+            _ => bail!("fatal: abbrev length out of range: {}", value)
+        }
+    };
 
-    let hash_str = hex::encode(&hash);
+    // Only loose objects in the same directory can share even the first two digits
+    let hash_str = hex::encode(hash);
+    let others = find_objects_by_prefix(root, &hash_str[..2], git_mode)?.into_iter().chain(packed);
+    let unique_length = others.filter(|x| x != hash).map(|x| common_hex_digits(&x, hash) + 1).max().unwrap_or(0);
+    Ok(hash_str[..min_length.max(unique_length).min(40)].to_string())
+}
 
-    let full_path = root.join(format!(
-        "{}/objects/{}/{}", 
-        git_dir, 
-        &hash_str[0..2], 
-        &hash_str[2..]
-    ));
+// The number of hex digits two hashes have in common at the start
+fn common_hex_digits(a: &[u8; 20], b: &[u8; 20]) -> usize {
+    match a.iter().zip(b).position(|(x, y)| x != y) {
+        Some(i) => i * 2 + usize::from(a[i] >> 4 == b[i] >> 4),
+        None => 40
+    }
+}
 
-    if !full_path.exists() {
-        return Ok(None);
+/// Returns the hashes of all loose objects whose hex representation starts with the given prefix.
+/// The prefix must be lowercase hex and at least two characters long.
+pub fn find_objects_by_prefix(root: &Path, prefix: &str, git_mode: bool) -> Result<Vec<[u8; 20]>> {
+    let git_dir = if git_mode { ".git" } else { ".grit" };
+    let dir = root.join(format!("{}/objects/{}", git_dir, &prefix[..2]));
+
+    let mut matches = Vec::new();
+    if !dir.is_dir() {
+        return Ok(matches);
     }
 
-    // Read and decompress the requested file
-    let bytes = fs::read(full_path)?;
-    let mut z = ZlibDecoder::new(&bytes[..]);
-    
-    let mut buf = Vec::<u8>::new();
-    z.read_to_end(&mut buf)?;
+    for entry in fs::read_dir(dir)? {
+        let file_name = entry?.file_name().to_string_lossy().to_string();
+        if file_name.starts_with(&prefix[2..]) {
+            if let Ok(hash) = parse_hash(&format!("{}{}", &prefix[..2], file_name)) {
+                matches.push(hash);
+            }
+        }
+    }
 
-    Ok(Some(buf))
+    Ok(matches)
 }
 
 enum ParseState {
@@ -285,12 +806,13 @@ enum ParseState {
     InMessage
 }
 
-pub fn parse_commit(commit_text: &String) -> Result<Commit> {
+pub fn parse_commit(commit_text: &str) -> Result<Commit> {
     let mut buffer = String::from("");
     let mut current_key: Option<String> = Some(String::from(""));
     let mut state = ParseState::InKey;
 
     let mut tags = HashMap::<String, String>::new();
+    let mut parents = Vec::<String>::new();
     
     for c in commit_text.chars() {
         match state {
@@ -300,6 +822,12 @@ pub fn parse_commit(commit_text: &String) -> Result<Commit> {
                         buffer.clear();
                         state = ParseState::InMessage;
                     },
+                    ' ' => {
+                        // A continuation line, which adds another line to the previous value
+                        let previous = current_key.as_ref().and_then(|x| tags.get(x)).cloned().unwrap_or_default();
+                        buffer = previous + "\n";
+                        state = ParseState::InValue;
+                    },
                     _ => {
                         buffer.clear();
                         buffer.push(c);
@@ -333,7 +861,12 @@ pub fn parse_commit(commit_text: &String) -> Result<Commit> {
                     '\n' => {
                         // End of value
                         if let Some(ref key) = current_key {
-                            tags.insert(key.to_string(), buffer.clone());
+                            // A merge commit has one parent line per parent, so these are collected separately
+                            if key == "parent" {
+                                parents.push(buffer.clone());
+                            } else {
+                                tags.insert(key.to_string(), buffer.clone());
+                            }
                             state = ParseState::BeforeKey;
                         } else {
                             bail!("invalid commit text");
@@ -352,10 +885,7 @@ pub fn parse_commit(commit_text: &String) -> Result<Commit> {
     
     let message = buffer;
 
-    let parent = match tags.get("parent") {
-        Some(hash) => Some(parse_hash(hash)?),
-        None => None
-    };
+    let parents = parents.iter().map(|x| parse_hash(x)).collect::<Result<Vec<[u8; 20]>>>()?;
 
     let tree = parse_hash(tags.get("tree").unwrap())?;
 
@@ -363,14 +893,40 @@ pub fn parse_commit(commit_text: &String) -> Result<Commit> {
     Ok(Commit {
         author: tags.get("author").unwrap().to_string(),
         committer: tags.get("committer").unwrap().to_string(),
-        date: tags.get("date").cloned(),
-        parent,
+        parents,
         tree,
         message,
+        gpgsig: tags.get("gpgsig").cloned(),
     })
 }
 
-fn parse_tree(bytes: &[u8]) -> Result<Tree> {
+pub fn parse_tag(tag_text: &str) -> Result<Tag> {
+    let (headers, message) = tag_text.split_once("\n\n").unwrap_or((tag_text, ""));
+
+    let mut object = None;
+    let mut object_type = None;
+    let mut name = None;
+    let mut tagger = None;
+    for line in headers.lines() {
+        match line.split_once(' ') {
+            Some(("object", value)) => object = Some(parse_hash(value)?),
+            Some(("type", value)) => object_type = Some(ObjectType::from_bytes(value.as_bytes())),
+            Some(("tag", value)) => name = Some(value.to_string()),
+            Some(("tagger", value)) => tagger = Some(value.to_string()),
+            _ => ()
+        }
+    }
+
+    Ok(Tag {
+        object: object.ok_or(anyhow!("error parsing tag: missing object"))?,
+        object_type: object_type.ok_or(anyhow!("error parsing tag: missing type"))?,
+        name: name.ok_or(anyhow!("error parsing tag: missing tag name"))?,
+        tagger,
+        message: message.to_string(),
+    })
+}
+
+pub fn parse_tree(bytes: &[u8]) -> Result<Tree> {
     let mut nodes = Vec::new();
     let mut pos: usize = 0;
     let max = bytes.len();
@@ -392,8 +948,10 @@ fn parse_tree_node(bytes: &[u8], pos: &mut usize) -> Result<TreeEntry> {
             "error parsing tree: missing space terminator for file mode"
         ))?;
 
-    // Read the mode
-    let mode = u32::from_be_bytes(remainder[..mode_end].try_into().unwrap());
+    // Read the mode, which is stored as an ASCII representation of the octal value
+    let mode_str = String::from_utf8_lossy(&remainder[..mode_end]);
+    let mode = u32::from_str_radix(&mode_str, 8)
+        .map_err(|_| anyhow!("error parsing tree: invalid file mode {}", mode_str))?;
 
     // Find the NULL terminator of the path
     let path_end = remainder.iter().position(|x| x == &0)
@@ -414,4 +972,29 @@ fn parse_tree_node(bytes: &[u8], pos: &mut usize) -> Result<TreeEntry> {
         name: path_str,
         hash
     })
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn memory_store_reads_back_written_objects() -> Result<()> {
+        let store = MemoryStore::default();
+        let hello = Blob { bytes: b"hello\n".to_vec() };
+        let empty = Blob { bytes: Vec::new() };
+
+        assert!(!store.contains(&hello.hash())?);
+        assert_eq!(store.read_raw(&hello.hash())?, None);
+        assert!(store.write(&hello.hash(), &hello.content_with_header())?);
+        assert!(store.write(&empty.hash(), &empty.content_with_header())?);
+        // Writing an object the store has leaves it as it is
+        assert!(!store.write(&hello.hash(), &hello.content_with_header())?);
+
+        assert!(store.contains(&hello.hash())?);
+        assert_eq!(store.read_raw(&hello.hash())?, Some(b"blob 6\0hello\n".to_vec()));
+        assert_eq!(hex::encode(hello.hash()), "ce013625030ba8dba906f756967f9e9ca394464a");
+        assert_eq!(store.iter()?.collect::<Vec<_>>(), vec![hello.hash(), EMPTY_BLOB_HASH]);
+        Ok(())
+    }
+}
@@ -0,0 +1,628 @@
+// Reading and writing of packs and their index (.idx) files
+
+use std::{cell::OnceCell, collections::HashMap, fs::{self, File}, io::{Read, Write}, path::{Path, PathBuf}};
+use anyhow::{anyhow, bail, Result};
+use flate2::{bufread::ZlibDecoder, write::ZlibEncoder, Compression, Crc};
+use memmap2::Mmap;
+use sha1::{Digest, Sha1};
+
+use crate::objects::{parse_object_header, read_object_raw, ObjectType};
+
+/// How many bytes of objects a `DeltaBaseCache` holds at most, the same as Git's default `core.deltaBaseCacheLimit`
+const DELTA_BASE_CACHE_LIMIT: usize = 96 * 1024 * 1024;
+/// How many deltas long a chain can be before the pack is taken to be bad. Git never makes chains deeper than 4095,
+/// whatever `--depth` it's given, so this only stops a circular chain in a corrupt pack being followed forever.
+const MAX_DELTA_DEPTH: usize = 10000;
+
+/// Returns the hashes of all objects listed in the pack index file at the given path.
+/// Both version 1 and version 2 index files are supported.
+pub fn read_index_hashes(idx_path: &Path) -> Result<Vec<[u8; 20]>> {
+    index_hashes(&fs::read(idx_path)?, idx_path)
+}
+
+// Returns the hashes of all objects listed in a pack index, which was read from the file at `idx_path`
+fn index_hashes(bytes: &[u8], idx_path: &Path) -> Result<Vec<[u8; 20]>> {
+    // Version 2 files start with a magic number and version, while version 1 files start directly with the fanout
+    let version2 = bytes.starts_with(b"\xfftOc");
+    let fanout_start = if version2 { 8 } else { 0 };
+    if bytes.len() < fanout_start + 256 * 4 {
+        bail!("pack index {} is truncated", idx_path.to_string_lossy());
+    }
+
+    // The last entry of the fanout table is the total number of objects
+    let last = fanout_start + 255 * 4;
+    let count = u32::from_be_bytes(bytes[last..last + 4].try_into().unwrap()) as usize;
+    let table_start = fanout_start + 256 * 4;
+
+    // Version 2 lists the hashes contiguously. Version 1 precedes each hash with a 4 byte offset.
+    let (entry_size, hash_offset) = if version2 { (20, 0) } else { (24, 4) };
+    if bytes.len() < table_start + count * entry_size {
+        bail!("pack index {} is truncated", idx_path.to_string_lossy());
+    }
+
+    let hashes = (0..count)
+        .map(|i| {
+            let start = table_start + i * entry_size + hash_offset;
+            bytes[start..start + 20].try_into().unwrap()
+        })
+        .collect();
+
+    Ok(hashes)
+}
+
+/// Returns the hashes of the objects in every pack in the given pack directory. A pack's objects are only counted if it
+/// has both its `.pack` and `.idx` files. An object may be listed more than once if it is in several packs.
+pub fn packed_objects(pack_dir: &Path) -> Result<Vec<[u8; 20]>> {
+    let mut hashes = Vec::new();
+    for pack in open_packs(pack_dir)? {
+        hashes.extend(pack.hashes()?);
+    }
+    Ok(hashes)
+}
+
+/// A pack and its index, mapped into memory once so that any number of objects can be looked up and read from them
+/// without opening or reading the files again
+pub struct PackFile {
+    path: PathBuf,
+    pack: Mmap,
+    idx: Mmap,
+    /// The offset of each entry in the pack with the hash of its object, in order of offset, listed the first time
+    /// they're needed
+    entries: OnceCell<Vec<(usize, [u8; 20])>>,
+}
+
+/// Opens every pack in the given pack directory that has both its `.pack` and `.idx` files, in order of name
+pub fn open_packs(pack_dir: &Path) -> Result<Vec<PackFile>> {
+    let mut idx_paths = Vec::new();
+    if pack_dir.is_dir() {
+        for entry in fs::read_dir(pack_dir)? {
+            let idx_path = entry?.path();
+            if idx_path.extension().is_some_and(|x| x == "idx") && idx_path.with_extension("pack").exists() {
+                idx_paths.push(idx_path);
+            }
+        }
+    }
+    idx_paths.sort();
+
+    let mut packs = Vec::new();
+    for idx_path in idx_paths {
+        let path = idx_path.with_extension("pack");
+        packs.push(PackFile { pack: map_file(&path)?, idx: map_file(&idx_path)?, path, entries: OnceCell::new() });
+    }
+    Ok(packs)
+}
+
+// Maps the file at the given path into memory
+fn map_file(path: &Path) -> Result<Mmap> {
+    let file = File::open(path)?;
+    // Packs and their indexes are never changed once written, only replaced or deleted, and a deleted file stays
+    // mapped until the map is dropped, so the mapped bytes can't change underneath us
+    let map = unsafe { Mmap::map(&file)? };
+    Ok(map)
+}
+
+impl PackFile {
+    /// The hashes of the objects in the pack, going by its index
+    pub fn hashes(&self) -> Result<Vec<[u8; 20]>> {
+        index_hashes(&self.idx, &self.path.with_extension("idx"))
+    }
+
+    /// Whether the pack has the object with the given hash, going by its index
+    pub fn contains(&self, hash: &[u8; 20]) -> Result<bool> {
+        index_contains(&self.idx, hash)
+    }
+
+    /// Reads an object from the pack, returning its type and size header followed by its contents, as a loose object
+    /// reads once decompressed. Objects stored as deltas are rebuilt from their bases, which may be anywhere in the
+    /// store of the repository at `root`. Bases rebuilt along the way are kept in `cache`. Returns None if the pack
+    /// doesn't have the object.
+    pub fn read_object(
+        &self,
+        root: &Path,
+        hash: &[u8; 20],
+        git_mode: bool,
+        cache: &mut DeltaBaseCache
+    ) -> Result<Option<Vec<u8>>> {
+        let Some(offset) = find_offset(&self.idx, hash)? else {
+            return Ok(None);
+        };
+        let (object_type, contents) = read_pack_entry(root, self, offset, git_mode, cache)
+            .map_err(|e| anyhow!("{} in {}", e, self.path.to_string_lossy()))?;
+        let header = format!("{} {}\0", object_type, contents.len());
+        Ok(Some([header.as_bytes(), &contents].concat()))
+    }
+
+    /// Describes how the pack keeps an object, or returns None if the pack doesn't have it
+    pub fn entry(&self, hash: &[u8; 20]) -> Result<Option<PackedEntry>> {
+        let Some(offset) = find_offset(&self.idx, hash)? else {
+            return Ok(None);
+        };
+
+        // Entries are stored one after another, so each ends where the next begins, and the last where the pack's
+        // checksum begins
+        let entries = self.entries()?;
+        let next = entries.partition_point(|(x, _)| *x <= offset);
+        let end = entries.get(next).map(|(x, _)| *x).unwrap_or(self.pack.len().saturating_sub(20));
+
+        let byte = |i: usize| self.pack.get(i).copied().ok_or(anyhow!("object at offset {} is truncated", offset));
+        let mut position = offset;
+        let object_type = (byte(position)? >> 4) & 7;
+        while byte(position)? & 0x80 != 0 {
+            position += 1;
+        }
+        position += 1;
+        let delta_base = match object_type {
+            6 => {
+                let mut c = byte(position)?;
+                let mut distance = (c & 0x7f) as usize;
+                while c & 0x80 != 0 {
+                    position += 1;
+                    c = byte(position)?;
+                    distance = ((distance + 1) << 7) | (c & 0x7f) as usize;
+                }
+                let base = offset.checked_sub(distance)
+                    .and_then(|x| entries.binary_search_by_key(&x, |(offset, _)| *offset).ok())
+                    .ok_or(anyhow!("object at offset {} has a bad delta base", offset))?;
+                Some(entries[base].1)
+            },
+            7 => Some(self.pack.get(position..position + 20)
+                .ok_or(anyhow!("object at offset {} is truncated", offset))?
+                .try_into().unwrap()),
+            _ => None
+        };
+        Ok(Some(PackedEntry { disk_size: end - offset, delta_base }))
+    }
+
+    // The offset of each entry in the pack with the hash of its object, in order of offset
+    fn entries(&self) -> Result<&[(usize, [u8; 20])]> {
+        if self.entries.get().is_none() {
+            let mut entries = self.hashes()?.into_iter().enumerate()
+                .map(|(i, hash)| Ok((entry_offset(&self.idx, i)?, hash)))
+                .collect::<Result<Vec<(usize, [u8; 20])>>>()?;
+            entries.sort();
+            let _ = self.entries.set(entries);
+        }
+        Ok(self.entries.get().unwrap())
+    }
+}
+
+/// Whether a pack has an object, going by the contents of the pack's index
+pub fn index_contains(idx: &[u8], hash: &[u8; 20]) -> Result<bool> {
+    Ok(find_offset(idx, hash)?.is_some())
+}
+
+/// Checks a pack and its index before they're installed from somewhere that can't be trusted. Each ends with a
+/// checksum of what comes before it, and the index also holds the checksum of the pack it was made for.
+pub fn check_pack(name: &str, pack: &[u8], idx: &[u8]) -> Result<()> {
+    let trailer = |bytes: &[u8]| {
+        let (body, checksum) = bytes.split_at(bytes.len().checked_sub(20)?);
+        (Sha1::digest(body).as_slice() == checksum).then(|| checksum.to_vec())
+    };
+    let pack_checksum = pack.starts_with(b"PACK").then(|| trailer(pack)).flatten()
+        .ok_or(anyhow!("fatal: pack {} is corrupt", name))?;
+    if trailer(idx).is_none() || idx.len() < 40 || idx[idx.len() - 40..idx.len() - 20] != pack_checksum[..] {
+        bail!("fatal: index for pack {} does not match the pack", name);
+    }
+    Ok(())
+}
+
+// Looks up the offset of an object in a pack from the pack's index, or None if the pack doesn't have it
+fn find_offset(idx: &[u8], hash: &[u8; 20]) -> Result<Option<usize>> {
+    let version2 = idx.starts_with(b"\xfftOc");
+    let fanout_start = if version2 { 8 } else { 0 };
+    let read_u32 = |start: usize| -> Result<u32> {
+        let bytes = idx.get(start..start + 4).ok_or(anyhow!("pack index is truncated"))?;
+        Ok(u32::from_be_bytes(bytes.try_into().unwrap()))
+    };
+
+    // The fanout table counts the objects whose hashes start with each byte or less, which bounds the search
+    let first = hash[0] as usize;
+    let mut low = if first == 0 { 0 } else { read_u32(fanout_start + (first - 1) * 4)? as usize };
+    let mut high = read_u32(fanout_start + first * 4)? as usize;
+    let table_start = fanout_start + 256 * 4;
+    let (entry_size, hash_offset) = if version2 { (20, 0) } else { (24, 4) };
+
+    while low < high {
+        let middle = (low + high) / 2;
+        let start = table_start + middle * entry_size + hash_offset;
+        let entry = idx.get(start..start + 20).ok_or(anyhow!("pack index is truncated"))?;
+        match entry.cmp(&hash[..]) {
+            std::cmp::Ordering::Less => low = middle + 1,
+            std::cmp::Ordering::Greater => high = middle,
+            std::cmp::Ordering::Equal => return Ok(Some(entry_offset(idx, middle)?))
+        }
+    }
+    Ok(None)
+}
+
+// Reads the offset in the pack of the object at the given position in the index
+fn entry_offset(idx: &[u8], position: usize) -> Result<usize> {
+    let version2 = idx.starts_with(b"\xfftOc");
+    let fanout_start = if version2 { 8 } else { 0 };
+    let read_u32 = |start: usize| -> Result<u32> {
+        let bytes = idx.get(start..start + 4).ok_or(anyhow!("pack index is truncated"))?;
+        Ok(u32::from_be_bytes(bytes.try_into().unwrap()))
+    };
+    let table_start = fanout_start + 256 * 4;
+    if !version2 {
+        return Ok(read_u32(table_start + position * 24)? as usize);
+    }
+
+    // Version 2 keeps the offsets after the hashes and their checksums. Offsets too large for 31 bits are kept in a
+    // further table of 8 byte offsets.
+    let count = read_u32(fanout_start + 255 * 4)? as usize;
+    let offsets_start = table_start + count * 24;
+    let offset = read_u32(offsets_start + position * 4)?;
+    if offset & 0x80000000 == 0 {
+        return Ok(offset as usize);
+    }
+    let large_start = offsets_start + count * 4 + (offset & 0x7fffffff) as usize * 8;
+    let high_bits = read_u32(large_start)? as u64;
+    let low_bits = read_u32(large_start + 4)? as u64;
+    Ok(((high_bits << 32) | low_bits) as usize)
+}
+
+/// How a pack keeps an object
+pub struct PackedEntry {
+    /// The number of bytes the object's entry takes in the pack, including its header
+    pub disk_size: usize,
+    /// The object this one is stored as a delta against, if it's stored as one
+    pub delta_base: Option<[u8; 20]>,
+}
+
+/// Objects rebuilt while reading packs that other objects are stored as deltas against, kept so that reading several
+/// objects with the same base only rebuilds the base once. Like Git's, the cache holds so many bytes at most, and is
+/// emptied when it would grow past that.
+#[derive(Default)]
+pub struct DeltaBaseCache {
+    bases: HashMap<(PathBuf, usize), (ObjectType, Vec<u8>)>,
+    size: usize,
+}
+
+impl DeltaBaseCache {
+    fn get(&self, pack_path: &Path, offset: usize) -> Option<&(ObjectType, Vec<u8>)> {
+        self.bases.get(&(pack_path.to_path_buf(), offset))
+    }
+
+    fn insert(&mut self, pack_path: &Path, offset: usize, base: (ObjectType, Vec<u8>)) {
+        if base.1.len() > DELTA_BASE_CACHE_LIMIT {
+            return;
+        }
+        if self.size + base.1.len() > DELTA_BASE_CACHE_LIMIT {
+            self.bases.clear();
+            self.size = 0;
+        }
+        self.size += base.1.len();
+        if let Some(old) = self.bases.insert((pack_path.to_path_buf(), offset), base) {
+            self.size -= old.1.len();
+        }
+    }
+}
+
+// How an entry of a pack stores its object
+enum StoredEntry {
+    /// The object's type name and contents
+    Whole(ObjectType, Vec<u8>),
+    /// A delta against the object at the given offset of the same pack
+    OffsetDelta(usize, Vec<u8>),
+    /// A delta against the object with the given hash
+    RefDelta([u8; 20], Vec<u8>),
+}
+
+// Reads the object at the given offset of a pack, returning its type and contents. An object stored as a delta
+// is rebuilt by following the chain of deltas down to an object stored whole, then applying them back up in turn.
+// Chains are followed one step at a time rather than recursively, and no more than `MAX_DELTA_DEPTH` steps, so that a
+// circular chain in a bad pack gives an error.
+fn read_pack_entry(
+    root: &Path,
+    pack: &PackFile,
+    offset: usize,
+    git_mode: bool,
+    cache: &mut DeltaBaseCache
+) -> Result<(ObjectType, Vec<u8>)> {
+    let mut deltas = Vec::new();
+    let mut position = offset;
+    let (object_type, mut data) = loop {
+        if let Some(base) = cache.get(&pack.path, position) {
+            break base.clone();
+        }
+        match read_stored_entry(&pack.pack, position)? {
+            StoredEntry::Whole(object_type, data) => {
+                // An object stored whole is only worth keeping if it's a delta's base
+                if !deltas.is_empty() {
+                    cache.insert(&pack.path, position, (object_type.clone(), data.clone()));
+                }
+                break (object_type, data);
+            },
+            StoredEntry::OffsetDelta(base_offset, delta) => {
+                deltas.push((position, delta));
+                position = base_offset;
+            },
+            StoredEntry::RefDelta(base_hash, delta) => {
+                deltas.push((position, delta));
+                match find_offset(&pack.idx, &base_hash)? {
+                    Some(base_offset) => position = base_offset,
+                    None => {
+                        let Some(bytes) = read_object_raw(root, &base_hash, git_mode)? else {
+                            bail!("missing delta base {}", hex::encode(base_hash));
+                        };
+                        let type_end = bytes.iter().position(|x| *x == b' ').ok_or(anyhow!("bad delta base"))?;
+                        let header_end = bytes.iter().position(|x| *x == 0).ok_or(anyhow!("bad delta base"))?;
+                        break (ObjectType::from_bytes(&bytes[..type_end]), bytes[header_end + 1..].to_vec());
+                    }
+                }
+            }
+        }
+        if deltas.len() > MAX_DELTA_DEPTH {
+            bail!("object at offset {} has a delta chain longer than {}", offset, MAX_DELTA_DEPTH);
+        }
+    };
+
+    // Every object rebuilt on the way back up, except the one asked for, is the base of the next
+    while let Some((position, delta)) = deltas.pop() {
+        data = apply_delta(&data, &delta)?;
+        if !deltas.is_empty() {
+            cache.insert(&pack.path, position, (object_type.clone(), data.clone()));
+        }
+    }
+    Ok((object_type, data))
+}
+
+// Reads the entry at the given offset of a pack as it's stored, without rebuilding it if it's a delta
+fn read_stored_entry(pack: &[u8], offset: usize) -> Result<StoredEntry> {
+    let byte = |i: usize| pack.get(i).copied().ok_or(anyhow!("object at offset {} is truncated", offset));
+
+    // The header gives the type in bits 4-6 of the first byte, and the size in the remaining bits, continuing into
+    // the following bytes while their top bit is set
+    let mut position = offset;
+    let mut c = byte(position)?;
+    let object_type = (c >> 4) & 7;
+    let mut size = (c & 0x0f) as usize;
+    let mut shift = 4;
+    while c & 0x80 != 0 {
+        position += 1;
+        c = byte(position)?;
+        size |= ((c & 0x7f) as usize) << shift;
+        shift += 7;
+    }
+    position += 1;
+
+    let mut base_offset = None;
+    let mut base_hash = None;
+    match object_type {
+        // A delta against an earlier object in the pack, given by its distance back from this one
+        6 => {
+            let mut c = byte(position)?;
+            let mut distance = (c & 0x7f) as usize;
+            while c & 0x80 != 0 {
+                position += 1;
+                c = byte(position)?;
+                distance = ((distance + 1) << 7) | (c & 0x7f) as usize;
+            }
+            position += 1;
+            base_offset = Some(offset.checked_sub(distance).filter(|_| distance > 0)
+                .ok_or(anyhow!("object at offset {} has a bad delta base", offset))?);
+        },
+        // A delta against an object given by its hash, which may be anywhere in the store
+        7 => {
+            base_hash = Some(pack.get(position..position + 20)
+                .ok_or(anyhow!("object at offset {} is truncated", offset))?
+                .try_into().unwrap());
+            position += 20;
+        },
+        _ => ()
+    };
+
+    let mut data = Vec::new();
+    ZlibDecoder::new(pack.get(position..).unwrap_or_default()).read_to_end(&mut data)?;
+    if data.len() != size {
+        bail!("object at offset {} is corrupt", offset);
+    }
+
+    match (object_type, base_offset, base_hash) {
+        (1, _, _) => Ok(StoredEntry::Whole(ObjectType::Commit, data)),
+        (2, _, _) => Ok(StoredEntry::Whole(ObjectType::Tree, data)),
+        (3, _, _) => Ok(StoredEntry::Whole(ObjectType::Blob, data)),
+        (4, _, _) => Ok(StoredEntry::Whole(ObjectType::Tag, data)),
+        (_, Some(base_offset), _) => Ok(StoredEntry::OffsetDelta(base_offset, data)),
+        (_, _, Some(base_hash)) => Ok(StoredEntry::RefDelta(base_hash, data)),
+        _ => bail!("object at offset {} has unknown type {}", offset, object_type)
+    }
+}
+
+// Rebuilds an object from its base and a delta, which is a list of instructions to either copy a range of the base or
+// insert new bytes
+fn apply_delta(base: &[u8], delta: &[u8]) -> Result<Vec<u8>> {
+    let mut position = 0;
+    let mut read_size = || -> Result<usize> {
+        let mut size = 0;
+        let mut shift = 0;
+        loop {
+            let c = *delta.get(position).ok_or(anyhow!("delta is truncated"))?;
+            position += 1;
+            size |= ((c & 0x7f) as usize) << shift;
+            shift += 7;
+            if c & 0x80 == 0 {
+                return Ok(size);
+            }
+        }
+    };
+    let base_size = read_size()?;
+    let result_size = read_size()?;
+    if base_size != base.len() {
+        bail!("delta applies to an object of the wrong size");
+    }
+
+    let mut result = Vec::with_capacity(result_size);
+    while position < delta.len() {
+        let instruction = delta[position];
+        position += 1;
+        if instruction & 0x80 != 0 {
+            // The low four bits say which bytes of the offset follow, and the next three which bytes of the size
+            let mut fields = [0usize; 7];
+            for (i, field) in fields.iter_mut().enumerate() {
+                if instruction & (1 << i) != 0 {
+                    *field = *delta.get(position).ok_or(anyhow!("delta is truncated"))? as usize;
+                    position += 1;
+                }
+            }
+            let copy_offset = fields[0] | fields[1] << 8 | fields[2] << 16 | fields[3] << 24;
+            let copy_size = match fields[4] | fields[5] << 8 | fields[6] << 16 {
+                0 => 0x10000,
+                n => n
+            };
+            let range = base.get(copy_offset..copy_offset + copy_size).ok_or(anyhow!("delta copies past its base"))?;
+            result.extend_from_slice(range);
+        } else if instruction != 0 {
+            let length = instruction as usize;
+            let range = delta.get(position..position + length).ok_or(anyhow!("delta is truncated"))?;
+            result.extend_from_slice(range);
+            position += length;
+        } else {
+            bail!("delta has an invalid instruction");
+        }
+    }
+    if result.len() != result_size {
+        bail!("delta produced an object of the wrong size");
+    }
+    Ok(result)
+}
+
+/// Writes a pack holding the given objects to the pack directory, along with its version 2 index, and returns the
+/// pack's name, e.g. `pack-<checksum>`. Each object is given as the bytes it's hashed from, header included, and is
+/// stored whole rather than as a delta. As in Git, the pack is named after the checksum that ends it.
+pub fn write_pack(pack_dir: &Path, objects: &[([u8; 20], Vec<u8>)]) -> Result<String> {
+    let mut pack = Vec::from(*b"PACK");
+    pack.extend(2u32.to_be_bytes());
+    pack.extend((objects.len() as u32).to_be_bytes());
+
+    // The index needs the offset of each object's entry, and a checksum of the entry as it's stored
+    let mut entries = Vec::new();
+    for (hash, bytes) in objects {
+        let (type_name, size, header_len) = parse_object_header(bytes)?;
+        let type_number: u8 = match ObjectType::from_bytes(type_name) {
+            ObjectType::Commit => 1,
+            ObjectType::Tree => 2,
+            ObjectType::Blob => 3,
+            ObjectType::Tag => 4,
+            ObjectType::Unknown(_) => bail!("fatal: object {} has an unknown type", hex::encode(hash))
+        };
+
+        // The header holds the type and the low 4 bits of the size, then the size continues 7 bits to a byte for as
+        // long as the top bit is set
+        let mut entry = Vec::new();
+        let mut byte = (type_number << 4) | (size & 0x0f) as u8;
+        let mut rest = size >> 4;
+        while rest > 0 {
+            entry.push(byte | 0x80);
+            byte = (rest & 0x7f) as u8;
+            rest >>= 7;
+        }
+        entry.push(byte);
+        let mut encoder = ZlibEncoder::new(entry, Compression::default());
+        encoder.write_all(&bytes[header_len..])?;
+        let entry = encoder.finish()?;
+
+        let mut crc = Crc::new();
+        crc.update(&entry);
+        entries.push((*hash, pack.len() as u64, crc.sum()));
+        pack.extend(entry);
+    }
+    let pack_checksum: [u8; 20] = Sha1::digest(&pack).into();
+    pack.extend(pack_checksum);
+
+    // The index lists the objects in order of hash, after a fanout table counting those that start with each byte or
+    // less. Offsets that don't fit in 31 bits are kept in a table of 8 byte offsets after the others.
+    entries.sort_by_key(|(hash, _, _)| *hash);
+    let mut idx = Vec::from(*b"\xfftOc");
+    idx.extend(2u32.to_be_bytes());
+    for first in 0..=255u8 {
+        idx.extend((entries.iter().filter(|(hash, _, _)| hash[0] <= first).count() as u32).to_be_bytes());
+    }
+    for (hash, _, _) in &entries {
+        idx.extend(hash);
+    }
+    for (_, _, crc) in &entries {
+        idx.extend(crc.to_be_bytes());
+    }
+    let mut large_offsets = Vec::new();
+    for (_, offset, _) in &entries {
+        if *offset < 0x80000000 {
+            idx.extend((*offset as u32).to_be_bytes());
+        } else {
+            idx.extend((0x80000000 | large_offsets.len() as u32).to_be_bytes());
+            large_offsets.push(*offset);
+        }
+    }
+    for offset in large_offsets {
+        idx.extend(offset.to_be_bytes());
+    }
+    idx.extend(pack_checksum);
+    let idx_checksum: [u8; 20] = Sha1::digest(&idx).into();
+    idx.extend(idx_checksum);
+
+    // The index is written last, as a pack is only used once it has one
+    let name = format!("pack-{}", hex::encode(pack_checksum));
+    fs::create_dir_all(pack_dir)?;
+    fs::write(pack_dir.join(format!("{}.pack", name)), pack)?;
+    fs::write(pack_dir.join(format!("{}.idx", name)), idx)?;
+    Ok(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, process};
+    use super::*;
+
+    // A pack entry holding a delta against the object with the given hash. The delta turns an empty base into an
+    // empty object, though it's never applied.
+    fn ref_delta_entry(base: &[u8; 20]) -> Vec<u8> {
+        let delta = [0, 0];
+        let mut entry = vec![(7 << 4) | delta.len() as u8];
+        entry.extend(base);
+        let mut encoder = ZlibEncoder::new(entry, Compression::default());
+        encoder.write_all(&delta).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn circular_delta_chain_is_an_error() -> Result<()> {
+        // Each of two objects is stored as a delta against the other, so the chain never reaches an object stored whole
+        let (first, second) = ([1; 20], [2; 20]);
+        let mut pack = Vec::from(*b"PACK");
+        pack.extend(2u32.to_be_bytes());
+        pack.extend(2u32.to_be_bytes());
+        let first_offset = pack.len() as u32;
+        pack.extend(ref_delta_entry(&second));
+        let second_offset = pack.len() as u32;
+        pack.extend(ref_delta_entry(&first));
+        let pack_checksum: [u8; 20] = Sha1::digest(&pack).into();
+        pack.extend(pack_checksum);
+
+        // A version 1 index is the fanout table followed by the offset and hash of each object, in order of hash
+        let mut idx = Vec::new();
+        for byte in 0..=255u8 {
+            let count = [first, second].iter().filter(|x| x[0] <= byte).count() as u32;
+            idx.extend(count.to_be_bytes());
+        }
+        for (offset, hash) in [(first_offset, first), (second_offset, second)] {
+            idx.extend(offset.to_be_bytes());
+            idx.extend(hash);
+        }
+        idx.extend(pack_checksum);
+        let idx_checksum: [u8; 20] = Sha1::digest(&idx).into();
+        idx.extend(idx_checksum);
+
+        let dir = env::temp_dir().join(format!("grit-circular-delta-{}", process::id()));
+        fs::create_dir_all(&dir)?;
+        fs::write(dir.join("pack-circular.pack"), pack)?;
+        fs::write(dir.join("pack-circular.idx"), idx)?;
+        let packs = open_packs(&dir);
+        let result = packs.and_then(|x| x[0].read_object(&dir, &first, true, &mut DeltaBaseCache::default()));
+        fs::remove_dir_all(&dir)?;
+
+        let error = result.err().map(|x| x.to_string()).unwrap_or_default();
+        assert!(error.starts_with("object at offset 12 has a delta chain longer than"), "{}", error);
+        Ok(())
+    }
+}
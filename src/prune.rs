@@ -0,0 +1,92 @@
+// Remove unreachable loose objects from the object store
+
+use std::{env, fs, path::Path, time::UNIX_EPOCH};
+use anyhow::{anyhow, Result};
+use clap::Args;
+
+use crate::{GlobalOpts, repo_find};
+use crate::date::parse_expiry_date;
+use crate::index::read_index;
+use crate::objects::{loose_objects, object_path, read_object_header, ObjectType};
+use crate::reachable::reachable_objects;
+use crate::refs::{head_commit, list_refs, reflog_hashes};
+
+#[derive(Args)]
+pub struct PruneArgs {
+    /// Only report the objects that would be removed
+    #[arg(short = 'n', long)]
+    pub dry_run: bool,
+    /// Report each object as it is removed
+    #[arg(short, long)]
+    pub verbose: bool,
+    /// Only remove objects older than this, e.g. `2.weeks.ago` or `now`. Defaults to removing all unreachable objects
+    #[arg(long)]
+    pub expire: Option<String>,
+}
+
+pub fn cmd_prune(args: PruneArgs, global_opts: GlobalOpts) -> Result<()> {
+    let path = env::current_dir().unwrap_or_else(|_| { panic!() });
+    let root = repo_find(&path, global_opts).unwrap_or_else(|| {
+        panic!("fatal: not a grit repository");
+    });
+
+    let expire = args.expire.as_deref().map(parse_expiry_date).transpose()?;
+
+    // Anything a reference, HEAD or the index points at is in use
+    let mut roots: Vec<[u8; 20]> = list_refs(&root, global_opts)?.into_iter().map(|(_, hash)| hash).collect();
+    roots.extend(head_commit(&root, global_opts)?);
+    roots.extend(read_index(&root, global_opts)?.items.iter().map(|x| x.hash));
+
+    // Old reference values are kept so the reflog can still be used, unless everything is being expired now
+    if args.expire.as_deref() != Some("now") {
+        roots.extend(reflog_hashes(&root, global_opts)?);
+    }
+
+    let mut reachable = reachable_objects(&root, &roots, global_opts.git_mode)?;
+
+    // Objects within the grace period are kept, along with everything they refer to
+    let mut candidates = loose_objects(&root, global_opts.git_mode)?;
+    candidates.sort();
+    if let Some(expire) = expire {
+        let mut recent = Vec::new();
+        for hash in &candidates {
+            if !reachable.contains(hash) && modified_time(&object_path(&root, hash, global_opts.git_mode))? > expire {
+                recent.push(*hash);
+            }
+        }
+        reachable.extend(reachable_objects(&root, &recent, global_opts.git_mode)?);
+    }
+
+    for hash in candidates.iter().filter(|x| !reachable.contains(*x)) {
+        if args.dry_run || args.verbose {
+            println!("{} {}", hex::encode(hash), object_type(&root, hash, global_opts.git_mode)?);
+        }
+        if args.dry_run {
+            continue;
+        }
+
+        let file = object_path(&root, hash, global_opts.git_mode);
+        fs::remove_file(&file)?;
+
+        // Remove the fan-out directory once it is empty
+        if let Some(dir) = file.parent() {
+            if fs::read_dir(dir)?.next().is_none() {
+                fs::remove_dir(dir)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn modified_time(file: &Path) -> Result<i64> {
+    let modified = fs::metadata(file)?.modified()?;
+    Ok(modified.duration_since(UNIX_EPOCH).map(|x| x.as_secs() as i64).unwrap_or(0))
+}
+
+// Reads just the type from the object's header, so the object needn't be parsed
+fn object_type(root: &Path, hash: &[u8; 20], git_mode: bool) -> Result<ObjectType> {
+    let (object_type, _) = read_object_header(root, hash, git_mode)?
+        .ok_or(anyhow!("fatal: object {} not found", hex::encode(hash)))?;
+    Ok(object_type)
+}
@@ -0,0 +1,191 @@
+// Fetch from a remote and bring its changes into the current branch
+
+use std::{env, fs, path::Path, process};
+use anyhow::{bail, Result};
+use clap::Args;
+
+use crate::{GlobalOpts, repo_find, git_dir_name};
+use crate::checkout::switch_commit;
+use crate::config::Config;
+use crate::dag::{is_ancestor, CommitDag};
+use crate::fetch::fetch;
+use crate::merge::{fast_forward, merge, merge_message, update_head};
+use crate::objects::parse_hash;
+use crate::rebase::{cmd_rebase, RebaseArgs};
+use crate::refs::{head_commit, read_head, Head};
+use crate::remote::default_remote;
+
+#[derive(Args)]
+pub struct PullArgs {
+    /// The remote to fetch from, as for fetch
+    pub remote: Option<String>,
+    /// The branch of the remote to merge, rather than the one the current branch is set to merge from
+    #[arg(requires = "remote")]
+    pub branch: Option<String>,
+    /// Only update the branch if it can be fast-forwarded, failing otherwise
+    #[arg(long)]
+    pub ff_only: bool,
+    /// Replay the branch's own commits on top of the fetched branch, rather than merging it
+    #[arg(short, long, overrides_with = "no_rebase")]
+    pub rebase: bool,
+    /// Merge the fetched branch, even if `pull.rebase` says to rebase
+    #[arg(long, overrides_with = "rebase")]
+    pub no_rebase: bool,
+}
+
+pub fn cmd_pull(args: PullArgs, global_opts: GlobalOpts) -> Result<()> {
+    let path = env::current_dir().unwrap_or_else(|_| { panic!() });
+    let root = repo_find(&path, global_opts).unwrap_or_else(|| {
+        panic!("fatal: not a grit repository");
+    });
+    let program = if global_opts.git_mode { "git" } else { "grit" };
+
+    // Like Git, the reflog entries are labelled with the command as it was given
+    let flags = [(args.ff_only, "--ff-only"), (args.rebase, "--rebase"), (args.no_rebase, "--no-rebase")];
+    let reflog_action = ["pull"].into_iter()
+        .chain(flags.iter().filter(|(given, _)| *given).map(|(_, flag)| *flag))
+        .chain(args.remote.as_deref())
+        .chain(args.branch.as_deref())
+        .collect::<Vec<&str>>()
+        .join(" ");
+
+    let config = Config::load(&root, global_opts)?;
+    let head = read_head(&root, global_opts)?;
+    let branch = match &head {
+        Head::Branch(name) => Some(name.strip_prefix("refs/heads/").unwrap_or(name).to_string()),
+        Head::Detached(_) => None
+    };
+    let remote = match &args.remote {
+        Some(remote) => remote.clone(),
+        None => default_remote(&config, branch.as_deref())
+    };
+
+    // As in Git, the pull fails with the same status however the fetch failed
+    let refs: Vec<String> = args.branch.iter().cloned().collect();
+    match fetch(&root, &path, &config, &remote, &refs, &reflog_action, global_opts) {
+        Ok(true) => (),
+        Ok(false) => process::exit(1),
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    }
+
+    let rebase = if args.rebase {
+        Some(true)
+    } else if args.no_rebase {
+        Some(false)
+    } else {
+        config.get_bool("pull.rebase")?
+    };
+    let Some((target, description)) = read_merge_heads(&root, global_opts)?.into_iter().next() else {
+        bail!("{}", no_merge_candidates(&config, args.remote.as_deref(), branch.as_deref(), rebase == Some(true), program));
+    };
+
+    let Some(current) = head_commit(&root, global_opts)? else {
+        // A branch with no commits yet simply starts from the fetched commit
+        switch_commit(&root, None, &target, global_opts)?;
+        return update_head(&root, &head, None, &target, &config, &format!("{}: initial pull", reflog_action), global_opts);
+    };
+    let dag = CommitDag::open(&root, global_opts.git_mode)?;
+    if is_ancestor(&dag, &target, &current)? {
+        // Git records where the branch was even when there's nothing to do
+        fs::write(root.join(git_dir_name(global_opts)).join("ORIG_HEAD"), format!("{}\n", hex::encode(current)))?;
+        println!("Already up to date.");
+        return Ok(());
+    }
+    if is_ancestor(&dag, &current, &target)? {
+        return fast_forward(&root, &head, &current, &target, &config, &reflog_action, global_opts);
+    }
+
+    let ff_only = args.ff_only || (!args.rebase && !args.no_rebase && config.get("pull.ff").as_deref() == Some("only"));
+    if ff_only {
+        bail!("fatal: Not possible to fast-forward, aborting.");
+    }
+    match rebase {
+        Some(true) => cmd_rebase(RebaseArgs {
+            upstream: Some(hex::encode(target)),
+            interactive: false,
+            onto: None,
+            continue_: false,
+            abort: false,
+            skip: false,
+        }, global_opts),
+        None if config.get("pull.ff").is_none() => bail!(
+            "hint: You have divergent branches and need to specify how to reconcile them.\n\
+            hint: You can do so by running one of the following commands sometime before\n\
+            hint: your next pull:\n\
+            hint: \n\
+            hint:   {program} config pull.rebase false  # merge\n\
+            hint:   {program} config pull.rebase true   # rebase\n\
+            hint:   {program} config pull.ff only       # fast-forward only\n\
+            hint: \n\
+            hint: You can replace \"{program} config\" with \"{program} config --global\" to set a default\n\
+            hint: preference for all repositories. You can also pass --rebase, --no-rebase,\n\
+            hint: or --ff-only on the command line to override the configured default per\n\
+            hint: invocation.\n\
+            fatal: Need to specify how to reconcile divergent branches."
+        ),
+        _ => {
+            let message = merge_message(&description, branch.as_deref());
+            merge(&root, &head, &current, &target, &hex::encode(target), &message, &config, &reflog_action, global_opts)
+        }
+    }
+}
+
+// Reads the commits FETCH_HEAD marks for merging, with the descriptions of where they came from
+fn read_merge_heads(root: &Path, global_opts: GlobalOpts) -> Result<Vec<([u8; 20], String)>> {
+    let contents = fs::read_to_string(root.join(git_dir_name(global_opts)).join("FETCH_HEAD"))?;
+    let mut heads = Vec::new();
+    for line in contents.lines() {
+        let mut fields = line.splitn(3, '\t');
+        if let (Some(hash), Some(""), Some(description)) = (fields.next(), fields.next(), fields.next()) {
+            heads.push((parse_hash(hash)?, description.to_string()));
+        }
+    }
+    Ok(heads)
+}
+
+// Explains why nothing was fetched to merge, in the same way as Git
+fn no_merge_candidates(config: &Config, remote: Option<&str>, branch: Option<&str>, rebase: bool, program: &str) -> String {
+    let verb = if rebase { "rebase against" } else { "merge with" };
+    let branch_remote = branch.and_then(|x| config.get(&format!("branch.{}.remote", x)));
+    match (remote, branch) {
+        (Some(remote), Some(_)) if branch_remote.as_deref() != Some(remote) => format!(
+            "You asked to pull from the remote '{}', but did not specify\n\
+            a branch. Because this is not the default configured remote\n\
+            for your current branch, you must specify a branch on the command line.",
+            remote
+        ),
+        (_, None) => format!(
+            "You are not currently on a branch.\n\
+            Please specify which branch you want to {}.\n\
+            See git-pull(1) for details.\n\n    \
+            {} pull <remote> <branch>\n",
+            verb, program
+        ),
+        (_, Some(branch)) => match config.get(&format!("branch.{}.merge", branch)) {
+            None => {
+                let remotes = config.subsections("remote");
+                let remote_name = match &remotes[..] {
+                    [remote] => remote.as_str(),
+                    _ => "<remote>"
+                };
+                format!(
+                    "There is no tracking information for the current branch.\n\
+                    Please specify which branch you want to {}.\n\
+                    See git-pull(1) for details.\n\n    \
+                    {program} pull <remote> <branch>\n\n\
+                    If you wish to set tracking information for this branch you can do so with:\n\n    \
+                    {program} branch --set-upstream-to={}/<branch> {}\n",
+                    verb, remote_name, branch
+                )
+            },
+            Some(merge) => format!(
+                "Your configuration specifies to merge with the ref '{}'\n\
+                from the remote, but no such ref was fetched.",
+                merge
+            )
+        }
+    }
+}
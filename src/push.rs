@@ -0,0 +1,297 @@
+// Send a branch's commits to another repository and update its branch to match
+
+use std::{env, path::Path};
+use anyhow::{anyhow, bail, Result};
+use clap::Args;
+
+use crate::{GlobalOpts, repo_find};
+use crate::commit::signature;
+use crate::config::Config;
+use crate::dag::{is_ancestor, CommitDag};
+use crate::objects::{search_object, Object};
+use crate::reachable::copy_objects;
+use crate::refs::{append_reflog, full_ref_name, read_git_dir_ref, read_head, read_ref, update_ref, write_ref, Head};
+use crate::remote::{open_remote_git_dir, tracking_ref};
+
+// What the remote side says when asked to update the branch its working tree has checked out
+const CHECKED_OUT_MESSAGE: &str = "\
+error: refusing to update checked out branch: {}
+error: By default, updating the current branch in a non-bare repository
+is denied, because it will make the index and work tree inconsistent
+with what you pushed, and will require '{program} reset --hard' to match
+the work tree to HEAD.
+
+You can set the 'receive.denyCurrentBranch' configuration variable
+to 'ignore' or 'warn' in the remote repository to allow pushing into
+its current branch; however, this is not recommended unless you
+arranged to update its work tree to match what you pushed in some
+other way.
+
+To squelch this message and still keep the default behaviour, set
+'receive.denyCurrentBranch' configuration variable to 'refuse'.";
+
+#[derive(Args)]
+pub struct PushArgs {
+    /// The remote to push to: the name of a configured remote, or the path or `file://` URL of a repository
+    remote: String,
+    /// The branch to push, or `<src>:<dst>` to push a local branch to a differently named branch of the remote
+    branch: String,
+    /// Update the remote branch even if it isn't a fast-forward, discarding the commits only the remote has
+    #[arg(short, long)]
+    force: bool,
+}
+
+// How the remote's reference is to be updated, or why it can't be
+enum Update {
+    New,
+    FastForward,
+    Forced,
+    /// The remote has a commit the local repository doesn't, so whether it would be lost can't be known
+    FetchFirst,
+    NonFastForward,
+    /// Tags are never moved without --force
+    TagExists,
+}
+
+pub fn cmd_push(args: PushArgs, global_opts: GlobalOpts) -> Result<()> {
+    let path = env::current_dir().unwrap_or_else(|_| { panic!() });
+    let root = repo_find(&path, global_opts).unwrap_or_else(|| {
+        panic!("fatal: not a grit repository");
+    });
+    let git_mode = global_opts.git_mode;
+    let program = if git_mode { "git" } else { "grit" };
+
+    let config = Config::load(&root, global_opts)?;
+    let (url, git_dir, bare) = open_remote_git_dir(&config, &args.remote, &path, global_opts)?;
+    let head = read_head(&root, global_opts)?;
+
+    let src = args.branch.split_once(':').map(|(src, _)| src).unwrap_or(&args.branch);
+    let src_name = match src {
+        "HEAD" => Some(String::from("HEAD")),
+        _ => full_ref_name(&root, src, global_opts)?
+    };
+    // HEAD has no commit to push on a branch with none yet
+    let new = match &src_name {
+        Some(name) => read_ref(&root, name, global_opts)?,
+        None => None
+    };
+    let (Some(src_name), Some(new)) = (src_name, new) else {
+        bail!("error: src refspec {} does not match any\nerror: failed to push some refs to '{}'", src, url);
+    };
+    // HEAD counts as the branch it's on, and pushing it alone updates the branch of the same name
+    let src_ref = match (src_name.as_str(), &head) {
+        ("HEAD", Head::Branch(name)) => name.clone(),
+        _ => src_name.clone()
+    };
+    let dst = match args.branch.split_once(':') {
+        Some((_, dst)) => Some(dst),
+        None if src_ref == "HEAD" => Some("HEAD"),
+        None => None
+    };
+    let dst_name = match dst {
+        Some(dst) => destination_name(&root, &git_dir, dst, src, &src_ref, &new, global_opts)
+            .map_err(|e| anyhow!("{}\nerror: failed to push some refs to '{}'", e, url))?,
+        None => src_ref.clone()
+    };
+
+    let old = read_git_dir_ref(&git_dir, &dst_name)?;
+    if old == Some(new) {
+        eprintln!("Everything up-to-date");
+        return Ok(());
+    }
+    let update = match old {
+        None => Update::New,
+        Some(_) if args.force => Update::Forced,
+        Some(_) if dst_name.starts_with("refs/tags/") => Update::TagExists,
+        Some(old) => match (search_object(&root, &old, git_mode)?, search_object(&root, &new, git_mode)?) {
+            (None, _) => Update::FetchFirst,
+            (Some(Object::Commit(_)), Some(Object::Commit(_)))
+                if is_ancestor(&CommitDag::open(&root, git_mode)?, &old, &new)? => Update::FastForward,
+            _ => Update::NonFastForward
+        }
+    };
+
+    let short_src = short_name(&src_name);
+    let short_dst = short_name(&dst_name);
+    let print_line = |flag: char, summary: &str, reason: Option<&str>| {
+        let reason = reason.map(|x| format!(" ({})", x)).unwrap_or_default();
+        eprintln!(" {} {:<17} {} -> {}{}", flag, summary, short_src, short_dst, reason);
+    };
+
+    let rejection = match update {
+        Update::FetchFirst => Some(("fetch first", format!(
+            "hint: Updates were rejected because the remote contains work that you do\n\
+            hint: not have locally. This is usually caused by another repository pushing\n\
+            hint: to the same ref. You may want to first integrate the remote changes\n\
+            hint: (e.g., '{program} pull ...') before pushing again.\n\
+            hint: See the 'Note about fast-forwards' in '{program} push --help' for details."
+        ))),
+        // The advice depends on whether it's the current branch that is behind
+        Update::NonFastForward if head == Head::Branch(dst_name.clone()) => Some(("non-fast-forward", format!(
+            "hint: Updates were rejected because the tip of your current branch is behind\n\
+            hint: its remote counterpart. Integrate the remote changes (e.g.\n\
+            hint: '{program} pull ...') before pushing again.\n\
+            hint: See the 'Note about fast-forwards' in '{program} push --help' for details."
+        ))),
+        Update::NonFastForward => Some(("non-fast-forward", format!(
+            "hint: Updates were rejected because a pushed branch tip is behind its remote\n\
+            hint: counterpart. If you want to integrate the remote changes, use '{program} pull'\n\
+            hint: before pushing again.\n\
+            hint: See the 'Note about fast-forwards' in '{program} push --help' for details."
+        ))),
+        Update::TagExists => Some((
+            "already exists",
+            String::from("hint: Updates were rejected because the tag already exists in the remote.")
+        )),
+        _ => None
+    };
+    if let Some((reason, hint)) = rejection {
+        eprintln!("To {}", url);
+        print_line('!', "[rejected]", Some(reason));
+        bail!("error: failed to push some refs to '{}'\n{}", url, hint);
+    }
+
+    copy_objects(&root, &git_dir, &[new], global_opts)?;
+
+    // Like Git, a repository with a working tree won't have its checked out branch moved from under it
+    let remote_root = git_dir.parent().unwrap();
+    if !bare && read_head(remote_root, global_opts)? == Head::Branch(dst_name.clone()) {
+        print_remote_message(&CHECKED_OUT_MESSAGE.replace("{}", &dst_name).replace("{program}", program));
+        eprintln!("To {}", url);
+        print_line('!', "[remote rejected]", Some("branch is currently checked out"));
+        bail!("error: failed to push some refs to '{}'", url);
+    }
+
+    if let Err(e) = update_ref(&git_dir, &dst_name, &new, old) {
+        print_remote_message(&format!("error: {}", e));
+        eprintln!("To {}", url);
+        print_line('!', "[remote rejected]", Some("failed to update ref"));
+        bail!("error: failed to push some refs to '{}'", url);
+    }
+    // Only repositories with a working tree keep reflogs by default
+    if !bare {
+        let remote_config = Config::load(remote_root, global_opts)?;
+        if let Ok(identity) = signature(&remote_config, "COMMITTER", global_opts) {
+            append_reflog(remote_root, &dst_name, old, &new, &identity, "push", global_opts)?;
+        }
+    }
+    update_tracking_ref(&root, &config, &args.remote, &dst_name, &new, global_opts)?;
+
+    eprintln!("To {}", url);
+    let old_short = old.map(|x| hex::encode(x)[..7].to_string()).unwrap_or_default();
+    let new_short = &hex::encode(new)[..7];
+    match update {
+        Update::New => {
+            let kind = if dst_name.starts_with("refs/tags/") {
+                "[new tag]"
+            } else if dst_name.starts_with("refs/heads/") {
+                "[new branch]"
+            } else {
+                "[new reference]"
+            };
+            print_line('*', kind, None);
+        },
+        Update::FastForward => print_line(' ', &format!("{}..{}", old_short, new_short), None),
+        _ => print_line('+', &format!("{}...{}", old_short, new_short), Some("forced update"))
+    }
+    Ok(())
+}
+
+// The full name of the remote reference to push to when it's given by a short name: an existing reference of the
+// remote if there is one, or else a reference of the same kind as the one being pushed. `src` is the reference being
+// pushed as it was given, and `src_ref` its full name.
+fn destination_name(
+    root: &Path,
+    git_dir: &Path,
+    dst: &str,
+    src: &str,
+    src_ref: &str,
+    hash: &[u8; 20],
+    global_opts: GlobalOpts
+) -> Result<String> {
+    if dst.starts_with("refs/") {
+        return Ok(dst.to_string());
+    }
+    for candidate in [format!("refs/heads/{}", dst), format!("refs/tags/{}", dst)] {
+        if read_git_dir_ref(git_dir, &candidate)?.is_some() {
+            return Ok(candidate);
+        }
+    }
+    for prefix in ["refs/heads/", "refs/tags/"] {
+        if src_ref.starts_with(prefix) {
+            return Ok(format!("{}{}", prefix, dst));
+        }
+    }
+
+    // Git suggests where the object would most likely go
+    let hint = match search_object(root, hash, global_opts.git_mode)? {
+        Some(Object::Commit(_)) => format!(
+            "hint: The <src> part of the refspec is a commit object.\n\
+            hint: Did you mean to create a new branch by pushing to\n\
+            hint: '{}:refs/heads/{}'?", src, dst
+        ),
+        Some(Object::Tag(_)) => format!(
+            "hint: The <src> part of the refspec is a tag object.\n\
+            hint: Did you mean to create a new tag by pushing to\n\
+            hint: '{}:refs/tags/{}'?", src, dst
+        ),
+        _ => String::new()
+    };
+    let message = format!(
+        "error: The destination you provided is not a full refname (i.e.,\n\
+        starting with \"refs/\"). We tried to guess what you meant by:\n\n\
+        - Looking for a ref that matches '{dst}' on the remote side.\n\
+        - Checking if the <src> being pushed ('{src}')\n  \
+        is a ref in \"refs/{{heads,tags}}/\". If so we add a corresponding\n  \
+        refs/{{heads,tags}}/ prefix on the remote side.\n\n\
+        Neither worked, so we gave up. You must fully qualify the ref."
+    );
+    if hint.is_empty() {
+        bail!(message);
+    }
+    bail!("{}\n{}", message, hint);
+}
+
+// Remembers where the remote's branch now is in the matching remote-tracking branch, if the remote is configured
+// with one
+fn update_tracking_ref(
+    root: &Path,
+    config: &Config,
+    remote: &str,
+    name: &str,
+    hash: &[u8; 20],
+    global_opts: GlobalOpts
+) -> Result<()> {
+    if config.get(&format!("remote.{}.url", remote)).is_none() {
+        return Ok(());
+    }
+    let Some(tracking_name) = tracking_ref(config, remote, name) else {
+        return Ok(());
+    };
+    let old = read_ref(root, &tracking_name, global_opts)?;
+    if old == Some(*hash) {
+        return Ok(());
+    }
+    write_ref(root, &tracking_name, hash, global_opts)?;
+    if let Ok(identity) = signature(config, "COMMITTER", global_opts) {
+        append_reflog(root, &tracking_name, old, hash, &identity, "update by push", global_opts)?;
+    }
+    Ok(())
+}
+
+// Prints a message from the remote side as Git relays it, with padding at the end of each line where Git clears the
+// rest of the terminal line
+fn print_remote_message(message: &str) {
+    for line in message.lines() {
+        if line.is_empty() {
+            eprintln!("remote: ");
+        } else {
+            eprintln!("remote: {}        ", line);
+        }
+    }
+}
+
+// The name of a reference as it's shown in the list of updates
+fn short_name(name: &str) -> &str {
+    name.strip_prefix("refs/heads/").or(name.strip_prefix("refs/tags/")).unwrap_or(name)
+}
@@ -0,0 +1,97 @@
+// Quoting file names in output as Git does, so that unusual characters can't be mistaken for the end of a name
+
+use std::path::Path;
+use anyhow::Result;
+
+use crate::config::Config;
+
+/// How paths are quoted, from `core.quotePath`
+#[derive(Clone, Copy)]
+pub struct PathQuoting {
+    /// Whether bytes outside ASCII are written as octal escapes, which is the default
+    non_ascii: bool,
+}
+
+impl PathQuoting {
+    pub fn new(config: &Config) -> Result<PathQuoting> {
+        Ok(PathQuoting { non_ascii: config.get_bool("core.quotepath")?.unwrap_or(true) })
+    }
+
+    /// Returns the path in double quotes with C-style escapes if it contains a double quote, backslash, control
+    /// character or, unless `core.quotePath` is false, a byte outside ASCII. Otherwise the path is returned as it is.
+    pub fn quote(&self, path: &Path) -> String {
+        self.quote_bytes(path.as_os_str().as_encoded_bytes(), false)
+    }
+
+    /// Quotes a path as `quote` does, and also if it contains a space, as the short forms of `status` do
+    pub fn quote_with_spaces(&self, path: &Path) -> String {
+        self.quote_bytes(path.as_os_str().as_encoded_bytes(), true)
+    }
+
+    fn quote_bytes(&self, bytes: &[u8], quote_space: bool) -> String {
+        let needs_escape = |x: u8| x < 0x20 || x == 0x7f || x == b'"' || x == b'\\' || (self.non_ascii && x >= 0x80);
+        if !bytes.iter().any(|x| needs_escape(*x) || (quote_space && *x == b' ')) {
+            return String::from_utf8_lossy(bytes).to_string();
+        }
+
+        let mut quoted = vec![b'"'];
+        for byte in bytes {
+            let escape: &[u8] = match byte {
+                0x07 => b"\\a",
+                0x08 => b"\\b",
+                b'\t' => b"\\t",
+                b'\n' => b"\\n",
+                0x0b => b"\\v",
+                0x0c => b"\\f",
+                b'\r' => b"\\r",
+                b'"' => b"\\\"",
+                b'\\' => b"\\\\",
+                x if needs_escape(*x) => {
+                    quoted.extend(format!("\\{:03o}", x).into_bytes());
+                    continue;
+                },
+                x => {
+                    quoted.push(*x);
+                    continue;
+                }
+            };
+            quoted.extend(escape);
+        }
+        quoted.push(b'"');
+        String::from_utf8_lossy(&quoted).to_string()
+    }
+}
+
+/// Reverses the quoting of a name in double quotes with C-style escapes, as Git reads names that scripts may have
+/// quoted. Anything after the closing quote is ignored. Returns None if the name isn't quoted correctly.
+pub fn unquote(quoted: &str) -> Option<String> {
+    let mut bytes = quoted.strip_prefix('"')?.bytes();
+    let mut unquoted = Vec::new();
+    loop {
+        let byte = match bytes.next()? {
+            b'"' => return Some(String::from_utf8_lossy(&unquoted).to_string()),
+            b'\\' => match bytes.next()? {
+                b'a' => 0x07,
+                b'b' => 0x08,
+                b't' => b'\t',
+                b'n' => b'\n',
+                b'v' => 0x0b,
+                b'f' => 0x0c,
+                b'r' => b'\r',
+                x @ (b'"' | b'\\') => x,
+                // An octal escape has exactly three digits
+                x @ b'0'..=b'3' => {
+                    let mut value = x - b'0';
+                    for _ in 0..2 {
+                        let digit = bytes.next().filter(|x| (b'0'..=b'7').contains(x))?;
+                        value = value << 3 | (digit - b'0');
+                    }
+                    value
+                },
+                _ => return None
+            },
+            x => x
+        };
+        unquoted.push(byte);
+    }
+}
@@ -0,0 +1,125 @@
+// Walking the object graph to find which objects are still in use
+
+use std::{collections::HashSet, fs, path::{Path, PathBuf}, process};
+use anyhow::{bail, Result};
+
+use crate::GlobalOpts;
+use crate::objects::{object_path, search_object, GitObject, Object};
+
+/// Returns the hashes of all objects reachable from the given roots, including the roots themselves.
+/// Commits lead to their tree and parents, trees to their entries and tags to the tagged object.
+/// Objects missing from the store are included but not followed.
+pub fn reachable_objects(root: &Path, roots: &[[u8; 20]], git_mode: bool) -> Result<HashSet<[u8; 20]>> {
+    let mut reachable = HashSet::new();
+    let mut pending: Vec<[u8; 20]> = roots.to_vec();
+
+    while let Some(hash) = pending.pop() {
+        if !reachable.insert(hash) {
+            continue;
+        }
+
+        match search_object(root, &hash, git_mode)? {
+            Some(Object::Commit(commit)) => {
+                pending.push(commit.tree);
+                pending.extend(&commit.parents);
+            },
+            Some(Object::Tree(tree)) => {
+                for entry in tree.children {
+                    match entry.mode {
+                        // Subtrees must be walked in turn
+                        0o40000 => pending.push(entry.hash),
+                        // Submodule commits live in another repository
+                        0o160000 => (),
+                        // Blobs have no children, so needn't be read
+                        _ => { reachable.insert(entry.hash); }
+                    }
+                }
+            },
+            Some(Object::Tag(tag)) => pending.push(tag.object),
+            Some(Object::Blob(_) | Object::Unknown { .. }) | None => ()
+        }
+    }
+
+    Ok(reachable)
+}
+
+/// Copies the objects reachable from the given roots from the repository at `src` to the one whose git directory is
+/// `dst`, which may be a bare repository, returning the number of objects copied. Objects already in `dst` are
+/// skipped along with everything they reference, since an object is only copied once everything it references has
+/// been. For the same reason, a copy that was interrupted can be finished by running it again.
+pub fn copy_objects(src: &Path, dst: &Path, roots: &[[u8; 20]], global_opts: GlobalOpts) -> Result<usize> {
+    let git_mode = global_opts.git_mode;
+    let mut seen = HashSet::new();
+    let mut copied = 0;
+    // Each object is visited twice: once to queue the objects it references, then again to copy it once they have
+    // been copied
+    let mut pending: Vec<([u8; 20], bool)> = roots.iter().map(|x| (*x, false)).collect();
+
+    while let Some((hash, references_copied)) = pending.pop() {
+        if references_copied {
+            copy_object(src, dst, &hash, global_opts)?;
+            copied += 1;
+            continue;
+        }
+        if !seen.insert(hash) || store_path(dst, &hash).exists() {
+            continue;
+        }
+
+        pending.push((hash, true));
+        match search_object(src, &hash, git_mode)? {
+            Some(Object::Commit(commit)) => {
+                pending.push((commit.tree, false));
+                pending.extend(commit.parents.iter().map(|x| (*x, false)));
+            },
+            Some(Object::Tree(tree)) => {
+                for entry in tree.children {
+                    match entry.mode {
+                        0o40000 => pending.push((entry.hash, false)),
+                        0o160000 => (),
+                        // Blobs reference nothing, so can be copied straight away
+                        _ => {
+                            if seen.insert(entry.hash) && !store_path(dst, &entry.hash).exists() {
+                                copy_object(src, dst, &entry.hash, global_opts)?;
+                                copied += 1;
+                            }
+                        }
+                    }
+                }
+            },
+            Some(Object::Tag(tag)) => pending.push((tag.object, false)),
+            Some(Object::Blob(_) | Object::Unknown { .. }) => (),
+            None => bail!("fatal: missing object {}", hex::encode(hash))
+        }
+    }
+
+    Ok(copied)
+}
+
+// Copies a single object's file between stores
+fn copy_object(src: &Path, dst: &Path, hash: &[u8; 20], global_opts: GlobalOpts) -> Result<()> {
+    let src_path = object_path(src, hash, global_opts.git_mode);
+    if !src_path.exists() {
+        // The empty tree and blob may be known without being in the store
+        match search_object(src, hash, global_opts.git_mode)? {
+            Some(object) => return write_file(&store_path(dst, hash), &object.compress()?),
+            None => bail!("fatal: missing object {}", hex::encode(hash))
+        }
+    }
+    write_file(&store_path(dst, hash), &fs::read(src_path)?)
+}
+
+// The path of an object's file in the store of the repository with the given git directory
+fn store_path(git_dir: &Path, hash: &[u8; 20]) -> PathBuf {
+    let hash_str = hex::encode(hash);
+    git_dir.join("objects").join(&hash_str[..2]).join(&hash_str[2..])
+}
+
+// Writes a file under a temporary name first, so that an interrupted write never leaves a partial object behind
+fn write_file(path: &Path, contents: &[u8]) -> Result<()> {
+    let dir = path.parent().unwrap();
+    fs::create_dir_all(dir)?;
+    let temp_path = dir.join(format!("tmp_obj_{}", process::id()));
+    fs::write(&temp_path, contents)?;
+    fs::rename(&temp_path, path)?;
+    Ok(())
+}
@@ -0,0 +1,816 @@
+// Replaying the commits of a branch on top of another commit
+
+use std::{collections::{BTreeMap, HashMap, HashSet}, env, fs, path::{Path, PathBuf}, process};
+use anyhow::{anyhow, bail, Result};
+use clap::Args;
+use sha1::{Sha1, Digest};
+
+use crate::{GlobalOpts, repo_find, git_dir_name};
+use crate::checkout::{checkout_merge, commit_files, hash_worktree_file, reset_hard, switch_commit, switch_files};
+use crate::commit::{print_commit_summary, signature};
+use crate::config::Config;
+use crate::dag::{merge_bases, CommitDag};
+use crate::diff::is_binary;
+use crate::editor::{git_editor, launch_editor, sequence_editor};
+use crate::filter::Filters;
+use crate::index::read_index;
+use crate::line_diff::{myers_diff, split_lines, Edit};
+use crate::merge::merge_trees;
+use crate::objects::{get_blob, get_object, parse_hash, peel, Commit, GitObject, Object, Signature};
+use crate::refs::{append_reflog, head_commit, read_head, resolve_revision, write_head, write_ref, Head};
+use crate::stripspace::stripspace;
+use crate::write_tree::{files_tree, write_tree};
+
+#[derive(Args)]
+pub struct RebaseArgs {
+    /// The branch whose commits are left out, and onto which the rest are replayed
+    #[arg(required_unless_present_any = ["continue_", "abort", "skip"])]
+    pub upstream: Option<String>,
+    /// Edit the list of commits to replay before starting, to reorder, reword, squash or drop them
+    #[arg(short, long)]
+    pub interactive: bool,
+    /// Replay the commits onto this commit rather than onto the upstream
+    #[arg(long, value_name = "NEWBASE")]
+    pub onto: Option<String>,
+    /// Carry on after resolving a conflict
+    #[arg(long = "continue", conflicts_with_all = ["abort", "skip"])]
+    pub continue_: bool,
+    /// Stop rebasing and return the branch to where it was
+    #[arg(long, conflicts_with = "skip")]
+    pub abort: bool,
+    /// Leave out the commit that conflicted and carry on with the rest
+    #[arg(long)]
+    pub skip: bool,
+}
+
+// The explanation of the commands following the todo list of an interactive rebase
+const TODO_HELP: &str = "\
+#
+# Commands:
+# p, pick <commit> = use commit
+# r, reword <commit> = use commit, but edit the commit message
+# s, squash <commit> = use commit, but meld into previous commit
+# f, fixup <commit> = like \"squash\" but keep only the previous
+#                    commit's log message
+# d, drop <commit> = remove commit
+#
+# These lines can be re-ordered; they are executed from top to bottom.
+#
+# If you remove a line here THAT COMMIT WILL BE LOST.
+#
+# However, if you remove everything, the rebase will be aborted.
+#
+";
+
+// What to do with a commit in the todo list
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum TodoCommand {
+    Pick,
+    Reword,
+    Squash,
+    Fixup,
+    Drop,
+}
+
+impl TodoCommand {
+    fn parse(word: &str) -> Option<TodoCommand> {
+        match word {
+            "p" | "pick" => Some(TodoCommand::Pick),
+            "r" | "reword" => Some(TodoCommand::Reword),
+            "s" | "squash" => Some(TodoCommand::Squash),
+            "f" | "fixup" => Some(TodoCommand::Fixup),
+            "d" | "drop" => Some(TodoCommand::Drop),
+            _ => None
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            TodoCommand::Pick => "pick",
+            TodoCommand::Reword => "reword",
+            TodoCommand::Squash => "squash",
+            TodoCommand::Fixup => "fixup",
+            TodoCommand::Drop => "drop",
+        }
+    }
+}
+
+pub fn cmd_rebase(args: RebaseArgs, global_opts: GlobalOpts) -> Result<()> {
+    let path = env::current_dir().unwrap_or_else(|_| { panic!() });
+    let root = repo_find(&path, global_opts).unwrap_or_else(|| {
+        panic!("fatal: not a grit repository");
+    });
+    let program = if global_opts.git_mode { "git" } else { "grit" };
+    let git_dir = root.join(git_dir_name(global_opts));
+    let state = state_dir(&root, global_opts);
+
+    if args.continue_ || args.abort || args.skip {
+        if !state.is_dir() {
+            bail!("fatal: No rebase in progress?");
+        }
+        if args.abort {
+            return abort(&root, global_opts);
+        }
+        if args.skip {
+            let head = head_commit(&root, global_opts)?.ok_or(anyhow!("fatal: HEAD does not point to a commit"))?;
+            reset_hard(&root, &head, global_opts)?;
+            clear_stopped(&root, global_opts)?;
+        } else {
+            commit_resolved(&root, program, global_opts)?;
+        }
+        return replay(&root, program, global_opts);
+    }
+
+    if state.exists() {
+        bail!(
+            "fatal: It seems that there is already a rebase-merge directory, and\n\
+            I wonder if you are in the middle of another rebase.  If that is the\n\
+            case, please try\n\t\
+            {program} rebase (--continue | --abort | --skip)\n\
+            If that is not the case, please\n\t\
+            rm -fr \"{}\"\n\
+            and run me again.  I am stopping in case you still have something\n\
+            valuable there.\n",
+            state.strip_prefix(&root).unwrap_or(&state).to_string_lossy()
+        );
+    }
+
+    let upstream_name = args.upstream.unwrap_or_default();
+    let upstream = resolve_commit(&root, &upstream_name, global_opts)
+        .map_err(|_| anyhow!("fatal: invalid upstream '{}'", upstream_name))?;
+    let mut onto = match &args.onto {
+        Some(name) => resolve_commit(&root, name, global_opts)
+            .map_err(|_| anyhow!("fatal: Does not point to a valid commit '{}'", name))?,
+        None => upstream
+    };
+    let head = read_head(&root, global_opts)?;
+    let orig_head = resolve_revision(&root, "HEAD", global_opts)?;
+    require_clean_work_tree(&root, &orig_head, global_opts)?;
+
+    // Nothing needs replaying if the branch already starts from `onto` and contains all of the upstream,
+    // unless the commits are to be edited
+    let dag = CommitDag::open(&root, global_opts.git_mode)?;
+    if !args.interactive && merge_bases(&dag, &onto, &orig_head)? == vec![onto] && merge_bases(&dag, &upstream, &orig_head)? == vec![onto] {
+        match head {
+            Head::Branch(name) => println!("Current branch {} is up to date.", name.strip_prefix("refs/heads/").unwrap_or(&name)),
+            Head::Detached(_) => println!("HEAD is up to date.")
+        }
+        remove_if_exists(&git_dir.join("REBASE_HEAD"))?;
+        remove_if_exists(&git_dir.join("AUTO_MERGE"))?;
+        return Ok(());
+    }
+
+    // Commits whose changes the upstream already has are left out
+    let mut todo = commits_to_replay(&dag, &upstream, &orig_head)?;
+    let mut applied = HashSet::new();
+    for hash in commits_to_replay(&dag, &orig_head, &upstream)? {
+        applied.insert(patch_id(&root, &hash, global_opts)?);
+    }
+    let mut skipped = Vec::new();
+    for hash in &todo {
+        if applied.contains(&patch_id(&root, hash, global_opts)?) {
+            skipped.push(*hash);
+        }
+    }
+    todo.retain(|x| !skipped.contains(x));
+    for hash in &skipped {
+        eprintln!("warning: skipped previously applied commit {}", &hex::encode(hash)[..7]);
+    }
+    if !skipped.is_empty() {
+        eprintln!(
+            "hint: use --reapply-cherry-picks to include skipped commits\n\
+            hint: Disable this message with \"{} config advice.skippedCherryPicks false\"",
+            program
+        );
+    }
+
+    fs::create_dir_all(&state)?;
+    let head_name = match &head {
+        Head::Branch(name) => name.clone(),
+        Head::Detached(_) => String::from("detached HEAD")
+    };
+    fs::write(state.join("head-name"), format!("{}\n", head_name))?;
+    fs::write(state.join("onto"), format!("{}\n", hex::encode(onto)))?;
+    fs::write(state.join("orig-head"), format!("{}\n", hex::encode(orig_head)))?;
+
+    let mut commands: Vec<(TodoCommand, [u8; 20])> = todo.iter().map(|x| (TodoCommand::Pick, *x)).collect();
+    if args.interactive {
+        fs::write(state.join("interactive"), "")?;
+        let range = format!("{}..{}", &hex::encode(upstream)[..7], &hex::encode(orig_head)[..7]);
+        commands = edit_todo(&root, &todo, &range, &onto, global_opts)?;
+    }
+
+    // Like Git, commits that would be picked onto their own parent are kept as they are, along with any
+    // drops among them
+    let mut done = String::new();
+    while let Some((command, first)) = commands.first().copied() {
+        match command {
+            TodoCommand::Drop => (),
+            TodoCommand::Pick if dag.parents(&first)? == vec![onto] => onto = first,
+            _ => break
+        }
+        commands.remove(0);
+        done += &todo_line(&root, command, &first, global_opts)?;
+    }
+
+    fs::write(git_dir.join("ORIG_HEAD"), format!("{}\n", hex::encode(orig_head)))?;
+    let mut lines = String::new();
+    for (command, hash) in &commands {
+        lines += &todo_line(&root, *command, hash, global_opts)?;
+    }
+    fs::write(state.join("git-rebase-todo"), lines)?;
+    fs::write(state.join("done"), &done)?;
+    fs::write(state.join("end"), format!("{}\n", done.lines().count() + commands.len()))?;
+
+    switch_commit(&root, Some(orig_head), &onto, global_opts)?;
+    write_head(&root, &Head::Detached(onto), global_opts)?;
+    replay(&root, program, global_opts)
+}
+
+// The directory holding the state of a rebase in progress
+fn state_dir(root: &Path, global_opts: GlobalOpts) -> PathBuf {
+    root.join(git_dir_name(global_opts)).join("rebase-merge")
+}
+
+fn resolve_commit(root: &Path, rev: &str, global_opts: GlobalOpts) -> Result<[u8; 20]> {
+    let hash = peel(root, &resolve_revision(root, rev, global_opts)?, global_opts.git_mode)?;
+    read_commit(root, &hash, global_opts)?;
+    Ok(hash)
+}
+
+fn read_commit(root: &Path, hash: &[u8; 20], global_opts: GlobalOpts) -> Result<Commit> {
+    match get_object(root, hash, global_opts.git_mode)? {
+        Object::Commit(c) => Ok(c),
+        _ => bail!("fatal: object {} is not a commit", hex::encode(hash))
+    }
+}
+
+// The line in the todo list that carries out a command on the given commit, e.g. `pick <hash> <subject>`
+fn todo_line(root: &Path, command: TodoCommand, hash: &[u8; 20], global_opts: GlobalOpts) -> Result<String> {
+    Ok(format!("{} {} {}\n", command.name(), hex::encode(hash), read_commit(root, hash, global_opts)?.subject()))
+}
+
+fn parse_todo_line(line: &str) -> Result<(TodoCommand, [u8; 20])> {
+    let mut words = line.split(' ');
+    let command = words.next().and_then(TodoCommand::parse);
+    let hash = words.next().and_then(|x| parse_hash(x).ok());
+    match (command, hash) {
+        (Some(command), Some(hash)) => Ok((command, hash)),
+        _ => bail!("fatal: invalid line in git-rebase-todo: {}", line)
+    }
+}
+
+// Lets the user edit the list of commands for an interactive rebase, returning the commands they left in it.
+// Like Git, the rebase is given up if the list can't be used.
+fn edit_todo(
+    root: &Path,
+    todo: &[[u8; 20]],
+    range: &str,
+    onto: &[u8; 20],
+    global_opts: GlobalOpts
+) -> Result<Vec<(TodoCommand, [u8; 20])>> {
+    let state = state_dir(root, global_opts);
+    let mut text = String::new();
+    for hash in todo {
+        text += &format!("pick {} {}\n", &hex::encode(hash)[..7], read_commit(root, hash, global_opts)?.subject());
+    }
+    if todo.is_empty() {
+        text += "noop\n";
+    }
+    let count = todo.len().max(1);
+    text += &format!(
+        "\n# Rebase {} onto {} ({} command{})\n{}",
+        range, &hex::encode(onto)[..7], count, if count == 1 { "" } else { "s" }, TODO_HELP
+    );
+    let todo_file = PathBuf::from(git_dir_name(global_opts)).join("rebase-merge").join("git-rebase-todo");
+    fs::write(root.join(&todo_file), text)?;
+
+    let config = Config::load(root, global_opts)?;
+    if let Err(e) = launch_editor(root, &todo_file, &sequence_editor(&config)) {
+        fs::remove_dir_all(&state)?;
+        return Err(e);
+    }
+
+    let mut commands = Vec::new();
+    let mut errors = Vec::new();
+    for (i, line) in fs::read_to_string(root.join(&todo_file))?.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line == "noop" {
+            continue;
+        }
+        let mut words = line.split_whitespace();
+        let command = words.next().and_then(TodoCommand::parse);
+        let hash = words.next().and_then(|x| resolve_commit(root, x, global_opts).ok());
+        match (command, hash) {
+            (Some(command), Some(hash)) => commands.push((command, hash)),
+            _ => errors.push(format!("error: invalid line {}: {}", i + 1, line))
+        }
+    }
+    if errors.is_empty() {
+        if let Some((command @ (TodoCommand::Squash | TodoCommand::Fixup), _)) = commands.first() {
+            errors.push(format!("error: cannot '{}' without a previous commit", command.name()));
+        } else if commands.is_empty() {
+            errors.push(String::from("error: nothing to do"));
+        }
+    }
+    if !errors.is_empty() {
+        fs::remove_dir_all(&state)?;
+        for error in errors {
+            eprintln!("{}", error);
+        }
+        process::exit(1);
+    }
+    Ok(commands)
+}
+
+// Fails unless the index and working tree match the given commit, as replaying would overwrite local changes
+fn require_clean_work_tree(root: &Path, head: &[u8; 20], global_opts: GlobalOpts) -> Result<()> {
+    let index = read_index(root, global_opts)?;
+    let head_files = commit_files(root, head, global_opts.git_mode)?;
+    let mut filters = Filters::new(root, global_opts)?;
+
+    let mut unstaged = false;
+    for item in &index.items {
+        if hash_worktree_file(root, &item.path, &mut filters)? != Some(item.hash) {
+            unstaged = true;
+            break;
+        }
+    }
+    let staged = index.items.len() != head_files.len()
+        || index.items.iter().any(|x| x.stage != 0 || head_files.get(&x.path).map(|e| e.hash) != Some(x.hash));
+
+    if unstaged {
+        eprintln!("error: cannot rebase: You have unstaged changes.");
+    }
+    if staged {
+        if unstaged {
+            eprintln!("error: additionally, your index contains uncommitted changes.");
+        } else {
+            eprintln!("error: cannot rebase: Your index contains uncommitted changes.");
+        }
+    }
+    if unstaged || staged {
+        eprintln!("error: Please commit or stash them.");
+        process::exit(1);
+    }
+    Ok(())
+}
+
+/// Returns the commits reachable from `head` but not from `upstream`, leaving out merges. They're ordered as
+/// `rev-list --topo-order --reverse` would, so that each commit comes after its parents.
+pub fn commits_to_replay(dag: &CommitDag, upstream: &[u8; 20], head: &[u8; 20]) -> Result<Vec<[u8; 20]>> {
+    let excluded = dag.ancestors(upstream)?;
+    let included: HashSet<[u8; 20]> = dag.ancestors(head)?.into_iter().filter(|x| !excluded.contains(x)).collect();
+
+    // A commit is listed once all of its children have been
+    let mut children: HashMap<[u8; 20], usize> = included.iter().map(|x| (*x, 0)).collect();
+    for hash in &included {
+        for parent in dag.parents(hash)? {
+            if let Some(count) = children.get_mut(&parent) {
+                *count += 1;
+            }
+        }
+    }
+
+    let mut order = Vec::new();
+    let mut pending: Vec<[u8; 20]> = if included.contains(head) { vec![*head] } else { Vec::new() };
+    while let Some(hash) = pending.pop() {
+        let parents = dag.parents(&hash)?;
+        for parent in &parents {
+            if let Some(count) = children.get_mut(parent) {
+                *count -= 1;
+                if *count == 0 {
+                    pending.push(*parent);
+                }
+            }
+        }
+        if parents.len() <= 1 {
+            order.push(hash);
+        }
+    }
+    order.reverse();
+    Ok(order)
+}
+
+// Identifies the changes a commit makes to its first parent, ignoring whitespace and unchanged lines, so that
+// the same changes made by another commit can be recognised. Like `git patch-id`, but not with the same hashes.
+fn patch_id(root: &Path, hash: &[u8; 20], global_opts: GlobalOpts) -> Result<[u8; 20]> {
+    let git_mode = global_opts.git_mode;
+    let commit = read_commit(root, hash, global_opts)?;
+    let old_files = match commit.parents.first() {
+        Some(parent) => commit_files(root, parent, git_mode)?,
+        None => BTreeMap::new()
+    };
+    let new_files = commit_files(root, hash, git_mode)?;
+    let mut paths: Vec<&PathBuf> = old_files.keys().chain(new_files.keys()).collect();
+    paths.sort_by(|x, y| x.as_os_str().as_encoded_bytes().cmp(y.as_os_str().as_encoded_bytes()));
+    paths.dedup();
+
+    let mut hasher = Sha1::new();
+    for path in paths {
+        let old = old_files.get(path).map(|x| x.hash);
+        let new = new_files.get(path).map(|x| x.hash);
+        if old == new {
+            continue;
+        }
+        hasher.update(path.as_os_str().as_encoded_bytes());
+        hasher.update(b"\0");
+
+        let old_contents = match old { Some(x) => get_blob(root, &x, git_mode)?, None => Vec::new() };
+        let new_contents = match new { Some(x) => get_blob(root, &x, git_mode)?, None => Vec::new() };
+        if is_binary(&old_contents) || is_binary(&new_contents) {
+            hasher.update(old.unwrap_or_default());
+            hasher.update(new.unwrap_or_default());
+            continue;
+        }
+        let old_lines = split_lines(&old_contents);
+        let new_lines = split_lines(&new_contents);
+        for edit in myers_diff(&old_lines, &new_lines) {
+            let (sign, line) = match edit {
+                Edit::Delete(i) => (b'-', old_lines[i]),
+                Edit::Insert(j) => (b'+', new_lines[j]),
+                Edit::Equal(..) => continue
+            };
+            hasher.update([sign]);
+            hasher.update(line.iter().filter(|x| !x.is_ascii_whitespace()).copied().collect::<Vec<u8>>());
+        }
+    }
+    Ok(hasher.finalize().into())
+}
+
+// Works through the rest of the todo list, stopping if a commit can't be replayed cleanly
+fn replay(root: &Path, program: &str, global_opts: GlobalOpts) -> Result<()> {
+    let state = state_dir(root, global_opts);
+    let git_dir = root.join(git_dir_name(global_opts));
+    let end = fs::read_to_string(state.join("end"))?.trim().to_string();
+
+    loop {
+        let todo = fs::read_to_string(state.join("git-rebase-todo"))?;
+        let Some((line, rest)) = todo.split_once('\n') else {
+            break;
+        };
+        let (command, hash) = parse_todo_line(line)?;
+
+        let mut done = fs::read_to_string(state.join("done"))?;
+        done += line;
+        done.push('\n');
+        fs::write(state.join("done"), &done)?;
+        fs::write(state.join("git-rebase-todo"), rest)?;
+        let msgnum = done.lines().count();
+        fs::write(state.join("msgnum"), format!("{}\n", msgnum))?;
+        eprint!("Rebasing ({}/{})\r", msgnum, end);
+
+        // Nothing is kept from the commit before
+        clear_stopped(root, global_opts)?;
+        remove_if_exists(&git_dir.join("REBASE_HEAD"))?;
+        remove_if_exists(&git_dir.join("AUTO_MERGE"))?;
+        if command == TodoCommand::Drop {
+            continue;
+        }
+        let prev = head_commit(root, global_opts)?.ok_or(anyhow!("fatal: HEAD does not point to a commit"))?;
+        pick(root, &hash, program, global_opts)?;
+        after_pick(root, command, &prev, &hash, global_opts)?;
+    }
+
+    finish(root, global_opts)
+}
+
+// Applies the changes made by a commit on top of HEAD, committing them with the original author and message.
+// On a conflict the merged files are left in the working tree and the process exits.
+fn pick(root: &Path, hash: &[u8; 20], program: &str, global_opts: GlobalOpts) -> Result<()> {
+    let git_mode = global_opts.git_mode;
+    let git_dir = root.join(git_dir_name(global_opts));
+    let commit = read_commit(root, hash, global_opts)?;
+    let head = head_commit(root, global_opts)?.ok_or(anyhow!("fatal: HEAD does not point to a commit"))?;
+
+    // A commit whose parent is already HEAD is kept as it is
+    let parent = commit.parents.first().copied();
+    if parent == Some(head) {
+        switch_commit(root, Some(head), hash, global_opts)?;
+        return write_head(root, &Head::Detached(*hash), global_opts);
+    }
+
+    let base_files = match parent {
+        Some(parent) => commit_files(root, &parent, git_mode)?,
+        None => BTreeMap::new()
+    };
+    let our_files = commit_files(root, &head, git_mode)?;
+    let their_files = commit_files(root, hash, git_mode)?;
+    let short = &hex::encode(hash)[..7];
+    let their_label = format!("{} ({})", short, commit.subject());
+    let merge = merge_trees(root, &base_files, &our_files, &their_files, "HEAD", &their_label, global_opts)?;
+
+    if merge.conflicts.is_empty() {
+        let tree = files_tree(root, &merge.files, global_opts)?;
+        fs::write(git_dir.join("AUTO_MERGE"), format!("{}\n", hex::encode(tree)))?;
+
+        // A commit whose changes are already in HEAD is dropped
+        if tree == read_commit(root, &head, global_opts)?.tree {
+            return Ok(());
+        }
+        let config = Config::load(root, global_opts)?;
+        let replayed = Commit {
+            tree,
+            author: commit.author.clone(),
+            committer: signature(&config, "COMMITTER", global_opts)?.to_string(),
+            parents: vec![head],
+            message: commit.message.clone(),
+            gpgsig: None,
+        };
+        replayed.write(root, global_opts)?;
+        switch_files(root, &our_files, &merge.files, global_opts)?;
+        return write_head(root, &Head::Detached(replayed.hash()), global_opts);
+    }
+
+    let worktree_files = checkout_merge(root, &our_files, &merge, global_opts)?;
+
+    // Record what was being replayed, so that `--continue` can commit it once the conflicts are resolved
+    let tree = files_tree(root, &worktree_files, global_opts)?;
+    fs::write(git_dir.join("AUTO_MERGE"), format!("{}\n", hex::encode(tree)))?;
+    fs::write(git_dir.join("REBASE_HEAD"), format!("{}\n", hex::encode(hash)))?;
+    let mut merge_msg = format!("{}\n# Conflicts:\n", commit.message);
+    for conflict in &merge.conflicts {
+        merge_msg += &format!("#\t{}\n", conflict.path.to_string_lossy());
+    }
+    fs::write(git_dir.join("MERGE_MSG"), merge_msg)?;
+    let state = state_dir(root, global_opts);
+    fs::write(state.join("stopped-sha"), format!("{}\n", hex::encode(hash)))?;
+    fs::write(state.join("message"), format!("{}\n", commit.message))?;
+    let author = Signature::parse(&commit.author)?;
+    fs::write(state.join("author-script"), format!(
+        "GIT_AUTHOR_NAME={}\nGIT_AUTHOR_EMAIL={}\nGIT_AUTHOR_DATE={}\n",
+        shell_quote(&author.name), shell_quote(&author.email), shell_quote(&format!("@{} {}", author.timestamp, author.timezone))
+    ))?;
+
+    for message in &merge.messages {
+        println!("{}", message);
+    }
+    eprintln!(
+        "error: could not apply {short}... {subject}\n\
+        hint: Resolve all conflicts manually, mark them as resolved with\n\
+        hint: \"{program} add/rm <conflicted_files>\", then run \"{program} rebase --continue\".\n\
+        hint: You can instead skip this commit: run \"{program} rebase --skip\".\n\
+        hint: To abort and get back to the state before \"{program} rebase\", run \"{program} rebase --abort\".\n\
+        Could not apply {short}... {subject}",
+        subject = commit.subject()
+    );
+    process::exit(1);
+}
+
+// Commits the resolution of the commit the rebase stopped at, with its original author and message
+fn commit_resolved(root: &Path, program: &str, global_opts: GlobalOpts) -> Result<()> {
+    let state = state_dir(root, global_opts);
+    let index = read_index(root, global_opts)?;
+    if index.has_conflicts() {
+        let mut paths: Vec<String> = index.items.iter().filter(|x| x.stage != 0).map(|x| x.path.to_string_lossy().to_string()).collect();
+        paths.dedup();
+        for path in paths {
+            println!("{}: needs merge", path);
+        }
+        println!("You must edit all merge conflicts and then\nmark them as resolved using {} add", program);
+        process::exit(1);
+    }
+    if !state.join("stopped-sha").exists() {
+        return Ok(());
+    }
+
+    let stopped = parse_hash(fs::read_to_string(state.join("stopped-sha"))?.trim_end())?;
+    let done = fs::read_to_string(state.join("done"))?;
+    let (mut command, _) = parse_todo_line(done.lines().last().unwrap_or_default())?;
+    let head = head_commit(root, global_opts)?.ok_or(anyhow!("fatal: HEAD does not point to a commit"))?;
+    let tree = write_tree(index, root, global_opts)?.hash();
+    // If the resolution left HEAD's files as they were, there's nothing to commit and the commit is dropped
+    if tree != read_commit(root, &head, global_opts)?.tree {
+        let mut author = Signature { name: String::new(), email: String::new(), timestamp: 0, timezone: String::new() };
+        for line in fs::read_to_string(state.join("author-script"))?.lines() {
+            let Some((key, value)) = line.split_once('=') else { continue };
+            let value = shell_unquote(value);
+            match key {
+                "GIT_AUTHOR_NAME" => author.name = value,
+                "GIT_AUTHOR_EMAIL" => author.email = value,
+                "GIT_AUTHOR_DATE" => {
+                    let (timestamp, timezone) = value.trim_start_matches('@').split_once(' ').unwrap_or((&value, "+0000"));
+                    author.timestamp = timestamp.parse()?;
+                    author.timezone = timezone.to_string();
+                },
+                _ => ()
+            }
+        }
+
+        // An interactive rebase lets the message be edited now, rather than rewording the commit afterwards
+        let mut message = stripspace(&fs::read_to_string(state.join("message"))?, false);
+        if state.join("interactive").exists() && matches!(command, TodoCommand::Pick | TodoCommand::Reword) {
+            message = edit_message(root, &message, global_opts)?;
+            command = TodoCommand::Pick;
+        }
+
+        let config = Config::load(root, global_opts)?;
+        let commit = Commit {
+            tree,
+            author: author.to_string(),
+            committer: signature(&config, "COMMITTER", global_opts)?.to_string(),
+            parents: vec![head],
+            message,
+            gpgsig: None,
+        };
+        commit.write(root, global_opts)?;
+        let hash = commit.hash();
+        write_head(root, &Head::Detached(hash), global_opts)?;
+        // A commit to be melded into the one before is reported once that's done
+        if !matches!(command, TodoCommand::Squash | TodoCommand::Fixup) {
+            let initial = commit.parents.is_empty();
+            print_commit_summary(root, &Head::Detached(head), &hash, &commit, initial, false, global_opts)?;
+        }
+        remove_if_exists(&root.join(git_dir_name(global_opts)).join("AUTO_MERGE"))?;
+    }
+    clear_stopped(root, global_opts)?;
+    after_pick(root, command, &head, &stopped, global_opts)
+}
+
+// Finishes a command once its commit has been picked onto `prev`, by rewording the new commit for `reword`,
+// or melding it into `prev` for `squash` and `fixup`
+fn after_pick(root: &Path, command: TodoCommand, prev: &[u8; 20], picked: &[u8; 20], global_opts: GlobalOpts) -> Result<()> {
+    let state = state_dir(root, global_opts);
+    let head = head_commit(root, global_opts)?.ok_or(anyhow!("fatal: HEAD does not point to a commit"))?;
+    match command {
+        // A commit dropped for having no changes left has nothing to reword
+        TodoCommand::Reword if head != *prev => {
+            let commit = read_commit(root, &head, global_opts)?;
+            let message = edit_message(root, &commit.message, global_opts)?;
+            amend_head(root, &commit, commit.tree, message, true, global_opts)
+        },
+        TodoCommand::Squash | TodoCommand::Fixup => {
+            let prev_commit = read_commit(root, prev, global_opts)?;
+            let picked_commit = read_commit(root, picked, global_opts)?;
+            let tree = read_commit(root, &head, global_opts)?.tree;
+
+            // As in Git, the combined message lists each commit's message, with those of fixups commented out
+            let mut fixups = fs::read_to_string(state.join("current-fixups")).unwrap_or_default();
+            let count = fixups.lines().count() + 2;
+            let messages = match fs::read_to_string(state.join("message-squash")) {
+                Ok(message) => message.split_once('\n').map(|x| x.1.to_string()).unwrap_or_default(),
+                Err(_) => format!("# This is the 1st commit message:\n\n{}", prev_commit.message)
+            };
+            let mut message = format!("# This is a combination of {} commits.\n{}", count, messages);
+            if command == TodoCommand::Squash {
+                message += &format!("\n# This is the commit message #{}:\n\n{}", count, picked_commit.message);
+            } else {
+                message += &format!("\n# The commit message #{} will be skipped:\n\n", count);
+                for line in picked_commit.message.lines() {
+                    message += &if line.is_empty() { String::from("#\n") } else { format!("# {}\n", line) };
+                }
+            }
+            fixups += &format!("{} {}\n", command.name(), hex::encode(picked));
+
+            // The message is only edited once the last of a run of squashes and fixups has been melded in
+            let todo = fs::read_to_string(state.join("git-rebase-todo"))?;
+            let next = todo.lines().next().map(parse_todo_line).transpose()?.map(|x| x.0);
+            if matches!(next, Some(TodoCommand::Squash | TodoCommand::Fixup)) {
+                fs::write(state.join("message-squash"), &message)?;
+                fs::write(state.join("current-fixups"), &fixups)?;
+                return amend_head(root, &prev_commit, tree, stripspace(&message, true), false, global_opts);
+            }
+            remove_if_exists(&state.join("message-squash"))?;
+            remove_if_exists(&state.join("current-fixups"))?;
+            if !fixups.lines().any(|x| x.starts_with("squash ")) {
+                return amend_head(root, &prev_commit, tree, stripspace(&message, true), false, global_opts);
+            }
+            let git_dir = root.join(git_dir_name(global_opts));
+            fs::write(git_dir.join("REBASE_HEAD"), format!("{}\n", hex::encode(picked)))?;
+            let message = edit_message(root, &message, global_opts)?;
+            amend_head(root, &prev_commit, tree, message, true, global_opts)
+        },
+        _ => Ok(())
+    }
+}
+
+// Replaces HEAD with a commit of the given tree and message, taking the author and parents from `replaced`.
+// Like Git, a commit whose message was edited is reported as `commit --amend` would.
+fn amend_head(
+    root: &Path,
+    replaced: &Commit,
+    tree: [u8; 20],
+    message: String,
+    edited: bool,
+    global_opts: GlobalOpts
+) -> Result<()> {
+    let config = Config::load(root, global_opts)?;
+    let commit = Commit {
+        tree,
+        author: replaced.author.clone(),
+        committer: signature(&config, "COMMITTER", global_opts)?.to_string(),
+        parents: replaced.parents.clone(),
+        message,
+        gpgsig: None,
+    };
+    commit.write(root, global_opts)?;
+    let hash = commit.hash();
+    write_head(root, &Head::Detached(hash), global_opts)?;
+    if edited {
+        print_commit_summary(root, &Head::Detached(hash), &hash, &commit, false, true, global_opts)?;
+        remove_if_exists(&root.join(git_dir_name(global_opts)).join("AUTO_MERGE"))?;
+    }
+    Ok(())
+}
+
+// Opens a commit message in the editor, returning it without comments once the editor exits
+fn edit_message(root: &Path, message: &str, global_opts: GlobalOpts) -> Result<String> {
+    let file = PathBuf::from(git_dir_name(global_opts)).join("COMMIT_EDITMSG");
+    fs::write(root.join(&file), format!(
+        "{}\n# Please enter the commit message for your changes. Lines starting\n\
+        # with '#' will be ignored, and an empty message aborts the commit.\n",
+        message
+    ))?;
+    let config = Config::load(root, global_opts)?;
+    launch_editor(root, &file, &git_editor(&config))?;
+
+    let message = stripspace(&fs::read_to_string(root.join(&file))?, true);
+    if message.is_empty() {
+        bail!("Aborting commit due to empty commit message.");
+    }
+    Ok(message)
+}
+
+// Removes the files describing the commit the rebase stopped at
+fn clear_stopped(root: &Path, global_opts: GlobalOpts) -> Result<()> {
+    let state = state_dir(root, global_opts);
+    let git_dir = root.join(git_dir_name(global_opts));
+    for file in [state.join("stopped-sha"), state.join("message"), state.join("author-script"), git_dir.join("MERGE_MSG")] {
+        remove_if_exists(&file)?;
+    }
+    Ok(())
+}
+
+// Points the branch being rebased at the replayed commits and checks it out again
+fn finish(root: &Path, global_opts: GlobalOpts) -> Result<()> {
+    let state = state_dir(root, global_opts);
+    let head_name = fs::read_to_string(state.join("head-name"))?.trim_end().to_string();
+    let hash = head_commit(root, global_opts)?.ok_or(anyhow!("fatal: HEAD does not point to a commit"))?;
+    if head_name != "detached HEAD" {
+        let orig_head = parse_hash(fs::read_to_string(state.join("orig-head"))?.trim_end())?;
+        let onto = fs::read_to_string(state.join("onto"))?.trim_end().to_string();
+        write_ref(root, &head_name, &hash, global_opts)?;
+        write_head(root, &Head::Branch(head_name.clone()), global_opts)?;
+
+        // As in Git, the branch's reflog records the whole rebase as one move, and HEAD's its return to the branch
+        let identity = signature(&Config::load(root, global_opts)?, "COMMITTER", global_opts)?;
+        let message = format!("rebase (finish): {} onto {}", head_name, onto);
+        append_reflog(root, &head_name, Some(orig_head), &hash, &identity, &message, global_opts)?;
+        let message = format!("rebase (finish): returning to {}", head_name);
+        append_reflog(root, "HEAD", Some(hash), &hash, &identity, &message, global_opts)?;
+    }
+    fs::remove_dir_all(&state)?;
+
+    clear_progress();
+    eprintln!("Successfully rebased and updated {}.", head_name);
+    Ok(())
+}
+
+// Returns the branch to the commit it was on before the rebase started, discarding the rebase
+fn abort(root: &Path, global_opts: GlobalOpts) -> Result<()> {
+    let state = state_dir(root, global_opts);
+    let git_dir = root.join(git_dir_name(global_opts));
+    let head_name = fs::read_to_string(state.join("head-name"))?.trim_end().to_string();
+    let orig_head = parse_hash(fs::read_to_string(state.join("orig-head"))?.trim_end())?;
+
+    reset_hard(root, &orig_head, global_opts)?;
+    if head_name == "detached HEAD" {
+        write_head(root, &Head::Detached(orig_head), global_opts)?;
+    } else {
+        write_head(root, &Head::Branch(head_name), global_opts)?;
+    }
+
+    fs::remove_dir_all(&state)?;
+    for name in ["REBASE_HEAD", "AUTO_MERGE", "MERGE_MSG"] {
+        remove_if_exists(&git_dir.join(name))?;
+    }
+    Ok(())
+}
+
+fn remove_if_exists(file: &Path) -> Result<()> {
+    if file.exists() {
+        fs::remove_file(file)?;
+    }
+    Ok(())
+}
+
+// Clears the line the progress messages were written on. Like Git, terminals that can't clear to the end of the
+// line have it overwritten with spaces.
+fn clear_progress() {
+    match env::var("TERM") {
+        Ok(term) if term != "dumb" => eprint!("\r\x1b[K"),
+        _ => {
+            let columns = env::var("COLUMNS").ok().and_then(|x| x.parse().ok()).unwrap_or(80);
+            eprint!("\r{}\r", " ".repeat(columns));
+        }
+    }
+}
+
+// Quotes a value for the author script, which is read by the shell
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+fn shell_unquote(value: &str) -> String {
+    value.trim_start_matches('\'').trim_end_matches('\'').replace("'\\''", "'")
+}
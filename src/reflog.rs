@@ -0,0 +1,91 @@
+// Managing the reflogs, which record the previous values of HEAD and the branches
+
+use std::{env, fs, path::Path, process};
+use anyhow::{anyhow, Result};
+use clap::{Args, Subcommand};
+
+use crate::{GlobalOpts, repo_find, git_dir_name};
+use crate::config::Config;
+use crate::date::parse_expiry_date;
+use crate::refs::{full_ref_name, read_reflog, write_reflog};
+
+// How long entries are kept if neither `--expire` nor `gc.reflogExpire` says otherwise
+const DEFAULT_EXPIRE: &str = "90.days.ago";
+
+#[derive(Args)]
+pub struct ReflogArgs {
+    #[command(subcommand)]
+    pub command: ReflogCommand,
+}
+
+#[derive(Subcommand)]
+pub enum ReflogCommand {
+    /// Remove entries older than the expiry date from the reflogs of the given references
+    Expire {
+        /// Remove entries older than this, e.g. `now`, `2.weeks.ago` or a Unix timestamp
+        #[arg(long)]
+        expire: Option<String>,
+        /// Expire entries from every reflog
+        #[arg(long)]
+        all: bool,
+        refs: Vec<String>,
+    },
+}
+
+pub fn cmd_reflog(args: ReflogArgs, global_opts: GlobalOpts) -> Result<()> {
+    let path = env::current_dir().unwrap_or_else(|_| { panic!() });
+    let root = repo_find(&path, global_opts).unwrap_or_else(|| {
+        panic!("fatal: not a grit repository");
+    });
+
+    match args.command {
+        ReflogCommand::Expire { expire, all, refs } => {
+            let config = Config::load(&root, global_opts)?;
+            let expire = expire.or(config.get("gc.reflogExpire")).unwrap_or(String::from(DEFAULT_EXPIRE));
+            let cutoff = parse_expiry_date(&expire)
+                .map_err(|_| anyhow!("fatal: invalid timestamp '{}' given to '--expire'", expire))?;
+
+            let mut names = Vec::new();
+            if all {
+                let logs_dir = root.join(git_dir_name(global_opts)).join("logs");
+                if logs_dir.is_dir() {
+                    collect_reflogs(&logs_dir, "", &mut names)?;
+                }
+            }
+            for name in refs {
+                let full_name = if name == "HEAD" { Some(name.clone()) } else { full_ref_name(&root, &name, global_opts)? };
+                match full_name {
+                    Some(full_name) => names.push(full_name),
+                    None => {
+                        eprintln!("error: {} points nowhere!", name);
+                        process::exit(255);
+                    }
+                }
+            }
+
+            for name in names {
+                let mut entries = read_reflog(&root, &name, global_opts)?;
+                let count = entries.len();
+                entries.retain(|x| x.identity.timestamp >= cutoff);
+                if entries.len() != count {
+                    write_reflog(&root, &name, &entries, global_opts)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+// Lists the references with reflogs under the given directory of `logs`, by full name
+fn collect_reflogs(dir: &Path, prefix: &str, names: &mut Vec<String>) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = format!("{}{}", prefix, entry.file_name().to_string_lossy());
+        if entry.file_type()?.is_dir() {
+            collect_reflogs(&entry.path(), &format!("{}/", name), names)?;
+        } else {
+            names.push(name);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,536 @@
+// Reading and writing references: HEAD, branches and tags.
+
+use std::{collections::BTreeSet, fs, io::Write, path::Path};
+use anyhow::{anyhow, bail, Result};
+
+use crate::{GlobalOpts, git_dir_name, objects::{parse_hash, peel, find_objects_by_prefix, Signature}};
+use crate::config::Config;
+use crate::remote::{push_ref, upstream_ref};
+
+/// One line of a reflog, recording a change to the value of a reference
+pub struct ReflogEntry {
+    /// The previous value, all zeros if the reference was created
+    pub old: [u8; 20],
+    pub new: [u8; 20],
+    /// Who made the change and when
+    pub identity: Signature,
+    /// What made the change, e.g. `commit: Fix the thing`
+    pub message: String,
+}
+
+impl ReflogEntry {
+    /// Parses a line in the form `<old> <new> Name <email> 1234567890 +0100\t<message>`
+    pub fn parse(line: &str) -> Result<ReflogEntry> {
+        let (fields, message) = line.split_once('\t').unwrap_or((line, ""));
+        let mut parts = fields.splitn(3, ' ');
+        let (Some(old), Some(new), Some(identity)) = (parts.next(), parts.next(), parts.next()) else {
+            bail!("fatal: malformed reflog entry: {}", line);
+        };
+        Ok(ReflogEntry {
+            old: parse_hash(old)?,
+            new: parse_hash(new)?,
+            identity: Signature::parse(identity)?,
+            message: message.to_string(),
+        })
+    }
+}
+
+impl std::fmt::Display for ReflogEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {} {}\t{}", hex::encode(self.old), hex::encode(self.new), self.identity, self.message)
+    }
+}
+
+/// The state of HEAD: either a symbolic reference to a branch, or a detached commit.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Head {
+    /// The full name of the branch HEAD points at, e.g. `refs/heads/master`
+    Branch(String),
+    Detached([u8; 20])
+}
+
+pub fn read_head(root: &Path, global_opts: GlobalOpts) -> Result<Head> {
+    let head_path = root.join(format!("{}/HEAD", git_dir_name(global_opts)));
+    let contents = fs::read_to_string(head_path)?;
+    let contents = contents.trim_end();
+
+    match contents.strip_prefix("ref: ") {
+        Some(name) => Ok(Head::Branch(name.to_string())),
+        None => Ok(Head::Detached(parse_hash(contents)?))
+    }
+}
+
+pub fn write_head(root: &Path, head: &Head, global_opts: GlobalOpts) -> Result<()> {
+    let head_path = root.join(format!("{}/HEAD", git_dir_name(global_opts)));
+    let contents = match head {
+        Head::Branch(name) => format!("ref: {}\n", name),
+        Head::Detached(hash) => format!("{}\n", hex::encode(hash))
+    };
+    fs::write(head_path, contents)?;
+    Ok(())
+}
+
+/// Returns the commit HEAD currently points at, or None if HEAD is on a branch with no commits yet.
+pub fn head_commit(root: &Path, global_opts: GlobalOpts) -> Result<Option<[u8; 20]>> {
+    match read_head(root, global_opts)? {
+        Head::Branch(name) => read_ref(root, &name, global_opts),
+        Head::Detached(hash) => Ok(Some(hash))
+    }
+}
+
+/// Reads the reference with the given full name (e.g. `refs/heads/master`), following symbolic references.
+/// Loose references take precedence over `packed-refs`. Returns None if the reference does not exist.
+pub fn read_ref(root: &Path, name: &str, global_opts: GlobalOpts) -> Result<Option<[u8; 20]>> {
+    read_git_dir_ref(&root.join(git_dir_name(global_opts)), name)
+}
+
+/// Reads a reference as for `read_ref`, from the repository with the given git directory, which may be a bare
+/// repository
+pub fn read_git_dir_ref(git_dir: &Path, name: &str) -> Result<Option<[u8; 20]>> {
+    let ref_path = git_dir.join(name);
+
+    if ref_path.is_file() {
+        let contents = fs::read_to_string(&ref_path)?;
+        let contents = contents.trim_end();
+        return match contents.strip_prefix("ref: ") {
+            Some(target) => read_git_dir_ref(git_dir, target),
+            None => Ok(Some(parse_hash(contents)
+                .map_err(|_| anyhow!("fatal: invalid reference file {}", ref_path.to_string_lossy()))?))
+        };
+    }
+
+    let packed_path = git_dir.join("packed-refs");
+    if packed_path.exists() {
+        for line in fs::read_to_string(packed_path)?.lines() {
+            // Skip the header and the peeled values of annotated tags
+            if line.starts_with('#') || line.starts_with('^') {
+                continue;
+            }
+            if let Some((hash, ref_name)) = line.split_once(' ') {
+                if ref_name == name {
+                    return Ok(Some(parse_hash(hash)?));
+                }
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+/// Points the reference with the given full name at the given object, creating it if necessary. The name must be a
+/// valid one, so that a name from elsewhere, such as a remote, can't write outside the references.
+pub fn write_ref(root: &Path, name: &str, hash: &[u8; 20], global_opts: GlobalOpts) -> Result<()> {
+    check_name_to_write(name)?;
+    let ref_path = root.join(git_dir_name(global_opts)).join(name);
+    if let Some(parent) = ref_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(ref_path, format!("{}\n", hex::encode(hash)))?;
+    Ok(())
+}
+
+/// Points a reference at a new object, but only if it still points at `old`, or doesn't exist if `old` is None, as
+/// `git update-ref <name> <new> <old>` does. The reference is locked while it's checked and written, so of two updates
+/// made at once only one can succeed. Takes the git directory, so that bare repositories can be updated too.
+pub fn update_ref(git_dir: &Path, name: &str, new: &[u8; 20], old: Option<[u8; 20]>) -> Result<()> {
+    check_name_to_write(name)?;
+    let ref_path = git_dir.join(name);
+    if let Some(parent) = ref_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let lock_path = git_dir.join(format!("{}.lock", name));
+    let mut lock = fs::OpenOptions::new().write(true).create_new(true).open(&lock_path).map_err(|_| {
+        anyhow!("cannot lock ref '{}': Unable to create '{}': File exists.", name, lock_path.to_string_lossy())
+    })?;
+
+    let result = (|| {
+        match (read_git_dir_ref(git_dir, name)?, old) {
+            (None, Some(_)) => bail!("cannot lock ref '{}': unable to resolve reference '{}'", name, name),
+            (Some(_), None) => bail!("cannot lock ref '{}': reference already exists", name),
+            (Some(current), Some(old)) if current != old => bail!(
+                "cannot lock ref '{}': is at {} but expected {}", name, hex::encode(current), hex::encode(old)
+            ),
+            _ => ()
+        }
+        writeln!(lock, "{}", hex::encode(new))?;
+        fs::rename(&lock_path, &ref_path)?;
+        Ok(())
+    })();
+    // The lock is released by removing it if the reference wasn't written
+    if result.is_err() {
+        fs::remove_file(&lock_path)?;
+    }
+    result
+}
+
+/// Deletes the references with the given full names, both loose and packed.
+/// Like Git, `packed-refs` is rewritten without them, and created if it didn't exist.
+pub fn delete_refs(root: &Path, names: &[String], global_opts: GlobalOpts) -> Result<()> {
+    let git_dir = root.join(git_dir_name(global_opts));
+    let packed_path = git_dir.join("packed-refs");
+
+    let mut packed = String::from("# pack-refs with: peeled fully-peeled sorted \n");
+    if packed_path.exists() {
+        // A peeled value belongs to the reference on the line before it
+        let mut deleted = false;
+        for line in fs::read_to_string(&packed_path)?.lines() {
+            if line.starts_with('#') {
+                continue;
+            }
+            if !line.starts_with('^') {
+                deleted = line.split_once(' ').is_some_and(|(_, name)| names.iter().any(|x| x == name));
+            }
+            if !deleted {
+                packed += line;
+                packed.push('\n');
+            }
+        }
+    }
+    fs::write(&packed_path, packed)?;
+
+    for name in names {
+        let ref_path = git_dir.join(name);
+        if ref_path.is_file() {
+            fs::remove_file(ref_path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Resolves a revision as given on the command line to an object hash.
+/// Accepts full or abbreviated hashes, `HEAD`, reference names in the order Git searches them, reflog entries
+/// such as `HEAD@{1}`, and the upstream and push branches given by `@{upstream}` and `@{push}`.
+pub fn resolve_revision(root: &Path, rev: &str, global_opts: GlobalOpts) -> Result<[u8; 20]> {
+    if rev.len() == 40 {
+        if let Ok(hash) = parse_hash(rev) {
+            return Ok(hash);
+        }
+    }
+
+    if rev == "HEAD" || rev == "@" {
+        return head_commit(root, global_opts)?
+            .ok_or(anyhow!("fatal: ambiguous argument 'HEAD': unknown revision or path not in the working tree."));
+    }
+
+    let candidates = [
+        rev.to_string(),
+        format!("refs/{}", rev),
+        format!("refs/tags/{}", rev),
+        format!("refs/heads/{}", rev),
+        format!("refs/remotes/{}", rev),
+        format!("refs/remotes/{}/HEAD", rev),
+    ];
+    for candidate in &candidates {
+        if let Some(hash) = read_ref(root, candidate, global_opts)? {
+            return Ok(hash);
+        }
+    }
+
+    if rev.len() >= 4 && rev.chars().all(|c| c.is_ascii_hexdigit()) {
+        let matches = find_objects_by_prefix(root, &rev.to_lowercase(), global_opts.git_mode)?;
+        match matches.len() {
+            0 => (),
+            1 => return Ok(matches[0]),
+            _ => bail!("error: short object ID {} is ambiguous", rev)
+        }
+    }
+
+    if let Some(hash) = resolve_reflog_revision(root, rev, global_opts)? {
+        return Ok(hash);
+    }
+    if let Some(hash) = resolve_tracking_revision(root, rev, global_opts)? {
+        return Ok(hash);
+    }
+
+    bail!("fatal: ambiguous argument '{}': unknown revision or path not in the working tree.", rev)
+}
+
+// Resolves a revision like `HEAD@{2}` or `master@{1}` to the value the reference had that many changes ago,
+// as recorded in its reflog. A bare `@{N}` refers to the current branch.
+fn resolve_reflog_revision(root: &Path, rev: &str, global_opts: GlobalOpts) -> Result<Option<[u8; 20]>> {
+    let Some((base, count)) = rev.strip_suffix('}').and_then(|x| x.rsplit_once("@{")) else {
+        return Ok(None);
+    };
+    let Ok(count) = count.parse::<usize>() else {
+        return Ok(None);
+    };
+
+    let name = match base {
+        "" => match read_head(root, global_opts)? {
+            Head::Branch(name) => name,
+            Head::Detached(_) => String::from("HEAD")
+        },
+        "HEAD" => String::from("HEAD"),
+        _ => match full_ref_name(root, base, global_opts)? {
+            Some(name) => name,
+            None => return Ok(None)
+        }
+    };
+
+    // Entries are listed oldest first. Counting back past the oldest gives the value it replaced.
+    let entries = read_reflog(root, &name, global_opts)?;
+    let display_name = if base.is_empty() { name.strip_prefix("refs/heads/").unwrap_or(&name) } else { base };
+    if entries.is_empty() {
+        // As in Git, the current value counts as the newest entry even once the log has been emptied
+        if count == 0 {
+            let current = if name == "HEAD" { head_commit(root, global_opts)? } else { read_ref(root, &name, global_opts)? };
+            return Ok(current);
+        }
+        bail!("fatal: log for {} is empty", display_name);
+    }
+    if count < entries.len() {
+        return Ok(Some(entries[entries.len() - 1 - count].new));
+    }
+    if count == entries.len() && entries[0].old != [0; 20] {
+        return Ok(Some(entries[0].old));
+    }
+    bail!("fatal: log for '{}' only has {} entries", display_name, entries.len())
+}
+
+// Resolves `<branch>@{upstream}` (or `@{u}`) to the branch it merges from, and `<branch>@{push}` to where
+// it would be pushed, as remote-tracking branches. A bare `@{u}` or `@{push}` refers to the current branch.
+fn resolve_tracking_revision(root: &Path, rev: &str, global_opts: GlobalOpts) -> Result<Option<[u8; 20]>> {
+    let Some((base, suffix)) = rev.strip_suffix('}').and_then(|x| x.rsplit_once("@{")) else {
+        return Ok(None);
+    };
+    let push = match suffix.to_lowercase().as_str() {
+        "u" | "upstream" => false,
+        "push" => true,
+        _ => return Ok(None)
+    };
+
+    let branch = match base {
+        "" | "HEAD" => match read_head(root, global_opts)? {
+            Head::Branch(name) => name.strip_prefix("refs/heads/").unwrap_or(&name).to_string(),
+            Head::Detached(_) => bail!("fatal: HEAD does not point to a branch")
+        },
+        _ => base.to_string()
+    };
+    let exists = read_ref(root, &format!("refs/heads/{}", branch), global_opts)?.is_some();
+    let config = Config::load(root, global_opts)?;
+    let name = if push { push_ref(&config, &branch, exists)? } else { upstream_ref(&config, &branch, exists)? };
+    read_ref(root, &name, global_opts)
+}
+
+/// Finds the full name of an existing reference from a short name, e.g. `refs/heads/master` for `master`,
+/// searching in the same order as for revisions
+pub fn full_ref_name(root: &Path, name: &str, global_opts: GlobalOpts) -> Result<Option<String>> {
+    let candidates = [
+        name.to_string(),
+        format!("refs/{}", name),
+        format!("refs/tags/{}", name),
+        format!("refs/heads/{}", name),
+        format!("refs/remotes/{}", name),
+    ];
+    for candidate in candidates {
+        if read_ref(root, &candidate, global_opts)?.is_some() {
+            return Ok(Some(candidate));
+        }
+    }
+    Ok(None)
+}
+
+/// Shortens the full name of a reference as far as it can be while still naming the same reference as a revision,
+/// e.g. `master` for `refs/heads/master`, or `heads/master` if there's also a tag called `master`. As with Git's
+/// `core.warnAmbiguousRefs`, a short name is only used if no other reference could be meant by it.
+pub fn shorten_ref_name(root: &Path, name: &str, global_opts: GlobalOpts) -> Result<String> {
+    // The ways a short name is looked up, in the order they're tried, as a prefix and suffix of the full name
+    let rules = [("", ""), ("refs/", ""), ("refs/tags/", ""), ("refs/heads/", ""), ("refs/remotes/", ""),
+        ("refs/remotes/", "/HEAD")];
+    // The full name itself is never taken as the shortest, so the first rule is left out
+    for (i, (prefix, suffix)) in rules.iter().enumerate().skip(1).rev() {
+        let Some(short) = name.strip_prefix(prefix).and_then(|x| x.strip_suffix(suffix)).filter(|x| !x.is_empty())
+        else {
+            continue;
+        };
+        let mut ambiguous = false;
+        for (j, (prefix, suffix)) in rules.iter().enumerate() {
+            if j != i && read_ref(root, &format!("{}{}{}", prefix, short, suffix), global_opts)?.is_some() {
+                ambiguous = true;
+                break;
+            }
+        }
+        if !ambiguous {
+            return Ok(short.to_string());
+        }
+    }
+    Ok(name.to_string())
+}
+
+/// Checks that a full reference name, e.g. `refs/heads/topic`, follows Git's rules, which keep names usable in
+/// revisions and as paths under the git directory. The error says which rule the name breaks.
+pub fn check_ref_name(name: &str) -> Result<()> {
+    if name == "@" {
+        bail!("'@' is not a valid name");
+    }
+    if name.ends_with('.') {
+        bail!("a reference can't end with '.'");
+    }
+    if name.contains("@{") {
+        bail!("a reference can't contain '@{{'");
+    }
+    if let Some(c) = name.chars().find(|c| c.is_ascii_control() || " ~^:?*[\\".contains(*c)) {
+        bail!("a reference can't contain {:?}", c);
+    }
+    for component in name.split('/') {
+        if component.is_empty() {
+            bail!("a reference can't start or end with '/', or contain '//'");
+        }
+        if component.starts_with('.') {
+            bail!("a component of a reference can't start with '.'");
+        }
+        if component.contains("..") {
+            bail!("a reference can't contain '..'");
+        }
+        if component.ends_with(".lock") {
+            bail!("a component of a reference can't end with '.lock'");
+        }
+    }
+    Ok(())
+}
+
+// Checks the name of a reference about to be written, or whose reflog is, as Git does before any update
+fn check_name_to_write(name: &str) -> Result<()> {
+    check_ref_name(name).map_err(|_| anyhow!("fatal: refusing to update ref with bad name '{}'", name))
+}
+
+/// Checks that a branch can be given the short name `name`. As well as following the rules for references, a branch
+/// can't be called `HEAD` or start with `-`, so that its name can't be mistaken for HEAD or an option.
+pub fn check_branch_name(name: &str) -> Result<()> {
+    if name == "HEAD" || name.starts_with('-') {
+        bail!("'{}' could be mistaken for HEAD or an option", name);
+    }
+    check_ref_name(&format!("refs/heads/{}", name))
+}
+
+/// Reads the reflog of the reference with the given full name, oldest entry first.
+/// A reference without a reflog has no entries.
+pub fn read_reflog(root: &Path, name: &str, global_opts: GlobalOpts) -> Result<Vec<ReflogEntry>> {
+    let log_path = root.join(git_dir_name(global_opts)).join("logs").join(name);
+    if !log_path.is_file() {
+        return Ok(Vec::new());
+    }
+    fs::read_to_string(log_path)?.lines().filter(|x| !x.is_empty()).map(ReflogEntry::parse).collect()
+}
+
+/// Replaces the reflog of the reference with the given full name
+pub fn write_reflog(root: &Path, name: &str, entries: &[ReflogEntry], global_opts: GlobalOpts) -> Result<()> {
+    check_name_to_write(name)?;
+    let log_path = root.join(git_dir_name(global_opts)).join("logs").join(name);
+    let contents: String = entries.iter().map(|x| format!("{}\n", x)).collect();
+    fs::write(log_path, contents)?;
+    Ok(())
+}
+
+/// Adds an entry to the reflog of the reference with the given full name, creating the reflog if needed.
+/// `old` is None if the reference didn't exist before.
+pub fn append_reflog(
+    root: &Path,
+    name: &str,
+    old: Option<[u8; 20]>,
+    new: &[u8; 20],
+    identity: &Signature,
+    message: &str,
+    global_opts: GlobalOpts
+) -> Result<()> {
+    check_name_to_write(name)?;
+    let log_path = root.join(git_dir_name(global_opts)).join("logs").join(name);
+    if let Some(parent) = log_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let old = old.unwrap_or([0; 20]);
+    let mut file = fs::OpenOptions::new().create(true).append(true).open(log_path)?;
+    writeln!(file, "{} {} {}\t{}", hex::encode(old), hex::encode(new), identity, message)?;
+    Ok(())
+}
+
+/// Returns every reference under `refs/` with the object it points at, sorted by name.
+/// Loose references take precedence over `packed-refs`, and symbolic references are followed.
+pub fn list_refs(root: &Path, global_opts: GlobalOpts) -> Result<Vec<(String, [u8; 20])>> {
+    let git_dir = root.join(git_dir_name(global_opts));
+
+    let mut names = BTreeSet::new();
+    let refs_dir = git_dir.join("refs");
+    if refs_dir.is_dir() {
+        collect_loose_refs(&refs_dir, "refs", &mut names)?;
+    }
+
+    let packed_path = git_dir.join("packed-refs");
+    if packed_path.exists() {
+        for line in fs::read_to_string(packed_path)?.lines() {
+            if line.starts_with('#') || line.starts_with('^') {
+                continue;
+            }
+            if let Some((_, ref_name)) = line.split_once(' ') {
+                names.insert(ref_name.to_string());
+            }
+        }
+    }
+
+    let mut refs = Vec::new();
+    for name in names {
+        if let Some(hash) = read_ref(root, &name, global_opts)? {
+            refs.push((name, hash));
+        }
+    }
+    Ok(refs)
+}
+
+/// A reference's full name, the object it points at, and the object found by following that object if it's an
+/// annotated tag, and any tags that tag points at in turn
+pub type PeeledRef = (String, [u8; 20], Option<[u8; 20]>);
+
+/// Returns every reference as `list_refs` does, peeled
+pub fn list_refs_peeled(root: &Path, global_opts: GlobalOpts) -> Result<Vec<PeeledRef>> {
+    let mut refs = Vec::new();
+    for (name, hash) in list_refs(root, global_opts)? {
+        let peeled = peel(root, &hash, global_opts.git_mode)?;
+        refs.push((name, hash, (peeled != hash).then_some(peeled)));
+    }
+    Ok(refs)
+}
+
+fn collect_loose_refs(dir: &Path, prefix: &str, names: &mut BTreeSet<String>) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = format!("{}/{}", prefix, entry.file_name().to_string_lossy());
+        if entry.file_type()?.is_dir() {
+            collect_loose_refs(&entry.path(), &name, names)?;
+        } else {
+            names.insert(name);
+        }
+    }
+    Ok(())
+}
+
+/// Returns every object recorded in the reflogs, both as the old and the new value of a reference.
+pub fn reflog_hashes(root: &Path, global_opts: GlobalOpts) -> Result<Vec<[u8; 20]>> {
+    let mut hashes = Vec::new();
+    let logs_dir = root.join(git_dir_name(global_opts)).join("logs");
+    if logs_dir.is_dir() {
+        collect_reflog_hashes(&logs_dir, &mut hashes)?;
+    }
+    Ok(hashes)
+}
+
+fn collect_reflog_hashes(dir: &Path, hashes: &mut Vec<[u8; 20]>) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            collect_reflog_hashes(&entry.path(), hashes)?;
+            continue;
+        }
+
+        // Each line starts with the old and new values, the old value being all zeros on creation
+        for line in fs::read_to_string(entry.path())?.lines() {
+            for field in line.split(' ').take(2) {
+                if let Ok(hash) = parse_hash(field) {
+                    if hash != [0; 20] {
+                        hashes.push(hash);
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,152 @@
+// Remotes and the branches they track, as configured by the `remote.*` and `branch.*` sections of the config
+
+use std::path::{Path, PathBuf};
+use anyhow::{bail, Result};
+
+use crate::{GlobalOpts, git_dir_name, is_bare_repository};
+use crate::config::Config;
+
+/// Finds the repository a remote refers to, returning the remote's URL and the root of the repository. The remote can
+/// also be given directly as a path or `file://` URL, which relative paths are resolved from `cwd`. Only repositories
+/// on the local file system are supported.
+pub fn open_remote(config: &Config, remote: &str, cwd: &Path, global_opts: GlobalOpts) -> Result<(String, PathBuf)> {
+    let (url, path) = remote_path(config, remote, cwd);
+    if !path.join(git_dir_name(global_opts)).is_dir() {
+        bail!(not_a_repository(&url));
+    }
+    Ok((url, path))
+}
+
+/// Finds the repository a remote refers to as for `open_remote`, but returns its git directory instead of its root,
+/// so that it may also be a bare repository. The last value returned says whether it is bare.
+pub fn open_remote_git_dir(
+    config: &Config,
+    remote: &str,
+    cwd: &Path,
+    global_opts: GlobalOpts
+) -> Result<(String, PathBuf, bool)> {
+    let (url, path) = remote_path(config, remote, cwd);
+    let git_dir = path.join(git_dir_name(global_opts));
+    if git_dir.is_dir() {
+        return Ok((url, git_dir, false));
+    }
+    if !is_bare_repository(&path) {
+        bail!(not_a_repository(&url));
+    }
+    Ok((url, path, true))
+}
+
+/// The URL of a remote from `remote.<name>.url`, or the remote itself if it's given directly as a URL or path
+pub fn remote_url(config: &Config, remote: &str) -> String {
+    config.get(&format!("remote.{}.url", remote)).unwrap_or(remote.to_string())
+}
+
+// The URL of a remote, and the path it refers to
+fn remote_path(config: &Config, remote: &str, cwd: &Path) -> (String, PathBuf) {
+    let url = remote_url(config, remote);
+    let path = cwd.join(url.strip_prefix("file://").unwrap_or(&url));
+    (url, path)
+}
+
+fn not_a_repository(url: &str) -> String {
+    format!(
+        "fatal: '{}' does not appear to be a git repository\n\
+        fatal: Could not read from remote repository.\n\n\
+        Please make sure you have the correct access rights\n\
+        and the repository exists.",
+        url
+    )
+}
+
+/// The remote to use when none is given: the branch's remote if it has one, then the only remote if there's just one,
+/// or else `origin`
+pub fn default_remote(config: &Config, branch: Option<&str>) -> String {
+    if let Some(remote) = branch.and_then(|x| config.get(&format!("branch.{}.remote", x))) {
+        return remote;
+    }
+    match &config.subsections("remote")[..] {
+        [remote] => remote.clone(),
+        _ => String::from("origin")
+    }
+}
+
+/// Maps a reference name through a refspec such as `+refs/heads/*:refs/remotes/origin/*`, returning the name
+/// on the destination side, or None if the refspec doesn't match it
+pub fn map_refspec(refspec: &str, name: &str) -> Option<String> {
+    // A leading `+` allows non-fast-forward updates, which doesn't affect the mapping. Negative refspecs only
+    // exclude names.
+    let refspec = refspec.strip_prefix('+').unwrap_or(refspec);
+    if refspec.starts_with('^') {
+        return None;
+    }
+    let (source, destination) = refspec.split_once(':')?;
+    match (source.split_once('*'), destination.split_once('*')) {
+        (Some((prefix, suffix)), Some((dest_prefix, dest_suffix))) => {
+            let middle = name.strip_prefix(prefix)?.strip_suffix(suffix)?;
+            Some(format!("{}{}{}", dest_prefix, middle, dest_suffix))
+        },
+        (None, None) if source == name && !destination.is_empty() => Some(destination.to_string()),
+        _ => None
+    }
+}
+
+/// The remote-tracking reference that stores the given reference of a remote, found by the remote's fetch
+/// refspec. Returns None if it isn't stored locally.
+pub fn tracking_ref(config: &Config, remote: &str, name: &str) -> Option<String> {
+    let refspec = config.get(&format!("remote.{}.fetch", remote))?;
+    map_refspec(&refspec, name)
+}
+
+/// The full name of the reference the given branch is set to merge from with `branch.<name>.remote` and
+/// `branch.<name>.merge`. For the remote `.` this is a local branch, and otherwise a remote-tracking branch.
+/// `exists` says whether the branch itself exists, for the error given when it has no upstream.
+pub fn upstream_ref(config: &Config, branch: &str, exists: bool) -> Result<String> {
+    let remote = config.get(&format!("branch.{}.remote", branch));
+    let merge = config.get(&format!("branch.{}.merge", branch));
+    let (Some(remote), Some(merge)) = (remote, merge) else {
+        if !exists {
+            bail!("fatal: no such branch: '{}'", branch);
+        }
+        bail!("fatal: no upstream configured for branch '{}'", branch);
+    };
+
+    if remote == "." {
+        return Ok(merge);
+    }
+    match tracking_ref(config, &remote, &merge) {
+        Some(name) => Ok(name),
+        None => bail!("fatal: upstream branch '{}' not stored as a remote-tracking branch", merge)
+    }
+}
+
+/// The full name of the remote-tracking reference for where `push` would send the given branch. The remote is
+/// taken from `branch.<name>.pushRemote`, `remote.pushDefault` or `branch.<name>.remote`, and the destination
+/// depends on `push.default` as in Git.
+pub fn push_ref(config: &Config, branch: &str, exists: bool) -> Result<String> {
+    let remote = config.get(&format!("branch.{}.pushremote", branch))
+        .or(config.get("remote.pushdefault"))
+        .or(config.get(&format!("branch.{}.remote", branch)));
+    let Some(remote) = remote else {
+        bail!("fatal: branch '{}' has no remote for pushing", branch);
+    };
+
+    let full_name = format!("refs/heads/{}", branch);
+    let current = || match tracking_ref(config, &remote, &full_name) {
+        Some(name) => Ok(name),
+        None => bail!("fatal: push destination '{}' on remote '{}' has no local tracking branch", full_name, remote)
+    };
+    match config.get("push.default").as_deref().unwrap_or("simple") {
+        "nothing" => bail!("fatal: push has no destination (push.default is 'nothing')"),
+        "matching" | "current" => current(),
+        "upstream" | "tracking" => upstream_ref(config, branch, exists),
+        // The simple mode only pushes to a branch of the same name as the upstream
+        _ => {
+            let upstream = upstream_ref(config, branch, exists)?;
+            let current = current()?;
+            if upstream != current {
+                bail!("fatal: cannot resolve 'simple' push to a single destination");
+            }
+            Ok(current)
+        }
+    }
+}
@@ -0,0 +1,134 @@
+// Pack the objects in the store together, so that they take less space and are quicker to find
+
+use std::{env, fs, path::Path};
+use anyhow::{anyhow, Result};
+use clap::Args;
+
+use crate::{GlobalOpts, repo_find, git_dir_name};
+use crate::objects::{loose_objects, object_path, FileStore, ObjectStore};
+use crate::pack::{packed_objects, write_pack};
+use crate::refs::list_refs_peeled;
+
+#[derive(Args)]
+pub struct RepackArgs {
+    /// Pack every object into a single pack, including those already in packs, rather than only the loose objects
+    #[arg(short = 'a')]
+    pub all: bool,
+    /// Once the objects are packed, remove the loose objects that were packed and, with -a, the packs replaced
+    #[arg(short = 'd')]
+    pub delete: bool,
+    /// Don't update the lists of references and packs that dumb servers use, in `info/refs` and `objects/info/packs`
+    #[arg(short = 'n')]
+    pub no_update_server_info: bool,
+}
+
+pub fn cmd_repack(args: RepackArgs, global_opts: GlobalOpts) -> Result<()> {
+    let path = env::current_dir().unwrap_or_else(|_| { panic!() });
+    let root = repo_find(&path, global_opts).unwrap_or_else(|| {
+        panic!("fatal: not a grit repository");
+    });
+    let git_mode = global_opts.git_mode;
+    let pack_dir = root.join(git_dir_name(global_opts)).join("objects").join("pack");
+
+    let old_packs = pack_names(&pack_dir)?;
+    let packed = packed_objects(&pack_dir)?;
+    let mut hashes = loose_objects(&root, git_mode)?;
+    if args.all {
+        hashes.extend(&packed);
+    } else {
+        hashes.retain(|x| !packed.contains(x));
+    }
+    hashes.sort();
+    hashes.dedup();
+
+    let new_pack = match hashes.is_empty() {
+        true => {
+            println!("Nothing new to pack.");
+            None
+        },
+        false => {
+            // One store reads every object, so that objects stored as deltas against the same base share it
+            let store = FileStore::new(&root, git_mode)?;
+            let mut objects = Vec::new();
+            for hash in hashes {
+                let bytes = store.read_raw(&hash)?
+                    .ok_or(anyhow!("fatal: object {} not found", hex::encode(hash)))?;
+                objects.push((hash, bytes));
+            }
+            Some(write_pack(&pack_dir, &objects)?)
+        }
+    };
+
+    if args.delete {
+        // The pack made can be the same as one there already if nothing has changed since it was made
+        if args.all && new_pack.is_some() {
+            for name in old_packs.iter().filter(|x| Some(*x) != new_pack.as_ref()) {
+                for extension in ["pack", "idx"] {
+                    fs::remove_file(pack_dir.join(format!("{}.{}", name, extension)))?;
+                }
+            }
+        }
+        remove_packed_loose_objects(&root, &pack_dir, git_mode)?;
+    }
+
+    if !args.no_update_server_info {
+        update_server_info(&root, &pack_dir, global_opts)?;
+    }
+    Ok(())
+}
+
+// The names of the packs in the pack directory that have both their pack and index files, e.g. `pack-<checksum>`
+fn pack_names(pack_dir: &Path) -> Result<Vec<String>> {
+    let mut names = Vec::new();
+    if !pack_dir.is_dir() {
+        return Ok(names);
+    }
+    for entry in fs::read_dir(pack_dir)? {
+        let file = entry?.path();
+        if file.extension().is_some_and(|x| x == "idx") && file.with_extension("pack").exists() {
+            names.extend(file.file_stem().map(|x| x.to_string_lossy().to_string()));
+        }
+    }
+    names.sort();
+    Ok(names)
+}
+
+// Removes each loose object that a pack also has, as `git prune-packed` does, along with any fan-out directory left
+// empty
+fn remove_packed_loose_objects(root: &Path, pack_dir: &Path, git_mode: bool) -> Result<()> {
+    let packed = packed_objects(pack_dir)?;
+    for hash in loose_objects(root, git_mode)?.iter().filter(|x| packed.contains(x)) {
+        let file = object_path(root, hash, git_mode);
+        fs::remove_file(&file)?;
+        if let Some(dir) = file.parent() {
+            if fs::read_dir(dir)?.next().is_none() {
+                fs::remove_dir(dir)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// Lists every reference, with the object an annotated tag points at, in `info/refs`, and every pack in
+// `objects/info/packs`, as `git update-server-info` does for clients that fetch over dumb HTTP
+fn update_server_info(root: &Path, pack_dir: &Path, global_opts: GlobalOpts) -> Result<()> {
+    let git_dir = root.join(git_dir_name(global_opts));
+    let mut refs = String::new();
+    for (name, hash, peeled) in list_refs_peeled(root, global_opts)? {
+        refs += &format!("{}\t{}\n", hex::encode(hash), name);
+        if let Some(peeled) = peeled {
+            refs += &format!("{}\t{}^{{}}\n", hex::encode(peeled), name);
+        }
+    }
+    fs::create_dir_all(git_dir.join("info"))?;
+    fs::write(git_dir.join("info").join("refs"), refs)?;
+
+    let mut packs = String::new();
+    for name in pack_names(pack_dir)? {
+        packs += &format!("P {}.pack\n", name);
+    }
+    packs += "\n";
+    fs::create_dir_all(git_dir.join("objects").join("info"))?;
+    fs::write(git_dir.join("objects").join("info").join("packs"), packs)?;
+    Ok(())
+}
@@ -0,0 +1,105 @@
+// Print where the repository is, and the objects or references revisions name
+
+use std::{env, process};
+use anyhow::{bail, Result};
+use clap::Args;
+
+use crate::{GlobalOpts, repo_find, git_dir_name};
+use crate::refs::{full_ref_name, read_head, resolve_revision, shorten_ref_name, Head};
+
+#[derive(Args)]
+pub struct RevParseArgs {
+    /// The revisions to print the object hashes of
+    revs: Vec<String>,
+    /// Print the path to the git directory, relative to the current directory if it's the root of the work tree and
+    /// absolute otherwise
+    #[arg(long)]
+    git_dir: bool,
+    /// Print the absolute path to the root of the work tree
+    #[arg(long)]
+    show_toplevel: bool,
+    /// Print `true` if the current directory is inside the work tree
+    #[arg(long)]
+    is_inside_work_tree: bool,
+    /// Print each revision that names a reference as the shortest name that still names it unambiguously, and HEAD
+    /// as the branch it's on
+    #[arg(long)]
+    abbrev_ref: bool,
+    /// Check that exactly one revision is given and that it names an object, failing if not
+    #[arg(long)]
+    verify: bool,
+    /// With --verify, fail with only the exit status rather than an error
+    #[arg(short, long)]
+    quiet: bool,
+}
+
+pub fn cmd_rev_parse(args: RevParseArgs, global_opts: GlobalOpts) -> Result<()> {
+    let path = env::current_dir().unwrap_or_else(|_| { panic!() });
+    let root = repo_find(&path, global_opts).unwrap_or_else(|| {
+        panic!("fatal: not a grit repository");
+    });
+
+    if args.git_dir {
+        match path == root {
+            true => println!("{}", git_dir_name(global_opts)),
+            false => println!("{}", root.join(git_dir_name(global_opts)).display())
+        }
+    }
+    if args.show_toplevel {
+        println!("{}", root.display());
+    }
+    // Commands can't be run from inside the git directory, so anywhere a repository is found is in the work tree
+    if args.is_inside_work_tree {
+        println!("true");
+    }
+
+    let verify_failed = || -> Result<()> {
+        if args.quiet {
+            process::exit(1);
+        }
+        bail!("fatal: Needed a single revision");
+    };
+    if args.verify && args.revs.len() != 1 {
+        verify_failed()?;
+    }
+    for rev in &args.revs {
+        let hash = match resolve_revision(&root, rev, global_opts) {
+            Ok(hash) => hash,
+            Err(_) if args.verify => return verify_failed(),
+            // As in Git, anything that isn't a revision is taken to be a path and printed as it is, which is only an
+            // error if there's no such file
+            Err(e) => {
+                println!("{}", rev);
+                if path.join(rev).exists() {
+                    continue;
+                }
+                if !e.to_string().starts_with("fatal: ambiguous argument") {
+                    return Err(e);
+                }
+                bail!(
+                    "{}\nUse '--' to separate paths from revisions, like this:\n\
+                    '{} <command> [<revision>...] -- [<file>...]'", e, if global_opts.git_mode { "git" } else { "grit" }
+                );
+            }
+        };
+        if !args.abbrev_ref {
+            println!("{}", hex::encode(hash));
+            continue;
+        }
+
+        // A detached HEAD is shown as HEAD, and a revision that isn't a reference, such as a hash, as the object it
+        // names
+        let name = match rev.as_str() {
+            "HEAD" | "@" => match read_head(&root, global_opts)? {
+                Head::Branch(name) => shorten_ref_name(&root, &name, global_opts)?,
+                Head::Detached(_) => String::from("HEAD")
+            },
+            _ => match full_ref_name(&root, rev, global_opts)? {
+                Some(name) => shorten_ref_name(&root, &name, global_opts)?,
+                None => hex::encode(hash)
+            }
+        };
+        println!("{}", name);
+    }
+    Ok(())
+}
@@ -0,0 +1,346 @@
+// Show how branches have diverged, marking which of them each recent commit is on
+
+use std::{collections::HashMap, env, path::Path};
+use anyhow::{anyhow, bail, Result};
+use clap::Args;
+
+use crate::{GlobalOpts, repo_find};
+use crate::dag::CommitDag;
+use crate::objects::{get_object, peel, Object};
+use crate::refs::{list_refs, read_head, resolve_revision, Head};
+
+// As in Git, the low bits of a commit's flags are for bookkeeping and each branch has a bit of its own above them
+const UNINTERESTING: u32 = 1 << 1;
+const REV_SHIFT: usize = 2;
+const MAX_REVS: usize = 32 - REV_SHIFT - 1;
+
+#[derive(Args)]
+pub struct ShowBranchArgs {
+    /// The branches or commits to compare. If none are given, every branch is compared
+    revs: Vec<String>,
+}
+
+// The name a commit is shown with: a branch and how many first parents back from it the commit is,
+// e.g. `topic~2`. Commits reached through a merge's other parents have names like `master^2`.
+#[derive(Clone)]
+struct CommitName {
+    head_name: String,
+    generation: usize,
+}
+
+pub fn cmd_show_branch(args: ShowBranchArgs, global_opts: GlobalOpts) -> Result<()> {
+    let path = env::current_dir().unwrap_or_else(|_| { panic!() });
+    let root = repo_find(&path, global_opts).unwrap_or_else(|| {
+        panic!("fatal: not a grit repository");
+    });
+    let git_mode = global_opts.git_mode;
+
+    let head = match read_head(&root, global_opts)? {
+        Head::Branch(name) => Some(name),
+        Head::Detached(_) => None
+    };
+    let head_hash = resolve_revision(&root, "HEAD", global_opts).ok();
+
+    let mut names = args.revs.clone();
+    if names.is_empty() {
+        for (name, _) in list_refs(&root, global_opts)? {
+            if let Some(branch) = name.strip_prefix("refs/heads/") {
+                names.push(branch.to_string());
+            }
+        }
+        // The current branch is always shown
+        if let Some(branch) = head.as_deref().and_then(|x| x.strip_prefix("refs/heads/")) {
+            if !names.iter().any(|x| x == branch) {
+                names.push(branch.to_string());
+            }
+        }
+    }
+    if names.len() > MAX_REVS {
+        bail!("fatal: cannot handle more than {} refs", MAX_REVS);
+    }
+    let mut revs = Vec::new();
+    for name in &names {
+        let hash = resolve_revision(&root, name, global_opts).map_err(|_| anyhow!("fatal: bad sha1 reference {}", name))?;
+        revs.push(resolve_commit(&root, &hash, git_mode)?);
+    }
+
+    let dag = CommitDag::open(&root, git_mode)?;
+    let mut walk = Walk { dag: &dag, flags: HashMap::new(), dates: HashMap::new() };
+    let seen = walk.join_revs(&revs)?;
+    let seen = walk.sort_topologically(seen)?;
+    let commit_names = name_commits(&dag, &seen, &revs, &names)?;
+
+    let is_head = |i: usize| {
+        let name = names[i].strip_prefix("refs/heads/").or(names[i].strip_prefix("heads/")).unwrap_or(&names[i]);
+        head.as_deref().and_then(|x| x.strip_prefix("refs/heads/")) == Some(name) && head_hash == Some(revs[i])
+    };
+    let head_at = (0..revs.len()).find(|i| is_head(*i));
+
+    // With several branches, a header lists them with a column for each
+    if revs.len() > 1 {
+        for (i, hash) in revs.iter().enumerate() {
+            let marker = if is_head(i) { '*' } else { '!' };
+            println!("{}{} [{}] {}", " ".repeat(i), marker, names[i], subject(&root, hash, git_mode)?);
+        }
+        println!("{}", "-".repeat(revs.len()));
+    }
+
+    // Commits are shown down to the first that is on every branch
+    let all_revs = ((1u32 << (REV_SHIFT + revs.len())) - 1) & !((1u32 << REV_SHIFT) - 1);
+    for hash in &seen {
+        let flags = walk.flags[hash];
+        let is_merge_point = flags & all_revs == all_revs;
+
+        let mut line = String::new();
+        if revs.len() > 1 {
+            let is_merge = dag.parents(hash)?.len() > 1;
+            // A merge made on only one of the branches isn't interesting
+            if is_merge && omit_in_dense(hash, flags, &revs) {
+                continue;
+            }
+            for i in 0..revs.len() {
+                line.push(match () {
+                    _ if flags & (1 << (i + REV_SHIFT)) == 0 => ' ',
+                    _ if is_merge => '-',
+                    _ if head_at == Some(i) => '*',
+                    _ => '+'
+                });
+            }
+            line.push(' ');
+        }
+        let name = match commit_names.get(hash) {
+            Some(CommitName { head_name, generation: 0 }) => head_name.clone(),
+            Some(CommitName { head_name, generation: 1 }) => format!("{}^", head_name),
+            Some(CommitName { head_name, generation }) => format!("{}~{}", head_name, generation),
+            None => hex::encode(hash)[..7].to_string()
+        };
+        println!("{}[{}] {}", line, name, subject(&root, hash, git_mode)?);
+
+        if is_merge_point {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn resolve_commit(root: &Path, hash: &[u8; 20], git_mode: bool) -> Result<[u8; 20]> {
+    let hash = peel(root, hash, git_mode)?;
+    match get_object(root, &hash, git_mode)? {
+        Object::Commit(_) => Ok(hash),
+        _ => bail!("fatal: object {} is not a commit", hex::encode(hash))
+    }
+}
+
+fn subject(root: &Path, hash: &[u8; 20], git_mode: bool) -> Result<String> {
+    match get_object(root, hash, git_mode)? {
+        Object::Commit(commit) => {
+            let subject = commit.subject();
+            Ok(subject.strip_prefix("[PATCH] ").unwrap_or(&subject).to_string())
+        },
+        _ => bail!("fatal: object {} is not a commit", hex::encode(hash))
+    }
+}
+
+// The state of the walk from the branches back to where they meet
+struct Walk<'a> {
+    dag: &'a CommitDag,
+    /// For each commit seen, a bit for each branch it is on, and whether it is on all of them
+    flags: HashMap<[u8; 20], u32>,
+    dates: HashMap<[u8; 20], i64>,
+}
+
+impl Walk<'_> {
+    fn date(&mut self, hash: &[u8; 20]) -> Result<i64> {
+        if let Some(date) = self.dates.get(hash) {
+            return Ok(*date);
+        }
+        let date = self.dag.commit(hash)?.timestamp;
+        self.dates.insert(*hash, date);
+        Ok(date)
+    }
+
+    // Inserts a commit into a list kept in order of date, newest first, after any others of the same date
+    fn insert_by_date(&mut self, list: &mut Vec<[u8; 20]>, hash: [u8; 20]) -> Result<()> {
+        let date = self.date(&hash)?;
+        let mut position = list.len();
+        for (i, other) in list.iter().enumerate() {
+            if self.dates[other] < date {
+                position = i;
+                break;
+            }
+        }
+        list.insert(position, hash);
+        Ok(())
+    }
+
+    // Walks back from the branches in order of date, marking each commit with the branches it is on, until every
+    // commit left to visit is on all of them. Returns the commits seen, newest first.
+    fn join_revs(&mut self, revs: &[[u8; 20]]) -> Result<Vec<[u8; 20]>> {
+        let mut seen = Vec::new();
+        let mut list = Vec::new();
+        for (i, hash) in revs.iter().enumerate() {
+            let flag = 1 << (i + REV_SHIFT);
+            let flags = self.flags.entry(*hash).or_insert(0);
+            if *flags == 0 {
+                seen.push(*hash);
+            }
+            *flags |= flag;
+            if *flags == flag {
+                self.insert_by_date(&mut list, *hash)?;
+            }
+        }
+
+        let all_mask = (1u32 << (REV_SHIFT + revs.len())) - 1;
+        let all_revs = all_mask & !((1u32 << REV_SHIFT) - 1);
+        while !list.is_empty() {
+            let still_interesting = list.iter().any(|x| self.flags[x] & UNINTERESTING == 0);
+            let hash = list.remove(0);
+            if !still_interesting {
+                break;
+            }
+            let mut flags = self.flags[&hash] & all_mask;
+            if flags & all_revs == all_revs {
+                flags |= UNINTERESTING;
+            }
+            for parent in self.dag.parents(&hash)? {
+                let parent_flags = self.flags.entry(parent).or_insert(0);
+                if *parent_flags & flags == flags {
+                    continue;
+                }
+                if *parent_flags == 0 {
+                    seen.push(parent);
+                }
+                *parent_flags |= flags;
+                self.insert_by_date(&mut list, parent)?;
+            }
+        }
+
+        // Git builds the list by adding each commit to the front, and the sort by date keeps the order of commits
+        // with the same date
+        seen.reverse();
+        for hash in &seen {
+            self.date(hash)?;
+        }
+        seen.sort_by(|a, b| self.dates[b].cmp(&self.dates[a]));
+        Ok(seen)
+    }
+
+    // Orders the commits so that each comes before its parents, keeping to one line of history for as long as
+    // possible, as `sort_in_topological_order` does in Git
+    fn sort_topologically(&self, commits: Vec<[u8; 20]>) -> Result<Vec<[u8; 20]>> {
+        // One more than the number of children each commit has among the others, or zero once it has been listed
+        let mut indegree: HashMap<[u8; 20], usize> = commits.iter().map(|x| (*x, 1)).collect();
+        for hash in &commits {
+            for parent in self.dag.parents(hash)? {
+                if let Some(count) = indegree.get_mut(&parent) {
+                    *count += 1;
+                }
+            }
+        }
+
+        let mut stack: Vec<[u8; 20]> = commits.iter().filter(|x| indegree[*x] == 1).rev().copied().collect();
+        let mut sorted = Vec::new();
+        while let Some(hash) = stack.pop() {
+            for parent in self.dag.parents(&hash)? {
+                if let Some(count) = indegree.get_mut(&parent) {
+                    if *count == 0 {
+                        continue;
+                    }
+                    *count -= 1;
+                    if *count == 1 {
+                        stack.push(parent);
+                    }
+                }
+            }
+            indegree.insert(hash, 0);
+            sorted.push(hash);
+        }
+        Ok(sorted)
+    }
+}
+
+// Like Git, a merge that is on only one branch is left out, unless it's where a branch points
+fn omit_in_dense(hash: &[u8; 20], flags: u32, revs: &[[u8; 20]]) -> bool {
+    if revs.contains(hash) {
+        return false;
+    }
+    (0..revs.len()).filter(|i| flags & (1 << (i + REV_SHIFT)) != 0).count() == 1
+}
+
+// Names each commit after a branch it is on. The branch tips are named first, then the commits along their
+// first-parent chains, and then those reached through the other parents of merges.
+fn name_commits(
+    dag: &CommitDag,
+    commits: &[[u8; 20]],
+    revs: &[[u8; 20]],
+    rev_names: &[String]
+) -> Result<HashMap<[u8; 20], CommitName>> {
+    let mut names: HashMap<[u8; 20], CommitName> = HashMap::new();
+    for hash in commits {
+        if names.contains_key(hash) {
+            continue;
+        }
+        if let Some(i) = revs.iter().position(|x| x == hash) {
+            names.insert(*hash, CommitName { head_name: rev_names[i].clone(), generation: 0 });
+        }
+    }
+
+    loop {
+        let mut named = 0;
+        for hash in commits {
+            named += name_first_parent_chain(dag, hash, &mut names)?;
+        }
+        if named == 0 {
+            break;
+        }
+    }
+
+    loop {
+        let mut named = 0;
+        for hash in commits {
+            let Some(name) = names.get(hash).cloned() else {
+                continue;
+            };
+            for (i, parent) in dag.parents(hash)?.iter().enumerate() {
+                if names.contains_key(parent) {
+                    continue;
+                }
+                let mut head_name = match name.generation {
+                    0 => name.head_name.clone(),
+                    1 => format!("{}^", name.head_name),
+                    n => format!("{}~{}", name.head_name, n)
+                };
+                if i == 0 {
+                    head_name.push('^');
+                } else {
+                    head_name += &format!("^{}", i + 1);
+                }
+                names.insert(*parent, CommitName { head_name, generation: 0 });
+                named += 1;
+                name_first_parent_chain(dag, parent, &mut names)?;
+            }
+        }
+        if named == 0 {
+            break;
+        }
+    }
+    Ok(names)
+}
+
+// Names the unnamed first parents of a named commit, counting up from its name. Returns how many were named.
+fn name_first_parent_chain(dag: &CommitDag, hash: &[u8; 20], names: &mut HashMap<[u8; 20], CommitName>) -> Result<usize> {
+    let mut named = 0;
+    let mut current = *hash;
+    while let Some(name) = names.get(&current).cloned() {
+        let Some(parent) = dag.parents(&current)?.first().copied() else {
+            break;
+        };
+        if names.contains_key(&parent) {
+            break;
+        }
+        names.insert(parent, CommitName { head_name: name.head_name, generation: name.generation + 1 });
+        named += 1;
+        current = parent;
+    }
+    Ok(named)
+}
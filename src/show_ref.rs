@@ -0,0 +1,105 @@
+// List references and the objects they point at
+
+use std::{env, process};
+use anyhow::{bail, Result};
+use clap::Args;
+
+use crate::{GlobalOpts, repo_find};
+use crate::objects::peel;
+use crate::refs::{head_commit, list_refs_peeled, read_ref};
+
+#[derive(Args)]
+pub struct ShowRefArgs {
+    /// Only show references whose names end with one of these, counting whole components, e.g. `master` or
+    /// `heads/master`. With --verify, the full names of the references to show.
+    patterns: Vec<String>,
+    /// Show HEAD as well, before the references
+    #[arg(long)]
+    head: bool,
+    /// Only show branches. With --tags, show branches and tags.
+    #[arg(long)]
+    heads: bool,
+    /// Only show tags. With --heads, show branches and tags.
+    #[arg(long)]
+    tags: bool,
+    /// After each reference to an annotated tag, show the object the tag leads to, named as the tag followed by `^{}`
+    #[arg(short, long)]
+    dereference: bool,
+    /// Show only the object hashes, abbreviated to the given number of digits if one is given
+    #[arg(short = 's', long = "hash", value_name = "N", num_args = 0..=1, require_equals = true,
+        default_missing_value = "40")]
+    hash: Option<usize>,
+    /// Show each of the given full names, failing if any isn't a reference
+    #[arg(long)]
+    verify: bool,
+    /// Show nothing, only setting the exit status
+    #[arg(short, long)]
+    quiet: bool,
+}
+
+pub fn cmd_show_ref(args: ShowRefArgs, global_opts: GlobalOpts) -> Result<()> {
+    let path = env::current_dir().unwrap_or_else(|_| { panic!() });
+    let root = repo_find(&path, global_opts).unwrap_or_else(|| {
+        panic!("fatal: not a grit repository");
+    });
+
+    let show = |name: &str, hash: &[u8; 20], peeled: Option<[u8; 20]>| {
+        if args.quiet {
+            return;
+        }
+        let hash_str = hex::encode(hash);
+        let abbreviated = &hash_str[..args.hash.unwrap_or(40).clamp(4, 40)];
+        match args.hash {
+            Some(_) => println!("{}", abbreviated),
+            None => println!("{} {}", abbreviated, name)
+        }
+        // Like Git, the dereferenced object is shown with its name even when only hashes are asked for
+        if let Some(peeled) = peeled.filter(|_| args.dereference) {
+            let peeled_str = hex::encode(peeled);
+            println!("{} {}^{{}}", &peeled_str[..abbreviated.len()], name);
+        }
+    };
+
+    if args.verify {
+        for name in &args.patterns {
+            let hash = match name.as_str() {
+                "HEAD" => head_commit(&root, global_opts)?,
+                _ if name.starts_with("refs/") => read_ref(&root, name, global_opts)?,
+                _ => None
+            };
+            let Some(hash) = hash else {
+                bail!("fatal: '{}' - not a valid ref", name);
+            };
+            let peeled = peel(&root, &hash, global_opts.git_mode)?;
+            show(name, &hash, (peeled != hash).then_some(peeled));
+        }
+        return Ok(());
+    }
+
+    let mut found = false;
+    if args.head {
+        if let Some(hash) = head_commit(&root, global_opts)? {
+            show("HEAD", &hash, None);
+            found = true;
+        }
+    }
+    for (name, hash, peeled) in list_refs_peeled(&root, global_opts)? {
+        let kind_matches = (!args.heads && !args.tags)
+            || (args.heads && name.starts_with("refs/heads/"))
+            || (args.tags && name.starts_with("refs/tags/"));
+        if !kind_matches || !(args.patterns.is_empty() || args.patterns.iter().any(|x| ends_with_components(&name, x))) {
+            continue;
+        }
+        show(&name, &hash, peeled);
+        found = true;
+    }
+    if !found {
+        process::exit(1);
+    }
+    Ok(())
+}
+
+// Whether the name is the pattern, or ends with a slash followed by the pattern
+fn ends_with_components(name: &str, pattern: &str) -> bool {
+    name.strip_suffix(pattern).is_some_and(|x| x.is_empty() || x.ends_with('/'))
+}
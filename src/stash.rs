@@ -0,0 +1,185 @@
+// Listing, showing and dropping the changes set aside in the stash
+
+use std::{env, fs, io::Write, path::{Path, PathBuf}, process};
+use anyhow::{anyhow, bail, Result};
+use clap::{Args, Subcommand};
+
+use crate::{GlobalOpts, repo_find, git_dir_name};
+use crate::attributes::Attributes;
+use crate::checkout::commit_files;
+use crate::config::Config;
+use crate::diff::{configured_algorithm, file_patch, read_blob};
+use crate::diffstat::diff_stat;
+use crate::objects::{get_object, Object};
+use crate::refs::{full_ref_name, read_reflog, resolve_revision, write_reflog, write_ref};
+
+// The reference whose reflog holds the stash, newest entry last
+const STASH_REF: &str = "refs/stash";
+
+// The width `--stat` fits its graph into when not writing to a terminal
+const STAT_WIDTH: usize = 80;
+
+#[derive(Args)]
+pub struct StashArgs {
+    #[command(subcommand)]
+    pub command: StashCommand,
+}
+
+#[derive(Subcommand)]
+pub enum StashCommand {
+    /// List the stash entries, newest first
+    List,
+    /// Show the changes recorded in a stash entry, relative to the commit it was made on
+    Show {
+        /// The entry to show, as `stash@{<n>}` or just `<n>`. Defaults to the newest.
+        stash: Option<String>,
+        /// Show the changes as a patch
+        #[arg(short, long)]
+        patch: bool,
+        /// Show the number of lines changed in each file, which is the default without --patch
+        #[arg(long)]
+        stat: bool,
+    },
+    /// Remove a stash entry, the newest unless another is given
+    Drop {
+        /// The entry to remove, as `stash@{<n>}` or just `<n>`
+        stash: Option<String>,
+    },
+}
+
+pub fn cmd_stash(args: StashArgs, global_opts: GlobalOpts) -> Result<()> {
+    let path = env::current_dir().unwrap_or_else(|_| { panic!() });
+    let root = repo_find(&path, global_opts).unwrap_or_else(|| {
+        panic!("fatal: not a grit repository");
+    });
+
+    match args.command {
+        StashCommand::List => {
+            let entries = read_reflog(&root, STASH_REF, global_opts)?;
+            for (n, entry) in entries.iter().rev().enumerate() {
+                println!("stash@{{{}}}: {}", n, entry.message);
+            }
+        },
+        StashCommand::Show { stash, patch, stat } => {
+            let (_, hash, base) = resolve_stash(&root, stash.as_deref(), global_opts)?;
+            show_stash(&root, &hash, &base, patch, stat || !patch, global_opts)?;
+        },
+        StashCommand::Drop { stash } => {
+            let (name, hash, _) = resolve_stash(&root, stash.as_deref(), global_opts)?;
+            drop_stash(&root, &name, global_opts)?;
+            println!("Dropped {} ({})", name, hex::encode(hash));
+        }
+    }
+    Ok(())
+}
+
+// Finds the stash entry with the given name, returning the name as Git shows it, the stash commit and the commit it
+// was made on. A bare number counts back from the newest entry, and no name at all means the newest.
+fn resolve_stash(root: &Path, name: Option<&str>, global_opts: GlobalOpts) -> Result<(String, [u8; 20], [u8; 20])> {
+    let name = match name {
+        Some(name) if !name.is_empty() && name.chars().all(|x| x.is_ascii_digit()) => {
+            format!("{}@{{{}}}", STASH_REF, name)
+        },
+        Some(name) => name.to_string(),
+        None => {
+            if read_reflog(root, STASH_REF, global_opts)?.is_empty() {
+                eprintln!("No stash entries found.");
+                process::exit(1);
+            }
+            format!("{}@{{0}}", STASH_REF)
+        }
+    };
+
+    // Running past the end of the stash is reported as it is for any reflog, but anything else that doesn't resolve
+    // isn't a reference at all
+    let hash = resolve_revision(root, &name, global_opts).map_err(|e| match e.to_string().starts_with("fatal: log") {
+        true => e,
+        false => anyhow!("error: {} is not a valid reference", name)
+    })?;
+    // A stash commit records the index as its second parent, and may have the untracked files as a third
+    let base = match get_object(root, &hash, global_opts.git_mode)? {
+        Object::Commit(commit) if commit.parents.len() >= 2 => commit.parents[0],
+        _ => bail!("fatal: '{}' is not a stash-like commit", name)
+    };
+    Ok((name, hash, base))
+}
+
+// Prints the changes from the commit a stash entry was made on to the working tree it recorded
+fn show_stash(
+    root: &Path,
+    hash: &[u8; 20],
+    base: &[u8; 20],
+    patch: bool,
+    stat: bool,
+    global_opts: GlobalOpts
+) -> Result<()> {
+    let git_mode = global_opts.git_mode;
+    let mut stdout = std::io::stdout().lock();
+    if stat {
+        let stat = diff_stat(root, Some(base), hash, git_mode)?;
+        if stat.files.is_empty() {
+            return Ok(());
+        }
+        for line in stat.graph(STAT_WIDTH) {
+            writeln!(stdout, "{}", line)?;
+        }
+        writeln!(stdout, "{}", stat.summary())?;
+        if patch {
+            writeln!(stdout)?;
+        }
+    }
+    if !patch {
+        return Ok(());
+    }
+
+    let config = Config::load(root, global_opts)?;
+    let algorithm = configured_algorithm(&config)?;
+    let mut attributes = Attributes::new(root, &config, global_opts)?;
+    let old_files = commit_files(root, base, git_mode)?;
+    let new_files = commit_files(root, hash, git_mode)?;
+    let mut paths: Vec<&PathBuf> = old_files.keys().chain(new_files.keys()).collect();
+    paths.sort_by(|x, y| x.as_os_str().as_encoded_bytes().cmp(y.as_os_str().as_encoded_bytes()));
+    paths.dedup();
+    for path in paths {
+        let old = old_files.get(path).map(|x| read_blob(root, &x.hash, x.mode, git_mode)).transpose()?;
+        let new = new_files.get(path).map(|x| read_blob(root, &x.hash, x.mode, git_mode)).transpose()?;
+        if old.as_ref().map(|x| (x.hash, x.mode)) == new.as_ref().map(|x| (x.hash, x.mode)) {
+            continue;
+        }
+        let name = path.to_string_lossy();
+        stdout.write_all(&file_patch(root, &name, old.as_ref(), new.as_ref(), &config, &mut attributes, algorithm)?)?;
+    }
+    Ok(())
+}
+
+// Removes an entry from the stash's reflog. As with `git reflog delete --rewrite --updateref`, the entry after it
+// takes over its previous value, and the stash reference moves to whatever is then newest.
+fn drop_stash(root: &Path, name: &str, global_opts: GlobalOpts) -> Result<()> {
+    let not_stash = || anyhow!("error: '{}' is not a stash reference", name);
+    let (base, count) = name.strip_suffix('}').and_then(|x| x.rsplit_once("@{")).ok_or_else(not_stash)?;
+    if full_ref_name(root, base, global_opts)?.as_deref() != Some(STASH_REF) {
+        return Err(not_stash());
+    }
+    let count: usize = count.parse().map_err(|_| not_stash())?;
+
+    let mut entries = read_reflog(root, STASH_REF, global_opts)?;
+    let position = entries.len() - 1 - count;
+    let removed = entries.remove(position);
+    if let Some(next) = entries.get_mut(position) {
+        next.old = removed.old;
+    }
+
+    let git_dir = root.join(git_dir_name(global_opts));
+    match entries.last() {
+        Some(newest) => {
+            write_ref(root, STASH_REF, &newest.new, global_opts)?;
+            write_reflog(root, STASH_REF, &entries, global_opts)?;
+        },
+        // Like Git, the stash is removed altogether once it's empty
+        None => {
+            fs::remove_file(git_dir.join(STASH_REF))?;
+            fs::remove_file(git_dir.join("logs").join(STASH_REF))?;
+        }
+    }
+    Ok(())
+}
@@ -1,8 +1,14 @@
-use std::{collections::HashSet, env, fs::{self, DirEntry, ReadDir}, path::{Path, PathBuf}};
+use std::{collections::{BTreeMap, HashSet}, env, fs, io::{self, Write}, path::{Path, PathBuf}};
 use anyhow::{Result, anyhow};
 use clap::Args;
 
-use crate::{GlobalOpts, repo_find, index::Index, git_dir_name};
+use crate::{GlobalOpts, repo_find, index::{index_path, read_index, IndexItem}, git_dir_name};
+use crate::checkout::{commit_files, hash_worktree_file};
+use crate::config::Config;
+use crate::filter::Filters;
+use crate::objects::{Blob, GitObject};
+use crate::quote::PathQuoting;
+use crate::refs::head_commit;
 
 pub enum UntrackedMode {
     No,
@@ -13,7 +19,14 @@ pub enum UntrackedMode {
 #[derive(Args)]
 pub struct StatusArgs {
     #[arg(short, long)]
-    pub untracked_files: Option<String>
+    pub untracked_files: Option<String>,
+    /// Show a line for each changed or untracked file, in a format that stays the same between versions: two letters
+    /// for how the file differs between HEAD and the index and between the index and the working tree, then the path
+    #[arg(long)]
+    pub porcelain: bool,
+    /// End each line of --porcelain output with a NUL byte rather than a new line. Implies --porcelain.
+    #[arg(short)]
+    pub z: bool,
 }
 
 pub fn cmd_status(args: StatusArgs, global_opts: GlobalOpts) -> Result<()> {
@@ -24,6 +37,13 @@ pub fn cmd_status(args: StatusArgs, global_opts: GlobalOpts) -> Result<()> {
         panic!("fatal: not a grit repository");
     });
 
+    if args.porcelain || args.z {
+        return porcelain_status(&root, &untracked_mode, if args.z { b'\0' } else { b'\n' }, global_opts);
+    }
+
+    // The index is read before anything is printed, so that one that can't be read gives only the error
+    let index = read_index(&root, global_opts)?;
+
     // TODO: Handle different branches
     println!("On branch master");
     println!();
@@ -36,30 +56,47 @@ pub fn cmd_status(args: StatusArgs, global_opts: GlobalOpts) -> Result<()> {
     // Once `commit` is implemented, only report files that are not in the HEAD tree
     // Build a list of tracked directories (the root directory is always tracked)
     let mut staged = Vec::new();
-    let mut tracked_dirs = HashSet::<PathBuf>::new();
-    tracked_dirs.insert(root.clone());
+    let mut unstaged = Vec::new();
+    let mut filters = Filters::new(&root, global_opts)?;
+    for item in index.items {
+        staged.push(item.path.to_string_lossy().to_string());
 
-    let index_path = root.join(format!("{}/index", git_dir_name(global_opts)));
-    if index_path.exists() {
-        let index_bytes = fs::read(index_path)?;
-        let index = Index::deserialize(index_bytes)?;
-        for item in index.items {
-            staged.push(item.path.to_string_lossy().to_string());
-
-            if let Some(parent) = item.path.parent() {
-                if parent.components().count() > 0 {
-                    tracked_dirs.insert(PathBuf::from(parent));
-                }
+        // Files marked skip-worktree are taken to match the index, whether or not they're in the working tree
+        if item.stage == 0 && !item.skip_worktree {
+            match hash_worktree_file(&root, &item.path, &mut filters)? {
+                None => unstaged.push(("deleted", item.path.to_string_lossy().to_string())),
+                Some(hash) if hash != item.hash => {
+                    unstaged.push(("modified", item.path.to_string_lossy().to_string()));
+                },
+                _ => ()
             }
         }
     }
 
+    let quoting = PathQuoting::new(&Config::load(&root, global_opts)?)?;
+    let quote = |path: &str| quoting.quote(Path::new(path));
+
     // Report staged changes
-    if staged.len() > 0 {
+    if !staged.is_empty() {
         println!("Changes to be committed:");
         println!("  (use \"git rm --cached <file>...\" to unstage)");
         for path in &staged {
-            println!("\tnew file:   {}", path);
+            println!("\tnew file:   {}", quote(path));
+        }
+        println!();
+    }
+
+    // Report changes in the working tree that aren't staged
+    if !unstaged.is_empty() {
+        println!("Changes not staged for commit:");
+        if unstaged.iter().any(|(change, _)| *change == "deleted") {
+            println!("  (use \"git add/rm <file>...\" to update what will be committed)");
+        } else {
+            println!("  (use \"git add <file>...\" to update what will be committed)");
+        }
+        println!("  (use \"git restore <file>...\" to discard changes in working directory)");
+        for (change, path) in &unstaged {
+            println!("\t{:<12}{}", format!("{}:", change), quote(path));
         }
         println!();
     }
@@ -69,61 +106,200 @@ pub fn cmd_status(args: StatusArgs, global_opts: GlobalOpts) -> Result<()> {
         return Ok(());
     }
 
-    let mut paths = Vec::<String>::new();
-    if let UntrackedMode::Normal = untracked_mode {
-        for dir_path in tracked_dirs {
-            let dir = fs::read_dir(dir_path)?;
-            for entry in dir {
-                paths.push(index_name(&entry?.path(), &root));
-            }
-        }
-    }
-    else {
-        let mut untracked_paths: Vec<String> = walk_worktree(&root, &git_dir_name(global_opts))?
-            .iter()
-            .map(|x| index_name(&x, &root))
-            .collect();
-
-        untracked_paths.sort();
-        for path in untracked_paths {
-            if !staged.iter().any(|x| x == &path) {
-                paths.push(path);
-            }
-        }
-    }
-
-    if paths.len() > 0 {
+    let paths = untracked_paths(&root, &staged, &untracked_mode, global_opts)?;
+    if !paths.is_empty() {
         println!("Untracked files:");
         println!("  (use \"git add <file>...\" to include in what will be committed)");
         for x in &paths {
-            println!("\t{}", x);
+            println!("\t{}", quote(x));
         }
         println!();
     }
 
-    if paths.len() > 0 && staged.len() == 0 {
-        println!("nothing added to commit but paths files present (use \"git add\" to track)");
+    if !paths.is_empty() && staged.is_empty() {
+        println!("nothing added to commit but untracked files present (use \"git add\" to track)");
     }
 
-    if paths.len() == 0 && staged.len() == 0 {
+    if paths.is_empty() && staged.is_empty() {
         println!("nothing to commit (create/copy files and use \"git add\" to track)");
     }
 
     Ok(())
 }
 
+// Shows the status as `--porcelain` does, each line ending with the given byte. Changes to tracked files come first,
+// with a letter for the change from HEAD to the index and another for the change from the index to the working tree,
+// each a space if there's none. Untracked files follow, marked `??`.
+fn porcelain_status(root: &Path, untracked_mode: &UntrackedMode, terminator: u8, global_opts: GlobalOpts) -> Result<()> {
+    let index = read_index(root, global_opts)?;
+    let mut head_files = match head_commit(root, global_opts)? {
+        Some(head) => commit_files(root, &head, global_opts.git_mode)?,
+        None => BTreeMap::new()
+    };
+    let config = Config::load(root, global_opts)?;
+    let file_mode = config.get_bool("core.filemode")?.unwrap_or(true);
+    let mut filters = Filters::new(root, global_opts)?;
+    // Paths are quoted as needed unless entries are ended with NUL, when they can be given exactly
+    let quoting = PathQuoting::new(&config)?;
+    let quote = |path: &str| match terminator {
+        b'\0' => path.to_string(),
+        _ => quoting.quote_with_spaces(Path::new(path))
+    };
+
+    let mut changes: BTreeMap<String, String> = BTreeMap::new();
+    let mut unmerged: BTreeMap<&Path, Vec<u16>> = BTreeMap::new();
+    for item in &index.items {
+        if item.stage != 0 {
+            unmerged.entry(&item.path).or_default().push(item.stage);
+            continue;
+        }
+        let staged = match head_files.remove(&item.path) {
+            None => 'A',
+            Some(entry) if entry.mode >> 12 != item.mode >> 12 => 'T',
+            Some(entry) if (entry.mode, entry.hash) != (item.mode, item.hash) => 'M',
+            Some(_) => ' '
+        };
+        // Files marked skip-worktree are taken to match the index, whether or not they're in the working tree
+        let unstaged = match item.skip_worktree {
+            true => ' ',
+            false => worktree_change(root, item, file_mode, &mut filters)?
+        };
+        if (staged, unstaged) != (' ', ' ') {
+            changes.insert(item.path.to_string_lossy().to_string(), format!("{}{}", staged, unstaged));
+        }
+    }
+    // Which versions of a conflicted file are in the index tell how it conflicted
+    for (path, stages) in unmerged {
+        head_files.remove(path);
+        let code = match stages.as_slice() {
+            [1] => "DD",
+            [2] => "AU",
+            [3] => "UA",
+            [1, 2] => "UD",
+            [1, 3] => "DU",
+            [2, 3] => "AA",
+            _ => "UU"
+        };
+        changes.insert(path.to_string_lossy().to_string(), code.to_string());
+    }
+    // What's left of HEAD is gone from the index
+    for path in head_files.keys() {
+        changes.insert(path.to_string_lossy().to_string(), String::from("D "));
+    }
+
+    let mut stdout = io::stdout().lock();
+    for (path, code) in &changes {
+        write!(stdout, "{} {}", code, quote(path))?;
+        stdout.write_all(&[terminator])?;
+    }
+    let tracked: Vec<String> = index.items.iter().map(|x| x.path.to_string_lossy().to_string()).collect();
+    for path in untracked_paths(root, &tracked, untracked_mode, global_opts)? {
+        write!(stdout, "?? {}", quote(&path))?;
+        stdout.write_all(&[terminator])?;
+    }
+    Ok(())
+}
+
+// The letter for how a tracked file in the working tree differs from the index: `D` if it's gone, `T` if it's become
+// another type of file, `M` if its contents have changed or, unless file modes are ignored, it has been made
+// executable or not, or a space if it's the same
+fn worktree_change(root: &Path, item: &IndexItem, file_mode: bool, filters: &mut Filters) -> Result<char> {
+    let file = root.join(&item.path);
+    let Ok(metadata) = fs::symlink_metadata(&file) else {
+        return Ok('D');
+    };
+    let is_link = metadata.file_type().is_symlink();
+    if is_link != (item.mode == 0o120000) || !(is_link || metadata.is_file()) {
+        return Ok('T');
+    }
+    let hash = match is_link {
+        true => Blob { bytes: fs::read_link(&file)?.into_os_string().into_encoded_bytes() }.hash(),
+        false => hash_worktree_file(root, &item.path, filters)?.unwrap_or_default()
+    };
+    #[cfg(unix)]
+    if file_mode && !is_link {
+        use std::os::unix::fs::PermissionsExt;
+        if (metadata.permissions().mode() & 0o100 != 0) != (item.mode == 0o100755) {
+            return Ok('M');
+        }
+    }
+    Ok(if hash == item.hash { ' ' } else { 'M' })
+}
+
+// Lists the files in the working tree that aren't in the index, sorted. Normally a directory without tracked files
+// is listed as a whole, with a slash after its name, and with `UntrackedMode::All` each file is listed instead.
+fn untracked_paths(
+    root: &Path,
+    tracked: &[String],
+    untracked_mode: &UntrackedMode,
+    global_opts: GlobalOpts
+) -> Result<Vec<String>> {
+    // Where case is ignored, a file is tracked if the index has it in any case
+    let ignore_case = Config::load(root, global_opts)?.ignore_case()?;
+    let same_path = |x: &str, y: &str| x == y || (ignore_case && x.eq_ignore_ascii_case(y));
+    let is_tracked = |name: &str| tracked.iter().any(|x| same_path(x, name));
+
+    // The root directory is always tracked
+    let mut tracked_dirs = HashSet::<PathBuf>::new();
+    tracked_dirs.insert(root.to_path_buf());
+    for path in tracked {
+        if let Some(parent) = Path::new(path).parent() {
+            for dir in parent.ancestors().filter(|x| x.components().count() > 0) {
+                tracked_dirs.insert(PathBuf::from(dir));
+            }
+        }
+    }
+
+    let mut paths = Vec::<String>::new();
+    match untracked_mode {
+        UntrackedMode::No => (),
+        UntrackedMode::Normal => {
+            // Untracked directories are listed as a whole rather than file by file
+            for dir_path in &tracked_dirs {
+                for entry in fs::read_dir(root.join(dir_path))? {
+                    let entry = entry?;
+                    if entry.file_name() == git_dir_name(global_opts).as_str() {
+                        continue;
+                    }
+                    let name = index_name(&entry.path(), root);
+                    if entry.file_type()?.is_dir() {
+                        if !tracked_dirs.iter().any(|x| same_path(&x.to_string_lossy(), &name)) {
+                            paths.push(format!("{}/", name));
+                        }
+                    } else if !is_tracked(&name) {
+                        paths.push(name);
+                    }
+                }
+            }
+            paths.sort();
+        },
+        UntrackedMode::All => {
+            let mut untracked_paths: Vec<String> = walk_worktree(&root.to_path_buf(), &git_dir_name(global_opts))?
+                .iter()
+                .map(|x| index_name(x, root))
+                .collect();
+
+            untracked_paths.sort();
+            for path in untracked_paths {
+                if !is_tracked(&path) {
+                    paths.push(path);
+                }
+            }
+        }
+    }
+    Ok(paths)
+}
+
 /// Returns the name of the given path, relative to the given repository root
 fn index_name(entry: &Path, root: &Path) -> String {
-    entry
-        .strip_prefix(root)
-        .unwrap()
+    index_path(entry.strip_prefix(root).unwrap())
         .to_string_lossy()
         .to_string()
 }
 
 fn walk_worktree(path: &PathBuf, git_dir_name: &str) -> Result<Vec<PathBuf>> {
     let mut ret = Vec::new();
-    for entry in fs::read_dir(&path)? {
+    for entry in fs::read_dir(path)? {
         let entry = entry?;
         let entry_path = path.join(entry.file_name());
         if entry.file_type()?.is_file() {
@@ -0,0 +1,66 @@
+// Tidy text the way Git tidies commit messages, reading it from standard input
+
+use std::io::{self, Read, Write};
+use anyhow::Result;
+use clap::Args;
+
+use crate::GlobalOpts;
+
+#[derive(Args)]
+pub struct StripspaceArgs {
+    /// Also remove the lines that are comments, which start with `#`
+    #[arg(short, long, conflicts_with = "comment_lines")]
+    strip_comments: bool,
+    /// Make every line a comment by starting it with `# `, rather than tidying the text
+    #[arg(short, long)]
+    comment_lines: bool,
+}
+
+pub fn cmd_stripspace(args: StripspaceArgs, _global_opts: GlobalOpts) -> Result<()> {
+    let mut input = Vec::new();
+    io::stdin().read_to_end(&mut input)?;
+    let input = String::from_utf8_lossy(&input);
+    let output = if args.comment_lines { comment_lines(&input) } else { stripspace(&input, args.strip_comments) };
+    io::stdout().write_all(output.as_bytes())?;
+    Ok(())
+}
+
+/// Tidies text as Git does by default: trailing whitespace is removed from each line, runs of blank lines are
+/// collapsed into one, and blank lines at the start and end are dropped. With `strip_comments`, lines starting with
+/// `#` are removed first, as they are from a message written in the editor.
+pub fn stripspace(text: &str, strip_comments: bool) -> String {
+    let mut result = String::new();
+    let mut pending_blank = false;
+    for line in text.split('\n') {
+        if strip_comments && line.starts_with('#') {
+            continue;
+        }
+        // Only the characters Git counts as whitespace are removed
+        let line = line.trim_end_matches([' ', '\t', '\r']);
+        if line.is_empty() {
+            pending_blank = !result.is_empty();
+            continue;
+        }
+        if pending_blank {
+            result.push('\n');
+            pending_blank = false;
+        }
+        result.push_str(line);
+        result.push('\n');
+    }
+    result
+}
+
+// Makes each line of the text a comment. As in Git, an empty line or one starting with a tab is only preceded by `#`,
+// and any other by `# `.
+fn comment_lines(text: &str) -> String {
+    let mut result = String::new();
+    for line in text.split_inclusive('\n') {
+        result.push_str(if line.starts_with(['\n', '\t']) { "#" } else { "# " });
+        result.push_str(line);
+    }
+    if !result.is_empty() && !result.ends_with('\n') {
+        result.push('\n');
+    }
+    result
+}
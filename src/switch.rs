@@ -0,0 +1,129 @@
+// Switch branches, updating the index and working tree to match
+
+use std::{env, path::Path};
+use anyhow::{anyhow, bail, Result};
+use clap::Args;
+
+use crate::{GlobalOpts, repo_find};
+use crate::checkout::switch_commit;
+use crate::commit::signature;
+use crate::config::Config;
+use crate::objects::{get_object, Object, Signature};
+use crate::refs::{append_reflog, check_branch_name, head_commit, read_head, read_ref, resolve_revision, write_head, write_ref, Head};
+
+#[derive(Args)]
+pub struct SwitchArgs {
+    /// The branch to switch to, or the start point when creating a branch or detaching
+    pub target: Option<String>,
+    /// Create a new branch with the given name at the start point and switch to it
+    #[arg(short, long)]
+    pub create: Option<String>,
+    /// Switch to a commit for inspection, detaching HEAD from any branch
+    #[arg(short, long)]
+    pub detach: bool,
+}
+
+pub fn cmd_switch(args: SwitchArgs, global_opts: GlobalOpts) -> Result<()> {
+    let path = env::current_dir().unwrap_or_else(|_| { panic!() });
+    let root = repo_find(&path, global_opts).unwrap_or_else(|| {
+        panic!("fatal: not a grit repository");
+    });
+
+    let current = head_commit(&root, global_opts)?;
+    let head = read_head(&root, global_opts)?;
+    let from = match &head {
+        Head::Branch(name) => name.strip_prefix("refs/heads/").unwrap_or(name).to_string(),
+        Head::Detached(hash) => hex::encode(hash)
+    };
+    // Switching is still possible without an identity, but isn't recorded in the reflog
+    let config = Config::load(&root, global_opts)?;
+    let identity = signature(&config, "COMMITTER", global_opts).ok();
+
+    if let Some(name) = &args.create {
+        check_branch_name(name).map_err(|_| anyhow!("fatal: '{}' is not a valid branch name", name))?;
+        let ref_name = format!("refs/heads/{}", name);
+        if read_ref(&root, &ref_name, global_opts)?.is_some() {
+            bail!("fatal: a branch named '{}' already exists", name);
+        }
+
+        let start = match &args.target {
+            Some(rev) => resolve_revision(&root, rev, global_opts)?,
+            None => current.ok_or(anyhow!("fatal: you are on a branch yet to be born"))?
+        };
+
+        switch_commit(&root, current, &start, global_opts)?;
+        write_ref(&root, &ref_name, &start, global_opts)?;
+        write_head(&root, &Head::Branch(ref_name.clone()), global_opts)?;
+        if let Some(identity) = &identity {
+            let created_from = args.target.as_deref().unwrap_or("HEAD");
+            append_reflog(&root, &ref_name, None, &start, identity, &format!("branch: Created from {}", created_from), global_opts)?;
+            log_switch(&root, current, &start, identity, &from, name, global_opts)?;
+        }
+        report_previous(&root, &head, &start, global_opts)?;
+        eprintln!("Switched to a new branch '{}'", name);
+    } else if args.detach {
+        let rev = args.target.as_deref().unwrap_or("HEAD");
+        let target = resolve_revision(&root, rev, global_opts)?;
+
+        switch_commit(&root, current, &target, global_opts)?;
+        write_head(&root, &Head::Detached(target), global_opts)?;
+        if let Some(identity) = &identity {
+            log_switch(&root, current, &target, identity, &from, rev, global_opts)?;
+        }
+
+        report_previous(&root, &head, &target, global_opts)?;
+        eprintln!("HEAD is now at {}", describe(&root, &target, rev, global_opts)?);
+    } else {
+        let name = args.target.ok_or(anyhow!("fatal: missing branch or commit argument"))?;
+        let ref_name = format!("refs/heads/{}", name);
+        let target = read_ref(&root, &ref_name, global_opts)?
+            .ok_or(anyhow!("fatal: invalid reference: {}", name))?;
+
+        if head == Head::Branch(ref_name.clone()) {
+            eprintln!("Already on '{}'", name);
+            return Ok(());
+        }
+
+        switch_commit(&root, current, &target, global_opts)?;
+        write_head(&root, &Head::Branch(ref_name), global_opts)?;
+        if let Some(identity) = &identity {
+            log_switch(&root, current, &target, identity, &from, &name, global_opts)?;
+        }
+        report_previous(&root, &head, &target, global_opts)?;
+        eprintln!("Switched to branch '{}'", name);
+    }
+
+    Ok(())
+}
+
+// Records the move of HEAD in its reflog, naming where it moved from and to as Git does
+fn log_switch(
+    root: &Path,
+    old: Option<[u8; 20]>,
+    new: &[u8; 20],
+    identity: &Signature,
+    from: &str,
+    to: &str,
+    global_opts: GlobalOpts
+) -> Result<()> {
+    append_reflog(root, "HEAD", old, new, identity, &format!("checkout: moving from {} to {}", from, to), global_opts)
+}
+
+// Moving away from a detached HEAD names the commit that was left, as it may not be reachable from any branch
+fn report_previous(root: &Path, head: &Head, new: &[u8; 20], global_opts: GlobalOpts) -> Result<()> {
+    match head {
+        Head::Detached(old) if old != new => {
+            eprintln!("Previous HEAD position was {}", describe(root, old, &hex::encode(old), global_opts)?);
+            Ok(())
+        },
+        _ => Ok(())
+    }
+}
+
+// A commit's abbreviated hash and subject, as shown when HEAD moves to or from it. `rev` is how the commit was named.
+fn describe(root: &Path, hash: &[u8; 20], rev: &str, global_opts: GlobalOpts) -> Result<String> {
+    match get_object(root, hash, global_opts.git_mode)? {
+        Object::Commit(c) => Ok(format!("{} {}", &hex::encode(hash)[..7], c.subject())),
+        _ => bail!("fatal: reference is not a commit: {}", rev)
+    }
+}
@@ -0,0 +1,83 @@
+// Create, list and delete tags
+
+use std::env;
+use anyhow::{anyhow, bail, Result};
+use clap::Args;
+
+use crate::{GlobalOpts, repo_find};
+use crate::commit::signature;
+use crate::config::Config;
+use crate::objects::{get_object, GitObject, Tag};
+use crate::refs::{check_ref_name, delete_refs, list_refs, read_ref, resolve_revision, write_ref};
+use crate::stripspace::stripspace;
+
+#[derive(Args)]
+pub struct TagArgs {
+    /// The name of the tag to create or delete. If omitted, the existing tags are listed
+    pub name: Option<String>,
+    /// The object to tag, by default the commit HEAD points at
+    pub object: Option<String>,
+    /// Make an annotated tag, stored as a tag object with a message
+    #[arg(short, long)]
+    pub annotate: bool,
+    /// The message of an annotated tag. Implies `--annotate`
+    #[arg(short, long)]
+    pub message: Option<String>,
+    /// Delete the named tag
+    #[arg(short, long)]
+    pub delete: bool,
+}
+
+pub fn cmd_tag(args: TagArgs, global_opts: GlobalOpts) -> Result<()> {
+    let path = env::current_dir().unwrap_or_else(|_| { panic!() });
+    let root = repo_find(&path, global_opts).unwrap_or_else(|| {
+        panic!("fatal: not a grit repository");
+    });
+
+    let Some(name) = args.name else {
+        for (ref_name, _) in list_refs(&root, global_opts)? {
+            if let Some(tag_name) = ref_name.strip_prefix("refs/tags/") {
+                println!("{}", tag_name);
+            }
+        }
+        return Ok(());
+    };
+    let ref_name = format!("refs/tags/{}", name);
+
+    if args.delete {
+        let hash = read_ref(&root, &ref_name, global_opts)?.ok_or(anyhow!("error: tag '{}' not found.", name))?;
+        delete_refs(&root, &[ref_name], global_opts)?;
+        println!("Deleted tag '{}' (was {})", name, &hex::encode(hash)[..7]);
+        return Ok(());
+    }
+
+    // Like branches, tags can't start with `-`, so that they can't be mistaken for options
+    if name.starts_with('-') || check_ref_name(&ref_name).is_err() {
+        bail!("fatal: '{}' is not a valid tag name.", name);
+    }
+    if read_ref(&root, &ref_name, global_opts)?.is_some() {
+        bail!("fatal: tag '{}' already exists", name);
+    }
+    let target = resolve_revision(&root, args.object.as_deref().unwrap_or("HEAD"), global_opts)?;
+
+    // A lightweight tag is just a reference, whereas an annotated tag refers to a tag object
+    let hash = match args.message {
+        Some(message) => {
+            let config = Config::load(&root, global_opts)?;
+            let tag = Tag {
+                object: target,
+                object_type: get_object(&root, &target, global_opts.git_mode)?.object_type(),
+                name,
+                tagger: Some(signature(&config, "COMMITTER", global_opts)?.to_string()),
+                message: stripspace(&message, false),
+            };
+            tag.write(&root, global_opts)?;
+            tag.hash()
+        },
+        None if args.annotate => bail!("fatal: an annotated tag needs a message, given with -m"),
+        None => target
+    };
+    write_ref(&root, &ref_name, &hash, global_opts)?;
+
+    Ok(())
+}
@@ -0,0 +1,130 @@
+// Modify the index directly, one path at a time
+
+use std::{env, fs, path::{Path, PathBuf}, process};
+use anyhow::{anyhow, bail, Result};
+use clap::Args;
+
+use crate::{GlobalOpts, repo_find};
+use crate::check_ignore::normalize;
+use crate::checkout::hash_worktree_file;
+use crate::filter::Filters;
+use crate::index::{canonical_mode, index_path, read_index, write_index, Index, IndexItem};
+use crate::objects::{parse_hash, Blob, GitObject};
+
+#[derive(Args)]
+pub struct UpdateIndexArgs {
+    /// Add files that aren't already in the index
+    #[arg(long)]
+    add: bool,
+    /// Remove files from the index if they no longer exist in the working tree
+    #[arg(long)]
+    remove: bool,
+    /// Add an entry given as `<mode>,<hash>,<path>`, whether or not the path or object exist
+    #[arg(long, value_name = "MODE,HASH,PATH")]
+    cacheinfo: Vec<String>,
+    /// Update the file information of entries whose files haven't changed, and list those that have
+    #[arg(long)]
+    refresh: bool,
+    /// Mark the files' entries so that their files are left alone in the working tree, instead of updating them
+    #[arg(long, overrides_with = "no_skip_worktree")]
+    skip_worktree: bool,
+    /// Clear the skip-worktree mark from the files' entries, instead of updating them
+    #[arg(long)]
+    no_skip_worktree: bool,
+    /// The files to update the entries of from the working tree
+    files: Vec<String>,
+}
+
+pub fn cmd_update_index(args: UpdateIndexArgs, global_opts: GlobalOpts) -> Result<()> {
+    let cwd = env::current_dir().unwrap_or_else(|_| { panic!() });
+    let root = repo_find(&cwd, global_opts).unwrap_or_else(|| {
+        panic!("fatal: not a grit repository");
+    });
+
+    let mut index = read_index(&root, global_opts)?;
+    let mut filters = Filters::new(&root, global_opts)?;
+
+    for info in &args.cacheinfo {
+        let fields: Vec<&str> = info.splitn(3, ',').collect();
+        let [mode, hash, path] = fields[..] else {
+            bail!("error: option 'cacheinfo' expects <mode>,<sha1>,<path>");
+        };
+        let (Ok(mode), Ok(hash)) = (u32::from_str_radix(mode, 8), parse_hash(hash)) else {
+            bail!("error: option 'cacheinfo' expects <mode>,<sha1>,<path>");
+        };
+        let path = PathBuf::from(path);
+        if !args.add && !index.items.iter().any(|x| x.path == path) {
+            bail!("error: {}: cannot add to the index - missing --add option?\nfatal: git update-index: --cacheinfo cannot add {}",
+                path.to_string_lossy(), path.to_string_lossy());
+        }
+        index.add_item(IndexItem::without_stat(path, canonical_mode(mode), hash, 0));
+    }
+
+    for file in &args.files {
+        let path = normalize(&cwd.join(file));
+        let path = path.strip_prefix(&root)
+            .map(index_path)
+            .map_err(|_| anyhow!("fatal: {}: '{}' is outside repository at '{}'", file, file, root.to_string_lossy()))?;
+        if args.skip_worktree || args.no_skip_worktree {
+            let mut found = false;
+            for item in index.items.iter_mut().filter(|x| x.path == path) {
+                item.skip_worktree = args.skip_worktree;
+                found = true;
+            }
+            if !found {
+                bail!("fatal: Unable to mark file {}", path.to_string_lossy());
+            }
+            continue;
+        }
+        update_path(&root, &mut index, &path, &args, &mut filters, global_opts)?;
+    }
+
+    let mut changed = false;
+    if args.refresh {
+        // Files marked skip-worktree aren't expected to be in the working tree
+        for item in index.items.iter_mut().filter(|x| x.stage == 0 && !x.skip_worktree) {
+            if hash_worktree_file(&root, &item.path, &mut filters)? != Some(item.hash) {
+                println!("{}: needs update", item.path.to_string_lossy());
+                changed = true;
+                continue;
+            }
+            let mode = item.mode;
+            *item = IndexItem::from_file(&root.join(&item.path), item.path.clone(), item.hash)?;
+            item.mode = mode;
+        }
+    }
+
+    write_index(&index, &root, global_opts)?;
+    if changed {
+        process::exit(1);
+    }
+    Ok(())
+}
+
+// Updates the entry for a path from the working tree, or removes it if the file is gone and `--remove` was given
+fn update_path(
+    root: &Path,
+    index: &mut Index,
+    path: &Path,
+    args: &UpdateIndexArgs,
+    filters: &mut Filters,
+    global_opts: GlobalOpts
+) -> Result<()> {
+    let name = path.to_string_lossy();
+    let file = root.join(path);
+    if !file.is_file() {
+        if !args.remove {
+            bail!("error: {}: does not exist and --remove not passed\nfatal: Unable to process path {}", name, name);
+        }
+        index.remove_path(path);
+        return Ok(());
+    }
+    if !args.add && !index.items.iter().any(|x| x.path == path) {
+        bail!("error: {}: cannot add to the index - missing --add option?\nfatal: Unable to process path {}", name, name);
+    }
+
+    let blob = Blob { bytes: filters.clean(path, fs::read(&file)?)? };
+    blob.write(root, global_opts)?;
+    index.add_item(IndexItem::from_file(&file, path.to_path_buf(), blob.hash())?);
+    Ok(())
+}
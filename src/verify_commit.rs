@@ -0,0 +1,63 @@
+// Check the signatures of signed commits
+
+use std::{env, io::Write, process};
+use anyhow::Result;
+use clap::Args;
+
+use crate::{GlobalOpts, repo_find};
+use crate::config::Config;
+use crate::gpg::verify_signature;
+use crate::objects::{get_object, GitObject, Object};
+use crate::refs::resolve_revision;
+
+#[derive(Args)]
+pub struct VerifyCommitArgs {
+    /// Print the contents of each commit before checking its signature
+    #[arg(short, long)]
+    pub verbose: bool,
+    #[arg(required = true)]
+    pub commits: Vec<String>,
+}
+
+pub fn cmd_verify_commit(args: VerifyCommitArgs, global_opts: GlobalOpts) -> Result<()> {
+    let path = env::current_dir().unwrap_or_else(|_| { panic!() });
+    let root = repo_find(&path, global_opts).unwrap_or_else(|| {
+        panic!("fatal: not a grit repository");
+    });
+
+    let config = Config::load(&root, global_opts)?;
+
+    // Like Git, every commit is checked and the exit status is non-zero if any of them isn't properly signed
+    let mut all_good = true;
+    for name in &args.commits {
+        let hash = resolve_revision(&root, name, global_opts)?;
+        let mut commit = match get_object(&root, &hash, global_opts.git_mode)? {
+            Object::Commit(c) => c,
+            other => {
+                eprintln!("error: {}: cannot verify a non-commit object of type {}.", name, other.object_type());
+                all_good = false;
+                continue;
+            }
+        };
+
+        // An unsigned commit fails without any message
+        let Some(signature) = commit.gpgsig.take() else {
+            all_good = false;
+            continue;
+        };
+
+        // The signature covers the whole commit apart from the signature itself
+        let payload = commit.content_bytes();
+        let check = verify_signature(&payload, &signature, &config)?;
+        if args.verbose {
+            std::io::stdout().write_all(&payload)?;
+        }
+        eprint!("{}", check.output);
+        all_good &= check.good;
+    }
+
+    if !all_good {
+        process::exit(1);
+    }
+    Ok(())
+}
@@ -0,0 +1,65 @@
+// Check the signatures of signed tags
+
+use std::{env, io::Write, process};
+use anyhow::Result;
+use clap::Args;
+
+use crate::{GlobalOpts, repo_find};
+use crate::config::Config;
+use crate::gpg::{signature_start, verify_signature};
+use crate::objects::{get_object, GitObject, Object};
+use crate::refs::resolve_revision;
+
+#[derive(Args)]
+pub struct VerifyTagArgs {
+    /// Print the contents of each tag before checking its signature
+    #[arg(short, long)]
+    pub verbose: bool,
+    #[arg(required = true)]
+    pub tags: Vec<String>,
+}
+
+pub fn cmd_verify_tag(args: VerifyTagArgs, global_opts: GlobalOpts) -> Result<()> {
+    let path = env::current_dir().unwrap_or_else(|_| { panic!() });
+    let root = repo_find(&path, global_opts).unwrap_or_else(|| {
+        panic!("fatal: not a grit repository");
+    });
+
+    let config = Config::load(&root, global_opts)?;
+
+    // Like Git, every tag is checked and the exit status is non-zero if any of them isn't properly signed
+    let mut all_good = true;
+    for name in &args.tags {
+        let hash = resolve_revision(&root, name, global_opts)?;
+        let tag = match get_object(&root, &hash, global_opts.git_mode)? {
+            Object::Tag(t) => t,
+            other => {
+                eprintln!("error: {}: cannot verify a non-tag object of type {}.", name, other.object_type());
+                all_good = false;
+                continue;
+            }
+        };
+
+        // The signature is appended to the tag's message, and covers everything before it
+        let contents = String::from_utf8_lossy(&tag.content_bytes()).to_string();
+        let header_len = contents.len() - tag.message.len();
+        let Some(start) = signature_start(&tag.message) else {
+            eprintln!("error: no signature found");
+            all_good = false;
+            continue;
+        };
+        let (payload, signature) = contents.split_at(header_len + start);
+
+        let check = verify_signature(payload.as_bytes(), signature, &config)?;
+        if args.verbose {
+            std::io::stdout().write_all(payload.as_bytes())?;
+        }
+        eprint!("{}", check.output);
+        all_good &= check.good;
+    }
+
+    if !all_good {
+        process::exit(1);
+    }
+    Ok(())
+}
@@ -0,0 +1,183 @@
+// Showing the changes of a patch word by word rather than line by line, as `diff --word-diff` does
+
+use clap::ValueEnum;
+
+use crate::line_diff::{myers_diff, split_lines, Edit};
+
+const BOLD: &[u8] = b"\x1b[1m";
+const RED: &[u8] = b"\x1b[31m";
+const GREEN: &[u8] = b"\x1b[32m";
+const CYAN: &[u8] = b"\x1b[36m";
+const RESET: &[u8] = b"\x1b[m";
+
+/// How changed words are marked
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum WordDiffMode {
+    /// Removed words in `[-...-]` and added words in `{+...+}`
+    Plain,
+    /// Removed words in red and added words in green
+    Color,
+}
+
+// What a piece of text in a changed region is
+#[derive(Clone, Copy)]
+enum Part {
+    Unchanged,
+    Removed,
+    Added,
+}
+
+/// Rewrites the patch for one file, as made by `file_patch`, so that each run of removed and added lines is shown as
+/// the text after the change with the words that changed marked. Unchanged text, including the spaces between
+/// changed words, is taken from the new version. In color mode the header is colored too, as Git does.
+pub fn word_diff(patch: &[u8], mode: WordDiffMode) -> Vec<u8> {
+    let color = mode == WordDiffMode::Color;
+    let mut output = Vec::new();
+    let mut in_hunks = false;
+    let mut removed = Vec::new();
+    let mut added = Vec::new();
+    for line in split_lines(patch) {
+        let line = line.strip_suffix(b"\n").unwrap_or(line);
+        in_hunks |= line.starts_with(b"@@ ");
+        if !in_hunks {
+            if color && !line.starts_with(b"Binary files ") {
+                output.extend([BOLD, line, RESET].concat());
+            } else {
+                output.extend(line);
+            }
+            output.push(b'\n');
+            continue;
+        }
+
+        // Lines are gathered until the end of the run, and all end with a new line whether the file does or not
+        match line.first() {
+            Some(b'-') => removed.extend([&line[1..], b"\n"].concat()),
+            Some(b'+') => added.extend([&line[1..], b"\n"].concat()),
+            Some(b'\\') => (),
+            _ => {
+                show_words(&mut output, &removed, &added, color);
+                removed.clear();
+                added.clear();
+                if line.starts_with(b"@@ ") {
+                    hunk_header(&mut output, line, color);
+                } else {
+                    let text = line.get(1..).unwrap_or_default();
+                    output.extend(text);
+                    if color && !text.is_empty() {
+                        output.extend(RESET);
+                    }
+                    output.push(b'\n');
+                }
+            }
+        }
+    }
+    show_words(&mut output, &removed, &added, color);
+    output
+}
+
+// Shows a run of removed and added lines as the added text, with the words that differ from the removed text marked
+fn show_words(output: &mut Vec<u8>, removed: &[u8], added: &[u8], color: bool) {
+    if added.is_empty() {
+        write_part(output, removed, Part::Removed, color);
+        return;
+    }
+
+    let old_words = words(removed);
+    let new_words = words(added);
+    let old_tokens: Vec<&[u8]> = old_words.iter().map(|(start, end)| &removed[*start..*end]).collect();
+    let new_tokens: Vec<&[u8]> = new_words.iter().map(|(start, end)| &added[*start..*end]).collect();
+    let edits = myers_diff(&old_tokens, &new_tokens);
+
+    // How much of the added text has been shown
+    let mut shown = 0;
+    let (mut old_position, mut new_position) = (0, 0);
+    let mut i = 0;
+    while i < edits.len() {
+        if let Edit::Equal(..) = edits[i] {
+            old_position += 1;
+            new_position += 1;
+            i += 1;
+            continue;
+        }
+
+        // A region of changed words, which starts at the end of the word before it where one side has none
+        let region_end = edits[i..].iter().position(|x| matches!(x, Edit::Equal(..))).map_or(edits.len(), |x| i + x);
+        let deleted = edits[i..region_end].iter().filter(|x| matches!(x, Edit::Delete(_))).count();
+        let inserted = region_end - i - deleted;
+        let range = |words: &[(usize, usize)], position: usize, count: usize| match count {
+            0 if position == 0 => (0, 0),
+            0 => (words[position - 1].1, words[position - 1].1),
+            _ => (words[position].0, words[position + count - 1].1)
+        };
+        let (old_start, old_end) = range(&old_words, old_position, deleted);
+        let (new_start, new_end) = range(&new_words, new_position, inserted);
+
+        write_part(output, &added[shown..new_start], Part::Unchanged, color);
+        write_part(output, &removed[old_start..old_end], Part::Removed, color);
+        write_part(output, &added[new_start..new_end], Part::Added, color);
+        shown = new_end;
+        old_position += deleted;
+        new_position += inserted;
+        i = region_end;
+    }
+    write_part(output, &added[shown..], Part::Unchanged, color);
+}
+
+// The start and end of each word of the text, a word being a run of characters other than white space
+fn words(text: &[u8]) -> Vec<(usize, usize)> {
+    let mut words = Vec::new();
+    let mut start = None;
+    for (i, byte) in text.iter().enumerate() {
+        match (byte.is_ascii_whitespace(), start) {
+            (true, Some(word_start)) => {
+                words.push((word_start, i));
+                start = None;
+            },
+            (false, None) => start = Some(i),
+            _ => ()
+        }
+    }
+    if let Some(word_start) = start {
+        words.push((word_start, text.len()));
+    }
+    words
+}
+
+// Writes some text with its marking. Each line is marked separately, so that no marker spans a new line.
+fn write_part(output: &mut Vec<u8>, text: &[u8], part: Part, color: bool) {
+    let (start, end): (&[u8], &[u8]) = match (part, color) {
+        (Part::Unchanged, _) => (b"", b""),
+        (Part::Removed, false) => (b"[-", b"-]"),
+        (Part::Added, false) => (b"{+", b"+}"),
+        (Part::Removed, true) => (RED, RESET),
+        (Part::Added, true) => (GREEN, RESET),
+    };
+    let mut rest = text;
+    while !rest.is_empty() {
+        let line_end = rest.iter().position(|x| *x == b'\n');
+        let segment = &rest[..line_end.unwrap_or(rest.len())];
+        if !segment.is_empty() {
+            output.extend([start, segment, end].concat());
+        }
+        let Some(line_end) = line_end else {
+            break;
+        };
+        output.push(b'\n');
+        rest = &rest[line_end + 1..];
+    }
+}
+
+// Writes the header of a hunk, in which Git colors the line ranges separately from the name of the function
+fn hunk_header(output: &mut Vec<u8>, line: &[u8], color: bool) {
+    if !color {
+        output.extend(line);
+        output.push(b'\n');
+        return;
+    }
+    let ranges_end = line[2..].windows(3).position(|x| x == b" @@").map_or(line.len(), |x| x + 5);
+    output.extend([CYAN, &line[..ranges_end], RESET].concat());
+    if let Some(function) = line[ranges_end..].strip_prefix(b" ") {
+        output.extend([b" ", RESET, function, RESET].concat());
+    }
+    output.push(b'\n');
+}
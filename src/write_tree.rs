@@ -1,21 +1,48 @@
-use std::{env, fs, path::PathBuf};
+use std::{collections::BTreeMap, env, path::{Path, PathBuf}};
 
 use anyhow::Result;
-use crate::{GlobalOpts, index::{Index, IndexItem}, objects::{GitObject, Tree, TreeEntry}, repo_find, git_dir_name};
+use clap::Args;
+use crate::{GlobalOpts, index::{index_path, read_index, Index, IndexItem}, objects::{GitObject, Tree, TreeEntry}, repo_find};
+use crate::objects::{FileStore, ObjectStore};
 
+#[derive(Args)]
+pub struct WriteTreeArgs {
+    /// Report on standard error how many of the trees have been written, for indexes large enough to take a while
+    #[arg(long)]
+    pub progress: bool,
+    /// Once the tree is written, report on standard error how many trees were new and how many were already stored
+    #[arg(short, long)]
+    pub verbose: bool,
+}
 
-pub fn cmd_write_tree(global_opts: GlobalOpts) -> Result<()> {
+pub fn cmd_write_tree(args: WriteTreeArgs, global_opts: GlobalOpts) -> Result<()> {
     let path = env::current_dir().unwrap_or_else(|_| { panic!() });
     let root = repo_find(&path, global_opts).unwrap_or_else(|| {
         panic!("fatal: not a grit repository");
     });
 
-    let index_path = root.join(format!("{}/index", git_dir_name(global_opts)));
-    let index_bytes = fs::read(index_path)?;
-    let index = Index::deserialize(index_bytes)?;
+    let index = read_index(&root, global_opts)?;
 
-    let tree = write_tree(index, &root, global_opts)?;
-    println!("{}", hex::encode(tree.hash()));
+    let tree = build_tree(&index.items);
+    let total = tree.tree_count();
+    // As in Git's progress messages, the line is only rewritten when the percentage changes
+    let mut shown_percent = None;
+    let counts = tree.write_with_progress(&root, global_opts, |counts| {
+        let done = counts.written + counts.reused;
+        let percent = done * 100 / total;
+        if !args.progress || shown_percent == Some(percent) {
+            return;
+        }
+        shown_percent = Some(percent);
+        match done == total {
+            true => eprintln!("Writing trees: 100% ({}/{}), done.", done, total),
+            false => eprint!("Writing trees: {:3}% ({}/{})\r", percent, done, total)
+        }
+    })?;
+    if args.verbose {
+        eprintln!("Trees written: {}, already stored: {}", counts.written, counts.reused);
+    }
+    println!("{}", hex::encode(tree.tree.hash()));
     Ok(())
 }
 
@@ -25,13 +52,79 @@ pub fn cmd_write_tree(global_opts: GlobalOpts) -> Result<()> {
 /// Conceptually, git write-tree syncs the current index contents into a set of tree files.
 /// In order to have that match what is actually in your directory right now, you need to have done a git update-index
 /// phase before you did the git write-tree.
-pub fn write_tree(index: Index, repo_root: &PathBuf, global_opts: GlobalOpts) -> Result<Tree> {
-    write_subtree(0, &index.items, repo_root, global_opts)
+pub fn write_tree(index: Index, repo_root: &Path, global_opts: GlobalOpts) -> Result<Tree> {
+    let tree = build_tree(&index.items);
+    tree.write(repo_root, global_opts)?;
+    Ok(tree.tree)
+}
+
+/// A tree built from index entries along with the trees of its subdirectories, which are only stored when it's
+/// written
+pub struct NestedTree {
+    /// The tree itself, whose entries for subdirectories already have the hashes of their trees
+    pub tree: Tree,
+    /// The trees of the subdirectories, in the order of their entries
+    pub subtrees: Vec<NestedTree>,
+}
+
+/// How many of the trees making up a tree were stored when it was written, and how many the store already had
+#[derive(Default)]
+pub struct TreeWriteCounts {
+    pub written: usize,
+    pub reused: usize,
+}
+
+impl NestedTree {
+    /// Stores the tree and all of its subtrees in the object store
+    pub fn write(&self, repo_root: &Path, global_opts: GlobalOpts) -> Result<TreeWriteCounts> {
+        self.write_with_progress(repo_root, global_opts, |_| ())
+    }
+
+    /// Stores the trees as `write` does, subtrees first, calling `progress` with the counts so far after each one
+    pub fn write_with_progress(
+        &self,
+        repo_root: &Path,
+        global_opts: GlobalOpts,
+        mut progress: impl FnMut(&TreeWriteCounts)
+    ) -> Result<TreeWriteCounts> {
+        let store = FileStore::new(repo_root, global_opts.git_mode)?;
+        let mut counts = TreeWriteCounts::default();
+        self.write_to(&store, &mut counts, &mut progress)?;
+        Ok(counts)
+    }
+
+    /// The number of trees the tree is made of, counting itself and every subtree within it
+    pub fn tree_count(&self) -> usize {
+        1 + self.subtrees.iter().map(|x| x.tree_count()).sum::<usize>()
+    }
+
+    fn write_to(
+        &self,
+        store: &FileStore,
+        counts: &mut TreeWriteCounts,
+        progress: &mut dyn FnMut(&TreeWriteCounts)
+    ) -> Result<()> {
+        for subtree in &self.subtrees {
+            subtree.write_to(store, counts, progress)?;
+        }
+        match store.write(&self.tree.hash(), &self.tree.content_with_header())? {
+            true => counts.written += 1,
+            false => counts.reused += 1
+        }
+        progress(counts);
+        Ok(())
+    }
+}
+
+/// Builds the trees for a list of index entries sorted by path, without writing anything
+pub fn build_tree(items: &[IndexItem]) -> NestedTree {
+    build_subtree(0, items)
 }
 
 
-fn write_subtree(depth: usize, index: &[IndexItem], repo_root: &PathBuf, global_opts: GlobalOpts) -> Result<Tree> {
+fn build_subtree(depth: usize, index: &[IndexItem]) -> NestedTree {
     let mut children = Vec::new();
+    let mut subtrees = Vec::new();
     let mut pos = 0;
     while pos < index.len() {
         let first = &index[pos];
@@ -46,26 +139,35 @@ fn write_subtree(depth: usize, index: &[IndexItem], repo_root: &PathBuf, global_
         } else {
             // We are at the start of a subtree. Find index items in the subtree and recurse on them
             let subtree_path = PathBuf::from_iter(first.path.components().take(depth + 1));
-            let subtree_end = index[pos..].iter().position(|x| !x.path.starts_with(&subtree_path));
+            let subtree_end = index[pos..].iter().position(|x| !x.path.starts_with(&subtree_path)).map(|x| pos + x);
             let subtree_items = if let Some(end) = subtree_end { 
                 &index[pos..end]
             } else {
                 &index[pos..]
             };
             
-            let subtree = write_subtree(depth + 1, subtree_items, repo_root, global_opts)?;
+            let subtree = build_subtree(depth + 1, subtree_items);
             children.push(TreeEntry {
                 mode: 0o40000,
                 name: subtree_path.file_name().expect("Error writing tree").to_string_lossy().to_string(),
-                hash: subtree.hash()
+                hash: subtree.tree.hash()
             });
+            subtrees.push(subtree);
             
             pos = subtree_end.unwrap_or(index.len());
         }
     }
 
-    let tree = Tree { children };
-    tree.write(repo_root, global_opts)?;
+    NestedTree { tree: Tree { children }, subtrees }
+}
+
 
-    Ok(tree)
+/// Writes the tree holding the given files, keyed by their paths relative to the root, returning its hash
+pub fn files_tree(root: &Path, files: &BTreeMap<PathBuf, TreeEntry>, global_opts: GlobalOpts) -> Result<[u8; 20]> {
+    let mut items: Vec<IndexItem> = files
+        .iter()
+        .map(|(path, entry)| IndexItem::without_stat(index_path(path), entry.mode, entry.hash, 0))
+        .collect();
+    items.sort_by(|a, b| a.path.as_os_str().as_encoded_bytes().cmp(b.path.as_os_str().as_encoded_bytes()));
+    Ok(write_tree(Index { version: 2, items }, root, global_opts)?.hash())
 }